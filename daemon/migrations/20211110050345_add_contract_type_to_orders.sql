@@ -0,0 +1,2 @@
+alter table orders
+add column contract_type text not null default 'Inverse';
@@ -0,0 +1,13 @@
+-- events are the source of truth for the state of a CFD, cfd_states only caches the result
+create table if not exists cfd_events
+(
+    id        integer primary key autoincrement,
+    cfd_id    integer not null,
+    timestamp integer not null,
+    kind      text    not null,
+    event     text    not null,
+    foreign key (cfd_id) references cfds (id)
+);
+
+create index if not exists cfd_events_cfd_id
+    on cfd_events (cfd_id);
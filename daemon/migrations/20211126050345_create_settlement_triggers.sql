@@ -0,0 +1,8 @@
+-- prices at which the taker settles a CFD automatically
+create table if not exists settlement_triggers
+(
+    id          integer primary key autoincrement,
+    order_uuid  text unique not null,
+    stop_loss   text,
+    take_profit text
+);
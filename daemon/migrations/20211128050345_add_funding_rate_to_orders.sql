@@ -0,0 +1,3 @@
+-- orders created before the maker quoted a funding rate do not charge funding
+alter table orders
+add column funding_rate text not null default '0';
@@ -0,0 +1,3 @@
+-- the noise static key of the taker of a CFD, unknown for CFDs taken before it was recorded
+alter table cfds
+add column taker_static_key text;
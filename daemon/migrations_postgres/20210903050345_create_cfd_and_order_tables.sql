@@ -0,0 +1,43 @@
+-- integers are bigint throughout, they are decoded as i64 like on SQLite
+create table if not exists orders
+(
+    id                              bigserial primary key,
+    uuid                            text unique not null,
+    trading_pair                    text        not null,
+    position                        text        not null,
+    initial_price                   text        not null,
+    min_quantity                    text        not null,
+    max_quantity                    text        not null,
+    leverage                        bigint      not null,
+    liquidation_price               text        not null,
+    creation_timestamp_seconds      bigint      not null,
+    creation_timestamp_nanoseconds  bigint      not null,
+    term_seconds                    bigint      not null,
+    term_nanoseconds                bigint      not null,
+    origin                          text        not null,
+    oracle_event_id                 text        not null
+);
+
+create unique index if not exists orders_uuid
+    on orders (uuid);
+
+create table if not exists cfds
+(
+    id           bigserial primary key,
+    order_id     bigint unique not null,
+    order_uuid   text unique   not null,
+    quantity_usd text          not null,
+
+    foreign key (order_id) references orders (id)
+);
+
+create unique index if not exists cfd_order_uuid
+    on cfds (order_uuid);
+
+create table if not exists cfd_states
+(
+    id     bigserial primary key,
+    cfd_id bigint not null,
+    state  text   not null,
+    foreign key (cfd_id) references cfds (id)
+);
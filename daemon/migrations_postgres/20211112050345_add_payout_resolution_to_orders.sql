@@ -0,0 +1,9 @@
+-- defaults match the payout curve of orders created before the resolution was configurable
+alter table orders
+add column n_payouts bigint not null default 200;
+
+alter table orders
+add column payout_bucket_digits bigint not null default 0;
+
+alter table orders
+add column payout_density text not null default 'Uniform';
@@ -0,0 +1,33 @@
+-- version 0 marks states stored as a single JSON blob, they are rewritten on startup
+alter table cfd_states
+add column state_version bigint not null default 0;
+
+alter table cfd_states
+add column kind text;
+
+alter table cfd_states
+add column transition_timestamp bigint;
+
+alter table cfd_states
+add column lock_txid text;
+
+alter table cfd_states
+add column commit_txid text;
+
+alter table cfd_states
+add column refund_txid text;
+
+alter table cfd_states
+add column cet_txid text;
+
+alter table cfd_states
+add column collaborative_close_txid text;
+
+alter table cfd_states
+add column payout_sats bigint;
+
+alter table cfd_states
+add column dlc text;
+
+create index if not exists cfd_states_kind
+on cfd_states (kind);
@@ -0,0 +1,35 @@
+-- finished CFDs are moved here so that loading the CFDs in progress stays cheap
+-- the columns mirror cfd_states and cfd_events, rows are moved with `select *`
+create table if not exists archived_cfd_states
+(
+    id                       bigint primary key,
+    cfd_id                   bigint not null,
+    state                    text   not null,
+    state_version            bigint not null,
+    kind                     text,
+    transition_timestamp     bigint,
+    lock_txid                text,
+    commit_txid              text,
+    refund_txid              text,
+    cet_txid                 text,
+    collaborative_close_txid text,
+    payout_sats              bigint,
+    dlc                      text,
+    foreign key (cfd_id) references cfds (id)
+);
+
+create index if not exists archived_cfd_states_cfd_id
+    on archived_cfd_states (cfd_id);
+
+create table if not exists archived_cfd_events
+(
+    id        bigint primary key,
+    cfd_id    bigint not null,
+    timestamp bigint not null,
+    kind      text   not null,
+    event     text   not null,
+    foreign key (cfd_id) references cfds (id)
+);
+
+create index if not exists archived_cfd_events_cfd_id
+    on archived_cfd_events (cfd_id);
@@ -0,0 +1,3 @@
+-- funding paid by the long party over all roll-overs, CFDs rolled over before had no funding
+alter table cfds
+add column funding_sats bigint not null default 0;
@@ -0,0 +1,9 @@
+-- proposals to settle or update a CFD that were neither accepted nor rejected yet
+create table if not exists cfd_proposals
+(
+    id               bigserial primary key,
+    order_uuid       text unique not null,
+    proposal         text        not null,
+    taker_id         text,
+    expiry_timestamp bigint      not null
+);
@@ -0,0 +1,118 @@
+use crate::db::load_cfd_history;
+use crate::model::cfd::{Cfd, CfdEvent, Order, OrderId};
+use crate::model::{Timestamp, Usd};
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::pool::PoolConnection;
+use sqlx::Any;
+use std::iter;
+
+/// An event of a CFD together with the state it led to.
+///
+/// Only a description of the event is included, the events themselves contain the secrets of
+/// the DLC.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditLogEntry {
+    pub timestamp: Timestamp,
+    pub event: &'static str,
+    pub description: String,
+    pub state: String,
+}
+
+/// Loads the full event timeline of a CFD, oldest first.
+pub async fn load(order_id: OrderId, conn: &mut PoolConnection<Any>) -> Result<Vec<AuditLogEntry>> {
+    let (order, quantity, events) = load_cfd_history(order_id, conn).await?;
+
+    timeline(order, quantity, events)
+}
+
+/// Replays the events and records the state of the CFD after each of them.
+pub fn timeline(
+    order: Order,
+    quantity: Usd,
+    events: Vec<(CfdEvent, Timestamp)>,
+) -> Result<Vec<AuditLogEntry>> {
+    let mut cfd = None::<Cfd>;
+    let mut entries = Vec::new();
+
+    for (event, timestamp) in events {
+        let kind = event.kind();
+        let description = event.to_string();
+
+        let state = match cfd.as_mut() {
+            Some(cfd) => {
+                cfd.apply(event, timestamp)?;
+                cfd.state.to_string()
+            }
+            None => {
+                let created = Cfd::replay(order.clone(), quantity, iter::once((event, timestamp)))?;
+                let state = created.state.to_string();
+                cfd = Some(created);
+                state
+            }
+        };
+
+        entries.push(AuditLogEntry {
+            timestamp,
+            event: kind,
+            description,
+            state,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::cfd::{CfdState, Origin};
+    use crate::model::Price;
+    use bdk::bitcoin::SignedAmount;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn timeline_shows_state_after_each_event() {
+        let order = Order {
+            max_quantity: Usd::new(dec!(1000)),
+            origin: Origin::Ours,
+            ..Order::dummy().with_price(Price::new(dec!(50_000)).unwrap())
+        };
+        let events = vec![
+            (
+                CfdEvent::Created {
+                    state: CfdState::outgoing_order_request(),
+                    quantity: Usd::new(dec!(100)),
+                    price: order.price,
+                    funding: SignedAmount::ZERO,
+                },
+                Timestamp::new(1),
+            ),
+            (CfdEvent::ContractSetupStarted, Timestamp::new(2)),
+            (
+                CfdEvent::ContractSetupFailed {
+                    info: String::from("dummy failure"),
+                },
+                Timestamp::new(3),
+            ),
+        ];
+
+        let timeline = timeline(order, Usd::new(dec!(100)), events).unwrap();
+
+        assert_eq!(
+            timeline
+                .iter()
+                .map(|entry| (entry.timestamp, entry.event))
+                .collect::<Vec<_>>(),
+            vec![
+                (Timestamp::new(1), "Created"),
+                (Timestamp::new(2), "ContractSetupStarted"),
+                (Timestamp::new(3), "ContractSetupFailed"),
+            ]
+        );
+        assert_eq!(
+            timeline.last().unwrap().state,
+            CfdState::setup_failed(String::new()).to_string()
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use hex::FromHexError;
-use rocket::http::Status;
+use rocket::http::{Cookie, Header, SameSite, Status};
 use rocket::outcome::{try_outcome, IntoOutcome};
 use rocket::request::{FromRequest, Outcome};
 use rocket::{Request, State};
@@ -10,7 +10,18 @@ use std::str::FromStr;
 /// A request guard that can be included in handler definitions to enforce authentication.
 pub struct Authenticated {}
 
+/// A request guard that can be included in handler definitions of requests that change state to
+/// enforce the [`CsrfToken`].
+pub struct CsrfVerified {}
+
 pub const MAKER_USERNAME: &str = "maker";
+pub const TAKER_USERNAME: &str = "taker";
+
+/// The cookie through which the embedded frontend learns the [`CsrfToken`].
+pub const CSRF_COOKIE: &str = "csrf-token";
+
+/// The header in which the embedded frontend sends the [`CsrfToken`] back.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
 
 #[derive(Debug)]
 pub enum Error {
@@ -20,9 +31,17 @@ pub enum Error {
     BadBasicAuthHeader(BasicAuthError),
     /// The auth password was not configured in Rocket's state.
     MissingPassword,
+    /// The username was not configured in Rocket's state.
+    MissingUsername,
     NoAuthHeader,
+    /// The CSRF token was not configured in Rocket's state.
+    MissingCsrfToken,
+    BadCsrfToken,
 }
 
+/// The user the [`Password`] belongs to.
+pub struct Username(pub &'static str);
+
 #[derive(PartialEq)]
 pub struct Password([u8; 32]);
 
@@ -38,6 +57,29 @@ impl fmt::Display for Password {
     }
 }
 
+/// A secret token that requests changing state have to carry in the [`CSRF_HEADER`].
+///
+/// Other sites can make the browser send requests to the API including the basic auth
+/// credentials, but they cannot read the cookie the token is handed out in. The token is derived
+/// from the seed like the [`Password`], so that open browser tabs keep working across restarts.
+#[derive(PartialEq)]
+pub struct CsrfToken(String);
+
+impl From<[u8; 32]> for CsrfToken {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(hex::encode(bytes))
+    }
+}
+
+impl CsrfToken {
+    pub fn cookie(&self) -> Cookie<'static> {
+        Cookie::build(CSRF_COOKIE, self.0.clone())
+            .path("/")
+            .same_site(SameSite::Strict)
+            .finish()
+    }
+}
+
 impl FromStr for Password {
     type Err = FromHexError;
 
@@ -63,8 +105,12 @@ impl<'r> FromRequest<'r> for Authenticated {
             .guard::<&'r State<Password>>()
             .await
             .map_failure(|(status, _)| (status, Error::MissingPassword)));
+        let username = try_outcome!(req
+            .guard::<&'r State<Username>>()
+            .await
+            .map_failure(|(status, _)| (status, Error::MissingUsername)));
 
-        if basic_auth.username != MAKER_USERNAME {
+        if basic_auth.username != username.0 {
             return Outcome::Failure((
                 Status::Unauthorized,
                 Error::UnknownUser(basic_auth.username),
@@ -84,3 +130,79 @@ impl<'r> FromRequest<'r> for Authenticated {
         Outcome::Success(Authenticated {})
     }
 }
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CsrfVerified {
+    type Error = Error;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = try_outcome!(req
+            .guard::<&'r State<CsrfToken>>()
+            .await
+            .map_failure(|(status, _)| (status, Error::MissingCsrfToken)));
+
+        match req.headers().get_one(CSRF_HEADER) {
+            Some(header) if header == token.0 => Outcome::Success(CsrfVerified {}),
+            _ => Outcome::Failure((Status::Forbidden, Error::BadCsrfToken)),
+        }
+    }
+}
+
+/// A "catcher" for all 401 responses, triggers the browser's basic auth implementation.
+#[rocket::catch(401)]
+pub fn unauthorized() -> PromptAuthentication {
+    PromptAuthentication {
+        inner: (),
+        www_authenticate: Header::new("WWW-Authenticate", r#"Basic charset="UTF-8"#),
+    }
+}
+
+/// A rocket responder that prompts the user to sign in to access the API.
+#[derive(rocket::Responder)]
+#[response(status = 401)]
+pub struct PromptAuthentication {
+    inner: (),
+    www_authenticate: Header<'static>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+    use rocket::{Build, Rocket};
+
+    #[test]
+    fn requests_without_csrf_token_are_forbidden() {
+        let client = Client::tracked(rocket()).unwrap();
+        let token = client.get("/token").dispatch().into_string().unwrap();
+
+        let missing = client.post("/protected").dispatch();
+        let wrong = client
+            .post("/protected")
+            .header(Header::new(CSRF_HEADER, "not the token"))
+            .dispatch();
+        let correct = client
+            .post("/protected")
+            .header(Header::new(CSRF_HEADER, token))
+            .dispatch();
+
+        assert_eq!(missing.status(), Status::Forbidden);
+        assert_eq!(wrong.status(), Status::Forbidden);
+        assert_eq!(correct.status(), Status::Ok);
+    }
+
+    #[rocket::post("/protected")]
+    async fn protected(_csrf: CsrfVerified) {}
+
+    #[rocket::get("/token")]
+    fn token(token: &State<CsrfToken>) -> String {
+        token.0.clone()
+    }
+
+    fn rocket() -> Rocket<Build> {
+        rocket::build()
+            .manage(CsrfToken::from([42u8; 32]))
+            .mount("/", rocket::routes![protected, token])
+    }
+}
@@ -0,0 +1,324 @@
+use crate::db;
+use crate::model::cfd::CfdState;
+use crate::model::Timestamp;
+use anyhow::{bail, Context, Result};
+use bdk::bitcoin::Txid;
+use sqlx::any::AnyKind;
+use sqlx::AnyPool;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// The directory snapshots are written to unless a path is given explicitly.
+#[derive(Debug, Clone)]
+pub struct SnapshotDir {
+    dir: PathBuf,
+    /// How many snapshots are kept per prefix, older ones are deleted when taking a new one.
+    keep: usize,
+}
+
+impl SnapshotDir {
+    pub fn new(data_dir: &Path, keep: usize) -> Self {
+        Self {
+            dir: data_dir.join("backups"),
+            keep,
+        }
+    }
+
+    /// Takes a snapshot into a new file named after the current time, e.g.
+    /// `maker-1636000000-<uuid>.sqlite`.
+    ///
+    /// The random part of the name keeps snapshots taken within the same second apart.
+    pub async fn snapshot(&self, pool: &AnyPool, prefix: &str) -> Result<PathBuf> {
+        let path = self.dir.join(format!(
+            "{}-{}-{}.sqlite",
+            prefix,
+            Timestamp::now()?.seconds(),
+            Uuid::new_v4()
+        ));
+
+        snapshot(pool, &path).await?;
+        self.prune(prefix).await?;
+
+        Ok(path)
+    }
+
+    /// Deletes the oldest snapshots with the given prefix until only `keep` of them are left.
+    async fn prune(&self, prefix: &str) -> Result<()> {
+        let mut snapshots = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let name = match name.to_str() {
+                Some(name) => name,
+                None => continue,
+            };
+            if !name.starts_with(&format!("{}-", prefix)) || !name.ends_with(".sqlite") {
+                continue;
+            }
+
+            let modified = entry.metadata().await?.modified()?;
+            snapshots.push((modified, entry.path()));
+        }
+
+        snapshots.sort();
+        // The snapshot that was just taken is always kept
+        let excess = snapshots.len().saturating_sub(self.keep.max(1));
+
+        for (_, path) in snapshots.into_iter().take(excess) {
+            // Another snapshot taken at the same time might have deleted it already
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => tracing::info!("Deleted old database snapshot {}", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Takes a consistent snapshot of the database, it does not have to be taken offline for this.
+///
+/// Only SQLite databases can be snapshotted, Postgres comes with `pg_dump` for this.
+pub async fn snapshot(pool: &AnyPool, path: &Path) -> Result<()> {
+    if !matches!(pool.any_kind(), AnyKind::Sqlite) {
+        bail!("Snapshots are only supported for SQLite, use pg_dump to back up Postgres");
+    }
+
+    if path.exists() {
+        bail!("Refusing to overwrite existing file {}", path.display());
+    }
+
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+
+    let path_str = path.to_str().context("Snapshot path is not valid UTF-8")?;
+
+    sqlx::query("vacuum into $1")
+        .bind(path_str)
+        .execute(pool)
+        .await
+        .with_context(|| format!("Failed to write snapshot to {}", path.display()))?;
+
+    tracing::info!("Database snapshot written to {}", path.display());
+
+    Ok(())
+}
+
+/// Replaces the database at `db_file` with a snapshot.
+///
+/// The snapshot is rejected if it contains an open CFD whose lock transaction is not part of
+/// `wallet_txids`, as it was then not taken with this wallet. The replaced database is kept next
+/// to the restored one.
+///
+/// Returns warnings about CFDs of the snapshot that predate a roll-over recorded in the replaced
+/// database. Their commit transaction was revoked and must not be published, the counterparty
+/// could punish us for it.
+pub async fn restore(
+    snapshot: &Path,
+    db_file: &Path,
+    wallet_txids: &HashSet<Txid>,
+) -> Result<Vec<String>> {
+    let candidate = with_suffix(db_file, ".restore");
+    tokio::fs::copy(snapshot, &candidate)
+        .await
+        .with_context(|| format!("Failed to copy snapshot {}", snapshot.display()))?;
+
+    let warnings = match validate(&candidate, db_file, wallet_txids).await {
+        Ok(warnings) => warnings,
+        Err(e) => {
+            tokio::fs::remove_file(&candidate).await?;
+            return Err(e);
+        }
+    };
+
+    let replaced = move_aside(db_file, "replaced").await?;
+    tokio::fs::rename(&candidate, db_file).await?;
+
+    tracing::info!(
+        "Database restored from {}, the previous database was moved to {}",
+        snapshot.display(),
+        replaced.display()
+    );
+
+    Ok(warnings)
+}
+
+/// Moves the database and its WAL files out of the way, returns the path it was moved to.
+///
+/// The database stays around under the returned path, it can be restored from there.
+pub async fn move_aside(db_file: &Path, label: &str) -> Result<PathBuf> {
+    let moved = with_suffix(
+        db_file,
+        &format!(".{}-{}", label, Timestamp::now()?.seconds()),
+    );
+    for suffix in ["", "-wal", "-shm"] {
+        let file = with_suffix(db_file, suffix);
+        if file.exists() {
+            tokio::fs::rename(&file, with_suffix(&moved, suffix)).await?;
+        }
+    }
+
+    Ok(moved)
+}
+
+async fn validate(
+    candidate: &Path,
+    db_file: &Path,
+    wallet_txids: &HashSet<Txid>,
+) -> Result<Vec<String>> {
+    // Brings snapshots of older versions up to date, they would be migrated on startup anyway
+    let pool = db::open_sqlite(candidate).await?;
+    db::run_migrations(&pool)
+        .await
+        .context("Failed to migrate snapshot")?;
+    let cfds = db::load_all_cfds(&mut pool.acquire().await?).await?;
+    pool.close().await;
+
+    for cfd in &cfds {
+        // The lock transaction of a CFD in PendingOpen may not have been broadcast yet
+        if matches!(cfd.state, CfdState::PendingOpen { .. }) {
+            continue;
+        }
+
+        if let Some(dlc) = cfd.state.get_dlc() {
+            let lock_txid = dlc.lock.0.txid();
+            if !wallet_txids.contains(&lock_txid) {
+                bail!(
+                    "Snapshot is not from this wallet, lock transaction {} of CFD {} is unknown",
+                    lock_txid,
+                    cfd.order.id
+                );
+            }
+        }
+    }
+
+    if !db_file.exists() {
+        return Ok(vec![
+            "No database to compare with, cannot tell whether the snapshot predates a roll-over"
+                .to_owned(),
+        ]);
+    }
+
+    let pool = db::open_sqlite(db_file).await?;
+    let states = db::load_all_cfd_states(&mut pool.acquire().await?).await?;
+    pool.close().await;
+
+    let revoked_commits = states
+        .iter()
+        .filter_map(|(_, state)| state.get_dlc())
+        .flat_map(|dlc| dlc.revoked_commit.iter().map(|revoked| revoked.txid))
+        .collect::<HashSet<_>>();
+
+    let warnings = cfds
+        .iter()
+        .filter_map(|cfd| {
+            let commit_txid = cfd.state.get_dlc()?.commit.0.txid();
+
+            revoked_commits.contains(&commit_txid).then(|| {
+                format!(
+                    "Snapshot predates a roll-over of CFD {}, commit transaction {} was revoked",
+                    cfd.order.id, commit_txid
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for warning in &warnings {
+        tracing::warn!("{}", warning);
+    }
+
+    Ok(warnings)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{insert_cfd, insert_order};
+    use crate::model::cfd::{Cfd, Order};
+    use crate::model::{Price, Usd};
+    use rust_decimal_macros::dec;
+
+    #[tokio::test]
+    async fn snapshot_can_be_restored() {
+        let dir = std::env::temp_dir().join(format!("backup-{}", uuid::Uuid::new_v4()));
+        let db_file = dir.join("maker.sqlite");
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let pool = db::open_sqlite(&db_file).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+
+        let order = Order {
+            max_quantity: Usd::new(dec!(1000)),
+            ..Order::dummy().with_price(Price::new(dec!(50_000)).unwrap())
+        };
+        let cfd = Cfd::new(
+            order.clone(),
+            Usd::new(dec!(100)),
+            CfdState::outgoing_order_request(),
+        );
+        let mut conn = pool.acquire().await.unwrap();
+        insert_order(&order, &mut conn).await.unwrap();
+        insert_cfd(&cfd, &mut conn).await.unwrap();
+        drop(conn);
+
+        let snapshot_path = SnapshotDir::new(&dir, 10)
+            .snapshot(&pool, "maker")
+            .await
+            .unwrap();
+        pool.close().await;
+
+        let warnings = restore(&snapshot_path, &db_file, &HashSet::new())
+            .await
+            .unwrap();
+
+        let pool = db::open_sqlite(&db_file).await.unwrap();
+        let restored = db::load_all_cfds(&mut pool.acquire().await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(restored, vec![cfd]);
+        assert!(warnings.is_empty());
+
+        pool.close().await;
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn snapshots_taken_at_once_do_not_collide_and_old_ones_are_deleted() {
+        let dir = std::env::temp_dir().join(format!("backup-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let pool = db::open_sqlite(&dir.join("maker.sqlite")).await.unwrap();
+        db::run_migrations(&pool).await.unwrap();
+        let snapshot_dir = SnapshotDir::new(&dir, 2);
+
+        let (first, second) = tokio::join!(
+            snapshot_dir.snapshot(&pool, "maker"),
+            snapshot_dir.snapshot(&pool, "maker")
+        );
+        assert_ne!(first.unwrap(), second.unwrap());
+
+        let latest = snapshot_dir.snapshot(&pool, "maker").await.unwrap();
+
+        let mut snapshots = Vec::new();
+        let mut entries = tokio::fs::read_dir(dir.join("backups")).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            snapshots.push(entry.path());
+        }
+        assert_eq!(snapshots.len(), 2);
+        assert!(snapshots.contains(&latest));
+
+        pool.close().await;
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
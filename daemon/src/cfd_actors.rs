@@ -1,14 +1,15 @@
 use crate::db::load_cfd_by_order_id;
-use crate::model::cfd::{Attestation, Cfd, CfdState, CfdStateChangeEvent, OrderId};
-use crate::{db, monitor, oracle, try_continue, wallet};
+use crate::model::cfd::{Attestation, Cfd, CfdEvent, CfdState, CfdStateChangeEvent, OrderId};
+use crate::model::Timestamp;
+use crate::{db, housekeeping, monitor, oracle, try_continue, wallet};
 use anyhow::{bail, Context, Result};
 use sqlx::pool::PoolConnection;
-use sqlx::Sqlite;
+use sqlx::{Any, AnyConnection};
 use tokio::sync::watch;
 
 pub async fn insert_cfd(
     cfd: &Cfd,
-    conn: &mut PoolConnection<Sqlite>,
+    conn: &mut PoolConnection<Any>,
     update_sender: &watch::Sender<Vec<Cfd>>,
 ) -> Result<()> {
     if load_cfd_by_order_id(cfd.order.id, conn).await.is_ok() {
@@ -23,19 +24,43 @@ pub async fn insert_cfd(
     Ok(())
 }
 
-pub async fn append_cfd_state(
-    cfd: &Cfd,
-    conn: &mut PoolConnection<Sqlite>,
+/// Applies the event to the CFD and records it, returns whether the state of the CFD changed.
+///
+/// Events that do not change the state are not recorded.
+pub async fn apply_event(
+    cfd: &mut Cfd,
+    event: CfdEvent,
+    conn: &mut AnyConnection,
     update_sender: &watch::Sender<Vec<Cfd>>,
-) -> Result<()> {
-    db::append_cfd_state(cfd, conn).await?;
+) -> Result<bool> {
+    let timestamp = Timestamp::now()?;
+    if !cfd.apply(event.clone(), timestamp)? {
+        return Ok(false);
+    }
+
+    db::append_cfd_event(cfd, &event, timestamp, conn).await?;
     update_sender.send(db::load_all_cfds(conn).await?)?;
+
+    Ok(true)
+}
+
+/// Archives the CFDs that have been finished for longer than `archive_after` and drops them from
+/// the feed.
+pub async fn archive_finished_cfds(
+    archive_after: time::Duration,
+    conn: &mut PoolConnection<Any>,
+    update_sender: &watch::Sender<Vec<Cfd>>,
+) -> Result<()> {
+    if housekeeping::archive_finished_cfds(conn, archive_after).await? > 0 {
+        update_sender.send(db::load_all_cfds(conn).await?)?;
+    }
+
     Ok(())
 }
 
 pub async fn try_cet_publication<W>(
     cfd: &mut Cfd,
-    conn: &mut PoolConnection<Sqlite>,
+    conn: &mut PoolConnection<Any>,
     wallet: &xtra::Address<W>,
     update_sender: &watch::Sender<Vec<Cfd>>,
 ) -> Result<()>
@@ -50,11 +75,10 @@ where
                 .context("Failed to send transaction")?;
             tracing::info!("CET published with txid {}", txid);
 
-            if cfd.handle(CfdStateChangeEvent::CetSent)?.is_none() {
+            let event = CfdEvent::StateChange(CfdStateChangeEvent::CetSent);
+            if !apply_event(cfd, event, conn, update_sender).await? {
                 bail!("If we can get the CET we should be able to transition")
             }
-
-            append_cfd_state(cfd, conn, update_sender).await?;
         }
         Err(not_ready_yet) => {
             tracing::debug!("{:#}", not_ready_yet);
@@ -67,7 +91,7 @@ where
 
 pub async fn handle_monitoring_event<W>(
     event: monitor::Event,
-    conn: &mut PoolConnection<Sqlite>,
+    conn: &mut PoolConnection<Any>,
     wallet: &xtra::Address<W>,
     update_sender: &watch::Sender<Vec<Cfd>>,
 ) -> Result<()>
@@ -78,14 +102,13 @@ where
 
     let mut cfd = db::load_cfd_by_order_id(order_id, conn).await?;
 
-    if cfd.handle(CfdStateChangeEvent::Monitor(event))?.is_none() {
+    let event = CfdEvent::StateChange(CfdStateChangeEvent::Monitor(event));
+    if !apply_event(&mut cfd, event, conn, update_sender).await? {
         // early exit if there was not state change
         // this is for cases where we are already in a final state
         return Ok(());
     }
 
-    append_cfd_state(&cfd, conn, update_sender).await?;
-
     if let CfdState::OpenCommitted { .. } = cfd.state {
         try_cet_publication(&mut cfd, conn, wallet, update_sender).await?;
     } else if let CfdState::PendingRefund { .. } = cfd.state {
@@ -104,7 +127,7 @@ where
 
 pub async fn handle_commit<W>(
     order_id: OrderId,
-    conn: &mut PoolConnection<Sqlite>,
+    conn: &mut PoolConnection<Any>,
     wallet: &xtra::Address<W>,
     update_sender: &watch::Sender<Vec<Cfd>>,
 ) -> Result<()>
@@ -122,11 +145,10 @@ where
         .await?
         .context("Failed to publish commit tx")?;
 
-    if cfd.handle(CfdStateChangeEvent::CommitTxSent)?.is_none() {
+    let event = CfdEvent::StateChange(CfdStateChangeEvent::CommitTxSent);
+    if !apply_event(&mut cfd, event, conn, update_sender).await? {
         bail!("If we can get the commit tx we should be able to transition")
     }
-
-    append_cfd_state(&cfd, conn, update_sender).await?;
     tracing::info!("Commit transaction published on chain: {}", txid);
 
     Ok(())
@@ -134,7 +156,7 @@ where
 
 pub async fn handle_oracle_attestation<W>(
     attestation: oracle::Attestation,
-    conn: &mut PoolConnection<Sqlite>,
+    conn: &mut PoolConnection<Any>,
     wallet: &xtra::Address<W>,
     update_sender: &watch::Sender<Vec<Cfd>>,
 ) -> Result<()>
@@ -160,17 +182,16 @@ where
             cfd.role(),
         ));
 
-        let new_state =
-            try_continue!(cfd.handle(CfdStateChangeEvent::OracleAttestation(attestation)));
+        let event = CfdEvent::StateChange(CfdStateChangeEvent::OracleAttestation(attestation));
+        let changed = try_continue!(apply_event(cfd, event, conn, update_sender).await);
 
-        if new_state.is_none() {
+        if !changed {
             // if we don't transition to a new state after oracle attestation we ignore the cfd
             // this is for cases where we cannot handle the attestation which should be in a
             // final state
             continue;
         }
 
-        try_continue!(append_cfd_state(cfd, conn, update_sender).await);
         try_continue!(try_cet_publication(cfd, conn, wallet, update_sender)
             .await
             .context("Error when trying to publish CET"));
@@ -1,19 +1,361 @@
-use crate::model::cfd::{Cfd, CfdState, Order, OrderId};
-use crate::model::{BitMexPriceEventId, Usd};
-use anyhow::{Context, Result};
-use rust_decimal::Decimal;
+use crate::model::cfd::{
+    Cfd, CfdEvent, CfdState, Order, OrderId, Payout, SettlementTriggers, UpdateCfdProposal,
+};
+use crate::model::{
+    BitMexPriceEventId, FundingRate, Leverage, PayoutResolution, TakerId, Timestamp, Usd,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::any::{AnyArguments, AnyKind, AnyRow};
 use sqlx::pool::PoolConnection;
-use sqlx::{Sqlite, SqlitePool};
-use std::mem;
-use std::str::FromStr;
+use sqlx::query::Query;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{Any, AnyConnection, AnyPool, Connection, Row};
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::path::Path;
 use time::Duration;
 
-pub async fn run_migrations(pool: &SqlitePool) -> anyhow::Result<()> {
-    sqlx::migrate!("./migrations").run(pool).await?;
+// The queries are not checked at compile time with `sqlx::query!`: the same queries run against
+// SQLite and Postgres through `sqlx::Any`, which the macros do not support. Instead the tests run
+// against either database, see `setup_test_db`.
+
+/// Opens the SQLite database in `file`, creating it if it does not exist yet.
+pub async fn open_sqlite(file: &Path) -> Result<AnyPool> {
+    let options = SqliteConnectOptions::new()
+        .create_if_missing(true)
+        .filename(file);
+    let pool = AnyPool::connect_with(options.into())
+        .await
+        .with_context(|| format!("Failed to open database {}", file.display()))?;
+
+    Ok(pool)
+}
+
+/// Runs the migrations of the dialect of the database, followed by the data migrations.
+///
+/// Both dialects have the same migrations, they only differ in their column types.
+pub async fn run_migrations(pool: &AnyPool) -> anyhow::Result<()> {
+    match pool.any_kind() {
+        AnyKind::Sqlite => sqlx::migrate!("./migrations").run(pool).await?,
+        AnyKind::Postgres => sqlx::migrate!("./migrations_postgres").run(pool).await?,
+    }
+    migrate_legacy_cfd_states(&mut pool.acquire().await?).await?;
+    migrate_cfd_states_to_events(&mut pool.acquire().await?).await?;
+    Ok(())
+}
+
+/// The version of the format [`CfdState`]s are stored in.
+///
+/// Version 0 stored the whole state as JSON in the `state` column. Version 1 splits the state
+/// into the columns of [`EncodedCfdState`].
+const CFD_STATE_VERSION: i64 = 1;
+
+/// Rewrites all CFD states that are still stored in a previous format.
+///
+/// Every row is rewritten on its own, an interrupted migration is picked up on the next start.
+async fn migrate_legacy_cfd_states(conn: &mut PoolConnection<Any>) -> anyhow::Result<()> {
+    let rows = sqlx::query(
+        r#"
+        select
+            id,
+            state_version,
+            state,
+            dlc
+        from cfd_states
+        where state_version != $1;
+        "#,
+    )
+    .bind(CFD_STATE_VERSION)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!(
+        "Migrating {} CFD states to version {}",
+        rows.len(),
+        CFD_STATE_VERSION
+    );
+
+    for row in rows {
+        let id = row.try_get::<i64, _>("id")?;
+        let state =
+            decode_cfd_state(&row).with_context(|| format!("Failed to decode CFD state {}", id))?;
+
+        EncodedCfdState::encode(&state)?
+            .bind(sqlx::query(
+                r#"
+                update cfd_states set
+                    state_version = $1,
+                    kind = $2,
+                    transition_timestamp = $3,
+                    lock_txid = $4,
+                    commit_txid = $5,
+                    refund_txid = $6,
+                    cet_txid = $7,
+                    collaborative_close_txid = $8,
+                    payout_sats = $9,
+                    state = $10,
+                    dlc = $11
+                where id = $12;
+                "#,
+            ))
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+    }
+
     Ok(())
 }
 
-pub async fn insert_order(order: &Order, conn: &mut PoolConnection<Sqlite>) -> anyhow::Result<()> {
+/// Records the states of CFDs that were created before events were kept as events.
+///
+/// Every state becomes a [`CfdEvent::LegacyState`] with the transition timestamp of the state,
+/// replaying them yields the latest state again.
+async fn migrate_cfd_states_to_events(conn: &mut PoolConnection<Any>) -> anyhow::Result<()> {
+    let rows = sqlx::query(
+        r#"
+        select
+            cfd_id,
+            state_version,
+            state,
+            dlc
+        from cfd_states
+        where cfd_id not in (
+            select
+                cfd_id
+            from cfd_events
+            union
+            select
+                cfd_id
+            from archived_cfd_events
+        )
+        order by id
+        "#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!("Migrating {} CFD states to events", rows.len());
+
+    for row in rows {
+        let cfd_id = row.try_get::<i64, _>("cfd_id")?;
+        let state = decode_cfd_state(&row)
+            .with_context(|| format!("Failed to decode state of CFD {}", cfd_id))?;
+        let timestamp = state.get_transition_timestamp();
+
+        insert_cfd_event(cfd_id, &CfdEvent::LegacyState(state), timestamp, conn).await?;
+    }
+
+    Ok(())
+}
+
+/// A [`CfdState`] split into the columns of the `cfd_states` table.
+///
+/// The DLC is moved out of the JSON of the state into its own column. All other columns
+/// duplicate parts of the state so that CFDs can be queried without deserializing it.
+struct EncodedCfdState {
+    kind: String,
+    transition_timestamp: i64,
+    lock_txid: Option<String>,
+    commit_txid: Option<String>,
+    refund_txid: Option<String>,
+    cet_txid: Option<String>,
+    collaborative_close_txid: Option<String>,
+    payout_sats: Option<i64>,
+    state: String,
+    dlc: Option<String>,
+}
+
+impl EncodedCfdState {
+    fn encode(state: &CfdState) -> Result<Self> {
+        let mut json = serde_json::to_value(state)?;
+
+        let kind = json
+            .get("type")
+            .and_then(Value::as_str)
+            .context("Serialized CFD state has no type")?
+            .to_owned();
+        let dlc = json
+            .get_mut("payload")
+            .and_then(Value::as_object_mut)
+            .and_then(|payload| payload.remove("dlc"))
+            .map(|dlc| serde_json::to_string(&dlc))
+            .transpose()?;
+
+        let collaborative_close = match state {
+            CfdState::Closed {
+                payout: Payout::CollaborativeClose(settlement),
+                ..
+            } => Some(settlement.clone()),
+            _ => state.get_collaborative_close(),
+        };
+        let payout = match state {
+            CfdState::Closed {
+                payout: Payout::CollaborativeClose(settlement),
+                ..
+            } => Some(settlement.payout()),
+            CfdState::Closed {
+                payout: Payout::Cet(attestation),
+                ..
+            } => Some(attestation.payout()),
+            _ => None,
+        };
+
+        Ok(Self {
+            kind,
+            transition_timestamp: state.get_transition_timestamp().seconds(),
+            lock_txid: state.get_dlc().map(|dlc| dlc.lock.0.txid().to_string()),
+            commit_txid: state.get_dlc().map(|dlc| dlc.commit.0.txid().to_string()),
+            refund_txid: state.get_dlc().map(|dlc| dlc.refund.0.txid().to_string()),
+            cet_txid: state
+                .get_attestation()
+                .map(|attestation| attestation.txid().to_string()),
+            collaborative_close_txid: collaborative_close
+                .map(|settlement| settlement.tx.txid().to_string()),
+            payout_sats: payout
+                .map(|payout| i64::try_from(payout.as_sat()))
+                .transpose()?,
+            state: serde_json::to_string(&json)?,
+            dlc,
+        })
+    }
+
+    /// Binds the version and all columns in the order of the struct, starting at `$1`.
+    fn bind(self, query: AnyQuery<'_>) -> AnyQuery<'_> {
+        query
+            .bind(CFD_STATE_VERSION)
+            .bind(self.kind)
+            .bind(self.transition_timestamp)
+            .bind(self.lock_txid)
+            .bind(self.commit_txid)
+            .bind(self.refund_txid)
+            .bind(self.cet_txid)
+            .bind(self.collaborative_close_txid)
+            .bind(self.payout_sats)
+            .bind(self.state)
+            .bind(self.dlc)
+    }
+}
+
+type AnyQuery<'q> = Query<'q, Any, AnyArguments<'q>>;
+
+/// Decodes a [`CfdState`] from the `state_version`, `state` and `dlc` columns of the row.
+fn decode_cfd_state(row: &AnyRow) -> Result<CfdState> {
+    let version = row.try_get::<i64, _>("state_version")?;
+    let state = row.try_get::<String, _>("state")?;
+    let dlc = row.try_get::<Option<String>, _>("dlc")?;
+
+    let state = match version {
+        0 => serde_json::from_str(&state)?,
+        CFD_STATE_VERSION => {
+            let mut state = serde_json::from_str::<Value>(&state)?;
+
+            if let Some(dlc) = dlc {
+                state
+                    .get_mut("payload")
+                    .and_then(Value::as_object_mut)
+                    .context("Stored CFD state with DLC has no payload")?
+                    .insert("dlc".to_owned(), serde_json::from_str(&dlc)?);
+            }
+
+            serde_json::from_value(state)?
+        }
+        version => bail!("Unsupported CFD state version {}", version),
+    };
+
+    Ok(state)
+}
+
+/// Encodes a value as text the way serde represents it, e.g. an enum by the name of its variant.
+///
+/// This is how the SQLite driver stored these types before both dialects were supported.
+fn encode_text<T: Serialize>(value: &T) -> Result<String> {
+    match serde_json::to_value(value)? {
+        Value::String(text) => Ok(text),
+        value => bail!("Expected value to serialize to a string but got {}", value),
+    }
+}
+
+/// Decodes a value that was stored with [`encode_text`].
+fn decode_text<T: DeserializeOwned>(row: &AnyRow, column: &str) -> Result<T> {
+    let text = row.try_get::<String, _>(column)?;
+
+    Ok(serde_json::from_value(Value::String(text))?)
+}
+
+/// Decodes an [`Order`] from the columns of the `orders` table, the columns have to keep their
+/// names.
+fn decode_order(row: &AnyRow) -> Result<Order> {
+    Ok(Order {
+        id: decode_text(row, "uuid")?,
+        trading_pair: decode_text(row, "trading_pair")?,
+        contract_type: decode_text(row, "contract_type")?,
+        position: decode_text(row, "position")?,
+        price: row.try_get::<String, _>("initial_price")?.parse()?,
+        min_quantity: row.try_get::<String, _>("min_quantity")?.parse()?,
+        max_quantity: row.try_get::<String, _>("max_quantity")?.parse()?,
+        leverage: Leverage::new(row.try_get::<i64, _>("leverage")?.try_into()?)?,
+        liquidation_price: row.try_get::<String, _>("liquidation_price")?.parse()?,
+        creation_timestamp: Timestamp::new(row.try_get("creation_timestamp_seconds")?),
+        settlement_time_interval_hours: Duration::new(
+            row.try_get("settlement_time_interval_seconds")?,
+            0,
+        ),
+        origin: decode_text(row, "origin")?,
+        oracle_event_id: row.try_get::<String, _>("oracle_event_id")?.parse()?,
+        payout_resolution: PayoutResolution::new(
+            row.try_get::<i64, _>("n_payouts")?.try_into()?,
+            row.try_get::<i64, _>("payout_bucket_digits")?.try_into()?,
+            decode_text(row, "payout_density")?,
+        )?,
+        funding_rate: row.try_get::<String, _>("funding_rate")?.parse()?,
+    })
+}
+
+/// The order of a CFD, the quantity it was created with and all its events, oldest first.
+pub type CfdHistory = (Order, Usd, Vec<(CfdEvent, Timestamp)>);
+
+/// Decodes the histories of the CFDs from the rows of [`select_cfd_events`], one row per event.
+fn decode_cfd_histories(rows: &[AnyRow]) -> Result<Vec<CfdHistory>> {
+    let mut histories = Vec::<(i64, CfdHistory)>::new();
+
+    for row in rows {
+        let cfd_id = row.try_get::<i64, _>("cfd_id")?;
+        let event = serde_json::from_str(row.try_get("event")?)?;
+        let timestamp = Timestamp::new(row.try_get("timestamp")?);
+
+        match histories.last_mut() {
+            Some((id, (_, _, events))) if *id == cfd_id => events.push((event, timestamp)),
+            _ => {
+                let order = decode_order(row)?;
+                let quantity = row.try_get::<String, _>("quantity_usd")?.parse()?;
+                histories.push((cfd_id, (order, quantity, vec![(event, timestamp)])));
+            }
+        }
+    }
+
+    Ok(histories.into_iter().map(|(_, history)| history).collect())
+}
+
+/// Rebuilds the CFDs from their histories, see [`Cfd::replay`].
+fn replay_cfds(histories: Vec<CfdHistory>) -> Result<Vec<Cfd>> {
+    histories
+        .into_iter()
+        .map(|(order, quantity, events)| Cfd::replay(order, quantity, events))
+        .collect()
+}
+
+pub async fn insert_order(order: &Order, conn: &mut PoolConnection<Any>) -> anyhow::Result<()> {
     let query_result = sqlx::query(
         r#"insert into orders (
             uuid,
@@ -27,21 +369,31 @@ pub async fn insert_order(order: &Order, conn: &mut PoolConnection<Sqlite>) -> a
             creation_timestamp_seconds,
             settlement_time_interval_seconds,
             origin,
-            oracle_event_id
-        ) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)"#,
+            oracle_event_id,
+            contract_type,
+            n_payouts,
+            payout_bucket_digits,
+            payout_density,
+            funding_rate
+        ) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)"#,
     )
-    .bind(&order.id)
-    .bind(&order.trading_pair)
-    .bind(&order.position)
-    .bind(&order.price.to_string())
-    .bind(&order.min_quantity.to_string())
-    .bind(&order.max_quantity.to_string())
-    .bind(order.leverage.get())
-    .bind(&order.liquidation_price.to_string())
-    .bind(&order.creation_timestamp.seconds())
-    .bind(&order.settlement_time_interval_hours.whole_seconds())
-    .bind(&order.origin)
-    .bind(&order.oracle_event_id.to_string())
+    .bind(order.id.to_string())
+    .bind(encode_text(&order.trading_pair)?)
+    .bind(encode_text(&order.position)?)
+    .bind(order.price.to_string())
+    .bind(order.min_quantity.to_string())
+    .bind(order.max_quantity.to_string())
+    .bind(i64::from(order.leverage.get()))
+    .bind(order.liquidation_price.to_string())
+    .bind(order.creation_timestamp.seconds())
+    .bind(order.settlement_time_interval_hours.whole_seconds())
+    .bind(encode_text(&order.origin)?)
+    .bind(order.oracle_event_id.to_string())
+    .bind(encode_text(&order.contract_type)?)
+    .bind(i64::try_from(order.payout_resolution.n_payouts())?)
+    .bind(i64::from(order.payout_resolution.bucket_digits()))
+    .bind(encode_text(&order.payout_resolution.density())?)
+    .bind(order.funding_rate.to_string())
     .execute(conn)
     .await?;
 
@@ -54,50 +406,44 @@ pub async fn insert_order(order: &Order, conn: &mut PoolConnection<Sqlite>) -> a
 
 pub async fn load_order_by_id(
     id: OrderId,
-    conn: &mut PoolConnection<Sqlite>,
+    conn: &mut PoolConnection<Any>,
 ) -> anyhow::Result<Order> {
-    let row = sqlx::query!(
+    let row = sqlx::query(
         r#"
         select
-            uuid as "uuid: crate::model::cfd::OrderId",
-            trading_pair as "trading_pair: crate::model::TradingPair",
-            position as "position: crate::model::Position",
+            uuid,
+            trading_pair,
+            contract_type,
+            position,
             initial_price,
             min_quantity,
             max_quantity,
-            leverage as "leverage: crate::model::Leverage",
+            leverage,
             liquidation_price,
-            creation_timestamp_seconds as "ts_secs: crate::model::Timestamp",
-            settlement_time_interval_seconds as "settlement_time_interval_secs: i64",
-            origin as "origin: crate::model::cfd::Origin",
-            oracle_event_id
+            creation_timestamp_seconds,
+            settlement_time_interval_seconds,
+            origin,
+            oracle_event_id,
+            n_payouts,
+            payout_bucket_digits,
+            payout_density,
+            funding_rate
 
         from orders
         where uuid = $1
         "#,
-        id
     )
+    .bind(id.to_string())
     .fetch_one(conn)
     .await?;
 
-    Ok(Order {
-        id: row.uuid,
-        trading_pair: row.trading_pair,
-        position: row.position,
-        price: row.initial_price.parse()?,
-        min_quantity: row.min_quantity.parse()?,
-        max_quantity: row.max_quantity.parse()?,
-        leverage: row.leverage,
-        liquidation_price: row.liquidation_price.parse()?,
-        creation_timestamp: row.ts_secs,
-        settlement_time_interval_hours: Duration::new(row.settlement_time_interval_secs, 0),
-        origin: row.origin,
-        oracle_event_id: row.oracle_event_id.parse()?,
-    })
+    decode_order(&row)
 }
 
-pub async fn insert_cfd(cfd: &Cfd, conn: &mut PoolConnection<Sqlite>) -> anyhow::Result<()> {
-    let state = serde_json::to_string(&cfd.state)?;
+/// Inserts a CFD together with its creation event and initial state in one transaction.
+pub async fn insert_cfd(cfd: &Cfd, conn: &mut AnyConnection) -> anyhow::Result<()> {
+    let mut tx = conn.begin().await?;
+
     let query_result = sqlx::query(
         r#"
         insert into cfds (
@@ -111,413 +457,729 @@ pub async fn insert_cfd(cfd: &Cfd, conn: &mut PoolConnection<Sqlite>) -> anyhow:
             $2 as quantity_usd
         from orders
         where uuid = $1;
-
-        insert into cfd_states (
-            cfd_id,
-            state
-        )
-        select
-            id as cfd_id,
-            $3 as state
-        from cfds
-        order by id desc limit 1;
         "#,
     )
-    .bind(&cfd.order.id)
-    .bind(&cfd.quantity_usd.to_string())
-    .bind(state)
-    .execute(conn)
+    .bind(cfd.order.id.to_string())
+    .bind(cfd.quantity_usd.to_string())
+    .execute(&mut tx)
     .await?;
 
-    // Should be 2 because we insert into cfds and cfd_states
-    if query_result.rows_affected() != 2 {
+    if query_result.rows_affected() != 1 {
         anyhow::bail!("failed to insert cfd");
     }
 
+    let cfd_id = load_cfd_id_by_order_uuid(cfd.order.id, &mut tx).await?;
+    insert_cfd_event(
+        cfd_id,
+        &CfdEvent::created(cfd),
+        cfd.state.get_transition_timestamp(),
+        &mut tx,
+    )
+    .await?;
+    insert_cfd_state(cfd_id, &cfd.state, &mut tx).await?;
+
+    tx.commit().await?;
+
     Ok(())
 }
 
-pub async fn append_cfd_state(cfd: &Cfd, conn: &mut PoolConnection<Sqlite>) -> anyhow::Result<()> {
-    let cfd_id = load_cfd_id_by_order_uuid(cfd.order.id, conn).await?;
-    let current_state = load_latest_cfd_state(cfd_id, conn)
-        .await
-        .context("loading latest state failed")?;
-    let new_state = &cfd.state;
-
-    if mem::discriminant(&current_state) == mem::discriminant(new_state) {
-        // Since we have states where we add information this happens quite frequently
-        tracing::trace!(
-            "Same state transition for cfd with order_id {}: {}",
-            cfd.order.id,
-            current_state
-        );
-    }
+/// Records an event that was applied to the CFD.
+///
+/// The event is the source of truth, CFDs are loaded by replaying their events. The state of the
+/// CFD after applying it is stored alongside, so CFDs can be selected by their state. Both are
+/// written in one transaction, so they cannot diverge.
+pub async fn append_cfd_event(
+    cfd: &Cfd,
+    event: &CfdEvent,
+    timestamp: Timestamp,
+    conn: &mut AnyConnection,
+) -> anyhow::Result<()> {
+    let mut tx = conn.begin().await?;
+    let cfd_id = load_cfd_id_by_order_uuid(cfd.order.id, &mut tx).await?;
+
+    insert_cfd_event(cfd_id, event, timestamp, &mut tx).await?;
+    insert_cfd_state(cfd_id, &cfd.state, &mut tx).await?;
+
+    tx.commit().await?;
 
-    let cfd_state = serde_json::to_string(new_state)?;
+    Ok(())
+}
 
+async fn insert_cfd_event(
+    cfd_id: i64,
+    event: &CfdEvent,
+    timestamp: Timestamp,
+    conn: &mut AnyConnection,
+) -> anyhow::Result<()> {
     sqlx::query(
         r#"
-        insert into cfd_states (
+        insert into cfd_events (
             cfd_id,
-            state
-        ) values ($1, $2);
+            timestamp,
+            kind,
+            event
+        ) values ($1, $2, $3, $4);
         "#,
     )
     .bind(cfd_id)
-    .bind(cfd_state)
+    .bind(timestamp.seconds())
+    .bind(event.kind())
+    .bind(serde_json::to_string(event)?)
     .execute(conn)
     .await?;
 
     Ok(())
 }
 
-async fn load_cfd_id_by_order_uuid(
-    order_uuid: OrderId,
-    conn: &mut PoolConnection<Sqlite>,
-) -> anyhow::Result<i64> {
-    let cfd_id = sqlx::query!(
+/// Records the noise static key of the taker of a CFD, only that taker can act on the CFD.
+pub async fn update_cfd_taker_static_key(
+    order_id: OrderId,
+    static_key: &x25519_dalek::PublicKey,
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<()> {
+    let query_result = sqlx::query(
         r#"
-        select
-            id
-        from cfds
-        where order_uuid = $1;
+        update cfds
+        set taker_static_key = $1
+        where order_uuid = $2
         "#,
-        order_uuid
     )
-    .fetch_one(conn)
+    .bind(hex::encode(static_key.as_bytes()))
+    .bind(order_id.to_string())
+    .execute(conn)
     .await?;
 
-    let cfd_id = cfd_id.id.context("No cfd found")?;
+    if query_result.rows_affected() != 1 {
+        bail!("No cfd found for order id {}", order_id);
+    }
 
-    Ok(cfd_id)
+    Ok(())
 }
 
-async fn load_latest_cfd_state(
-    cfd_id: i64,
-    conn: &mut PoolConnection<Sqlite>,
-) -> anyhow::Result<CfdState> {
-    let latest_cfd_state = sqlx::query!(
+/// Loads the noise static key of the taker of a CFD, if it was recorded.
+pub async fn load_cfd_taker_static_key(
+    order_id: OrderId,
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<Option<x25519_dalek::PublicKey>> {
+    let row = sqlx::query(
         r#"
         select
-            state
-        from cfd_states
-        where cfd_id = $1
-        order by id desc
-        limit 1;
+            taker_static_key
+        from cfds
+        where order_uuid = $1
         "#,
-        cfd_id
     )
-    .fetch_one(conn)
-    .await?;
-
-    let latest_cfd_state_in_db: CfdState = serde_json::from_str(latest_cfd_state.state.as_str())?;
+    .bind(order_id.to_string())
+    .fetch_optional(conn)
+    .await?
+    .with_context(|| format!("No cfd found for order id {}", order_id))?;
+
+    let static_key = match row.try_get::<Option<String>, _>("taker_static_key")? {
+        Some(static_key) => {
+            let bytes: [u8; 32] = hex::decode(static_key)?
+                .try_into()
+                .map_err(|_| anyhow!("Static key has to be 32 bytes"))?;
+            Some(x25519_dalek::PublicKey::from(bytes))
+        }
+        None => None,
+    };
 
-    Ok(latest_cfd_state_in_db)
+    Ok(static_key)
 }
 
-pub async fn load_cfd_by_order_id(
-    order_id: OrderId,
-    conn: &mut PoolConnection<Sqlite>,
-) -> Result<Cfd> {
-    let row = sqlx::query!(
+/// Loads the order ids of all CFDs taken by the taker with the given noise static key.
+pub async fn load_order_ids_by_taker_static_key(
+    static_key: &x25519_dalek::PublicKey,
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<Vec<OrderId>> {
+    let rows = sqlx::query(
         r#"
-        with ord as (
-            select
-                id as order_id,
-                uuid,
-                trading_pair,
-                position,
-                initial_price,
-                min_quantity,
-                max_quantity,
-                leverage,
-                liquidation_price,
-                creation_timestamp_seconds as ts_secs,
-                settlement_time_interval_seconds as settlement_time_interval_secs,
-                origin,
-                oracle_event_id
-            from orders
-        ),
+        select
+            order_uuid
+        from cfds
+        where taker_static_key = $1
+        order by id
+        "#,
+    )
+    .bind(hex::encode(static_key.as_bytes()))
+    .fetch_all(conn)
+    .await?;
 
-        cfd as (
-            select
-                ord.order_id,
-                id as cfd_id,
-                quantity_usd
-            from cfds
-                inner join ord on ord.order_id = cfds.order_id
-        ),
+    rows.iter()
+        .map(|row| decode_text(row, "order_uuid"))
+        .collect()
+}
 
-        state as (
-            select
-                id as state_id,
-                cfd.order_id,
-                cfd.quantity_usd,
-                state
-            from cfd_states
-                inner join cfd on cfd.cfd_id = cfd_states.cfd_id
-            where id in (
-                select
-                    max(id) as id
-                from cfd_states
-                group by (cfd_id)
-            )
-        )
+/// Stores a pending proposal, replacing an earlier proposal for the same CFD.
+///
+/// The maker records the taker the proposal was exchanged with, the taker records none.
+pub async fn insert_proposal(
+    proposal: &UpdateCfdProposal,
+    taker_id: Option<TakerId>,
+    conn: &mut AnyConnection,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        insert into cfd_proposals (
+            order_uuid,
+            proposal,
+            taker_id,
+            expiry_timestamp
+        ) values ($1, $2, $3, $4)
+        on conflict (order_uuid) do update set
+            proposal = excluded.proposal,
+            taker_id = excluded.taker_id,
+            expiry_timestamp = excluded.expiry_timestamp
+        "#,
+    )
+    .bind(proposal.order_id().to_string())
+    .bind(serde_json::to_string(proposal)?)
+    .bind(taker_id.as_ref().map(encode_text).transpose()?)
+    .bind(proposal.expiry_timestamp().seconds())
+    .execute(conn)
+    .await?;
 
-        select
-            ord.uuid as "uuid: crate::model::cfd::OrderId",
-            ord.trading_pair as "trading_pair: crate::model::TradingPair",
-            ord.position as "position: crate::model::Position",
-            ord.initial_price,
-            ord.min_quantity,
-            ord.max_quantity,
-            ord.leverage as "leverage: crate::model::Leverage",
-            ord.liquidation_price,
-            ord.ts_secs as "ts_secs: crate::model::Timestamp",
-            ord.settlement_time_interval_secs as "settlement_time_interval_secs: i64",
-            ord.origin as "origin: crate::model::cfd::Origin",
-            ord.oracle_event_id,
-            state.quantity_usd,
-            state.state
+    Ok(())
+}
 
-        from ord
-            inner join state on state.order_id = ord.order_id
+pub async fn delete_proposal(order_id: OrderId, conn: &mut AnyConnection) -> Result<()> {
+    sqlx::query(
+        r#"
+        delete from cfd_proposals
+        where order_uuid = $1
+        "#,
+    )
+    .bind(order_id.to_string())
+    .execute(conn)
+    .await?;
 
-        where ord.uuid = $1
+    Ok(())
+}
+
+/// Loads all pending proposals together with the taker they were exchanged with, if recorded.
+///
+/// Expired proposals are included, it is up to the caller to expire them.
+pub async fn load_proposals(
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<Vec<(UpdateCfdProposal, Option<TakerId>)>> {
+    let rows = sqlx::query(
+        r#"
+        select
+            proposal,
+            taker_id
+        from cfd_proposals
+        order by id
         "#,
-        order_id
     )
-    .fetch_one(conn)
+    .fetch_all(conn)
     .await?;
 
-    let order = Order {
-        id: row.uuid,
-        trading_pair: row.trading_pair,
-        position: row.position,
-        price: row.initial_price.parse()?,
-        min_quantity: row.min_quantity.parse()?,
-        max_quantity: row.max_quantity.parse()?,
-        leverage: row.leverage,
-        liquidation_price: row.liquidation_price.parse()?,
-        creation_timestamp: row.ts_secs,
-        settlement_time_interval_hours: Duration::new(row.settlement_time_interval_secs, 0),
-        origin: row.origin,
-        oracle_event_id: row.oracle_event_id.parse()?,
-    };
+    rows.iter()
+        .map(|row| {
+            let proposal = serde_json::from_str(&row.try_get::<String, _>("proposal")?)?;
+            let taker_id = row
+                .try_get::<Option<String>, _>("taker_id")?
+                .map(|taker_id| serde_json::from_value(Value::String(taker_id)))
+                .transpose()?;
 
-    // TODO:
-    // still have the use of serde_json::from_str() here, which will be dealt with
-    // via https://github.com/comit-network/hermes/issues/290
-    Ok(Cfd {
-        order,
-        quantity_usd: Usd::new(Decimal::from_str(&row.quantity_usd)?),
-        state: serde_json::from_str(row.state.as_str())?,
-    })
+            Ok((proposal, taker_id))
+        })
+        .collect()
 }
 
-/// Loads all CFDs with the latest state as the CFD state
-pub async fn load_all_cfds(conn: &mut PoolConnection<Sqlite>) -> anyhow::Result<Vec<Cfd>> {
-    let rows = sqlx::query!(
+/// Stores the settlement triggers of a CFD, replacing earlier ones.
+pub async fn insert_settlement_triggers(
+    order_id: OrderId,
+    triggers: &SettlementTriggers,
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<()> {
+    sqlx::query(
         r#"
-        with ord as (
-            select
-                id as order_id,
-                uuid,
-                trading_pair,
-                position,
-                initial_price,
-                min_quantity,
-                max_quantity,
-                leverage,
-                liquidation_price,
-                creation_timestamp_seconds as ts_secs,
-                settlement_time_interval_seconds as settlement_time_interval_secs,
-                origin,
-                oracle_event_id
-            from orders
-        ),
+        insert into settlement_triggers (
+            order_uuid,
+            stop_loss,
+            take_profit
+        ) values ($1, $2, $3)
+        on conflict (order_uuid) do update set
+            stop_loss = excluded.stop_loss,
+            take_profit = excluded.take_profit
+        "#,
+    )
+    .bind(order_id.to_string())
+    .bind(triggers.stop_loss.map(|price| price.to_string()))
+    .bind(triggers.take_profit.map(|price| price.to_string()))
+    .execute(conn)
+    .await?;
 
-        cfd as (
-            select
-                ord.order_id,
-                id as cfd_id,
-                quantity_usd
-            from cfds
-                inner join ord on ord.order_id = cfds.order_id
-        ),
+    Ok(())
+}
 
-        state as (
-            select
-                id as state_id,
-                cfd.order_id,
-                cfd.quantity_usd,
-                state
-            from cfd_states
-                inner join cfd on cfd.cfd_id = cfd_states.cfd_id
-            where id in (
-                select
-                    max(id) as id
-                from cfd_states
-                group by (cfd_id)
-            )
-        )
+pub async fn delete_settlement_triggers(
+    order_id: OrderId,
+    conn: &mut PoolConnection<Any>,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        delete from settlement_triggers
+        where order_uuid = $1
+        "#,
+    )
+    .bind(order_id.to_string())
+    .execute(conn)
+    .await?;
 
-        select
-            ord.uuid as "uuid: crate::model::cfd::OrderId",
-            ord.trading_pair as "trading_pair: crate::model::TradingPair",
-            ord.position as "position: crate::model::Position",
-            ord.initial_price,
-            ord.min_quantity,
-            ord.max_quantity,
-            ord.leverage as "leverage: crate::model::Leverage",
-            ord.liquidation_price,
-            ord.ts_secs as "ts_secs: crate::model::Timestamp",
-            ord.settlement_time_interval_secs as "settlement_time_interval_secs: i64",
-            ord.origin as "origin: crate::model::cfd::Origin",
-            ord.oracle_event_id,
-            state.quantity_usd,
-            state.state
+    Ok(())
+}
 
-        from ord
-            inner join state on state.order_id = ord.order_id
-        "#
+pub async fn load_settlement_triggers(
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<HashMap<OrderId, SettlementTriggers>> {
+    let rows = sqlx::query(
+        r#"
+        select
+            order_uuid,
+            stop_loss,
+            take_profit
+        from settlement_triggers
+        "#,
     )
     .fetch_all(conn)
     .await?;
 
-    let cfds = rows
-        .into_iter()
+    rows.iter()
         .map(|row| {
-            let order = Order {
-                id: row.uuid,
-                trading_pair: row.trading_pair,
-                position: row.position,
-                price: row.initial_price.parse()?,
-                min_quantity: row.min_quantity.parse()?,
-                max_quantity: row.max_quantity.parse()?,
-                leverage: row.leverage,
-                liquidation_price: row.liquidation_price.parse()?,
-                creation_timestamp: row.ts_secs,
-                settlement_time_interval_hours: Duration::new(row.settlement_time_interval_secs, 0),
-                origin: row.origin,
-                oracle_event_id: row.oracle_event_id.parse()?,
+            let order_id = decode_text(row, "order_uuid")?;
+            let triggers = SettlementTriggers {
+                stop_loss: row
+                    .try_get::<Option<String>, _>("stop_loss")?
+                    .map(|price| price.parse())
+                    .transpose()?,
+                take_profit: row
+                    .try_get::<Option<String>, _>("take_profit")?
+                    .map(|price| price.parse())
+                    .transpose()?,
             };
 
-            Ok(Cfd {
-                order,
-                quantity_usd: Usd::new(Decimal::from_str(&row.quantity_usd)?),
-                state: serde_json::from_str(row.state.as_str())?,
-            })
+            Ok((order_id, triggers))
         })
-        .collect::<Result<Vec<_>>>()?;
+        .collect()
+}
 
-    Ok(cfds)
+/// Loads all events of a CFD in the order they were recorded, including archived ones.
+async fn insert_cfd_state(
+    cfd_id: i64,
+    state: &CfdState,
+    conn: &mut AnyConnection,
+) -> anyhow::Result<()> {
+    EncodedCfdState::encode(state)?
+        .bind(sqlx::query(
+            r#"
+            insert into cfd_states (
+                state_version,
+                kind,
+                transition_timestamp,
+                lock_txid,
+                commit_txid,
+                refund_txid,
+                cet_txid,
+                collaborative_close_txid,
+                payout_sats,
+                state,
+                dlc,
+                cfd_id
+            ) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12);
+            "#,
+        ))
+        .bind(cfd_id)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+async fn load_cfd_id_by_order_uuid(
+    order_uuid: OrderId,
+    conn: &mut AnyConnection,
+) -> anyhow::Result<i64> {
+    let row = sqlx::query(
+        r#"
+        select
+            id
+        from cfds
+        where order_uuid = $1;
+        "#,
+    )
+    .bind(order_uuid.to_string())
+    .fetch_optional(conn)
+    .await?
+    .context("No cfd found")?;
+
+    Ok(row.try_get("id")?)
 }
 
-/// Loads all CFDs with the latest state as the CFD state
-pub async fn load_cfds_by_oracle_event_id(
-    oracle_event_id: BitMexPriceEventId,
-    conn: &mut PoolConnection<Sqlite>,
-) -> anyhow::Result<Vec<Cfd>> {
-    let event_id = oracle_event_id.to_string();
-    let rows = sqlx::query!(
+/// The events of the CFDs in progress.
+const CFD_EVENTS: &str = r#"
+            select
+                id,
+                cfd_id,
+                timestamp,
+                event
+            from cfd_events"#;
+
+/// The events of all CFDs, including archived ones.
+const ALL_CFD_EVENTS: &str = r#"
+            select
+                id,
+                cfd_id,
+                timestamp,
+                event
+            from cfd_events
+            union all
+            select
+                id,
+                cfd_id,
+                timestamp,
+                event
+            from archived_cfd_events"#;
+
+/// Builds the query selecting the events from `events` together with the order and initial
+/// quantity of their CFD, restricted by `condition` on the columns of `ord`.
+///
+/// The rows are ordered by CFD and then by event, as [`decode_cfd_histories`] expects them.
+fn select_cfd_events(events: &str, condition: &str) -> String {
+    format!(
         r#"
-        with ord as (
+        with events as (
+            {events}
+        ),
+
+        ord as (
             select
                 id as order_id,
                 uuid,
                 trading_pair,
+                contract_type,
                 position,
                 initial_price,
                 min_quantity,
                 max_quantity,
                 leverage,
                 liquidation_price,
-                creation_timestamp_seconds as ts_secs,
-                settlement_time_interval_seconds as settlement_time_interval_secs,
+                creation_timestamp_seconds,
+                settlement_time_interval_seconds,
                 origin,
-                oracle_event_id
+                oracle_event_id,
+                n_payouts,
+                payout_bucket_digits,
+                payout_density,
+                funding_rate
             from orders
-        ),
+        )
+
+        select
+            ord.uuid,
+            ord.trading_pair,
+            ord.contract_type,
+            ord.position,
+            ord.initial_price,
+            ord.min_quantity,
+            ord.max_quantity,
+            ord.leverage,
+            ord.liquidation_price,
+            ord.creation_timestamp_seconds,
+            ord.settlement_time_interval_seconds,
+            ord.origin,
+            ord.oracle_event_id,
+            ord.n_payouts,
+            ord.payout_bucket_digits,
+            ord.payout_density,
+            ord.funding_rate,
+            cfds.id as cfd_id,
+            cfds.quantity_usd,
+            events.timestamp,
+            events.event
+
+        from events
+            inner join cfds on cfds.id = events.cfd_id
+            inner join ord on ord.order_id = cfds.order_id
+
+        {condition}
+        order by cfds.id, events.id
+        "#,
+        events = events,
+        condition = condition,
+    )
+}
+
+/// Loads the history of a CFD in progress or an archived one.
+pub async fn load_cfd_history(
+    order_id: OrderId,
+    conn: &mut PoolConnection<Any>,
+) -> Result<CfdHistory> {
+    let rows = sqlx::query(&select_cfd_events(ALL_CFD_EVENTS, "where ord.uuid = $1"))
+        .bind(order_id.to_string())
+        .fetch_all(conn)
+        .await?;
+
+    decode_cfd_histories(&rows)?
+        .pop()
+        .with_context(|| format!("No cfd found for order id {}", order_id))
+}
+
+/// Loads the histories of all CFDs, including archived ones, oldest CFD first.
+pub async fn load_all_cfd_histories(conn: &mut PoolConnection<Any>) -> Result<Vec<CfdHistory>> {
+    let rows = sqlx::query(&select_cfd_events(ALL_CFD_EVENTS, ""))
+        .fetch_all(conn)
+        .await?;
+
+    decode_cfd_histories(&rows)
+}
+
+/// Loads a CFD in progress or an archived one by replaying its events.
+pub async fn load_cfd_by_order_id(
+    order_id: OrderId,
+    conn: &mut PoolConnection<Any>,
+) -> Result<Cfd> {
+    let (order, quantity, events) = load_cfd_history(order_id, conn).await?;
+
+    Cfd::replay(order, quantity, events)
+}
 
-        cfd as (
+/// Loads all CFDs in progress by replaying their events.
+pub async fn load_all_cfds(conn: &mut AnyConnection) -> anyhow::Result<Vec<Cfd>> {
+    let rows = sqlx::query(&select_cfd_events(CFD_EVENTS, ""))
+        .fetch_all(conn)
+        .await?;
+
+    replay_cfds(decode_cfd_histories(&rows)?)
+}
+
+/// Moves CFDs that reached a final state before `finished_before` to the archive.
+///
+/// All states and events of an archived CFD are moved to the archive tables, which takes them
+/// out of [`load_all_cfds`] and the other bulk queries. They can still be loaded one by one with
+/// [`load_cfd_by_order_id`] or all at once with [`load_archived_cfds`].
+///
+/// Returns the number of CFDs that were archived.
+pub async fn archive_finished_cfds(
+    finished_before: Timestamp,
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<usize> {
+    let rows = sqlx::query(
+        r#"
+        select
+            cfd_id
+        from cfd_states
+        where id in (
             select
-                ord.order_id,
-                id as cfd_id,
-                quantity_usd
-            from cfds
-                inner join ord on ord.order_id = cfds.order_id
-        ),
+                max(id) as id
+            from cfd_states
+            group by (cfd_id)
+        )
+            and kind in ('Closed', 'Refunded', 'Rejected', 'SetupFailed')
+            and transition_timestamp < $1
+        "#,
+    )
+    .bind(finished_before.seconds())
+    .fetch_all(&mut *conn)
+    .await?;
 
-        state as (
+    for row in &rows {
+        let cfd_id = row.try_get::<i64, _>("cfd_id")?;
+
+        let mut tx = conn.begin().await?;
+        for statement in [
+            r#"
+            insert into archived_cfd_states (
+                id,
+                cfd_id,
+                state,
+                state_version,
+                kind,
+                transition_timestamp,
+                lock_txid,
+                commit_txid,
+                refund_txid,
+                cet_txid,
+                collaborative_close_txid,
+                payout_sats,
+                dlc
+            )
             select
-                id as state_id,
-                cfd.order_id,
-                cfd.quantity_usd,
-                state
+                id,
+                cfd_id,
+                state,
+                state_version,
+                kind,
+                transition_timestamp,
+                lock_txid,
+                commit_txid,
+                refund_txid,
+                cet_txid,
+                collaborative_close_txid,
+                payout_sats,
+                dlc
             from cfd_states
-                inner join cfd on cfd.cfd_id = cfd_states.cfd_id
-            where id in (
+            where cfd_id = $1
+            "#,
+            "delete from cfd_states where cfd_id = $1",
+            r#"
+            insert into archived_cfd_events (
+                id,
+                cfd_id,
+                timestamp,
+                kind,
+                event
+            )
+            select
+                id,
+                cfd_id,
+                timestamp,
+                kind,
+                event
+            from cfd_events
+            where cfd_id = $1
+            "#,
+            "delete from cfd_events where cfd_id = $1",
+        ] {
+            sqlx::query(statement).bind(cfd_id).execute(&mut tx).await?;
+        }
+        tx.commit().await?;
+    }
+
+    Ok(rows.len())
+}
+
+/// Selects the order ids of archived CFDs, `order` and `limit` are appended as is.
+fn select_archived_cfds(order: &str, limit: &str) -> String {
+    format!(
+        r#"
+        select
+            order_uuid
+        from cfds
+        where id in (
+            select
+                cfd_id
+            from archived_cfd_states
+        )
+            and id not in (
                 select
-                    max(id) as id
+                    cfd_id
                 from cfd_states
-                group by (cfd_id)
             )
-        )
+        order by id {order}
+        {limit}
+        "#,
+        order = order,
+        limit = limit,
+    )
+}
 
-        select
-            ord.uuid as "uuid: crate::model::cfd::OrderId",
-            ord.trading_pair as "trading_pair: crate::model::TradingPair",
-            ord.position as "position: crate::model::Position",
-            ord.initial_price,
-            ord.min_quantity,
-            ord.max_quantity,
-            ord.leverage as "leverage: crate::model::Leverage",
-            ord.liquidation_price,
-            ord.ts_secs as "ts_secs: crate::model::Timestamp",
-            ord.settlement_time_interval_secs as "settlement_time_interval_secs: i64",
-            ord.origin as "origin: crate::model::cfd::Origin",
-            ord.oracle_event_id,
-            state.quantity_usd,
-            state.state
+/// Loads all archived CFDs with their latest state.
+///
+/// Unlike the CFDs in progress, archived CFDs are not kept in the `cfd_states` table, loading
+/// them is only meant for rare occasions like an export.
+pub async fn load_archived_cfds(conn: &mut PoolConnection<Any>) -> anyhow::Result<Vec<Cfd>> {
+    let rows = sqlx::query(&select_archived_cfds("asc", ""))
+        .fetch_all(&mut *conn)
+        .await?;
+
+    load_cfds_of_rows(rows, conn).await
+}
+
+/// Loads one page of archived CFDs, most recent first.
+///
+/// This allows to show archived CFDs on demand without putting them back into the feed.
+pub async fn load_archived_cfds_page(
+    offset: u32,
+    limit: u32,
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<Vec<Cfd>> {
+    let rows = sqlx::query(&select_archived_cfds("desc", "limit $1 offset $2"))
+        .bind(i64::from(limit))
+        .bind(i64::from(offset))
+        .fetch_all(&mut *conn)
+        .await?;
+
+    load_cfds_of_rows(rows, conn).await
+}
+
+async fn load_cfds_of_rows(
+    rows: Vec<AnyRow>,
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<Vec<Cfd>> {
+    let mut cfds = Vec::with_capacity(rows.len());
+    for row in rows {
+        let order_id = decode_text(&row, "order_uuid")?;
+        cfds.push(load_cfd_by_order_id(order_id, conn).await?);
+    }
+
+    Ok(cfds)
+}
 
-        from ord
-            inner join state on state.order_id = ord.order_id
+/// Loads every state ever recorded for every CFD, oldest first
+///
+/// In contrast to [`load_all_cfds`] this includes historic states and the states of archived
+/// CFDs, which is needed to recover transactions of DLCs that were replaced by a roll-over.
+pub async fn load_all_cfd_states(
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<Vec<(OrderId, CfdState)>> {
+    let rows = sqlx::query(
+        r#"
+        with states as (
+            select
+                id,
+                cfd_id,
+                state,
+                state_version,
+                dlc
+            from cfd_states
+            union all
+            select
+                id,
+                cfd_id,
+                state,
+                state_version,
+                dlc
+            from archived_cfd_states
+        )
 
-        where ord.oracle_event_id = $1
+        select
+            orders.uuid as order_uuid,
+            states.state as state,
+            states.state_version as state_version,
+            states.dlc as dlc
+        from states
+            inner join cfds on cfds.id = states.cfd_id
+            inner join orders on orders.id = cfds.order_id
+        order by states.id
         "#,
-        event_id
     )
     .fetch_all(conn)
     .await?;
 
-    let cfds = rows
-        .into_iter()
-        .map(|row| {
-            let order = Order {
-                id: row.uuid,
-                trading_pair: row.trading_pair,
-                position: row.position,
-                price: row.initial_price.parse()?,
-                min_quantity: row.min_quantity.parse()?,
-                max_quantity: row.max_quantity.parse()?,
-                leverage: row.leverage,
-                liquidation_price: row.liquidation_price.parse()?,
-                creation_timestamp: row.ts_secs,
-                settlement_time_interval_hours: Duration::new(row.settlement_time_interval_secs, 0),
-                origin: row.origin,
-                oracle_event_id: row.oracle_event_id.parse()?,
-            };
-
-            Ok(Cfd {
-                order,
-                quantity_usd: row.quantity_usd.parse()?,
-                state: serde_json::from_str(row.state.as_str())?,
-            })
-        })
+    let states = rows
+        .iter()
+        .map(|row| Ok((decode_text(row, "order_uuid")?, decode_cfd_state(row)?)))
         .collect::<Result<Vec<_>>>()?;
 
-    Ok(cfds)
+    Ok(states)
+}
+
+/// Loads all CFDs in progress that are attested by the given oracle event.
+pub async fn load_cfds_by_oracle_event_id(
+    oracle_event_id: BitMexPriceEventId,
+    conn: &mut PoolConnection<Any>,
+) -> anyhow::Result<Vec<Cfd>> {
+    let rows = sqlx::query(&select_cfd_events(
+        CFD_EVENTS,
+        "where ord.oracle_event_id = $1",
+    ))
+    .bind(oracle_event_id.to_string())
+    .fetch_all(conn)
+    .await?;
+
+    replay_cfds(decode_cfd_histories(&rows)?)
 }
 
 #[cfg(test)]
@@ -526,13 +1188,14 @@ mod tests {
     use pretty_assertions::assert_eq;
     use rand::Rng;
     use rust_decimal_macros::dec;
-    use sqlx::SqlitePool;
+    use sqlx::any::AnyPoolOptions;
     use time::macros::datetime;
-    use time::OffsetDateTime;
     use tokio::sync::watch;
 
     use crate::db::{self, insert_order};
-    use crate::model::cfd::{Cfd, CfdState, Order, Origin};
+    use crate::model::cfd::{
+        AddToPositionProposal, Cfd, CfdEvent, CfdState, Order, RollOverProposal, SettlementKind,
+    };
     use crate::model::{Price, Usd};
 
     use super::*;
@@ -541,7 +1204,10 @@ mod tests {
     async fn test_insert_and_load_order() {
         let mut conn = setup_test_db().await;
 
-        let order = Order::dummy().insert(&mut conn).await;
+        let order = Order::dummy()
+            .with_funding_rate(FundingRate::new(dec!(0.0005)).unwrap())
+            .insert(&mut conn)
+            .await;
         let loaded = load_order_by_id(order.id, &mut conn).await.unwrap();
 
         assert_eq!(order, loaded);
@@ -557,6 +1223,38 @@ mod tests {
         assert_eq!(vec![cfd], loaded);
     }
 
+    #[tokio::test]
+    async fn test_taker_static_key_is_stored_per_cfd() {
+        let mut conn = setup_test_db().await;
+
+        let cfd = Cfd::dummy().insert(&mut conn).await;
+        let order_id = cfd.order.id;
+        assert_eq!(
+            load_cfd_taker_static_key(order_id, &mut conn)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let static_key = x25519_dalek::PublicKey::from([7u8; 32]);
+        update_cfd_taker_static_key(order_id, &static_key, &mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            load_cfd_taker_static_key(order_id, &mut conn)
+                .await
+                .unwrap(),
+            Some(static_key)
+        );
+        assert_eq!(
+            load_order_ids_by_taker_static_key(&static_key, &mut conn)
+                .await
+                .unwrap(),
+            vec![order_id]
+        );
+    }
+
     #[tokio::test]
     async fn test_insert_like_cfd_actor() {
         let mut conn = setup_test_db().await;
@@ -646,8 +1344,9 @@ mod tests {
 
         let mut cfd_1 = Cfd::dummy().insert(&mut conn).await;
 
-        cfd_1.state = CfdState::accepted();
-        append_cfd_state(&cfd_1, &mut conn).await.unwrap();
+        cfd_1
+            .record(CfdEvent::ContractSetupStarted, &mut conn)
+            .await;
 
         let cfds_from_db = load_all_cfds(&mut conn).await.unwrap();
         assert_eq!(vec![cfd_1.clone()], cfds_from_db);
@@ -657,8 +1356,7 @@ mod tests {
         let cfds_from_db = load_all_cfds(&mut conn).await.unwrap();
         assert_eq!(vec![cfd_1.clone(), cfd_2.clone()], cfds_from_db);
 
-        cfd_2.state = CfdState::rejected();
-        append_cfd_state(&cfd_2, &mut conn).await.unwrap();
+        cfd_2.record(CfdEvent::OrderRejected, &mut conn).await;
 
         let cfds_from_db = load_all_cfds(&mut conn).await.unwrap();
         assert_eq!(vec![cfd_1, cfd_2], cfds_from_db);
@@ -694,8 +1392,7 @@ mod tests {
             let n_updates = rand::thread_rng().gen_range(1, 30);
 
             for _ in 0..n_updates {
-                cfd.state = random_simple_state();
-                append_cfd_state(&cfd, &mut conn).await.unwrap();
+                cfd.record(random_simple_event(), &mut conn).await;
             }
 
             // verify current state is correct
@@ -716,24 +1413,403 @@ mod tests {
         assert_eq!(data.len(), 100);
     }
 
-    fn random_simple_state() -> CfdState {
-        match rand::thread_rng().gen_range(0, 5) {
+    #[tokio::test]
+    async fn test_load_all_cfd_states_includes_historic_states() {
+        let mut conn = setup_test_db().await;
+
+        let mut cfd_1 = Cfd::dummy().insert(&mut conn).await;
+        let initial_state = cfd_1.state.clone();
+        cfd_1
+            .record(CfdEvent::ContractSetupStarted, &mut conn)
+            .await;
+
+        let cfd_2 = Cfd::dummy().insert(&mut conn).await;
+
+        let states = load_all_cfd_states(&mut conn).await.unwrap();
+
+        assert_eq!(
+            states,
+            vec![
+                (cfd_1.order.id, initial_state),
+                (cfd_1.order.id, cfd_1.state),
+                (cfd_2.order.id, cfd_2.state),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_legacy_cfd_states_are_migrated() {
+        let mut conn = setup_test_db().await;
+
+        let cfd = Cfd::dummy().insert(&mut conn).await;
+        sqlx::query(
+            r#"
+            update cfd_states set
+                state_version = 0,
+                kind = null,
+                transition_timestamp = null,
+                state = $1
+            "#,
+        )
+        .bind(serde_json::to_string(&cfd.state).unwrap())
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+        migrate_legacy_cfd_states(&mut conn).await.unwrap();
+
+        let row = sqlx::query("select state_version, kind, transition_timestamp from cfd_states")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(row.get::<i64, _>("state_version"), CFD_STATE_VERSION);
+        assert_eq!(row.get::<String, _>("kind"), "OutgoingOrderRequest");
+        assert_eq!(
+            row.get::<i64, _>("transition_timestamp"),
+            cfd.state.get_transition_timestamp().seconds()
+        );
+        assert_eq!(load_all_cfds(&mut conn).await.unwrap(), vec![cfd]);
+    }
+
+    #[tokio::test]
+    async fn test_cfd_is_replayed_from_events() {
+        let mut conn = setup_test_db().await;
+
+        let mut cfd = Cfd::dummy().insert(&mut conn).await;
+        let created = CfdEvent::created(&cfd);
+        cfd.record(CfdEvent::ContractSetupStarted, &mut conn).await;
+        let failed = CfdEvent::ContractSetupFailed {
+            info: String::from("dummy failure"),
+        };
+        cfd.record(failed.clone(), &mut conn).await;
+
+        let (order, quantity, events) = load_cfd_history(cfd.order.id, &mut conn).await.unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|(event, _)| event.clone())
+                .collect::<Vec<_>>(),
+            vec![created, CfdEvent::ContractSetupStarted, failed]
+        );
+
+        let replayed = Cfd::replay(order, quantity, events).unwrap();
+        assert_eq!(replayed, cfd);
+        assert_eq!(
+            load_cfd_by_order_id(cfd.order.id, &mut conn).await.unwrap(),
+            cfd
+        );
+        assert_eq!(load_all_cfds(&mut conn).await.unwrap(), vec![cfd]);
+    }
+
+    #[tokio::test]
+    async fn test_cfd_states_are_migrated_to_events() {
+        let mut conn = setup_test_db().await;
+
+        let mut cfd = Cfd::dummy().insert(&mut conn).await;
+        let initial_state = cfd.state.clone();
+        cfd.record(CfdEvent::OrderRejected, &mut conn).await;
+        sqlx::query("delete from cfd_events")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+
+        migrate_cfd_states_to_events(&mut conn).await.unwrap();
+
+        let (_, _, events) = load_cfd_history(cfd.order.id, &mut conn).await.unwrap();
+        assert_eq!(
+            events,
+            vec![
+                (
+                    CfdEvent::LegacyState(initial_state.clone()),
+                    initial_state.get_transition_timestamp()
+                ),
+                (
+                    CfdEvent::LegacyState(cfd.state.clone()),
+                    cfd.state.get_transition_timestamp()
+                ),
+            ]
+        );
+        assert_eq!(
+            load_cfd_by_order_id(cfd.order.id, &mut conn).await.unwrap(),
+            cfd
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proposals_are_kept_until_deleted() {
+        let mut conn = setup_test_db().await;
+
+        let order_id = OrderId::default();
+        let taker_id = TakerId::default();
+        let roll_over = UpdateCfdProposal::RollOverProposal {
+            proposal: RollOverProposal {
+                order_id,
+                timestamp: Timestamp::new(1_637_000_000),
+                funding_rate: None,
+            },
+            direction: SettlementKind::Incoming,
+        };
+        let add_to_position = UpdateCfdProposal::AddToPosition {
+            proposal: AddToPositionProposal {
+                order_id,
+                timestamp: Timestamp::new(1_637_000_100),
+                quantity: Usd::new(dec!(100)),
+                price: Price::new(dec!(60_000)).unwrap(),
+            },
+            direction: SettlementKind::Incoming,
+        };
+
+        insert_proposal(&roll_over, Some(taker_id), &mut conn)
+            .await
+            .unwrap();
+        insert_proposal(&add_to_position, Some(taker_id), &mut conn)
+            .await
+            .unwrap();
+        let loaded = load_proposals(&mut conn).await.unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert!(matches!(
+            loaded[0],
+            (UpdateCfdProposal::AddToPosition { .. }, Some(id)) if id == taker_id
+        ));
+        assert_eq!(
+            loaded[0].0.expiry_timestamp(),
+            add_to_position.expiry_timestamp()
+        );
+
+        delete_proposal(order_id, &mut conn).await.unwrap();
+
+        assert!(load_proposals(&mut conn).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_settlement_triggers_are_replaced_and_deleted() {
+        let mut conn = setup_test_db().await;
+
+        let order_id = OrderId::default();
+        let stop_loss_only = SettlementTriggers {
+            stop_loss: Some(Price::new(dec!(40_000.5)).unwrap()),
+            take_profit: None,
+        };
+        let both = SettlementTriggers {
+            stop_loss: Some(Price::new(dec!(45_000)).unwrap()),
+            take_profit: Some(Price::new(dec!(60_000)).unwrap()),
+        };
+
+        insert_settlement_triggers(order_id, &stop_loss_only, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(
+            load_settlement_triggers(&mut conn).await.unwrap()[&order_id],
+            stop_loss_only
+        );
+
+        insert_settlement_triggers(order_id, &both, &mut conn)
+            .await
+            .unwrap();
+        let loaded = load_settlement_triggers(&mut conn).await.unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[&order_id], both);
+
+        delete_settlement_triggers(order_id, &mut conn)
+            .await
+            .unwrap();
+
+        assert!(load_settlement_triggers(&mut conn)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_finished_cfds_are_archived() {
+        let mut conn = setup_test_db().await;
+
+        let mut finished = Cfd::dummy().insert(&mut conn).await;
+        finished.record(CfdEvent::OrderRejected, &mut conn).await;
+        let in_progress = Cfd::dummy().insert(&mut conn).await;
+
+        let archived = archive_finished_cfds(Timestamp::new(i64::MAX), &mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(archived, 1);
+        assert_eq!(
+            load_all_cfds(&mut conn).await.unwrap(),
+            vec![in_progress.clone()]
+        );
+        assert_eq!(
+            load_archived_cfds(&mut conn).await.unwrap(),
+            vec![finished.clone()]
+        );
+        assert_eq!(
+            load_cfd_by_order_id(finished.order.id, &mut conn)
+                .await
+                .unwrap(),
+            finished
+        );
+        assert_eq!(load_all_cfd_states(&mut conn).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_recently_finished_cfds_are_not_archived() {
+        let mut conn = setup_test_db().await;
+
+        let mut finished = Cfd::dummy().insert(&mut conn).await;
+        finished.record(CfdEvent::OrderRejected, &mut conn).await;
+
+        let archived = archive_finished_cfds(Timestamp::new(0), &mut conn)
+            .await
+            .unwrap();
+
+        assert_eq!(archived, 0);
+        assert_eq!(load_all_cfds(&mut conn).await.unwrap(), vec![finished]);
+        assert!(load_archived_cfds(&mut conn).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_archived_cfds_are_loaded_page_by_page_most_recent_first() {
+        let mut conn = setup_test_db().await;
+
+        let mut cfds = Vec::new();
+        for _ in 0..3 {
+            let mut cfd = Cfd::dummy().insert(&mut conn).await;
+            cfd.record(CfdEvent::OrderRejected, &mut conn).await;
+            cfds.push(cfd);
+        }
+        archive_finished_cfds(Timestamp::new(i64::MAX), &mut conn)
+            .await
+            .unwrap();
+
+        let first_page = load_archived_cfds_page(0, 2, &mut conn).await.unwrap();
+        let second_page = load_archived_cfds_page(2, 2, &mut conn).await.unwrap();
+
+        assert_eq!(first_page, vec![cfds[2].clone(), cfds[1].clone()]);
+        assert_eq!(second_page, vec![cfds[0].clone()]);
+    }
+
+    /// The schema is migrated separately for each database, both have to end up with the same
+    /// tables. The columns are only compared on Postgres, see `setup_test_db`.
+    #[tokio::test]
+    async fn test_sqlite_and_postgres_migrations_create_the_same_tables() {
+        assert_eq!(
+            migration_files("migrations"),
+            migration_files("migrations_postgres")
+        );
+
+        let url = match std::env::var("ITCHYSATS_TEST_POSTGRES_URL") {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+        let sqlite = AnyPool::connect("sqlite::memory:").await.unwrap();
+        run_migrations(&sqlite).await.unwrap();
+        let postgres = connect_postgres_test_schema(&url).await;
+        run_migrations(&postgres).await.unwrap();
+
+        let sqlite_columns = table_columns(
+            &sqlite,
+            r#"
+            select
+                tables.name as table_name,
+                columns.name as column_name
+            from sqlite_master as tables
+                inner join pragma_table_info(tables.name) as columns
+            where tables.type = 'table'
+            "#,
+        )
+        .await;
+        let postgres_columns = table_columns(
+            &postgres,
+            r#"
+            select
+                table_name::text as table_name,
+                column_name::text as column_name
+            from information_schema.columns
+            where table_schema = current_schema()
+            "#,
+        )
+        .await;
+
+        assert_eq!(sqlite_columns, postgres_columns);
+    }
+
+    /// Legacy states can follow any state, unlike the events that only happen in some states
+    fn random_simple_event() -> CfdEvent {
+        let state = match rand::thread_rng().gen_range(0, 5) {
             0 => CfdState::outgoing_order_request(),
             1 => CfdState::accepted(),
             2 => CfdState::rejected(),
             3 => CfdState::contract_setup(),
             _ => CfdState::setup_failed(String::from("dummy failure")),
-        }
+        };
+
+        CfdEvent::LegacyState(state)
     }
 
-    async fn setup_test_db() -> PoolConnection<Sqlite> {
-        let pool = SqlitePool::connect(":memory:").await.unwrap();
+    /// Connects to the Postgres database in `ITCHYSATS_TEST_POSTGRES_URL` if it is set and to an
+    /// in-memory SQLite database otherwise.
+    ///
+    /// On Postgres every test gets a schema of its own so that the tests can run in parallel.
+    async fn setup_test_db() -> PoolConnection<Any> {
+        let pool = match std::env::var("ITCHYSATS_TEST_POSTGRES_URL") {
+            Ok(url) => connect_postgres_test_schema(&url).await,
+            Err(_) => AnyPool::connect("sqlite::memory:").await.unwrap(),
+        };
 
         run_migrations(&pool).await.unwrap();
 
         pool.acquire().await.unwrap()
     }
 
+    async fn connect_postgres_test_schema(url: &str) -> AnyPool {
+        let schema = format!("test_{}", uuid::Uuid::new_v4().to_simple());
+
+        AnyPoolOptions::new()
+            .max_connections(1)
+            .after_connect(move |conn| {
+                let create_schema = format!("create schema if not exists {}", schema);
+                let set_search_path = format!("set search_path to {}", schema);
+
+                Box::pin(async move {
+                    sqlx::query(&create_schema).execute(&mut *conn).await?;
+                    sqlx::query(&set_search_path).execute(&mut *conn).await?;
+                    Ok(())
+                })
+            })
+            .connect(url)
+            .await
+            .unwrap()
+    }
+
+    fn migration_files(dir: &str) -> Vec<String> {
+        let mut files = std::fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join(dir))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    /// All tables and their columns, without the bookkeeping tables of sqlx and SQLite
+    async fn table_columns(pool: &AnyPool, query: &str) -> Vec<(String, String)> {
+        let mut columns = sqlx::query(query)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| {
+                (
+                    row.try_get::<String, _>("table_name").unwrap(),
+                    row.try_get::<String, _>("column_name").unwrap(),
+                )
+            })
+            .filter(|(table, _)| !table.starts_with("_sqlx") && !table.starts_with("sqlite_"))
+            .collect::<Vec<_>>();
+        columns.sort();
+        columns
+    }
+
     impl BitMexPriceEventId {
         fn event1() -> Self {
             BitMexPriceEventId::with_20_digits(datetime!(2021-10-13 10:00:00).assume_utc())
@@ -754,13 +1830,22 @@ mod tests {
         }
 
         /// Insert this [`Cfd`] into the database, returning the instance for further chaining.
-        async fn insert(self, conn: &mut PoolConnection<Sqlite>) -> Self {
+        async fn insert(self, conn: &mut PoolConnection<Any>) -> Self {
             insert_order(&self.order, conn).await.unwrap();
             insert_cfd(&self, conn).await.unwrap();
 
             self
         }
 
+        /// Apply the event to this [`Cfd`] and record it in the database.
+        async fn record(&mut self, event: CfdEvent, conn: &mut PoolConnection<Any>) {
+            let timestamp = Timestamp::now().unwrap();
+            self.apply(event.clone(), timestamp).unwrap();
+            append_cfd_event(self, &event, timestamp, conn)
+                .await
+                .unwrap();
+        }
+
         fn with_event_id(mut self, id: BitMexPriceEventId) -> Self {
             self.order.oracle_event_id = id;
             self
@@ -768,20 +1853,8 @@ mod tests {
     }
 
     impl Order {
-        fn dummy() -> Self {
-            Order::new(
-                Price::new(dec!(1000)).unwrap(),
-                Usd::new(dec!(100)),
-                Usd::new(dec!(1000)),
-                Origin::Theirs,
-                BitMexPriceEventId::with_20_digits(OffsetDateTime::now_utc()),
-                time::Duration::hours(24),
-            )
-            .unwrap()
-        }
-
         /// Insert this [`Order`] into the database, returning the instance for further chaining.
-        async fn insert(self, conn: &mut PoolConnection<Sqlite>) -> Self {
+        async fn insert(self, conn: &mut PoolConnection<Any>) -> Self {
             insert_order(&self, conn).await.unwrap();
 
             self
@@ -0,0 +1,374 @@
+use crate::db::load_all_cfd_histories;
+use crate::model::cfd::{Cfd, CfdEvent, CfdState, Dlc, Order, OrderId, Role};
+use crate::model::{ContractType, Leverage, Position, Price, Timestamp, Usd};
+use anyhow::{Context, Result};
+use bdk::bitcoin::{Amount, SignedAmount, Txid};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Serialize;
+use sqlx::pool::PoolConnection;
+use sqlx::Any;
+use std::iter;
+
+/// The formats CFDs can be exported in.
+#[derive(Debug, Clone, Copy, PartialEq, rocket::FromFormField)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl Default for ExportFormat {
+    fn default() -> Self {
+        ExportFormat::Json
+    }
+}
+
+/// A CFD as it is exported for accounting.
+///
+/// Fields that depend on the CFD being closed are `None` until the payout is fixed. Every partial
+/// settlement of a CFD is exported as an entry of its own for the settled quantity, with the
+/// partial settlement transaction as its collaborative close transaction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CfdExportEntry {
+    pub order_id: OrderId,
+    pub role: Role,
+    pub position: Position,
+    pub contract_type: ContractType,
+    pub state: String,
+    pub opened_at: Option<Timestamp>,
+    pub closed_at: Option<Timestamp>,
+    pub entry_price: Price,
+    pub exit_price: Option<Price>,
+    pub quantity_usd: Usd,
+    pub leverage: Leverage,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_sat")]
+    pub margin: Amount,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_sat::opt")]
+    pub payout: Option<Amount>,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_sat::opt")]
+    pub realized_pnl: Option<SignedAmount>,
+    pub realized_pnl_usd: Option<Decimal>,
+    /// The part of our margin that did not end up in the payout although the price movement
+    /// would have allowed for it, i.e. our share of the transaction fees.
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_sat::opt")]
+    pub fees: Option<Amount>,
+    /// The lock transaction the CFD was opened with
+    pub lock_txid: Option<Txid>,
+    pub commit_txid: Option<Txid>,
+    pub refund_txid: Option<Txid>,
+    pub cet_txid: Option<Txid>,
+    pub collaborative_close_txid: Option<Txid>,
+}
+
+/// Loads all CFDs from the database, including archived ones, and prepares them for export.
+pub async fn load(conn: &mut PoolConnection<Any>) -> Result<Vec<CfdExportEntry>> {
+    let mut entries = Vec::new();
+    for (order, quantity, events) in load_all_cfd_histories(conn).await? {
+        entries.extend(export(order, quantity, events)?);
+    }
+
+    Ok(entries)
+}
+
+/// Derives the export entries of a CFD by replaying its events, oldest first.
+///
+/// Returns an entry for every partial settlement, followed by the entry of the position that
+/// remains.
+pub fn export(
+    order: Order,
+    quantity: Usd,
+    events: Vec<(CfdEvent, Timestamp)>,
+) -> Result<Vec<CfdExportEntry>> {
+    let mut events = events.into_iter();
+    let created = events.next().context("No events recorded for CFD")?;
+
+    let mut cfd = Cfd::replay(order, quantity, iter::once(created))?;
+    let mut states = vec![cfd.state.clone()];
+    let mut entries = Vec::new();
+
+    for (event, timestamp) in events {
+        if let CfdEvent::PartialSettlementCompleted {
+            dlc,
+            quantity,
+            price,
+        } = &event
+        {
+            entries.push(export_partial_settlement(
+                &cfd, &states, dlc, *quantity, *price, timestamp,
+            )?);
+        }
+
+        cfd.apply(event, timestamp)?;
+        states.push(cfd.state.clone());
+    }
+
+    entries.push(export_position(&cfd, &states)?);
+
+    Ok(entries)
+}
+
+/// Derives the export entry of the position of a CFD from its current and all historic states,
+/// oldest first.
+///
+/// Historic states are needed because the DLC and the time the CFD was opened are no longer
+/// part of the state once the CFD is closed.
+fn export_position(cfd: &Cfd, states: &[CfdState]) -> Result<CfdExportEntry> {
+    let opened_at = opened_at(states);
+    let closed_at = states
+        .iter()
+        .find(|state| matches!(state, CfdState::Closed { .. } | CfdState::Refunded { .. }))
+        .map(CfdState::get_transition_timestamp);
+
+    let attestation = cfd.attestation();
+    let collaborative_close = cfd.collaborative_close();
+
+    let exit_price = match (&collaborative_close, &attestation) {
+        (Some(collaborative_close), _) => Some(collaborative_close.price()),
+        (None, Some(attestation)) => Some(attestation.price()?),
+        (None, None) => None,
+    };
+
+    let margin = cfd.margin()?;
+    let payout = cfd.payout();
+
+    let realized_pnl = payout
+        .map(|payout| Ok::<_, anyhow::Error>(payout.to_signed()? - margin.to_signed()?))
+        .transpose()?;
+    let realized_pnl_usd = match (realized_pnl, exit_price) {
+        (Some(pnl), Some(exit_price)) => Some(in_usd(pnl, exit_price)),
+        _ => None,
+    };
+
+    let fees = match (payout, exit_price) {
+        (Some(payout), Some(exit_price)) => Some(paid_fees(cfd, margin, payout, exit_price)?),
+        _ => None,
+    };
+
+    // Partial settlements and added positions replace the lock transaction
+    let lock_txid = first_lock_txid(states);
+    let dlc = states.iter().rev().find_map(CfdState::get_dlc);
+
+    Ok(CfdExportEntry {
+        order_id: cfd.order.id,
+        role: cfd.role(),
+        position: cfd.position(),
+        contract_type: cfd.order.contract_type,
+        state: cfd.state.to_string(),
+        opened_at,
+        closed_at,
+        entry_price: cfd.order.price,
+        exit_price,
+        quantity_usd: cfd.quantity_usd,
+        leverage: cfd.order.leverage,
+        margin,
+        payout,
+        realized_pnl,
+        realized_pnl_usd,
+        fees,
+        lock_txid,
+        commit_txid: dlc.map(|dlc| dlc.commit.0.txid()),
+        refund_txid: dlc.map(|dlc| dlc.refund.0.txid()),
+        cet_txid: attestation.map(|attestation| attestation.txid()),
+        collaborative_close_txid: collaborative_close.map(|settlement| settlement.tx.txid()),
+    })
+}
+
+/// Derives the export entry of the part of `cfd` that was settled by the partial settlement
+/// leaving `remaining` open, with `dlc` as the DLC of the remaining position.
+fn export_partial_settlement(
+    cfd: &Cfd,
+    states: &[CfdState],
+    dlc: &Dlc,
+    remaining: Usd,
+    price: Price,
+    timestamp: Timestamp,
+) -> Result<CfdExportEntry> {
+    let settled = cfd.with_quantity(cfd.quantity_usd - remaining)?;
+    let margin = settled.margin()?;
+
+    // The partial settlement transaction leaves payouts below the dust limit to the fee
+    let settlement_tx = &dlc.lock.0;
+    let our_script_pubkey = dlc.script_pubkey_for(cfd.role());
+    let payout = settlement_tx
+        .output
+        .iter()
+        .find(|output| output.script_pubkey == our_script_pubkey)
+        .map(|output| Amount::from_sat(output.value))
+        .unwrap_or(Amount::ZERO);
+    let realized_pnl = payout.to_signed()? - margin.to_signed()?;
+
+    Ok(CfdExportEntry {
+        order_id: cfd.order.id,
+        role: cfd.role(),
+        position: cfd.position(),
+        contract_type: cfd.order.contract_type,
+        state: String::from("Partially Settled"),
+        opened_at: opened_at(states),
+        closed_at: Some(timestamp),
+        entry_price: cfd.order.price,
+        exit_price: Some(price),
+        quantity_usd: settled.quantity_usd,
+        leverage: cfd.order.leverage,
+        margin,
+        payout: Some(payout),
+        realized_pnl: Some(realized_pnl),
+        realized_pnl_usd: Some(in_usd(realized_pnl, price)),
+        fees: Some(paid_fees(&settled, margin, payout, price)?),
+        lock_txid: first_lock_txid(states),
+        commit_txid: None,
+        refund_txid: None,
+        cet_txid: None,
+        collaborative_close_txid: Some(settlement_tx.txid()),
+    })
+}
+
+/// The time the CFD was first opened, given its states oldest first.
+fn opened_at(states: &[CfdState]) -> Option<Timestamp> {
+    states
+        .iter()
+        .find(|state| matches!(state, CfdState::PendingOpen { .. } | CfdState::Open { .. }))
+        .map(CfdState::get_transition_timestamp)
+}
+
+/// The txid of the first lock transaction among the states, oldest first.
+fn first_lock_txid(states: &[CfdState]) -> Option<Txid> {
+    states
+        .iter()
+        .find_map(CfdState::get_dlc)
+        .map(|dlc| dlc.lock.0.txid())
+}
+
+/// Converts `amount` into USD at `price`, rounded to cents.
+fn in_usd(amount: SignedAmount, price: Price) -> Decimal {
+    (Decimal::from(amount.as_sat()) / dec!(100_000_000) * price.into_decimal()).round_dp(2)
+}
+
+/// The part of `margin` that did not end up in `payout` although closing `cfd` at `exit_price`
+/// would have allowed for it.
+fn paid_fees(cfd: &Cfd, margin: Amount, payout: Amount, exit_price: Price) -> Result<Amount> {
+    let (expected_pnl, _) = cfd.profit(exit_price)?;
+    let fees = margin.to_signed()? + expected_pnl - payout.to_signed()?;
+
+    fees.max(SignedAmount::ZERO)
+        .to_unsigned()
+        .context("Fees cannot be negative")
+}
+
+/// Serializes the entries as CSV with a header row.
+pub fn to_csv(entries: &[CfdExportEntry]) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for entry in entries {
+        writer.serialize(entry)?;
+    }
+
+    let bytes = writer.into_inner().context("Failed to flush CSV writer")?;
+
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::cfd::{CfdStateChangeEvent, CollaborativeSettlement, Payout};
+    use crate::monitor;
+    use bdk::bitcoin::{Script, Transaction, TxOut};
+
+    #[test]
+    fn exports_collaboratively_closed_cfd() {
+        let close_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 13_000_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let settlement = CollaborativeSettlement::new(
+            close_tx.clone(),
+            Script::new(),
+            Price::new(dec!(60_000)).unwrap(),
+        )
+        .unwrap();
+        let cfd = Cfd::new(
+            dummy_order(),
+            Usd::new(dec!(10_000)),
+            CfdState::closed(Payout::CollaborativeClose(settlement)),
+        );
+        let created = (CfdEvent::created(&cfd), Timestamp::new(1));
+
+        let entries = export(cfd.order, cfd.quantity_usd, vec![created]).unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = entries[0].clone();
+
+        assert_eq!(entry.position, Position::Long);
+        assert_eq!(entry.exit_price, Some(Price::new(dec!(60_000)).unwrap()));
+        assert_eq!(entry.payout, Some(Amount::from_sat(13_000_000)));
+        assert_eq!(entry.collaborative_close_txid, Some(close_tx.txid()));
+        assert!(entry.realized_pnl.unwrap() > SignedAmount::ZERO);
+        assert!(entry.closed_at.is_some());
+
+        let csv = to_csv(&[entry]).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("order_id,role,position"));
+        assert_eq!(lines.count(), 1);
+    }
+
+    #[test]
+    fn exports_partial_settlements_separately() {
+        let cfd = Cfd::new(
+            dummy_order(),
+            Usd::new(dec!(10_000)),
+            CfdState::contract_setup(),
+        );
+        let created = (CfdEvent::created(&cfd), Timestamp::new(1));
+        let dlc = Dlc::dummy(Amount::ONE_BTC, Amount::ONE_BTC);
+        let lock_txid = dlc.lock.0.txid();
+
+        let mut remaining_dlc = Dlc::dummy(Amount::ONE_BTC, Amount::ONE_BTC);
+        remaining_dlc.lock.0.output.push(TxOut {
+            value: 2_000_000,
+            script_pubkey: remaining_dlc.script_pubkey_for(cfd.role()),
+        });
+        let events = vec![
+            created,
+            (CfdEvent::ContractSetupCompleted { dlc }, Timestamp::new(2)),
+            (
+                CfdEvent::StateChange(CfdStateChangeEvent::Monitor(monitor::Event::LockFinality(
+                    cfd.order.id,
+                ))),
+                Timestamp::new(3),
+            ),
+            (
+                CfdEvent::PartialSettlementCompleted {
+                    dlc: remaining_dlc.clone(),
+                    quantity: Usd::new(dec!(9_000)),
+                    price: Price::new(dec!(55_000)).unwrap(),
+                },
+                Timestamp::new(4),
+            ),
+        ];
+
+        let entries = export(cfd.order, cfd.quantity_usd, events).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        let (settled, remaining) = (&entries[0], &entries[1]);
+        assert_eq!(settled.quantity_usd, Usd::new(dec!(1_000)));
+        assert_eq!(settled.exit_price, Some(Price::new(dec!(55_000)).unwrap()));
+        assert_eq!(settled.payout, Some(Amount::from_sat(2_000_000)));
+        assert_eq!(settled.closed_at, Some(Timestamp::new(4)));
+        assert_eq!(
+            settled.collaborative_close_txid,
+            Some(remaining_dlc.lock.0.txid())
+        );
+        assert_eq!(remaining.quantity_usd, Usd::new(dec!(9_000)));
+        assert_eq!(remaining.payout, None);
+        assert_eq!(remaining.opened_at, Some(Timestamp::new(2)));
+        assert_eq!(settled.lock_txid, Some(lock_txid));
+        assert_eq!(remaining.lock_txid, Some(lock_txid));
+    }
+
+    fn dummy_order() -> Order {
+        Order::dummy().with_price(Price::new(dec!(50_000)).unwrap())
+    }
+}
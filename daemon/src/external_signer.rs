@@ -0,0 +1,242 @@
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::Mutex;
+
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait for the signer to provide the signed PSBT.
+const FILE_SIGNER_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Signs PSBTs on behalf of a watch-only wallet.
+///
+/// Implementations are expected to add signatures for all inputs they hold keys for and return
+/// the PSBT; finalizing the transaction is left to the wallet.
+#[async_trait]
+pub trait ExternalSigner: Send + Sync {
+    async fn sign(&self, psbt: PartiallySignedTransaction) -> Result<PartiallySignedTransaction>;
+}
+
+/// Exchanges PSBTs with the signer through files in a directory.
+///
+/// For every signing request `<txid>.psbt` is written to the directory and the signer waits until
+/// the signed PSBT shows up as `<txid>.signed.psbt`. Both files are base64 encoded and removed
+/// once the signed PSBT was picked up, or when the signer did not respond in time.
+pub struct FileSigner {
+    dir: PathBuf,
+    poll_interval: Duration,
+    timeout: Duration,
+}
+
+impl FileSigner {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            poll_interval: FILE_POLL_INTERVAL,
+            timeout: FILE_SIGNER_TIMEOUT,
+        }
+    }
+
+    /// Waits until the file at `path` exists and returns its content.
+    async fn wait_for_file(&self, path: &Path) -> Result<String> {
+        loop {
+            match tokio::fs::read_to_string(path).await {
+                Ok(content) => return Ok(content),
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    tokio::time::sleep(self.poll_interval).await
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to read {}", path.display()))
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ExternalSigner for FileSigner {
+    async fn sign(&self, psbt: PartiallySignedTransaction) -> Result<PartiallySignedTransaction> {
+        let txid = psbt.global.unsigned_tx.txid();
+        let unsigned_path = self.dir.join(format!("{}.psbt", txid));
+        let signed_path = self.dir.join(format!("{}.signed.psbt", txid));
+
+        tokio::fs::write(&unsigned_path, psbt.to_string())
+            .await
+            .with_context(|| format!("Failed to write PSBT to {}", unsigned_path.display()))?;
+
+        tracing::info!(
+            %txid,
+            "Waiting for external signer, expecting signed PSBT at {}",
+            signed_path.display()
+        );
+
+        let signed =
+            match tokio::time::timeout(self.timeout, self.wait_for_file(&signed_path)).await {
+                Ok(signed) => signed?,
+                Err(_) => {
+                    tokio::fs::remove_file(&unsigned_path).await?;
+                    bail!(
+                        "External signer did not provide a signed PSBT within {}s",
+                        self.timeout.as_secs()
+                    )
+                }
+            };
+
+        let signed = parse_signed_psbt(&signed, &psbt)?;
+
+        tokio::fs::remove_file(&unsigned_path).await?;
+        tokio::fs::remove_file(&signed_path).await?;
+
+        Ok(signed)
+    }
+}
+
+/// Prints PSBTs to stderr and reads the signed PSBT back from stdin, one line per PSBT.
+///
+/// Stdout is left to the logs, which can hide or interleave with the PSBT.
+#[derive(Default)]
+pub struct StdinSigner {
+    // Only one PSBT can be on the terminal at any time
+    lock: Mutex<()>,
+}
+
+#[async_trait]
+impl ExternalSigner for StdinSigner {
+    async fn sign(&self, psbt: PartiallySignedTransaction) -> Result<PartiallySignedTransaction> {
+        let _guard = self.lock.lock().await;
+
+        eprintln!(
+            "Please sign the following PSBT and paste the result:\n{}",
+            psbt
+        );
+
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        let signed = lines
+            .next_line()
+            .await?
+            .context("Stdin closed before a signed PSBT was provided")?;
+
+        parse_signed_psbt(&signed, &psbt)
+    }
+}
+
+fn parse_signed_psbt(
+    signed: &str,
+    unsigned: &PartiallySignedTransaction,
+) -> Result<PartiallySignedTransaction> {
+    let signed = PartiallySignedTransaction::from_str(signed.trim())
+        .context("Failed to parse signed PSBT")?;
+
+    let expected_txid = unsigned.global.unsigned_tx.txid();
+    let actual_txid = signed.global.unsigned_tx.txid();
+    if actual_txid != expected_txid {
+        bail!(
+            "Signed PSBT spends transaction {}, expected {}",
+            actual_txid,
+            expected_txid
+        );
+    }
+
+    Ok(signed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+
+    #[tokio::test]
+    async fn file_signer_round_trip() {
+        let dir = std::env::temp_dir().join(format!("file-signer-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let psbt = dummy_psbt();
+        let txid = psbt.global.unsigned_tx.txid();
+
+        let signer = FileSigner {
+            dir: dir.clone(),
+            poll_interval: Duration::from_millis(10),
+            timeout: Duration::from_secs(10),
+        };
+
+        // Simulates the external signer by returning the PSBT as is
+        let external_signer = {
+            let dir = dir.clone();
+            async move {
+                let unsigned_path = dir.join(format!("{}.psbt", txid));
+                let unsigned = loop {
+                    if let Ok(unsigned) = tokio::fs::read_to_string(&unsigned_path).await {
+                        break unsigned;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                };
+                tokio::fs::write(dir.join(format!("{}.signed.psbt", txid)), unsigned)
+                    .await
+                    .unwrap();
+            }
+        };
+
+        let (signed, _) = tokio::join!(signer.sign(psbt.clone()), external_signer);
+
+        assert_eq!(signed.unwrap(), psbt);
+        assert!(!dir.join(format!("{}.psbt", txid)).exists());
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_signer_gives_up_without_signed_psbt() {
+        let dir = std::env::temp_dir().join(format!("file-signer-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let psbt = dummy_psbt();
+        let txid = psbt.global.unsigned_tx.txid();
+
+        let signer = FileSigner {
+            dir: dir.clone(),
+            poll_interval: Duration::from_millis(10),
+            timeout: Duration::from_millis(50),
+        };
+
+        assert!(signer.sign(psbt).await.is_err());
+        assert!(!dir.join(format!("{}.psbt", txid)).exists());
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[test]
+    fn rejects_psbt_for_different_transaction() {
+        let unsigned = dummy_psbt();
+        let mut other_tx = unsigned.global.unsigned_tx.clone();
+        other_tx.lock_time = 1;
+        let other = PartiallySignedTransaction::from_unsigned_tx(other_tx).unwrap();
+
+        let result = parse_signed_psbt(&other.to_string(), &unsigned);
+
+        assert!(result.is_err());
+    }
+
+    fn dummy_psbt() -> PartiallySignedTransaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+
+        PartiallySignedTransaction::from_unsigned_tx(tx).unwrap()
+    }
+}
@@ -1,30 +1,50 @@
-use crate::db::{append_cfd_state, load_all_cfds};
-use crate::model::cfd::{Cfd, CfdState};
+use crate::db::{self, append_cfd_event, load_all_cfds};
+use crate::model::cfd::{Cfd, CfdEvent};
+use crate::model::Timestamp;
 use crate::{try_continue, wallet};
 use anyhow::Result;
 use sqlx::pool::PoolConnection;
-use sqlx::Sqlite;
+use sqlx::Any;
 use xtra::Address;
 
 pub async fn transition_non_continue_cfds_to_setup_failed(
-    conn: &mut PoolConnection<Sqlite>,
+    conn: &mut PoolConnection<Any>,
 ) -> Result<()> {
     let mut cfds = load_all_cfds(conn).await?;
 
     for cfd in cfds.iter_mut().filter(|cfd| Cfd::is_cleanup(cfd)) {
-        cfd.state = CfdState::setup_failed(format!(
-            "Was in state {} which cannot be continued.",
-            cfd.state
-        ));
+        let event = CfdEvent::ContractSetupFailed {
+            info: format!("Was in state {} which cannot be continued.", cfd.state),
+        };
+        let timestamp = Timestamp::now()?;
 
-        append_cfd_state(cfd, conn).await?;
+        cfd.apply(event.clone(), timestamp)?;
+        append_cfd_event(cfd, &event, timestamp, conn).await?;
     }
 
     Ok(())
 }
 
+/// Moves CFDs that have been finished for longer than `archive_after` to the archive.
+///
+/// Returns the number of CFDs that were archived.
+pub async fn archive_finished_cfds(
+    conn: &mut PoolConnection<Any>,
+    archive_after: time::Duration,
+) -> Result<usize> {
+    let finished_before =
+        Timestamp::new(Timestamp::now()?.seconds() - archive_after.whole_seconds());
+
+    let archived = db::archive_finished_cfds(finished_before, conn).await?;
+    if archived > 0 {
+        tracing::info!("Archived {} finished CFDs", archived);
+    }
+
+    Ok(archived)
+}
+
 pub async fn rebroadcast_transactions(
-    conn: &mut PoolConnection<Sqlite>,
+    conn: &mut PoolConnection<Any>,
     wallet: &Address<wallet::Actor>,
 ) -> Result<()> {
     let cfds = load_all_cfds(conn).await?;
@@ -1,13 +1,13 @@
 #![cfg_attr(not(test), warn(clippy::unwrap_used))]
-use crate::db::load_all_cfds;
-use crate::maker_cfd::{FromTaker, NewTakerOnline};
-use crate::model::cfd::{Cfd, Order, UpdateCfdProposals};
+use crate::db::{load_all_cfds, load_proposals, load_settlement_triggers};
+use crate::maker_cfd::{FromTaker, NewTakerOnline, RollOverPolicy, SettlementPolicy};
+use crate::model::cfd::{AutoRollOverFailures, Cfd, Order, UpdateCfdProposals};
+use crate::model::FundingRate;
 use crate::oracle::Attestation;
 use anyhow::Result;
 use futures::Stream;
 use maia::secp256k1_zkp::schnorrsig;
-use sqlx::SqlitePool;
-use std::collections::HashMap;
+use sqlx::AnyPool;
 use std::future::Future;
 use std::time::Duration;
 use tokio::sync::watch;
@@ -16,11 +16,15 @@ use xtra::spawn::TokioGlobalSpawnExt;
 use xtra::{Actor, Address};
 
 pub mod actors;
+pub mod audit_log;
 pub mod auth;
+pub mod backup;
 pub mod bitmex_price_feed;
 pub mod cfd_actors;
 pub mod connection;
 pub mod db;
+pub mod export;
+pub mod external_signer;
 pub mod fan_out;
 pub mod forward_only_ok;
 pub mod housekeeping;
@@ -41,11 +45,15 @@ pub mod setup_contract;
 pub mod taker_cfd;
 pub mod to_sse_event;
 pub mod tokio_ext;
+pub mod transaction_history;
 pub mod try_continue;
 pub mod wallet;
 pub mod wallet_sync;
 pub mod wire;
 
+/// How often to check for finished CFDs that are due to be archived
+const ARCHIVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 pub struct MakerActorSystem<O, M, T, W> {
     pub cfd_actor_addr: Address<maker_cfd::Actor<O, M, T, W>>,
     pub cfd_feed_receiver: watch::Receiver<Vec<Cfd>>,
@@ -71,7 +79,7 @@ where
         + xtra::Handler<wallet::TryBroadcastTransaction>,
 {
     pub async fn new<F>(
-        db: SqlitePool,
+        db: AnyPool,
         wallet_addr: Address<W>,
         oracle_pk: schnorrsig::PublicKey,
         oracle_constructor: impl FnOnce(Vec<Cfd>, Box<dyn StrongMessageChannel<Attestation>>) -> O,
@@ -81,6 +89,11 @@ where
             Box<dyn MessageChannel<FromTaker>>,
         ) -> T,
         settlement_time_interval_hours: time::Duration,
+        funding_rate: FundingRate,
+        roll_over_policy: RollOverPolicy,
+        settlement_policy: SettlementPolicy,
+        price_feed: watch::Receiver<bitmex_price_feed::Quote>,
+        archive_after: time::Duration,
     ) -> Result<Self>
     where
         F: Future<Output = Result<M>>,
@@ -88,20 +101,41 @@ where
         let mut conn = db.acquire().await?;
 
         let cfds = load_all_cfds(&mut conn).await?;
+        let proposals = load_proposals(&mut conn).await?;
 
         let (cfd_feed_sender, cfd_feed_receiver) = watch::channel(cfds.clone());
         let (order_feed_sender, order_feed_receiver) = watch::channel::<Option<Order>>(None);
-        let (update_cfd_feed_sender, update_cfd_feed_receiver) =
-            watch::channel::<UpdateCfdProposals>(HashMap::new());
+        let pending_proposals: UpdateCfdProposals = proposals
+            .iter()
+            .map(|(proposal, _)| (proposal.order_id(), proposal.clone()))
+            .collect();
+        let (update_cfd_feed_sender, update_cfd_feed_receiver) = watch::channel(pending_proposals);
 
         let (monitor_addr, mut monitor_ctx) = xtra::Context::new(None);
         let (oracle_addr, mut oracle_ctx) = xtra::Context::new(None);
         let (inc_conn_addr, inc_conn_ctx) = xtra::Context::new(None);
+        let (cfd_actor_addr, mut cfd_actor_ctx) = xtra::Context::new(None);
 
-        let cfd_actor_addr = maker_cfd::Actor::new(
+        tokio::spawn(
+            cfd_actor_ctx
+                .notify_interval(Duration::from_secs(60), || maker_cfd::ExpireProposals)
+                .map_err(|e| anyhow::anyhow!(e))?,
+        );
+        tokio::spawn(
+            cfd_actor_ctx
+                .notify_interval(ARCHIVE_INTERVAL, move || maker_cfd::ArchiveFinishedCfds {
+                    archive_after,
+                })
+                .map_err(|e| anyhow::anyhow!(e))?,
+        );
+        tokio::spawn(cfd_actor_ctx.run(maker_cfd::Actor::new(
             db,
             wallet_addr,
             settlement_time_interval_hours,
+            funding_rate,
+            roll_over_policy,
+            settlement_policy,
+            price_feed,
             oracle_pk,
             cfd_feed_sender,
             order_feed_sender,
@@ -109,9 +143,8 @@ where
             inc_conn_addr.clone(),
             monitor_addr.clone(),
             oracle_addr.clone(),
-        )
-        .create(None)
-        .spawn_global();
+            proposals,
+        )));
 
         tokio::spawn(inc_conn_ctx.run(inc_conn_constructor(
             Box::new(cfd_actor_addr.clone()),
@@ -156,6 +189,7 @@ pub struct TakerActorSystem<O, M, W> {
     pub cfd_feed_receiver: watch::Receiver<Vec<Cfd>>,
     pub order_feed_receiver: watch::Receiver<Option<Order>>,
     pub update_cfd_feed_receiver: watch::Receiver<UpdateCfdProposals>,
+    pub auto_roll_over_failures_receiver: watch::Receiver<AutoRollOverFailures>,
 }
 
 impl<O, M, W> TakerActorSystem<O, M, W>
@@ -173,13 +207,16 @@ where
         + xtra::Handler<wallet::TryBroadcastTransaction>,
 {
     pub async fn new<F>(
-        db: SqlitePool,
+        db: AnyPool,
         wallet_addr: Address<W>,
         oracle_pk: schnorrsig::PublicKey,
         send_to_maker: Box<dyn MessageChannel<wire::TakerToMaker>>,
         read_from_maker: Box<dyn Stream<Item = taker_cfd::MakerStreamMessage> + Unpin + Send>,
         oracle_constructor: impl FnOnce(Vec<Cfd>, Box<dyn StrongMessageChannel<Attestation>>) -> O,
         monitor_constructor: impl FnOnce(Box<dyn StrongMessageChannel<monitor::Event>>, Vec<Cfd>) -> F,
+        auto_roll_over: Option<time::Duration>,
+        price_feed: watch::Receiver<bitmex_price_feed::Quote>,
+        archive_after: time::Duration,
     ) -> Result<Self>
     where
         F: Future<Output = Result<M>>,
@@ -187,16 +224,63 @@ where
         let mut conn = db.acquire().await?;
 
         let cfds = load_all_cfds(&mut conn).await?;
+        let proposals = load_proposals(&mut conn).await?;
+        let settlement_triggers = load_settlement_triggers(&mut conn).await?;
 
         let (cfd_feed_sender, cfd_feed_receiver) = watch::channel(cfds.clone());
         let (order_feed_sender, order_feed_receiver) = watch::channel::<Option<Order>>(None);
-        let (update_cfd_feed_sender, update_cfd_feed_receiver) =
-            watch::channel::<UpdateCfdProposals>(HashMap::new());
+        let pending_proposals: UpdateCfdProposals = proposals
+            .iter()
+            .map(|(proposal, _)| (proposal.order_id(), proposal.clone()))
+            .collect();
+        let (update_cfd_feed_sender, update_cfd_feed_receiver) = watch::channel(pending_proposals);
+        let (auto_roll_over_failures_sender, auto_roll_over_failures_receiver) =
+            watch::channel(AutoRollOverFailures::new());
 
         let (monitor_addr, mut monitor_ctx) = xtra::Context::new(None);
         let (oracle_addr, mut oracle_ctx) = xtra::Context::new(None);
+        let (cfd_actor_addr, mut cfd_actor_ctx) = xtra::Context::new(None);
 
-        let cfd_actor_addr = taker_cfd::Actor::new(
+        let proposals = proposals
+            .into_iter()
+            .map(|(proposal, _)| proposal)
+            .collect();
+        tokio::spawn(
+            cfd_actor_ctx
+                .notify_interval(Duration::from_secs(60), || taker_cfd::ExpireProposals)
+                .map_err(|e| anyhow::anyhow!(e))?,
+        );
+        tokio::spawn(
+            cfd_actor_ctx
+                .notify_interval(ARCHIVE_INTERVAL, move || taker_cfd::ArchiveFinishedCfds {
+                    archive_after,
+                })
+                .map_err(|e| anyhow::anyhow!(e))?,
+        );
+        tokio::spawn({
+            let cfd_actor_addr = cfd_actor_addr.clone();
+            let mut quote_updates = price_feed.clone();
+
+            async move {
+                while quote_updates.changed().await.is_ok() {
+                    if cfd_actor_addr
+                        .send(taker_cfd::CheckSettlementTriggers)
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        });
+        if auto_roll_over.is_some() {
+            tokio::spawn(
+                cfd_actor_ctx
+                    .notify_interval(Duration::from_secs(60), || taker_cfd::AutoRollOver)
+                    .map_err(|e| anyhow::anyhow!(e))?,
+            );
+        }
+        tokio::spawn(cfd_actor_ctx.run(taker_cfd::Actor::new(
             db,
             wallet_addr,
             oracle_pk,
@@ -206,9 +290,22 @@ where
             send_to_maker,
             monitor_addr.clone(),
             oracle_addr,
-        )
-        .create(None)
-        .spawn_global();
+            auto_roll_over,
+            auto_roll_over_failures_sender,
+            cfd_feed_receiver.clone(),
+            price_feed,
+            proposals,
+            settlement_triggers,
+        )));
+
+        // The maker does not know which CFDs are ours and which proposals we made before we
+        // reconnected
+        cfd_actor_addr
+            .do_send_async(taker_cfd::AnnounceCfds)
+            .await?;
+        cfd_actor_addr
+            .do_send_async(taker_cfd::ReannounceProposals)
+            .await?;
 
         tokio::spawn(cfd_actor_addr.clone().attach_stream(read_from_maker));
 
@@ -239,6 +336,7 @@ where
             cfd_feed_receiver,
             order_feed_receiver,
             update_cfd_feed_receiver,
+            auto_roll_over_failures_receiver,
         })
     }
 }
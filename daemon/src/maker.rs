@@ -1,11 +1,13 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bdk::bitcoin::secp256k1::schnorrsig;
 use bdk::{bitcoin, FeeRate};
 use clap::{Parser, Subcommand};
 use daemon::auth::{self, MAKER_USERNAME};
+use daemon::backup::{self, SnapshotDir};
 use daemon::db::{self};
+use daemon::external_signer::{ExternalSigner, FileSigner, StdinSigner};
 
-use daemon::model::WalletInfo;
+use daemon::model::{FundingRate, WalletInfo};
 
 use daemon::seed::Seed;
 use daemon::{
@@ -13,14 +15,16 @@ use daemon::{
     wallet, wallet_sync, MakerActorSystem,
 };
 
-use sqlx::sqlite::SqliteConnectOptions;
-use sqlx::SqlitePool;
+use rust_decimal::Decimal;
+use sqlx::AnyPool;
 
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use bdk::bitcoin::util::bip32::{ExtendedPubKey, Fingerprint};
 use bdk::bitcoin::Amount;
+use std::sync::Arc;
 use std::task::Poll;
 use tokio::sync::watch;
 use tracing_subscriber::filter::LevelFilter;
@@ -44,14 +48,63 @@ struct Opts {
     #[clap(long)]
     data_dir: Option<PathBuf>,
 
+    /// Store CFDs in this database instead of the SQLite database in the data directory, e.g.
+    /// `postgres://maker@localhost/maker`.
+    #[clap(long)]
+    database_url: Option<String>,
+
     /// If enabled logs will be in json format
     #[clap(short, long)]
     json: bool,
 
+    /// Finished CFDs are moved to the archive after this many days, archived CFDs are no longer
+    /// part of the feed.
+    #[clap(long, default_value = "7")]
+    archive_after_days: u16,
+
+    /// How many database snapshots are kept in the data directory, the oldest ones are deleted
+    /// when taking a new snapshot.
+    #[clap(long, default_value = "10")]
+    keep_snapshots: usize,
+
     /// The time interval until potential settlement of each CFD in hours
     #[clap(long, default_value = "24")]
     settlement_time_interval_hours: u8,
 
+    /// The share of the value of a position the long party pays the short party with every
+    /// roll-over, negative if the short party pays. It is quoted in new orders and applies to all
+    /// CFDs taken from them.
+    #[clap(long, default_value = "0", allow_hyphen_values = true)]
+    funding_rate: FundingRate,
+
+    /// Which roll-over proposals are accepted automatically: `manual` for none, `all` or the
+    /// maximum quantity of a CFD in USD.
+    #[clap(long, default_value = "manual")]
+    roll_over_policy: maker_cfd::RollOverPolicy,
+
+    /// Settlement proposals of takers made longer ago than this are rejected.
+    #[clap(long, default_value = "300")]
+    settlement_max_age_secs: u32,
+
+    /// Settlement proposals of takers are rejected if their price deviates from the current price
+    /// by more than this share of it.
+    #[clap(long, default_value = "0.01")]
+    settlement_max_price_deviation: Decimal,
+
+    /// Run the wallet watch-only, using this account-level extended public key derived at
+    /// `m/84'/<coin>'/0'`. All signing is delegated to an external signer.
+    #[clap(long)]
+    wallet_xpub: Option<ExtendedPubKey>,
+
+    /// The fingerprint of the master key the watch-only extended public key was derived from.
+    #[clap(long)]
+    wallet_master_fingerprint: Option<Fingerprint>,
+
+    /// Directory through which PSBTs are exchanged with the external signer. If not set, PSBTs
+    /// are exchanged through stdout and stdin.
+    #[clap(long)]
+    signer_dir: Option<PathBuf>,
+
     #[clap(subcommand)]
     network: Network,
 }
@@ -65,7 +118,7 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
     },
     /// Run on testnet.
     Testnet {
@@ -74,7 +127,7 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
     },
     /// Run on signet
     Signet {
@@ -83,12 +136,12 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
     },
 }
 
 #[derive(Subcommand)]
-enum Withdraw {
+enum Command {
     Withdraw {
         /// Optionally specify the amount of Bitcoin to be withdrawn. If not specified the wallet
         /// will be drained. Amount is to be specified with denomination, e.g. "0.1 BTC"
@@ -102,6 +155,29 @@ enum Withdraw {
         #[clap(long)]
         address: bdk::bitcoin::Address,
     },
+    /// Print the mnemonic to back up the seed with.
+    ExportSeed,
+    /// Restore the seed from a mnemonic entered on stdin and rebuild the wallet from the chain.
+    Restore,
+    /// Encrypt an existing plaintext seed file with a passphrase.
+    ///
+    /// The passphrase is read from the `SEED_PASSPHRASE` environment variable or prompted for.
+    EncryptSeed,
+    /// Take a consistent snapshot of the CFD database, also while the daemon is running.
+    Backup {
+        /// Where to write the snapshot to. Defaults to a file named after the current time in
+        /// the `backups` directory of the data directory.
+        #[clap(long)]
+        path: Option<PathBuf>,
+    },
+    /// Replace the CFD database with a snapshot, after validating it against the wallet.
+    ///
+    /// The daemon must not be running while the database is restored.
+    RestoreDb {
+        /// The snapshot to restore.
+        #[clap(long)]
+        path: PathBuf,
+    },
 }
 
 impl Network {
@@ -129,11 +205,11 @@ impl Network {
         }
     }
 
-    fn withdraw(&self) -> &Option<Withdraw> {
+    fn command(&self) -> &Option<Command> {
         match self {
-            Network::Mainnet { withdraw, .. } => withdraw,
-            Network::Testnet { withdraw, .. } => withdraw,
-            Network::Signet { withdraw, .. } => withdraw,
+            Network::Mainnet { command, .. } => command,
+            Network::Testnet { command, .. } => command,
+            Network::Signet { command, .. } => command,
         }
     }
 }
@@ -156,28 +232,120 @@ async fn main() -> Result<()> {
         tokio::fs::create_dir_all(&data_dir).await?;
     }
 
-    let seed = Seed::initialize(&data_dir.join("maker_seed")).await?;
+    let seed_file = data_dir.join("maker_seed");
+    let wallet_file = data_dir.join("maker_wallet.sqlite");
+    let db_file = data_dir.join("maker.sqlite");
+    let snapshot_dir = SnapshotDir::new(&data_dir, opts.keep_snapshots);
+
+    if let Some(Command::Backup { path }) = opts.network.command() {
+        let db = connect_db(opts.database_url.as_deref(), &db_file).await?;
+        match path {
+            Some(path) => backup::snapshot(&db, path).await?,
+            None => {
+                snapshot_dir.snapshot(&db, "maker").await?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Command::Restore) = opts.network.command() {
+        if let Some(url) = opts.database_url.as_deref() {
+            let db = connect_db(Some(url), &db_file).await?;
+            db::run_migrations(&db).await?;
+            let cfds = db::load_all_cfds(&mut db.acquire().await?).await?;
+            db.close().await;
+
+            if !cfds.is_empty() {
+                bail!(
+                    "The database contains {} CFDs of the previous seed, restore into an empty \
+                     database",
+                    cfds.len()
+                );
+            }
+        }
+
+        Seed::restore(&seed_file).await?;
+
+        // The wallet database only caches what can be recovered from the chain, it has to go as
+        // it is bound to the previous seed.
+        if wallet_file.exists() {
+            tokio::fs::remove_file(&wallet_file).await?;
+        }
+
+        // The CFDs in the database were set up with the keys of the previous seed. The database is
+        // kept, it can be restored with `restore-db` if it belongs to the restored seed.
+        if db_file.exists() {
+            let moved = backup::move_aside(&db_file, "previous-seed").await?;
+            tracing::warn!(
+                "Moved the database of the previous seed to {}",
+                moved.display()
+            );
+        }
+
+        tracing::info!("Seed restored from mnemonic, rebuilding wallet");
+    }
+
+    if let Some(Command::EncryptSeed) = opts.network.command() {
+        Seed::encrypt_file(&seed_file).await?;
+        tracing::info!("Seed file encrypted");
+
+        return Ok(());
+    }
+
+    if let Some(Command::ExportSeed) = opts.network.command() {
+        let seed = Seed::open(&seed_file).await?;
+        let mnemonic = seed
+            .mnemonic()
+            .with_context(|| format!("Cannot export the seed at {}", seed_file.display()))?;
+        println!("{}", mnemonic);
+
+        return Ok(());
+    }
+
+    let seed = Seed::initialize(&seed_file).await?;
 
     let bitcoin_network = opts.network.bitcoin_network();
-    let ext_priv_key = seed.derive_extended_priv_key(bitcoin_network)?;
 
-    let wallet = wallet::Actor::new(
-        opts.network.electrum(),
-        &data_dir.join("maker_wallet.sqlite"),
-        ext_priv_key,
-    )
-    .await?
+    let wallet = match opts.wallet_xpub {
+        Some(ext_pub_key) => {
+            let master_fingerprint = opts
+                .wallet_master_fingerprint
+                .context("A watch-only wallet requires the master key fingerprint")?;
+            let external_signer: Arc<dyn ExternalSigner> = match opts.signer_dir.clone() {
+                Some(dir) => Arc::new(FileSigner::new(dir)),
+                None => Arc::new(StdinSigner::default()),
+            };
+
+            tracing::info!(%ext_pub_key, "Running watch-only wallet with external signer");
+
+            wallet::Actor::new_watch_only(
+                opts.network.electrum(),
+                &data_dir.join("maker_wallet_watch_only.sqlite"),
+                ext_pub_key,
+                master_fingerprint,
+                bitcoin_network,
+                external_signer,
+            )
+            .await?
+        }
+        None => {
+            let ext_priv_key = seed.derive_extended_priv_key(bitcoin_network)?;
+
+            wallet::Actor::new(opts.network.electrum(), &wallet_file, ext_priv_key).await?
+        }
+    }
     .create(None)
     .spawn_global();
 
     // do this before withdraw to ensure the wallet is synced
     let wallet_info = wallet.send(wallet::Sync).await??;
 
-    if let Some(Withdraw::Withdraw {
+    if let Some(Command::Withdraw {
         amount,
         address,
         fee,
-    }) = opts.network.withdraw()
+    }) = opts.network.command()
     {
         let txid = wallet
             .send(wallet::Withdraw {
@@ -192,6 +360,41 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(Command::RestoreDb { path }) = opts.network.command() {
+        if opts.database_url.is_some() {
+            bail!("Snapshots can only be restored into SQLite, use pg_restore for Postgres");
+        }
+
+        let wallet_txids = wallet
+            .send(wallet::TransactionHistory)
+            .await??
+            .into_iter()
+            .map(|tx| tx.txid)
+            .collect();
+
+        let warnings = backup::restore(path, &db_file, &wallet_txids).await?;
+        if !warnings.is_empty() {
+            tracing::warn!(
+                "Database restored with {} warnings, do not start the daemon before resolving them",
+                warnings.len()
+            );
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Command::Restore) = opts.network.command() {
+        let noise_static_pk = x25519_dalek::PublicKey::from(&seed.derive_noise_static_secret());
+
+        tracing::info!(
+            balance = %wallet_info.balance,
+            noise_public_key = %hex::encode(noise_static_pk.to_bytes()),
+            "Restore successful"
+        );
+
+        return Ok(());
+    }
+
     let auth_password = seed.derive_auth_password::<auth::Password>();
 
     let noise_static_sk = seed.derive_noise_static_secret();
@@ -228,12 +431,7 @@ async fn main() -> Result<()> {
     let (task, quote_updates) = bitmex_price_feed::new().await?;
     tokio::spawn(task);
 
-    let db = SqlitePool::connect_with(
-        SqliteConnectOptions::new()
-            .create_if_missing(true)
-            .filename(data_dir.join("maker.sqlite")),
-    )
-    .await?;
+    let db = connect_db(opts.database_url.as_deref(), &db_file).await?;
 
     db::run_migrations(&db)
         .await
@@ -243,6 +441,8 @@ async fn main() -> Result<()> {
     let mut conn = db.acquire().await?;
 
     housekeeping::transition_non_continue_cfds_to_setup_failed(&mut conn).await?;
+    let archive_after = time::Duration::days(opts.archive_after_days.into());
+    housekeeping::archive_finished_cfds(&mut conn, archive_after).await?;
     housekeeping::rebroadcast_transactions(&mut conn, &wallet).await?;
 
     let settlement_time_interval_hours =
@@ -266,6 +466,14 @@ async fn main() -> Result<()> {
         },
         |channel0, channel1| maker_inc_connections::Actor::new(channel0, channel1, noise_static_sk),
         time::Duration::hours(opts.settlement_time_interval_hours as i64),
+        opts.funding_rate,
+        opts.roll_over_policy,
+        maker_cfd::SettlementPolicy {
+            max_age: time::Duration::seconds(opts.settlement_max_age_secs.into()),
+            max_price_deviation: opts.settlement_max_price_deviation,
+        },
+        quote_updates.clone(),
+        archive_after,
     )
     .await?;
 
@@ -282,36 +490,64 @@ async fn main() -> Result<()> {
 
     tokio::spawn(incoming_connection_addr.attach_stream(listener_stream));
 
+    let transaction_history_channel =
+        MessageChannel::<wallet::TransactionHistory>::clone_channel(&wallet);
+    let preview_withdraw_channel =
+        MessageChannel::<wallet::PreviewWithdraw>::clone_channel(&wallet);
+    let broadcast_withdraw_channel =
+        MessageChannel::<wallet::BroadcastWithdraw>::clone_channel(&wallet);
+
     tokio::spawn(wallet_sync::new(wallet, wallet_feed_sender));
 
     let cfd_action_channel = MessageChannel::<maker_cfd::CfdAction>::clone_channel(&cfd_actor_addr);
     let new_order_channel = MessageChannel::<maker_cfd::NewOrder>::clone_channel(&cfd_actor_addr);
+    let counter_settlement_channel =
+        MessageChannel::<maker_cfd::CounterSettlement>::clone_channel(&cfd_actor_addr);
+    let counter_roll_over_channel =
+        MessageChannel::<maker_cfd::CounterRollOver>::clone_channel(&cfd_actor_addr);
 
     rocket::custom(figment)
         .manage(order_feed_receiver)
         .manage(update_cfd_feed_receiver)
         .manage(cfd_action_channel)
         .manage(new_order_channel)
+        .manage(counter_settlement_channel)
+        .manage(counter_roll_over_channel)
+        .manage(transaction_history_channel)
+        .manage(preview_withdraw_channel)
+        .manage(broadcast_withdraw_channel)
         .manage(cfd_feed_receiver)
         .manage(wallet_feed_receiver)
         .manage(auth_password)
+        .manage(auth::Username(MAKER_USERNAME))
         .manage(quote_updates)
         .manage(bitcoin_network)
+        .manage(db.clone())
+        .manage(snapshot_dir)
         .mount(
             "/api",
             rocket::routes![
                 routes_maker::maker_feed,
                 routes_maker::post_sell_order,
                 routes_maker::post_cfd_action,
+                routes_maker::post_counter_settlement,
+                routes_maker::post_counter_roll_over,
+                routes_maker::get_wallet_transactions,
+                routes_maker::export_cfds,
+                routes_maker::get_archived_cfds,
+                routes_maker::get_cfd_events,
+                routes_maker::post_backup,
+                routes_maker::post_withdraw_request,
+                routes_maker::post_withdraw_broadcast,
                 routes_maker::get_health_check
             ],
         )
-        .register("/api", rocket::catchers![routes_maker::unauthorized])
+        .register("/api", rocket::catchers![auth::unauthorized])
         .mount(
             "/",
             rocket::routes![routes_maker::dist, routes_maker::index],
         )
-        .register("/", rocket::catchers![routes_maker::unauthorized])
+        .register("/", rocket::catchers![auth::unauthorized])
         .launch()
         .await?;
 
@@ -319,3 +555,12 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+async fn connect_db(database_url: Option<&str>, db_file: &Path) -> Result<AnyPool> {
+    match database_url {
+        Some(url) => AnyPool::connect(url)
+            .await
+            .context("Failed to connect to database"),
+        None => db::open_sqlite(db_file).await,
+    }
+}
@@ -1,45 +1,255 @@
-use crate::cfd_actors::{self, append_cfd_state, insert_cfd};
-use crate::db::{insert_order, load_cfd_by_order_id, load_order_by_id};
+use crate::bitmex_price_feed::Quote;
+use crate::cfd_actors::{self, apply_event, insert_cfd};
+use crate::db::{self, insert_order, load_cfd_by_order_id, load_order_by_id};
 use crate::maker_inc_connections::TakerCommand;
 use crate::model::cfd::{
-    Cfd, CfdState, CfdStateChangeEvent, CfdStateCommon, CollaborativeSettlement, Dlc, Order,
-    OrderId, Origin, Role, RollOverProposal, SettlementKind, SettlementProposal, UpdateCfdProposal,
-    UpdateCfdProposals,
+    AddToPositionProposal, Cfd, CfdEvent, CfdState, CfdStateChangeEvent, CfdStateCommon,
+    CollaborativeSettlement, Dlc, Order, OrderId, Origin, PartialSettlementProposal, Role,
+    RollOverProposal, SettlementKind, SettlementProposal, SettlementRejectionReason,
+    UpdateCfdProposal, UpdateCfdProposals,
 };
-use crate::model::{Price, TakerId, Timestamp, Usd};
+use crate::model::{ContractType, FundingRate, PayoutResolution, Price, TakerId, Timestamp, Usd};
 use crate::monitor::MonitorParams;
 use crate::{log_error, maker_inc_connections, monitor, oracle, setup_contract, wallet, wire};
 use anyhow::{Context as _, Result};
 use async_trait::async_trait;
 use bdk::bitcoin::secp256k1::schnorrsig;
+use bdk::bitcoin::SignedAmount;
 use futures::channel::mpsc;
 use futures::{future, SinkExt};
 use maia::secp256k1_zkp::Signature;
+use rust_decimal::Decimal;
 use sqlx::pool::PoolConnection;
-use sqlx::Sqlite;
+use sqlx::Any;
 use std::collections::HashMap;
+use std::str::FromStr;
 use time::Duration;
 use tokio::sync::watch;
 use xtra::prelude::*;
 
 pub enum CfdAction {
-    AcceptOrder { order_id: OrderId },
-    RejectOrder { order_id: OrderId },
-    AcceptSettlement { order_id: OrderId },
-    RejectSettlement { order_id: OrderId },
-    AcceptRollOver { order_id: OrderId },
-    RejectRollOver { order_id: OrderId },
-    Commit { order_id: OrderId },
+    AcceptOrder {
+        order_id: OrderId,
+    },
+    RejectOrder {
+        order_id: OrderId,
+    },
+    AcceptSettlement {
+        order_id: OrderId,
+    },
+    RejectSettlement {
+        order_id: OrderId,
+    },
+    AcceptRollOver {
+        order_id: OrderId,
+    },
+    RejectRollOver {
+        order_id: OrderId,
+    },
+    /// Proposes to the taker to settle the CFD at the current price.
+    ProposeSettlement {
+        order_id: OrderId,
+        current_price: Price,
+    },
+    AcceptPartialSettlement {
+        order_id: OrderId,
+    },
+    RejectPartialSettlement {
+        order_id: OrderId,
+    },
+    AcceptAddToPosition {
+        order_id: OrderId,
+    },
+    RejectAddToPosition {
+        order_id: OrderId,
+    },
+    Commit {
+        order_id: OrderId,
+    },
 }
 
 pub struct NewOrder {
     pub price: Price,
     pub min_quantity: Usd,
     pub max_quantity: Usd,
+    pub contract_type: ContractType,
+    pub payout_resolution: PayoutResolution,
+}
+
+/// Replies to a settlement proposal with a settlement at a different price.
+pub struct CounterSettlement {
+    pub order_id: OrderId,
+    pub price: Price,
+}
+
+/// Replies to a roll-over proposal with a different funding rate.
+pub struct CounterRollOver {
+    pub order_id: OrderId,
+    pub funding_rate: FundingRate,
+}
+
+/// Decides which roll-over proposals of takers are accepted without confirmation by the maker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RollOverPolicy {
+    /// Every proposal has to be accepted or rejected manually.
+    Manual,
+    /// Proposals for CFDs up to this quantity are accepted automatically.
+    AcceptUpTo(Usd),
+    /// Every proposal is accepted automatically.
+    AcceptAll,
+}
+
+impl RollOverPolicy {
+    fn accepts(&self, cfd: &Cfd) -> bool {
+        match self {
+            RollOverPolicy::Manual => false,
+            RollOverPolicy::AcceptUpTo(max_quantity) => cfd.quantity_usd <= *max_quantity,
+            RollOverPolicy::AcceptAll => true,
+        }
+    }
+}
+
+impl Default for RollOverPolicy {
+    fn default() -> Self {
+        RollOverPolicy::Manual
+    }
+}
+
+impl FromStr for RollOverPolicy {
+    type Err = anyhow::Error;
+
+    /// Parses `manual`, `all` or the maximum quantity of CFDs that are rolled over automatically.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let policy = match s {
+            "manual" => RollOverPolicy::Manual,
+            "all" => RollOverPolicy::AcceptAll,
+            quantity => RollOverPolicy::AcceptUpTo(
+                quantity
+                    .parse()
+                    .with_context(|| format!("Invalid roll-over policy {}", quantity))?,
+            ),
+        };
+
+        Ok(policy)
+    }
+}
+
+/// How far ahead of our clock the clock of a taker may be when it timestamps a proposal.
+const MAX_CLOCK_SKEW: Duration = Duration::seconds(30);
+
+/// Bounds for settlement proposals of takers, proposals outside of them are rejected right away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SettlementPolicy {
+    /// Proposals made longer ago than this are stale.
+    pub max_age: Duration,
+    /// How far the proposed price may be off the current price, as a share of the current price.
+    pub max_price_deviation: Decimal,
+}
+
+impl SettlementPolicy {
+    fn check(
+        &self,
+        cfd: &Cfd,
+        proposal: &SettlementProposal,
+        current_price: Price,
+        now: Timestamp,
+    ) -> Option<SettlementRejectionReason> {
+        self.check_terms(proposal.timestamp, proposal.price, current_price, now)
+            .or_else(|| {
+                cfd.verify_settlement(proposal)
+                    .err()
+                    .map(|_| SettlementRejectionReason::InvalidPayout)
+            })
+    }
+
+    fn check_partial(
+        &self,
+        cfd: &Cfd,
+        proposal: &PartialSettlementProposal,
+        current_price: Price,
+        now: Timestamp,
+    ) -> Option<SettlementRejectionReason> {
+        if let Some(reason) =
+            self.check_terms(proposal.timestamp, proposal.price, current_price, now)
+        {
+            return Some(reason);
+        }
+
+        let expected = match cfd.calculate_partial_settlement(proposal.quantity, proposal.price) {
+            Ok(expected) => expected,
+            Err(_) => return Some(SettlementRejectionReason::InvalidQuantity),
+        };
+        if (expected.taker, expected.maker) != (proposal.taker, proposal.maker) {
+            return Some(SettlementRejectionReason::InvalidPayout);
+        }
+
+        None
+    }
+
+    fn check_add_to_position(
+        &self,
+        cfd: &Cfd,
+        proposal: &AddToPositionProposal,
+        current_price: Price,
+        now: Timestamp,
+    ) -> Option<SettlementRejectionReason> {
+        if let Some(reason) =
+            self.check_terms(proposal.timestamp, proposal.price, current_price, now)
+        {
+            return Some(reason);
+        }
+
+        if proposal.quantity < cfd.order.min_quantity {
+            return Some(SettlementRejectionReason::InvalidQuantity);
+        }
+        match cfd.with_added_position(proposal.quantity, proposal.price) {
+            Ok(increased) if increased.quantity_usd <= cfd.order.max_quantity => None,
+            _ => Some(SettlementRejectionReason::InvalidQuantity),
+        }
+    }
+
+    /// Checks when and at which price a proposal was made, regardless of what it settles
+    fn check_terms(
+        &self,
+        timestamp: Timestamp,
+        price: Price,
+        current_price: Price,
+        now: Timestamp,
+    ) -> Option<SettlementRejectionReason> {
+        let age = now.seconds() - timestamp.seconds();
+        if age > self.max_age.whole_seconds() {
+            return Some(SettlementRejectionReason::Stale { timestamp });
+        }
+        if -age > MAX_CLOCK_SKEW.whole_seconds() {
+            return Some(SettlementRejectionReason::FromTheFuture { timestamp });
+        }
+
+        let current = current_price.into_decimal();
+        let deviation = (price.into_decimal() - current).abs() / current;
+        if deviation > self.max_price_deviation {
+            return Some(SettlementRejectionReason::PriceDeviation {
+                proposed: price,
+                current: current_price,
+            });
+        }
+
+        None
+    }
+}
+
+impl Default for SettlementPolicy {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::minutes(5),
+            max_price_deviation: Decimal::new(1, 2),
+        }
+    }
 }
 
 pub struct NewTakerOnline {
     pub id: TakerId,
+    /// The noise static key the taker authenticated the connection with
+    pub static_key: x25519_dalek::PublicKey,
 }
 
 pub struct CfdSetupCompleted {
@@ -49,18 +259,45 @@ pub struct CfdSetupCompleted {
 
 pub struct CfdRollOverCompleted {
     pub order_id: OrderId,
+    pub funding_fee: SignedAmount,
+    pub dlc: Result<Dlc>,
+}
+
+pub struct CfdPartialSettlementCompleted {
+    pub order_id: OrderId,
+    pub proposal: PartialSettlementProposal,
+    pub dlc: Result<Dlc>,
+}
+
+pub struct CfdAddToPositionCompleted {
+    pub order_id: OrderId,
+    pub proposal: AddToPositionProposal,
     pub dlc: Result<Dlc>,
 }
 
 pub struct FromTaker {
     pub taker_id: TakerId,
+    /// The noise static key the taker authenticated the connection with
+    pub static_key: x25519_dalek::PublicKey,
     pub msg: wire::TakerToMaker,
 }
 
+/// Periodic message to drop proposals that were not answered in time
+pub struct ExpireProposals;
+
+/// Periodic message to archive the CFDs that have been finished for longer than `archive_after`
+pub struct ArchiveFinishedCfds {
+    pub archive_after: Duration,
+}
+
 pub struct Actor<O, M, T, W> {
-    db: sqlx::SqlitePool,
+    db: sqlx::AnyPool,
     wallet: Address<W>,
     settlement_time_interval_hours: Duration,
+    funding_rate: FundingRate,
+    roll_over_policy: RollOverPolicy,
+    settlement_policy: SettlementPolicy,
+    price_feed: watch::Receiver<Quote>,
     oracle_pk: schnorrsig::PublicKey,
     cfd_feed_actor_inbox: watch::Sender<Vec<Cfd>>,
     order_feed_sender: watch::Sender<Option<Order>>,
@@ -70,10 +307,14 @@ pub struct Actor<O, M, T, W> {
     monitor_actor: Address<M>,
     setup_state: SetupState,
     roll_over_state: RollOverState,
+    partial_settlement_state: PartialSettlementState,
+    add_to_position_state: AddToPositionState,
     oracle_actor: Address<O>,
     // Maker needs to also store TakerId to be able to send a reply back
     current_pending_proposals: HashMap<OrderId, (UpdateCfdProposal, TakerId)>,
     current_agreed_proposals: HashMap<OrderId, (SettlementProposal, TakerId)>,
+    /// The connection of the taker of each CFD, as far as it is known since the taker connected
+    cfd_takers: HashMap<OrderId, TakerId>,
 }
 
 enum SetupState {
@@ -92,12 +333,32 @@ enum RollOverState {
     None,
 }
 
+enum PartialSettlementState {
+    Active {
+        taker: TakerId,
+        sender: mpsc::UnboundedSender<wire::PartialSettlementMsg>,
+    },
+    None,
+}
+
+enum AddToPositionState {
+    Active {
+        taker: TakerId,
+        sender: mpsc::UnboundedSender<wire::AddToPositionMsg>,
+    },
+    None,
+}
+
 impl<O, M, T, W> Actor<O, M, T, W> {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        db: sqlx::SqlitePool,
+        db: sqlx::AnyPool,
         wallet: Address<W>,
         settlement_time_interval_hours: Duration,
+        funding_rate: FundingRate,
+        roll_over_policy: RollOverPolicy,
+        settlement_policy: SettlementPolicy,
+        price_feed: watch::Receiver<Quote>,
         oracle_pk: schnorrsig::PublicKey,
         cfd_feed_actor_inbox: watch::Sender<Vec<Cfd>>,
         order_feed_sender: watch::Sender<Option<Order>>,
@@ -105,11 +366,22 @@ impl<O, M, T, W> Actor<O, M, T, W> {
         takers: Address<T>,
         monitor_actor: Address<M>,
         oracle_actor: Address<O>,
+        proposals: Vec<(UpdateCfdProposal, Option<TakerId>)>,
     ) -> Self {
+        // Only proposals the maker exchanged with a taker are restored, they all have one
+        let current_pending_proposals = proposals
+            .into_iter()
+            .filter_map(|(proposal, taker_id)| Some((proposal.order_id(), (proposal, taker_id?))))
+            .collect();
+
         Self {
             db,
             wallet,
             settlement_time_interval_hours,
+            funding_rate,
+            roll_over_policy,
+            settlement_policy,
+            price_feed,
             oracle_pk,
             cfd_feed_actor_inbox,
             order_feed_sender,
@@ -119,9 +391,12 @@ impl<O, M, T, W> Actor<O, M, T, W> {
             monitor_actor,
             setup_state: SetupState::None,
             roll_over_state: RollOverState::None,
+            partial_settlement_state: PartialSettlementState::None,
+            add_to_position_state: AddToPositionState::None,
             oracle_actor,
-            current_pending_proposals: HashMap::new(),
+            current_pending_proposals,
             current_agreed_proposals: HashMap::new(),
+            cfd_takers: HashMap::new(),
         }
     }
 
@@ -150,17 +425,43 @@ impl<O, M, T, W> Actor<O, M, T, W> {
             }
         };
 
-        self.current_pending_proposals.insert(
-            proposal.order_id,
-            (
-                UpdateCfdProposal::RollOverProposal {
-                    proposal,
-                    direction: SettlementKind::Incoming,
-                },
-                taker_id,
-            ),
-        );
-        self.send_pending_proposals()?;
+        self.add_pending_proposal(
+            UpdateCfdProposal::RollOverProposal {
+                proposal,
+                direction: SettlementKind::Incoming,
+            },
+            taker_id,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Accepts a pending roll-over proposal if the roll-over policy allows it.
+    async fn handle_auto_accept_roll_over(
+        &mut self,
+        order_id: OrderId,
+        ctx: &mut Context<Self>,
+    ) -> Result<()>
+    where
+        Self: xtra::Handler<CfdAction>,
+    {
+        if !self.current_pending_proposals.contains_key(&order_id) {
+            return Ok(());
+        }
+
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        if !self.roll_over_policy.accepts(&cfd) {
+            return Ok(());
+        }
+
+        tracing::info!(%order_id, policy = ?self.roll_over_policy, "Accepting roll-over automatically");
+
+        ctx.address()
+            .expect("actor to be able to give address to itself")
+            .do_send_async(CfdAction::AcceptRollOver { order_id })
+            .await?;
 
         Ok(())
     }
@@ -169,22 +470,162 @@ impl<O, M, T, W> Actor<O, M, T, W> {
         &mut self,
         taker_id: TakerId,
         proposal: SettlementProposal,
-    ) -> Result<()> {
+    ) -> Result<()>
+    where
+        T: xtra::Handler<maker_inc_connections::TakerMessage>,
+    {
         tracing::info!(
             "Received settlement proposal from the taker: {:?}",
             proposal
         );
-        self.current_pending_proposals.insert(
-            proposal.order_id,
-            (
-                UpdateCfdProposal::Settlement {
-                    proposal,
-                    direction: SettlementKind::Incoming,
-                },
-                taker_id,
-            ),
+
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(proposal.order_id, &mut conn).await?;
+
+        // The taker priced the proposal with its own view of the market, which has to be close to
+        // ours
+        let current_price = self.price_feed.borrow().for_taker();
+        let rejection =
+            self.settlement_policy
+                .check(&cfd, &proposal, current_price, Timestamp::now()?);
+        if let Some(reason) = rejection {
+            tracing::info!(order_id = %proposal.order_id, %reason, "Rejecting settlement proposal");
+
+            self.takers
+                .do_send_async(maker_inc_connections::TakerMessage {
+                    taker_id,
+                    command: TakerCommand::NotifySettlementRejected {
+                        id: proposal.order_id,
+                        reason,
+                    },
+                })
+                .await?;
+            return Ok(());
+        }
+
+        self.add_pending_proposal(
+            UpdateCfdProposal::Settlement {
+                proposal,
+                direction: SettlementKind::Incoming,
+            },
+            taker_id,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn handle_propose_partial_settlement(
+        &mut self,
+        taker_id: TakerId,
+        proposal: PartialSettlementProposal,
+    ) -> Result<()>
+    where
+        T: xtra::Handler<maker_inc_connections::TakerMessage>,
+    {
+        tracing::info!(
+            "Received partial settlement proposal from the taker {}: {:?}",
+            taker_id,
+            proposal
         );
-        self.send_pending_proposals()?;
+
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(proposal.order_id, &mut conn).await?;
+        if !matches!(cfd.state, CfdState::Open { .. }) {
+            anyhow::bail!("Order is in invalid state. Cannot propose partial settlement.")
+        }
+
+        // Partial settlements are bound like settlements, and the settled part has to pay out
+        // according to the payout curve
+        let current_price = self.price_feed.borrow().for_taker();
+        let rejection =
+            self.settlement_policy
+                .check_partial(&cfd, &proposal, current_price, Timestamp::now()?);
+        if let Some(reason) = rejection {
+            tracing::info!(
+                order_id = %proposal.order_id,
+                %reason,
+                "Rejecting partial settlement proposal"
+            );
+
+            self.takers
+                .do_send_async(maker_inc_connections::TakerMessage {
+                    taker_id,
+                    command: TakerCommand::NotifyPartialSettlementRejected {
+                        id: proposal.order_id,
+                    },
+                })
+                .await?;
+            return Ok(());
+        }
+
+        self.add_pending_proposal(
+            UpdateCfdProposal::PartialSettlement {
+                proposal,
+                direction: SettlementKind::Incoming,
+            },
+            taker_id,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn handle_propose_add_to_position(
+        &mut self,
+        taker_id: TakerId,
+        proposal: AddToPositionProposal,
+    ) -> Result<()>
+    where
+        T: xtra::Handler<maker_inc_connections::TakerMessage>,
+    {
+        tracing::info!(
+            "Received proposal from the taker {} to add to the position: {:?}",
+            taker_id,
+            proposal
+        );
+
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(proposal.order_id, &mut conn).await?;
+        if !matches!(cfd.state, CfdState::Open { .. }) {
+            anyhow::bail!("Order is in invalid state. Cannot add to the position.")
+        }
+
+        // The added quantity enters at the proposed price, which is bound like the price of a
+        // settlement, and has to stay within the limits of the order
+        let current_price = self.price_feed.borrow().for_taker();
+        let rejection = self.settlement_policy.check_add_to_position(
+            &cfd,
+            &proposal,
+            current_price,
+            Timestamp::now()?,
+        );
+        if let Some(reason) = rejection {
+            tracing::info!(
+                order_id = %proposal.order_id,
+                %reason,
+                "Rejecting proposal to add to the position"
+            );
+
+            self.takers
+                .do_send_async(maker_inc_connections::TakerMessage {
+                    taker_id,
+                    command: TakerCommand::NotifyAddToPositionRejected {
+                        id: proposal.order_id,
+                    },
+                })
+                .await?;
+            return Ok(());
+        }
+
+        self.add_pending_proposal(
+            UpdateCfdProposal::AddToPosition {
+                proposal,
+                direction: SettlementKind::Incoming,
+            },
+            taker_id,
+        )
+        .await?;
 
         Ok(())
     }
@@ -227,6 +668,52 @@ impl<O, M, T, W> Actor<O, M, T, W> {
         Ok(())
     }
 
+    async fn handle_inc_partial_settlement_protocol_msg(
+        &mut self,
+        taker_id: TakerId,
+        msg: wire::PartialSettlementMsg,
+    ) -> Result<()> {
+        match &mut self.partial_settlement_state {
+            PartialSettlementState::Active { taker, sender } if taker_id == *taker => {
+                sender.send(msg).await?;
+            }
+            PartialSettlementState::Active { taker, .. } => {
+                anyhow::bail!(
+                    "Currently settling partially with different taker {}",
+                    taker
+                )
+            }
+            PartialSettlementState::None => {
+                anyhow::bail!("Received message without an active partial settlement")
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_inc_add_to_position_protocol_msg(
+        &mut self,
+        taker_id: TakerId,
+        msg: wire::AddToPositionMsg,
+    ) -> Result<()> {
+        match &mut self.add_to_position_state {
+            AddToPositionState::Active { taker, sender } if taker_id == *taker => {
+                sender.send(msg).await?;
+            }
+            AddToPositionState::Active { taker, .. } => {
+                anyhow::bail!(
+                    "Currently adding to the position with different taker {}",
+                    taker
+                )
+            }
+            AddToPositionState::None => {
+                anyhow::bail!("Received message without an active add to position")
+            }
+        }
+
+        Ok(())
+    }
+
     /// Send pending proposals for the purposes of UI updates.
     /// Filters out the TakerIds, as they are an implementation detail inside of
     /// the actor
@@ -239,11 +726,37 @@ impl<O, M, T, W> Actor<O, M, T, W> {
         )?)
     }
 
+    /// Stores a proposal, replacing an earlier proposal for the same CFD, and updates the update
+    /// cfd proposals' feed
+    async fn add_pending_proposal(
+        &mut self,
+        proposal: UpdateCfdProposal,
+        taker_id: TakerId,
+    ) -> Result<()> {
+        let order_id = proposal.order_id();
+        if proposal.is_expired()? {
+            anyhow::bail!("Proposal for order id {} has already expired", order_id)
+        }
+
+        let mut conn = self.db.acquire().await?;
+        db::insert_proposal(&proposal, Some(taker_id), &mut conn).await?;
+
+        self.cfd_takers.insert(order_id, taker_id);
+        self.current_pending_proposals
+            .insert(order_id, (proposal, taker_id));
+        self.send_pending_proposals()?;
+        Ok(())
+    }
+
     /// Removes a proposal and updates the update cfd proposals' feed
-    fn remove_pending_proposal(&mut self, order_id: &OrderId) -> Result<()> {
+    async fn remove_pending_proposal(&mut self, order_id: &OrderId) -> Result<()> {
         if self.current_pending_proposals.remove(order_id).is_none() {
             anyhow::bail!("Could not find proposal with order id: {}", &order_id)
         }
+
+        let mut conn = self.db.acquire().await?;
+        db::delete_proposal(*order_id, &mut conn).await?;
+
         self.send_pending_proposals()?;
         Ok(())
     }
@@ -267,9 +780,84 @@ impl<O, M, T, W> Actor<O, M, T, W> {
             UpdateCfdProposal::RollOverProposal { .. } => {
                 anyhow::bail!("did not expect a rollover proposal");
             }
+            UpdateCfdProposal::PartialSettlement { .. } => {
+                anyhow::bail!("did not expect a partial settlement proposal");
+            }
+            UpdateCfdProposal::AddToPosition { .. } => {
+                anyhow::bail!("did not expect an add to position proposal");
+            }
         };
         Ok((proposal.clone(), *taker_id))
     }
+
+    /// A taker tells us which CFDs are its own after connecting
+    ///
+    /// A CFD is only assigned to the connection if the taker authenticated with the noise static
+    /// key the CFD was taken with. CFDs taken before we recorded that key are bound to the first
+    /// key announcing them.
+    async fn handle_cfds_announced(
+        &mut self,
+        taker_id: TakerId,
+        static_key: x25519_dalek::PublicKey,
+        order_ids: Vec<OrderId>,
+    ) -> Result<()> {
+        tracing::debug!(%taker_id, "Taker announced {} CFDs", order_ids.len());
+
+        let mut conn = self.db.acquire().await?;
+
+        for order_id in order_ids {
+            let known_key = match db::load_cfd_taker_static_key(order_id, &mut conn).await {
+                Ok(known_key) => known_key,
+                Err(e) => {
+                    tracing::warn!(%taker_id, %order_id, "Ignoring announced CFD: {:#}", e);
+                    continue;
+                }
+            };
+
+            if !may_claim_cfd(known_key, static_key) {
+                tracing::warn!(
+                    %taker_id,
+                    %order_id,
+                    "Ignoring announced CFD taken by a different taker"
+                );
+                continue;
+            }
+
+            if known_key.is_none() {
+                db::update_cfd_taker_static_key(order_id, &static_key, &mut conn).await?;
+            }
+
+            self.assign_cfd_to_taker(order_id, taker_id, &mut conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Routes everything concerning the CFD to the given connection of its taker
+    ///
+    /// Proposals of the CFD were exchanged with an earlier connection of the same taker, they are
+    /// moved over so answers reach the taker.
+    async fn assign_cfd_to_taker(
+        &mut self,
+        order_id: OrderId,
+        taker_id: TakerId,
+        conn: &mut PoolConnection<Any>,
+    ) -> Result<()> {
+        self.cfd_takers.insert(order_id, taker_id);
+
+        if let Some((proposal, proposed_to)) = self.current_pending_proposals.get_mut(&order_id) {
+            if *proposed_to != taker_id {
+                db::insert_proposal(proposal, Some(taker_id), conn).await?;
+                *proposed_to = taker_id;
+            }
+        }
+        if let Some((_, agreed_with)) = self.current_agreed_proposals.get_mut(&order_id) {
+            *agreed_with = taker_id;
+        }
+
+        Ok(())
+    }
 }
 
 impl<O, M, T, W> Actor<O, M, T, W>
@@ -318,9 +906,23 @@ impl<O, M, T, W> Actor<O, M, T, W>
 where
     T: xtra::Handler<maker_inc_connections::TakerMessage>,
 {
-    async fn handle_new_taker_online(&mut self, taker_id: TakerId) -> Result<()> {
+    /// Sends the current order to a newly connected taker and assigns its CFDs to the connection
+    ///
+    /// The CFDs are recognised by the noise static key they were taken with, so they are
+    /// assigned on every reconnect. CFDs without a recorded key are assigned once the taker
+    /// announces them.
+    async fn handle_new_taker_online(
+        &mut self,
+        taker_id: TakerId,
+        static_key: x25519_dalek::PublicKey,
+    ) -> Result<()> {
         let mut conn = self.db.acquire().await?;
 
+        for order_id in db::load_order_ids_by_taker_static_key(&static_key, &mut conn).await? {
+            self.assign_cfd_to_taker(order_id, taker_id, &mut conn)
+                .await?;
+        }
+
         let current_order = match self.current_order_id {
             Some(current_order_id) => Some(load_order_by_id(current_order_id, &mut conn).await?),
             None => None,
@@ -353,6 +955,7 @@ where
         self.current_agreed_proposals
             .insert(order_id, self.get_settlement_proposal(order_id)?);
         self.remove_pending_proposal(&order_id)
+            .await
             .context("accepted settlement")?;
         Ok(())
     }
@@ -365,11 +968,15 @@ where
         self.takers
             .do_send_async(maker_inc_connections::TakerMessage {
                 taker_id,
-                command: TakerCommand::NotifySettlementRejected { id: order_id },
+                command: TakerCommand::NotifySettlementRejected {
+                    id: order_id,
+                    reason: SettlementRejectionReason::Declined,
+                },
             })
             .await?;
 
         self.remove_pending_proposal(&order_id)
+            .await
             .context("rejected settlement")?;
         Ok(())
     }
@@ -391,15 +998,312 @@ where
             }
         };
 
-        self.takers
-            .do_send_async(maker_inc_connections::TakerMessage {
-                taker_id,
-                command: TakerCommand::NotifyRollOverRejected { id: order_id },
-            })
-            .await?;
+        self.takers
+            .do_send_async(maker_inc_connections::TakerMessage {
+                taker_id,
+                command: TakerCommand::NotifyRollOverRejected { id: order_id },
+            })
+            .await?;
+
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("rejected roll_over")?;
+        Ok(())
+    }
+
+    async fn handle_reject_partial_settlement(&mut self, order_id: OrderId) -> Result<()> {
+        tracing::debug!(%order_id, "Maker rejects a partial settlement proposal" );
+
+        let taker_id = match self.current_pending_proposals.get(&order_id) {
+            Some((
+                UpdateCfdProposal::PartialSettlement {
+                    direction: SettlementKind::Incoming,
+                    ..
+                },
+                taker_id,
+            )) => *taker_id,
+            _ => {
+                anyhow::bail!("Order is in invalid state. Ignoring reject partial settlement.")
+            }
+        };
+
+        self.takers
+            .do_send_async(maker_inc_connections::TakerMessage {
+                taker_id,
+                command: TakerCommand::NotifyPartialSettlementRejected { id: order_id },
+            })
+            .await?;
+
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("rejected partial settlement")?;
+        Ok(())
+    }
+
+    async fn handle_reject_add_to_position(&mut self, order_id: OrderId) -> Result<()> {
+        tracing::debug!(%order_id, "Maker rejects adding to the position");
+
+        let taker_id = match self.current_pending_proposals.get(&order_id) {
+            Some((
+                UpdateCfdProposal::AddToPosition {
+                    direction: SettlementKind::Incoming,
+                    ..
+                },
+                taker_id,
+            )) => *taker_id,
+            _ => {
+                anyhow::bail!("Order is in invalid state. Ignoring reject add to position.")
+            }
+        };
+
+        self.takers
+            .do_send_async(maker_inc_connections::TakerMessage {
+                taker_id,
+                command: TakerCommand::NotifyAddToPositionRejected { id: order_id },
+            })
+            .await?;
+
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("rejected add to position")?;
+        Ok(())
+    }
+
+    async fn handle_counter_settlement(&mut self, order_id: OrderId, price: Price) -> Result<()> {
+        tracing::debug!(%order_id, %price, "Maker counters a settlement proposal");
+
+        let taker_id = match self.current_pending_proposals.get(&order_id) {
+            Some((
+                UpdateCfdProposal::Settlement {
+                    direction: SettlementKind::Incoming,
+                    ..
+                },
+                taker_id,
+            )) => *taker_id,
+            _ => {
+                anyhow::bail!("Order is in invalid state. Ignoring counter settlement.")
+            }
+        };
+
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        let proposal = cfd.calculate_settlement(price)?;
+
+        self.takers
+            .do_send_async(maker_inc_connections::TakerMessage {
+                taker_id,
+                command: TakerCommand::NotifySettlementCountered {
+                    proposal: proposal.clone(),
+                },
+            })
+            .await?;
+
+        self.add_pending_proposal(
+            UpdateCfdProposal::Settlement {
+                proposal,
+                direction: SettlementKind::Outgoing,
+            },
+            taker_id,
+        )
+        .await
+        .context("countered settlement")?;
+        Ok(())
+    }
+
+    async fn handle_counter_roll_over(
+        &mut self,
+        order_id: OrderId,
+        funding_rate: FundingRate,
+    ) -> Result<()> {
+        tracing::debug!(%order_id, %funding_rate, "Maker counters a roll_over proposal");
+
+        let taker_id = match self.current_pending_proposals.get(&order_id) {
+            Some((
+                UpdateCfdProposal::RollOverProposal {
+                    direction: SettlementKind::Incoming,
+                    ..
+                },
+                taker_id,
+            )) => *taker_id,
+            _ => {
+                anyhow::bail!("Order is in invalid state. Ignoring counter roll over.")
+            }
+        };
+
+        // Fail early if the position cannot pay for the funding
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        cfd.with_funding_fee(cfd.funding_fee(funding_rate)?)?;
+
+        let proposal = RollOverProposal {
+            order_id,
+            timestamp: Timestamp::now()?,
+            funding_rate: Some(funding_rate),
+        };
+
+        self.takers
+            .do_send_async(maker_inc_connections::TakerMessage {
+                taker_id,
+                command: TakerCommand::NotifyRollOverCountered {
+                    id: order_id,
+                    timestamp: proposal.timestamp,
+                    funding_rate,
+                },
+            })
+            .await?;
+
+        self.add_pending_proposal(
+            UpdateCfdProposal::RollOverProposal {
+                proposal,
+                direction: SettlementKind::Outgoing,
+            },
+            taker_id,
+        )
+        .await
+        .context("countered roll_over")?;
+        Ok(())
+    }
+
+    async fn handle_settle(&mut self, order_id: OrderId, current_price: Price) -> Result<()> {
+        tracing::debug!(%order_id, %current_price, "Maker proposes to settle");
+
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        if !matches!(cfd.state, CfdState::Open { .. }) {
+            anyhow::bail!("Order is in invalid state. Cannot propose settlement.")
+        }
+
+        if self.current_pending_proposals.contains_key(&order_id) {
+            anyhow::bail!("An update for order id {} is already in progress", order_id)
+        }
+
+        let taker_id = *self
+            .cfd_takers
+            .get(&order_id)
+            .context("The taker of the CFD has not connected since we started")?;
+
+        let proposal = cfd.calculate_settlement(current_price)?;
+
+        self.takers
+            .send(maker_inc_connections::TakerMessage {
+                taker_id,
+                command: TakerCommand::ProposeSettlement {
+                    proposal: proposal.clone(),
+                },
+            })
+            .await?
+            .context("The taker of the CFD is not connected")?;
+
+        self.add_pending_proposal(
+            UpdateCfdProposal::Settlement {
+                proposal,
+                direction: SettlementKind::Outgoing,
+            },
+            taker_id,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// The taker agreed to our proposal or counter-proposal, which is then accepted like a
+    /// proposal of the taker with these terms
+    async fn handle_proposal_accepted_by_taker(
+        &mut self,
+        taker_id: TakerId,
+        order_id: OrderId,
+        ctx: &mut Context<Self>,
+    ) -> Result<()>
+    where
+        Self: xtra::Handler<CfdAction>,
+    {
+        tracing::info!(%order_id, "Taker accepted our proposal");
+
+        let (accept, proposal) =
+            accept_proposal_of_ours(&self.current_pending_proposals, order_id, taker_id)?;
+
+        self.current_pending_proposals
+            .insert(order_id, (proposal, taker_id));
+
+        ctx.address()
+            .expect("actor to be able to give address to itself")
+            .do_send_async(accept)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn handle_proposal_rejected_by_taker(
+        &mut self,
+        taker_id: TakerId,
+        order_id: OrderId,
+    ) -> Result<()> {
+        tracing::info!(%order_id, "Taker rejected our proposal");
+
+        match self.current_pending_proposals.get(&order_id) {
+            Some((UpdateCfdProposal::Settlement { direction, .. }, proposed_to))
+            | Some((UpdateCfdProposal::RollOverProposal { direction, .. }, proposed_to))
+                if direction == &SettlementKind::Outgoing && *proposed_to == taker_id => {}
+            _ => anyhow::bail!("No proposal of ours pending for order id {}", order_id),
+        }
+
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("rejected proposal")?;
+        Ok(())
+    }
+
+    async fn handle_archive_finished_cfds(&mut self, archive_after: Duration) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        cfd_actors::archive_finished_cfds(archive_after, &mut conn, &self.cfd_feed_actor_inbox)
+            .await
+    }
+
+    /// Drops all proposals that were not answered in time
+    ///
+    /// The taker is told that its proposals were rejected so it does not wait for an answer that
+    /// is never going to come.
+    async fn handle_expire_proposals(&mut self) -> Result<()> {
+        let mut expired = Vec::new();
+        for (order_id, (proposal, taker_id)) in self.current_pending_proposals.iter() {
+            if proposal.is_expired()? {
+                expired.push((*order_id, proposal.clone(), *taker_id));
+            }
+        }
+
+        for (order_id, proposal, taker_id) in expired {
+            tracing::info!(%order_id, "Proposal expired");
+
+            if proposal.direction() == &SettlementKind::Incoming {
+                let command = match proposal {
+                    UpdateCfdProposal::Settlement { .. } => {
+                        TakerCommand::NotifySettlementRejected {
+                            id: order_id,
+                            reason: SettlementRejectionReason::Expired,
+                        }
+                    }
+                    UpdateCfdProposal::RollOverProposal { .. } => {
+                        TakerCommand::NotifyRollOverRejected { id: order_id }
+                    }
+                    UpdateCfdProposal::PartialSettlement { .. } => {
+                        TakerCommand::NotifyPartialSettlementRejected { id: order_id }
+                    }
+                    UpdateCfdProposal::AddToPosition { .. } => {
+                        TakerCommand::NotifyAddToPositionRejected { id: order_id }
+                    }
+                };
+
+                if let Err(e) = self
+                    .takers
+                    .do_send_async(maker_inc_connections::TakerMessage { taker_id, command })
+                    .await
+                {
+                    tracing::warn!(%order_id, "Failed to notify taker about expiry: {:#}", e);
+                }
+            }
+
+            self.remove_pending_proposal(&order_id).await?;
+        }
 
-        self.remove_pending_proposal(&order_id)
-            .context("rejected roll_over")?;
         Ok(())
     }
 }
@@ -412,6 +1316,7 @@ where
     async fn handle_take_order(
         &mut self,
         taker_id: TakerId,
+        static_key: x25519_dalek::PublicKey,
         order_id: OrderId,
         quantity: Usd,
     ) -> Result<()> {
@@ -454,6 +1359,8 @@ where
             },
         );
         insert_cfd(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
+        db::update_cfd_taker_static_key(order_id, &static_key, &mut conn).await?;
+        self.cfd_takers.insert(order_id, taker_id);
 
         // 3. check if order has acceptable amounts
         if quantity < current_order.min_quantity || quantity > current_order.max_quantity {
@@ -509,11 +1416,15 @@ where
         &mut self,
         taker_id: TakerId,
         mut cfd: Cfd,
-        mut conn: PoolConnection<Sqlite>,
+        mut conn: PoolConnection<Any>,
     ) -> Result<()> {
-        cfd.state = CfdState::rejected();
-
-        append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
+        apply_event(
+            &mut cfd,
+            CfdEvent::OrderRejected,
+            &mut conn,
+            &self.cfd_feed_actor_inbox,
+        )
+        .await?;
 
         self.takers
             .do_send_async(maker_inc_connections::TakerMessage {
@@ -585,8 +1496,13 @@ where
             .await??;
 
         // 4. Insert that we are in contract setup and refresh our own feed
-        cfd.state = CfdState::contract_setup();
-        append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
+        apply_event(
+            &mut cfd,
+            CfdEvent::ContractSetupStarted,
+            &mut conn,
+            &self.cfd_feed_actor_inbox,
+        )
+        .await?;
 
         // 5. Spawn away the contract setup
         let (sender, receiver) = mpsc::unbounded();
@@ -634,6 +1550,8 @@ where
         price: Price,
         min_quantity: Usd,
         max_quantity: Usd,
+        contract_type: ContractType,
+        payout_resolution: PayoutResolution,
     ) -> Result<()> {
         let oracle_event_id = oracle::next_announcement_after(
             time::OffsetDateTime::now_utc() + self.settlement_time_interval_hours,
@@ -643,6 +1561,9 @@ where
             price,
             min_quantity,
             max_quantity,
+            contract_type,
+            payout_resolution,
+            self.funding_rate,
             Origin::Ours,
             oracle_event_id,
             self.settlement_time_interval_hours,
@@ -685,24 +1606,307 @@ where
         let dlc = match dlc {
             Ok(dlc) => dlc,
             Err(e) => {
-                cfd.state = CfdState::SetupFailed {
-                    common: CfdStateCommon::default(),
+                let event = CfdEvent::ContractSetupFailed {
                     info: e.to_string(),
                 };
-
-                append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
+                apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
 
                 return Err(e);
             }
         };
 
-        cfd.state = CfdState::PendingOpen {
-            common: CfdStateCommon::default(),
+        let event = CfdEvent::ContractSetupCompleted { dlc: dlc.clone() };
+        apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
+
+        let txid = self
+            .wallet
+            .send(wallet::TryBroadcastTransaction {
+                tx: dlc.lock.0.clone(),
+            })
+            .await??;
+
+        tracing::info!("Lock transaction published with txid {}", txid);
+
+        self.monitor_actor
+            .do_send_async(monitor::StartMonitoring {
+                id: order_id,
+                params: MonitorParams::new(
+                    dlc,
+                    cfd.refund_timelock_in_blocks(),
+                    cfd.order.oracle_event_id,
+                ),
+            })
+            .await?;
+
+        self.oracle_actor
+            .do_send_async(oracle::MonitorAttestation {
+                event_id: cfd.order.oracle_event_id,
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl<O, M, T, W> Actor<O, M, T, W>
+where
+    Self: xtra::Handler<CfdRollOverCompleted>,
+    O: xtra::Handler<oracle::MonitorAttestation> + xtra::Handler<oracle::GetAnnouncement>,
+    T: xtra::Handler<maker_inc_connections::TakerMessage>,
+{
+    async fn handle_accept_roll_over(
+        &mut self,
+        order_id: OrderId,
+        ctx: &mut Context<Self>,
+    ) -> Result<()> {
+        tracing::debug!(%order_id, "Maker accepts a roll_over proposal" );
+
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+
+        // Validate if order is actually being requested to be extended
+        let (proposal, taker_id) = match self.current_pending_proposals.get(&order_id) {
+            Some((
+                UpdateCfdProposal::RollOverProposal {
+                    proposal,
+                    direction: SettlementKind::Incoming,
+                },
+                taker_id,
+            )) => (proposal, *taker_id),
+            _ => {
+                anyhow::bail!("Order is in invalid state. Ignoring trying to accept the roll over request it.")
+            }
+        };
+
+        let dlc = cfd.open_dlc().context("CFD was in wrong state")?;
+
+        // A counter-proposal the taker agreed to fixes the funding rate, otherwise we charge the
+        // rate quoted in the order, the taker does not roll over at any other rate
+        let funding_rate = proposal.funding_rate.unwrap_or(cfd.order.funding_rate);
+        let funding_fee = cfd.funding_fee(funding_rate)?;
+        cfd.with_funding_fee(funding_fee)?;
+
+        let oracle_event_id = oracle::next_announcement_after(
+            time::OffsetDateTime::now_utc() + cfd.order.settlement_time_interval_hours,
+        )?;
+        let announcement = self
+            .oracle_actor
+            .send(oracle::GetAnnouncement(oracle_event_id))
+            .await?
+            .with_context(|| format!("Announcement {} not found", oracle_event_id))?;
+
+        self.takers
+            .send(maker_inc_connections::TakerMessage {
+                taker_id,
+                command: TakerCommand::NotifyRollOverAccepted {
+                    id: proposal.order_id,
+                    oracle_event_id,
+                    funding_rate,
+                },
+            })
+            .await??;
+
+        self.oracle_actor
+            .do_send_async(oracle::MonitorAttestation {
+                event_id: announcement.id,
+            })
+            .await?;
+
+        let (sender, receiver) = mpsc::unbounded();
+        let contract_future = setup_contract::roll_over(
+            self.takers.clone().into_sink().with(move |msg| {
+                future::ok(maker_inc_connections::TakerMessage {
+                    taker_id,
+                    command: TakerCommand::RollOverProtocol(msg),
+                })
+            }),
+            receiver,
+            (self.oracle_pk, announcement),
+            cfd,
+            funding_fee,
+            Role::Maker,
+            dlc,
+        );
+
+        let this = ctx
+            .address()
+            .expect("actor to be able to give address to itself");
+
+        self.roll_over_state = RollOverState::Active {
+            sender,
+            taker: taker_id,
+        };
+
+        tokio::spawn(async move {
+            let dlc = contract_future.await;
+
+            this.do_send_async(CfdRollOverCompleted {
+                order_id,
+                funding_fee,
+                dlc,
+            })
+            .await
+        });
+
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("accepted roll_over")?;
+        Ok(())
+    }
+}
+
+impl<O, M, T, W> Actor<O, M, T, W>
+where
+    M: xtra::Handler<monitor::StartMonitoring>,
+{
+    async fn handle_cfd_roll_over_completed(
+        &mut self,
+        order_id: OrderId,
+        funding_fee: SignedAmount,
+        dlc: Result<Dlc>,
+    ) -> Result<()> {
+        let dlc = dlc.context("Failed to roll over contract with taker")?;
+        self.roll_over_state = RollOverState::None;
+
+        let mut conn = self.db.acquire().await?;
+        let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        let event = CfdEvent::RollOverCompleted {
+            dlc: dlc.clone(),
+            funding_fee,
+        };
+        apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
+
+        self.monitor_actor
+            .do_send_async(monitor::StartMonitoring {
+                id: order_id,
+                params: MonitorParams::new(
+                    dlc,
+                    cfd.refund_timelock_in_blocks(),
+                    cfd.order.oracle_event_id,
+                ),
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl<O, M, T, W> Actor<O, M, T, W>
+where
+    Self: xtra::Handler<CfdPartialSettlementCompleted>,
+    O: xtra::Handler<oracle::GetAnnouncement>,
+    T: xtra::Handler<maker_inc_connections::TakerMessage>,
+{
+    async fn handle_accept_partial_settlement(
+        &mut self,
+        order_id: OrderId,
+        ctx: &mut Context<Self>,
+    ) -> Result<()> {
+        tracing::debug!(%order_id, "Maker accepts a partial settlement proposal" );
+
+        if let PartialSettlementState::Active { .. } = self.partial_settlement_state {
+            anyhow::bail!("Already settling a contract partially!")
+        }
+
+        let (proposal, taker_id) = match self.current_pending_proposals.get(&order_id) {
+            Some((
+                UpdateCfdProposal::PartialSettlement {
+                    proposal,
+                    direction: SettlementKind::Incoming,
+                },
+                taker_id,
+            )) => (proposal.clone(), *taker_id),
+            _ => {
+                anyhow::bail!(
+                    "Order is in invalid state. Ignoring trying to accept the partial settlement."
+                )
+            }
+        };
+
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        let dlc = cfd.open_dlc().context("CFD was in wrong state")?;
+
+        // The remaining position keeps the oracle event of the current contract
+        let oracle_event_id = *dlc.cets.keys().next().context("Contract has no CETs")?;
+        let announcement = self
+            .oracle_actor
+            .send(oracle::GetAnnouncement(oracle_event_id))
+            .await?
+            .with_context(|| format!("Announcement {} not found", oracle_event_id))?;
+
+        self.takers
+            .send(maker_inc_connections::TakerMessage {
+                taker_id,
+                command: TakerCommand::NotifyPartialSettlementAccepted { id: order_id },
+            })
+            .await??;
+
+        let (sender, receiver) = mpsc::unbounded();
+        let contract_future = setup_contract::partial_settlement(
+            self.takers.clone().into_sink().with(move |msg| {
+                future::ok(maker_inc_connections::TakerMessage {
+                    taker_id,
+                    command: TakerCommand::PartialSettlementProtocol(msg),
+                })
+            }),
+            receiver,
+            (self.oracle_pk, announcement),
+            cfd,
+            proposal.clone(),
+            Role::Maker,
+            dlc,
+        );
+
+        let this = ctx
+            .address()
+            .expect("actor to be able to give address to itself");
+
+        self.partial_settlement_state = PartialSettlementState::Active {
+            sender,
+            taker: taker_id,
+        };
+
+        tokio::spawn(async move {
+            let dlc = contract_future.await;
+
+            this.do_send_async(CfdPartialSettlementCompleted {
+                order_id,
+                proposal,
+                dlc,
+            })
+            .await
+        });
+
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("accepted partial settlement")?;
+        Ok(())
+    }
+}
+
+impl<O, M, T, W> Actor<O, M, T, W>
+where
+    M: xtra::Handler<monitor::StartMonitoring>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
+{
+    async fn handle_cfd_partial_settlement_completed(
+        &mut self,
+        order_id: OrderId,
+        proposal: PartialSettlementProposal,
+        dlc: Result<Dlc>,
+    ) -> Result<()> {
+        self.partial_settlement_state = PartialSettlementState::None;
+        let dlc = dlc.context("Failed to settle contract partially with taker")?;
+
+        let mut conn = self.db.acquire().await?;
+        let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        let event = CfdEvent::PartialSettlementCompleted {
             dlc: dlc.clone(),
-            attestation: None,
+            quantity: cfd.quantity_usd - proposal.quantity,
+            price: proposal.price,
         };
-
-        append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
+        apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
 
         let txid = self
             .wallet
@@ -711,7 +1915,10 @@ where
             })
             .await??;
 
-        tracing::info!("Lock transaction published with txid {}", txid);
+        tracing::info!(
+            "Partial settlement transaction published with txid {}",
+            txid
+        );
 
         self.monitor_actor
             .do_send_async(monitor::StartMonitoring {
@@ -724,51 +1931,49 @@ where
             })
             .await?;
 
-        self.oracle_actor
-            .do_send_async(oracle::MonitorAttestation {
-                event_id: cfd.order.oracle_event_id,
-            })
-            .await?;
-
         Ok(())
     }
 }
 
 impl<O, M, T, W> Actor<O, M, T, W>
 where
-    Self: xtra::Handler<CfdRollOverCompleted>,
-    O: xtra::Handler<oracle::MonitorAttestation> + xtra::Handler<oracle::GetAnnouncement>,
+    Self: xtra::Handler<CfdAddToPositionCompleted>,
+    O: xtra::Handler<oracle::GetAnnouncement>,
     T: xtra::Handler<maker_inc_connections::TakerMessage>,
+    W: xtra::Handler<wallet::Sign> + xtra::Handler<wallet::BuildPartyParams>,
 {
-    async fn handle_accept_roll_over(
+    async fn handle_accept_add_to_position(
         &mut self,
         order_id: OrderId,
         ctx: &mut Context<Self>,
     ) -> Result<()> {
-        tracing::debug!(%order_id, "Maker accepts a roll_over proposal" );
+        tracing::debug!(%order_id, "Maker accepts adding to the position");
 
-        let mut conn = self.db.acquire().await?;
-        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        if let AddToPositionState::Active { .. } = self.add_to_position_state {
+            anyhow::bail!("Already adding to a position!")
+        }
 
-        // Validate if order is actually being requested to be extended
         let (proposal, taker_id) = match self.current_pending_proposals.get(&order_id) {
             Some((
-                UpdateCfdProposal::RollOverProposal {
+                UpdateCfdProposal::AddToPosition {
                     proposal,
                     direction: SettlementKind::Incoming,
                 },
                 taker_id,
-            )) => (proposal, *taker_id),
+            )) => (proposal.clone(), *taker_id),
             _ => {
-                anyhow::bail!("Order is in invalid state. Ignoring trying to accept the roll over request it.")
+                anyhow::bail!(
+                    "Order is in invalid state. Ignoring trying to accept adding to the position."
+                )
             }
         };
 
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
         let dlc = cfd.open_dlc().context("CFD was in wrong state")?;
 
-        let oracle_event_id = oracle::next_announcement_after(
-            time::OffsetDateTime::now_utc() + cfd.order.settlement_time_interval_hours,
-        )?;
+        // The increased position keeps the oracle event of the current contract
+        let oracle_event_id = *dlc.cets.keys().next().context("Contract has no CETs")?;
         let announcement = self
             .oracle_actor
             .send(oracle::GetAnnouncement(oracle_event_id))
@@ -778,30 +1983,23 @@ where
         self.takers
             .send(maker_inc_connections::TakerMessage {
                 taker_id,
-                command: TakerCommand::NotifyRollOverAccepted {
-                    id: proposal.order_id,
-                    oracle_event_id,
-                },
+                command: TakerCommand::NotifyAddToPositionAccepted { id: order_id },
             })
             .await??;
 
-        self.oracle_actor
-            .do_send_async(oracle::MonitorAttestation {
-                event_id: announcement.id,
-            })
-            .await?;
-
         let (sender, receiver) = mpsc::unbounded();
-        let contract_future = setup_contract::roll_over(
+        let contract_future = setup_contract::add_to_position(
             self.takers.clone().into_sink().with(move |msg| {
                 future::ok(maker_inc_connections::TakerMessage {
                     taker_id,
-                    command: TakerCommand::RollOverProtocol(msg),
+                    command: TakerCommand::AddToPositionProtocol(msg),
                 })
             }),
             receiver,
             (self.oracle_pk, announcement),
             cfd,
+            proposal.clone(),
+            self.wallet.clone(),
             Role::Maker,
             dlc,
         );
@@ -810,7 +2008,7 @@ where
             .address()
             .expect("actor to be able to give address to itself");
 
-        self.roll_over_state = RollOverState::Active {
+        self.add_to_position_state = AddToPositionState::Active {
             sender,
             taker: taker_id,
         };
@@ -818,12 +2016,17 @@ where
         tokio::spawn(async move {
             let dlc = contract_future.await;
 
-            this.do_send_async(CfdRollOverCompleted { order_id, dlc })
-                .await
+            this.do_send_async(CfdAddToPositionCompleted {
+                order_id,
+                proposal,
+                dlc,
+            })
+            .await
         });
 
         self.remove_pending_proposal(&order_id)
-            .context("accepted roll_over")?;
+            .await
+            .context("accepted add to position")?;
         Ok(())
     }
 }
@@ -831,24 +2034,38 @@ where
 impl<O, M, T, W> Actor<O, M, T, W>
 where
     M: xtra::Handler<monitor::StartMonitoring>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
 {
-    async fn handle_cfd_roll_over_completed(
+    async fn handle_cfd_add_to_position_completed(
         &mut self,
         order_id: OrderId,
+        proposal: AddToPositionProposal,
         dlc: Result<Dlc>,
     ) -> Result<()> {
-        let dlc = dlc.context("Failed to roll over contract with taker")?;
-        self.roll_over_state = RollOverState::None;
+        self.add_to_position_state = AddToPositionState::None;
+        let dlc = dlc.context("Failed to add to the position with taker")?;
 
         let mut conn = self.db.acquire().await?;
         let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
-        cfd.state = CfdState::Open {
-            common: CfdStateCommon::default(),
+        let increased = cfd.with_added_position(proposal.quantity, proposal.price)?;
+        let event = CfdEvent::AddToPositionCompleted {
             dlc: dlc.clone(),
-            attestation: None,
-            collaborative_close: None,
+            quantity: increased.quantity_usd,
+            price: increased.order.price,
         };
-        append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
+        apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
+
+        let txid = self
+            .wallet
+            .send(wallet::TryBroadcastTransaction {
+                tx: dlc.lock.0.clone(),
+            })
+            .await??;
+
+        tracing::info!(
+            "Lock transaction of increased position published with txid {}",
+            txid
+        );
 
         self.monitor_actor
             .do_send_async(monitor::StartMonitoring {
@@ -903,10 +2120,10 @@ where
         let (tx, sig_maker) = dlc.close_transaction(proposal)?;
 
         let own_script_pubkey = dlc.script_pubkey_for(cfd.role());
-        cfd.handle(CfdStateChangeEvent::ProposalSigned(
+        let event = CfdEvent::StateChange(CfdStateChangeEvent::ProposalSigned(
             CollaborativeSettlement::new(tx.clone(), own_script_pubkey.clone(), proposal.price)?,
-        ))?;
-        append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
+        ));
+        apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
 
         let spend_tx = dlc.finalize_spend_transaction((tx, sig_maker), sig_taker)?;
 
@@ -937,7 +2154,10 @@ where
 #[async_trait]
 impl<O: 'static, M: 'static, T: 'static, W: 'static> Handler<CfdAction> for Actor<O, M, T, W>
 where
-    Self: xtra::Handler<CfdSetupCompleted> + xtra::Handler<CfdRollOverCompleted>,
+    Self: xtra::Handler<CfdSetupCompleted>
+        + xtra::Handler<CfdRollOverCompleted>
+        + xtra::Handler<CfdPartialSettlementCompleted>
+        + xtra::Handler<CfdAddToPositionCompleted>,
     O: xtra::Handler<oracle::MonitorAttestation> + xtra::Handler<oracle::GetAnnouncement>,
     T: xtra::Handler<maker_inc_connections::TakerMessage>
         + xtra::Handler<maker_inc_connections::BroadcastOrder>,
@@ -954,6 +2174,20 @@ where
             RejectSettlement { order_id } => self.handle_reject_settlement(order_id).await,
             AcceptRollOver { order_id } => self.handle_accept_roll_over(order_id, ctx).await,
             RejectRollOver { order_id } => self.handle_reject_roll_over(order_id).await,
+            ProposeSettlement {
+                order_id,
+                current_price,
+            } => self.handle_settle(order_id, current_price).await,
+            AcceptPartialSettlement { order_id } => {
+                self.handle_accept_partial_settlement(order_id, ctx).await
+            }
+            RejectPartialSettlement { order_id } => {
+                self.handle_reject_partial_settlement(order_id).await
+            }
+            AcceptAddToPosition { order_id } => {
+                self.handle_accept_add_to_position(order_id, ctx).await
+            }
+            RejectAddToPosition { order_id } => self.handle_reject_add_to_position(order_id).await,
             Commit { order_id } => self.handle_commit(order_id).await,
         } {
             anyhow::bail!("Message handler failed: {:#}", e);
@@ -968,7 +2202,36 @@ where
     T: xtra::Handler<maker_inc_connections::BroadcastOrder>,
 {
     async fn handle(&mut self, msg: NewOrder, _ctx: &mut Context<Self>) -> Result<()> {
-        self.handle_new_order(msg.price, msg.min_quantity, msg.max_quantity)
+        self.handle_new_order(
+            msg.price,
+            msg.min_quantity,
+            msg.max_quantity,
+            msg.contract_type,
+            msg.payout_resolution,
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, T: 'static, W: 'static> Handler<CounterSettlement>
+    for Actor<O, M, T, W>
+where
+    T: xtra::Handler<maker_inc_connections::TakerMessage>,
+{
+    async fn handle(&mut self, msg: CounterSettlement, _ctx: &mut Context<Self>) -> Result<()> {
+        self.handle_counter_settlement(msg.order_id, msg.price)
+            .await
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, T: 'static, W: 'static> Handler<CounterRollOver> for Actor<O, M, T, W>
+where
+    T: xtra::Handler<maker_inc_connections::TakerMessage>,
+{
+    async fn handle(&mut self, msg: CounterRollOver, _ctx: &mut Context<Self>) -> Result<()> {
+        self.handle_counter_roll_over(msg.order_id, msg.funding_rate)
             .await
     }
 }
@@ -979,7 +2242,26 @@ where
     T: xtra::Handler<maker_inc_connections::TakerMessage>,
 {
     async fn handle(&mut self, msg: NewTakerOnline, _ctx: &mut Context<Self>) {
-        log_error!(self.handle_new_taker_online(msg.id));
+        log_error!(self.handle_new_taker_online(msg.id, msg.static_key));
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, T: 'static, W: 'static> Handler<ExpireProposals> for Actor<O, M, T, W>
+where
+    T: xtra::Handler<maker_inc_connections::TakerMessage>,
+{
+    async fn handle(&mut self, _msg: ExpireProposals, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_expire_proposals());
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, T: 'static, W: 'static> Handler<ArchiveFinishedCfds>
+    for Actor<O, M, T, W>
+{
+    async fn handle(&mut self, msg: ArchiveFinishedCfds, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_archive_finished_cfds(msg.archive_after));
     }
 }
 
@@ -1003,7 +2285,35 @@ where
     M: xtra::Handler<monitor::StartMonitoring>,
 {
     async fn handle(&mut self, msg: CfdRollOverCompleted, _ctx: &mut Context<Self>) {
-        log_error!(self.handle_cfd_roll_over_completed(msg.order_id, msg.dlc));
+        log_error!(self.handle_cfd_roll_over_completed(msg.order_id, msg.funding_fee, msg.dlc));
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, T: 'static, W: 'static> Handler<CfdPartialSettlementCompleted>
+    for Actor<O, M, T, W>
+where
+    M: xtra::Handler<monitor::StartMonitoring>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
+{
+    async fn handle(&mut self, msg: CfdPartialSettlementCompleted, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_cfd_partial_settlement_completed(
+            msg.order_id,
+            msg.proposal,
+            msg.dlc
+        ));
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, T: 'static, W: 'static> Handler<CfdAddToPositionCompleted>
+    for Actor<O, M, T, W>
+where
+    M: xtra::Handler<monitor::StartMonitoring>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
+{
+    async fn handle(&mut self, msg: CfdAddToPositionCompleted, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_cfd_add_to_position_completed(msg.order_id, msg.proposal, msg.dlc));
     }
 }
 
@@ -1020,15 +2330,22 @@ where
 #[async_trait]
 impl<O: 'static, M: 'static, T: 'static, W: 'static> Handler<FromTaker> for Actor<O, M, T, W>
 where
+    Self: xtra::Handler<CfdAction>,
     T: xtra::Handler<maker_inc_connections::BroadcastOrder>
         + xtra::Handler<maker_inc_connections::TakerMessage>,
     M: xtra::Handler<monitor::CollaborativeSettlement>,
     W: xtra::Handler<wallet::TryBroadcastTransaction>,
 {
-    async fn handle(&mut self, FromTaker { taker_id, msg }: FromTaker, _ctx: &mut Context<Self>) {
+    async fn handle(&mut self, msg: FromTaker, ctx: &mut Context<Self>) {
+        let FromTaker {
+            taker_id,
+            static_key,
+            msg,
+        } = msg;
+
         match msg {
             wire::TakerToMaker::TakeOrder { order_id, quantity } => {
-                log_error!(self.handle_take_order(taker_id, order_id, quantity))
+                log_error!(self.handle_take_order(taker_id, static_key, order_id, quantity))
             }
             wire::TakerToMaker::ProposeSettlement {
                 order_id,
@@ -1065,14 +2382,69 @@ where
                     RollOverProposal {
                         order_id,
                         timestamp,
+                        funding_rate: None,
                     },
                     taker_id,
-                ))
+                ));
+                log_error!(self.handle_auto_accept_roll_over(order_id, ctx))
             }
 
             wire::TakerToMaker::RollOverProtocol(msg) => {
                 log_error!(self.handle_inc_roll_over_protocol_msg(taker_id, msg))
             }
+            wire::TakerToMaker::ProposePartialSettlement {
+                order_id,
+                timestamp,
+                quantity,
+                taker,
+                maker,
+                price,
+            } => {
+                log_error!(self.handle_propose_partial_settlement(
+                    taker_id,
+                    PartialSettlementProposal {
+                        order_id,
+                        timestamp,
+                        quantity,
+                        taker,
+                        maker,
+                        price
+                    }
+                ))
+            }
+            wire::TakerToMaker::PartialSettlementProtocol(msg) => {
+                log_error!(self.handle_inc_partial_settlement_protocol_msg(taker_id, msg))
+            }
+            wire::TakerToMaker::ProposeAddToPosition {
+                order_id,
+                timestamp,
+                quantity,
+                price,
+            } => {
+                log_error!(self.handle_propose_add_to_position(
+                    taker_id,
+                    AddToPositionProposal {
+                        order_id,
+                        timestamp,
+                        quantity,
+                        price
+                    }
+                ))
+            }
+            wire::TakerToMaker::AddToPositionProtocol(msg) => {
+                log_error!(self.handle_inc_add_to_position_protocol_msg(taker_id, msg))
+            }
+            wire::TakerToMaker::AcceptSettlement { order_id }
+            | wire::TakerToMaker::AcceptCounterProposal { order_id } => {
+                log_error!(self.handle_proposal_accepted_by_taker(taker_id, order_id, ctx))
+            }
+            wire::TakerToMaker::RejectSettlement { order_id }
+            | wire::TakerToMaker::RejectCounterProposal { order_id } => {
+                log_error!(self.handle_proposal_rejected_by_taker(taker_id, order_id))
+            }
+            wire::TakerToMaker::AnnounceCfds { order_ids } => {
+                log_error!(self.handle_cfds_announced(taker_id, static_key, order_ids))
+            }
         }
     }
 }
@@ -1092,6 +2464,14 @@ impl Message for NewOrder {
     type Result = Result<()>;
 }
 
+impl Message for CounterSettlement {
+    type Result = Result<()>;
+}
+
+impl Message for CounterRollOver {
+    type Result = Result<()>;
+}
+
 impl Message for NewTakerOnline {
     type Result = ();
 }
@@ -1104,12 +2484,315 @@ impl Message for CfdRollOverCompleted {
     type Result = ();
 }
 
+impl Message for CfdPartialSettlementCompleted {
+    type Result = ();
+}
+
+impl Message for CfdAddToPositionCompleted {
+    type Result = ();
+}
+
 impl Message for CfdAction {
     type Result = Result<()>;
 }
 
+impl Message for ExpireProposals {
+    type Result = ();
+}
+
+impl Message for ArchiveFinishedCfds {
+    type Result = ();
+}
+
 impl Message for FromTaker {
     type Result = ();
 }
 
+/// Whether a taker authenticated with `static_key` may claim a CFD taken with `taker_static_key`
+///
+/// CFDs taken before we recorded the key of the taker go to the first taker claiming them.
+fn may_claim_cfd(
+    taker_static_key: Option<x25519_dalek::PublicKey>,
+    static_key: x25519_dalek::PublicKey,
+) -> bool {
+    taker_static_key.map_or(true, |taker_static_key| taker_static_key == static_key)
+}
+
+/// The action accepting our proposal for `order_id` once `taker_id` agreed to it, together with
+/// the proposal as if the taker made it
+fn accept_proposal_of_ours(
+    pending_proposals: &HashMap<OrderId, (UpdateCfdProposal, TakerId)>,
+    order_id: OrderId,
+    taker_id: TakerId,
+) -> Result<(CfdAction, UpdateCfdProposal)> {
+    let accept = match pending_proposals.get(&order_id) {
+        Some((_, proposed_to)) if *proposed_to != taker_id => {
+            anyhow::bail!(
+                "Taker {} accepted our proposal for order id {} that was made to taker {}",
+                taker_id,
+                order_id,
+                proposed_to
+            )
+        }
+        Some((
+            UpdateCfdProposal::Settlement {
+                proposal,
+                direction: SettlementKind::Outgoing,
+            },
+            _,
+        )) => (
+            CfdAction::AcceptSettlement { order_id },
+            UpdateCfdProposal::Settlement {
+                proposal: proposal.clone(),
+                direction: SettlementKind::Incoming,
+            },
+        ),
+        Some((
+            UpdateCfdProposal::RollOverProposal {
+                proposal,
+                direction: SettlementKind::Outgoing,
+            },
+            _,
+        )) => (
+            CfdAction::AcceptRollOver { order_id },
+            UpdateCfdProposal::RollOverProposal {
+                proposal: proposal.clone(),
+                direction: SettlementKind::Incoming,
+            },
+        ),
+        _ => anyhow::bail!("No proposal of ours pending for order id {}", order_id),
+    };
+
+    Ok(accept)
+}
+
 impl<O: 'static, M: 'static, T: 'static, W: 'static> xtra::Actor for Actor<O, M, T, W> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::Amount;
+    use rust_decimal_macros::dec;
+
+    fn dummy_cfd() -> Cfd {
+        let order = Order {
+            origin: Origin::Ours,
+            ..Order::dummy().with_price(Price::new(dec!(50_000)).unwrap())
+        };
+
+        Cfd::new(
+            order,
+            Usd::new(dec!(10_000)),
+            CfdState::outgoing_order_request(),
+        )
+    }
+
+    #[test]
+    fn settlement_policy_rejects_stale_and_deviating_proposals() {
+        let policy = SettlementPolicy::default();
+        let cfd = dummy_cfd();
+        let now = Timestamp::new(1_000_000);
+        let current_price = Price::new(dec!(50_000)).unwrap();
+        let proposal = |timestamp, price| SettlementProposal {
+            timestamp: Timestamp::new(timestamp),
+            ..cfd
+                .calculate_settlement(Price::new(price).unwrap())
+                .unwrap()
+        };
+
+        assert_eq!(
+            policy.check(&cfd, &proposal(999_900, dec!(50_400)), current_price, now),
+            None
+        );
+        assert_eq!(
+            policy.check(&cfd, &proposal(999_000, dec!(50_000)), current_price, now),
+            Some(SettlementRejectionReason::Stale {
+                timestamp: Timestamp::new(999_000)
+            })
+        );
+        assert_eq!(
+            policy.check(&cfd, &proposal(1_000_100, dec!(50_000)), current_price, now),
+            Some(SettlementRejectionReason::FromTheFuture {
+                timestamp: Timestamp::new(1_000_100)
+            })
+        );
+        assert_eq!(
+            policy.check(&cfd, &proposal(999_900, dec!(49_000)), current_price, now),
+            Some(SettlementRejectionReason::PriceDeviation {
+                proposed: Price::new(dec!(49_000)).unwrap(),
+                current: current_price
+            })
+        );
+    }
+
+    #[test]
+    fn settlement_policy_rejects_payouts_off_the_curve() {
+        let policy = SettlementPolicy::default();
+        let cfd = dummy_cfd();
+        let now = Timestamp::new(1_000_000);
+        let price = Price::new(dec!(50_000)).unwrap();
+
+        let settlement = SettlementProposal {
+            timestamp: now,
+            ..cfd.calculate_settlement(price).unwrap()
+        };
+        let overpaid = SettlementProposal {
+            taker: settlement.taker + Amount::from_sat(1),
+            maker: settlement.maker - Amount::from_sat(1),
+            ..settlement.clone()
+        };
+        assert_eq!(policy.check(&cfd, &settlement, price, now), None);
+        assert_eq!(
+            policy.check(&cfd, &overpaid, price, now),
+            Some(SettlementRejectionReason::InvalidPayout)
+        );
+
+        let partial = PartialSettlementProposal {
+            timestamp: now,
+            ..cfd
+                .calculate_partial_settlement(Usd::new(dec!(1_000)), price)
+                .unwrap()
+        };
+        let overpaid = PartialSettlementProposal {
+            taker: partial.taker + Amount::from_sat(1),
+            ..partial.clone()
+        };
+        let stale = PartialSettlementProposal {
+            timestamp: Timestamp::new(999_000),
+            ..partial.clone()
+        };
+        let everything = PartialSettlementProposal {
+            quantity: cfd.quantity_usd,
+            ..partial.clone()
+        };
+        assert_eq!(policy.check_partial(&cfd, &partial, price, now), None);
+        assert_eq!(
+            policy.check_partial(&cfd, &overpaid, price, now),
+            Some(SettlementRejectionReason::InvalidPayout)
+        );
+        assert_eq!(
+            policy.check_partial(&cfd, &stale, price, now),
+            Some(SettlementRejectionReason::Stale {
+                timestamp: Timestamp::new(999_000)
+            })
+        );
+        assert_eq!(
+            policy.check_partial(&cfd, &everything, price, now),
+            Some(SettlementRejectionReason::InvalidQuantity)
+        );
+    }
+
+    #[test]
+    fn settlement_policy_bounds_added_positions() {
+        let policy = SettlementPolicy::default();
+        let cfd = dummy_cfd();
+        let now = Timestamp::new(1_000_000);
+        let price = Price::new(dec!(50_000)).unwrap();
+        let proposal = |timestamp, quantity, price| AddToPositionProposal {
+            order_id: cfd.order.id,
+            timestamp: Timestamp::new(timestamp),
+            quantity: Usd::new(quantity),
+            price: Price::new(price).unwrap(),
+        };
+
+        assert_eq!(
+            policy.check_add_to_position(
+                &cfd,
+                &proposal(999_900, dec!(1_000), dec!(50_000)),
+                price,
+                now
+            ),
+            None
+        );
+        assert_eq!(
+            policy.check_add_to_position(
+                &cfd,
+                &proposal(999_000, dec!(1_000), dec!(50_000)),
+                price,
+                now
+            ),
+            Some(SettlementRejectionReason::Stale {
+                timestamp: Timestamp::new(999_000)
+            })
+        );
+        assert_eq!(
+            policy.check_add_to_position(
+                &cfd,
+                &proposal(999_900, dec!(1_000), dec!(40_000)),
+                price,
+                now
+            ),
+            Some(SettlementRejectionReason::PriceDeviation {
+                proposed: Price::new(dec!(40_000)).unwrap(),
+                current: price
+            })
+        );
+        assert_eq!(
+            policy.check_add_to_position(
+                &cfd,
+                &proposal(999_900, dec!(99), dec!(50_000)),
+                price,
+                now
+            ),
+            Some(SettlementRejectionReason::InvalidQuantity)
+        );
+        assert_eq!(
+            policy.check_add_to_position(
+                &cfd,
+                &proposal(999_900, dec!(90_001), dec!(50_000)),
+                price,
+                now
+            ),
+            Some(SettlementRejectionReason::InvalidQuantity)
+        );
+    }
+
+    #[test]
+    fn only_the_taker_we_proposed_to_can_accept() {
+        let order_id = OrderId::default();
+        let proposed_to = TakerId::default();
+        let proposal = SettlementProposal {
+            order_id,
+            timestamp: Timestamp::new(1_000_000),
+            taker: Amount::ONE_BTC,
+            maker: Amount::ONE_BTC,
+            price: Price::new(dec!(50_000)).unwrap(),
+        };
+        let pending = |direction| {
+            let proposal = UpdateCfdProposal::Settlement {
+                proposal: proposal.clone(),
+                direction,
+            };
+            vec![(order_id, (proposal, proposed_to))]
+                .into_iter()
+                .collect::<HashMap<_, _>>()
+        };
+
+        let (accept, accepted) =
+            accept_proposal_of_ours(&pending(SettlementKind::Outgoing), order_id, proposed_to)
+                .unwrap();
+        assert!(matches!(accept, CfdAction::AcceptSettlement { .. }));
+        assert_eq!(accepted.direction(), &SettlementKind::Incoming);
+
+        assert!(accept_proposal_of_ours(
+            &pending(SettlementKind::Outgoing),
+            order_id,
+            TakerId::default()
+        )
+        .is_err());
+        assert!(
+            accept_proposal_of_ours(&pending(SettlementKind::Incoming), order_id, proposed_to)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn only_the_taker_of_a_cfd_can_claim_it() {
+        let taker = x25519_dalek::PublicKey::from([1u8; 32]);
+        let other_taker = x25519_dalek::PublicKey::from([2u8; 32]);
+
+        assert!(may_claim_cfd(Some(taker), taker));
+        assert!(!may_claim_cfd(Some(taker), other_taker));
+        assert!(may_claim_cfd(None, other_taker));
+    }
+}
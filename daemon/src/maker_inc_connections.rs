@@ -1,10 +1,11 @@
 use crate::maker_cfd::{FromTaker, NewTakerOnline};
-use crate::model::cfd::{Order, OrderId};
-use crate::model::{BitMexPriceEventId, TakerId};
+use crate::model::cfd::{Order, OrderId, SettlementProposal, SettlementRejectionReason};
+use crate::model::{BitMexPriceEventId, FundingRate, TakerId, Timestamp};
 use crate::{forward_only_ok, maker_cfd, noise, send_to_socket, wire};
 use anyhow::{Context as AnyhowContext, Result};
 use futures::{StreamExt, TryStreamExt};
 use std::collections::HashMap;
+use std::convert::TryInto;
 use std::io;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
@@ -36,16 +37,43 @@ pub enum TakerCommand {
     },
     NotifySettlementRejected {
         id: OrderId,
+        reason: SettlementRejectionReason,
     },
     NotifyRollOverAccepted {
         id: OrderId,
         oracle_event_id: BitMexPriceEventId,
+        funding_rate: FundingRate,
     },
     NotifyRollOverRejected {
         id: OrderId,
     },
+    NotifySettlementCountered {
+        proposal: SettlementProposal,
+    },
+    NotifyRollOverCountered {
+        id: OrderId,
+        timestamp: Timestamp,
+        funding_rate: FundingRate,
+    },
+    ProposeSettlement {
+        proposal: SettlementProposal,
+    },
+    NotifyPartialSettlementAccepted {
+        id: OrderId,
+    },
+    NotifyPartialSettlementRejected {
+        id: OrderId,
+    },
+    NotifyAddToPositionAccepted {
+        id: OrderId,
+    },
+    NotifyAddToPositionRejected {
+        id: OrderId,
+    },
     Protocol(wire::SetupMsg),
     RollOverProtocol(wire::RollOverMsg),
+    PartialSettlementProtocol(wire::PartialSettlementMsg),
+    AddToPositionProtocol(wire::AddToPositionMsg),
 }
 
 pub struct TakerMessage {
@@ -106,13 +134,22 @@ impl Actor {
 
         tracing::info!("New taker {} connected on {}", taker_id, taker_address);
 
-        let noise = Arc::new(Mutex::new(
-            noise::responder_handshake(&mut stream, &self.noise_priv_key).await?,
-        ));
+        let transport_state = noise::responder_handshake(&mut stream, &self.noise_priv_key).await?;
+        let static_key: [u8; 32] = transport_state
+            .get_remote_static()
+            .context("Taker did not provide a static key")?
+            .try_into()
+            .context("Static key has to be 32 bytes")?;
+        let static_key = x25519_dalek::PublicKey::from(static_key);
+        let noise = Arc::new(Mutex::new(transport_state));
 
         let (read, write) = stream.into_split();
         let read = FramedRead::new(read, wire::EncryptedJsonCodec::new(noise.clone()))
-            .map_ok(move |msg| FromTaker { taker_id, msg })
+            .map_ok(move |msg| FromTaker {
+                taker_id,
+                static_key,
+                msg,
+            })
             .map(forward_only_ok::Message);
 
         let (out_msg_actor_address, mut out_msg_actor_context) = xtra::Context::new(None);
@@ -139,7 +176,10 @@ impl Actor {
 
         let _ = self
             .new_taker_channel
-            .send(maker_cfd::NewTakerOnline { id: taker_id })
+            .send(maker_cfd::NewTakerOnline {
+                id: taker_id,
+                static_key,
+            })
             .await;
 
         Ok(())
@@ -181,9 +221,15 @@ impl Actor {
                 self.send_to_taker(msg.taker_id, wire::MakerToTaker::ConfirmSettlement(id))
                     .await?;
             }
-            TakerCommand::NotifySettlementRejected { id } => {
-                self.send_to_taker(msg.taker_id, wire::MakerToTaker::RejectSettlement(id))
-                    .await?;
+            TakerCommand::NotifySettlementRejected { id, reason } => {
+                self.send_to_taker(
+                    msg.taker_id,
+                    wire::MakerToTaker::RejectSettlementWithReason {
+                        order_id: id,
+                        reason,
+                    },
+                )
+                .await?;
             }
             TakerCommand::Protocol(setup_msg) => {
                 self.send_to_taker(msg.taker_id, wire::MakerToTaker::Protocol(setup_msg))
@@ -192,12 +238,14 @@ impl Actor {
             TakerCommand::NotifyRollOverAccepted {
                 id,
                 oracle_event_id,
+                funding_rate,
             } => {
                 self.send_to_taker(
                     msg.taker_id,
                     wire::MakerToTaker::ConfirmRollOver {
                         order_id: id,
                         oracle_event_id,
+                        funding_rate,
                     },
                 )
                 .await?;
@@ -206,6 +254,47 @@ impl Actor {
                 self.send_to_taker(msg.taker_id, wire::MakerToTaker::RejectRollOver(id))
                     .await?;
             }
+            TakerCommand::NotifySettlementCountered { proposal } => {
+                self.send_to_taker(
+                    msg.taker_id,
+                    wire::MakerToTaker::CounterSettlement {
+                        order_id: proposal.order_id,
+                        timestamp: proposal.timestamp,
+                        taker: proposal.taker,
+                        maker: proposal.maker,
+                        price: proposal.price,
+                    },
+                )
+                .await?;
+            }
+            TakerCommand::NotifyRollOverCountered {
+                id,
+                timestamp,
+                funding_rate,
+            } => {
+                self.send_to_taker(
+                    msg.taker_id,
+                    wire::MakerToTaker::CounterRollOver {
+                        order_id: id,
+                        timestamp,
+                        funding_rate,
+                    },
+                )
+                .await?;
+            }
+            TakerCommand::ProposeSettlement { proposal } => {
+                self.send_to_taker(
+                    msg.taker_id,
+                    wire::MakerToTaker::ProposeSettlement {
+                        order_id: proposal.order_id,
+                        timestamp: proposal.timestamp,
+                        taker: proposal.taker,
+                        maker: proposal.maker,
+                        price: proposal.price,
+                    },
+                )
+                .await?;
+            }
             TakerCommand::RollOverProtocol(roll_over_msg) => {
                 self.send_to_taker(
                     msg.taker_id,
@@ -213,6 +302,42 @@ impl Actor {
                 )
                 .await?;
             }
+            TakerCommand::NotifyPartialSettlementAccepted { id } => {
+                self.send_to_taker(
+                    msg.taker_id,
+                    wire::MakerToTaker::ConfirmPartialSettlement(id),
+                )
+                .await?;
+            }
+            TakerCommand::NotifyPartialSettlementRejected { id } => {
+                self.send_to_taker(
+                    msg.taker_id,
+                    wire::MakerToTaker::RejectPartialSettlement(id),
+                )
+                .await?;
+            }
+            TakerCommand::PartialSettlementProtocol(partial_settlement_msg) => {
+                self.send_to_taker(
+                    msg.taker_id,
+                    wire::MakerToTaker::PartialSettlementProtocol(partial_settlement_msg),
+                )
+                .await?;
+            }
+            TakerCommand::NotifyAddToPositionAccepted { id } => {
+                self.send_to_taker(msg.taker_id, wire::MakerToTaker::ConfirmAddToPosition(id))
+                    .await?;
+            }
+            TakerCommand::NotifyAddToPositionRejected { id } => {
+                self.send_to_taker(msg.taker_id, wire::MakerToTaker::RejectAddToPosition(id))
+                    .await?;
+            }
+            TakerCommand::AddToPositionProtocol(add_to_position_msg) => {
+                self.send_to_taker(
+                    msg.taker_id,
+                    wire::MakerToTaker::AddToPositionProtocol(add_to_position_msg),
+                )
+                .await?;
+            }
         }
         Ok(())
     }
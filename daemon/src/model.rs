@@ -7,7 +7,7 @@ use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_with::{DeserializeFromStr, SerializeDisplay};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::num::NonZeroU8;
 use std::ops::{Add, Div, Mul, Sub};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -372,11 +372,168 @@ impl From<Decimal> for Percent {
     }
 }
 
+/// The share of the value of a position that is paid as funding with every roll-over.
+///
+/// With a positive rate the long party pays the short party, with a negative rate the short party
+/// pays the long party.
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Default, Serialize, Deserialize)]
+pub struct FundingRate(Decimal);
+
+impl FundingRate {
+    pub fn new(rate: Decimal) -> Result<Self> {
+        if rate.abs() > Decimal::ONE {
+            anyhow::bail!("Funding rate {} must be between -1 and 1", rate)
+        }
+
+        Ok(Self(rate))
+    }
+
+    #[must_use]
+    pub fn into_decimal(self) -> Decimal {
+        self.0
+    }
+}
+
+impl fmt::Display for FundingRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl str::FromStr for FundingRate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FundingRate::new(Decimal::from_str(s)?)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, sqlx::Type)]
 pub enum TradingPair {
     BtcUsd,
 }
 
+/// How the payout of a CFD relates to the price of the underlying.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, sqlx::Type)]
+pub enum ContractType {
+    /// The contract is denominated in USD, i.e. a quantity of 1 USD contracts whose value in BTC
+    /// changes with the inverse of the price.
+    Inverse,
+    /// The contract is denominated in BTC, the payout changes linearly with the price.
+    ///
+    /// The quantity still represents the USD value of the position at the initial price.
+    Linear,
+}
+
+impl Default for ContractType {
+    fn default() -> Self {
+        ContractType::Inverse
+    }
+}
+
+/// How the payouts of a CFD are distributed over the price range.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, sqlx::Type)]
+pub enum PayoutDensity {
+    /// Payouts are spread evenly between the liquidation prices.
+    Uniform,
+    /// Payouts are finer around the initial price and coarser towards the liquidation prices.
+    Adaptive,
+}
+
+/// Determines how many CETs are created for a CFD.
+///
+/// Contract setup bandwidth and signing time scale with the number of CETs, this allows trading
+/// off the accuracy of the payout against the cost of setting up the contract.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(try_from = "PayoutResolutionParams")]
+pub struct PayoutResolution {
+    n_payouts: usize,
+    bucket_digits: u8,
+    density: PayoutDensity,
+}
+
+impl PayoutResolution {
+    pub const MIN_PAYOUTS: usize = 20;
+    pub const MAX_PAYOUTS: usize = 1000;
+    pub const MAX_BUCKET_DIGITS: u8 = 10;
+
+    /// Creates a new resolution for the payout curve.
+    ///
+    /// * n_payouts: The number of segments the payout curve is split into.
+    /// * bucket_digits: The number of least significant binary digits of the attested price that
+    /// are ignored, i.e. segment boundaries are aligned to multiples of `2^bucket_digits`. The
+    /// oracle attests to the binary digits of the price, aligned segments can be covered with
+    /// fewer CETs.
+    /// * density: How the segments are distributed over the price range.
+    pub fn new(n_payouts: usize, bucket_digits: u8, density: PayoutDensity) -> Result<Self> {
+        if !(Self::MIN_PAYOUTS..=Self::MAX_PAYOUTS).contains(&n_payouts) {
+            anyhow::bail!(
+                "Number of payouts must be between {} and {}, got {}",
+                Self::MIN_PAYOUTS,
+                Self::MAX_PAYOUTS,
+                n_payouts
+            )
+        }
+        if bucket_digits > Self::MAX_BUCKET_DIGITS {
+            anyhow::bail!(
+                "Price buckets can ignore at most {} digits, got {}",
+                Self::MAX_BUCKET_DIGITS,
+                bucket_digits
+            )
+        }
+
+        Ok(Self {
+            n_payouts,
+            bucket_digits,
+            density,
+        })
+    }
+
+    pub fn n_payouts(&self) -> usize {
+        self.n_payouts
+    }
+
+    pub fn bucket_digits(&self) -> u8 {
+        self.bucket_digits
+    }
+
+    /// The size of a price bucket in USD.
+    pub fn bucket_size(&self) -> u64 {
+        1 << self.bucket_digits
+    }
+
+    pub fn density(&self) -> PayoutDensity {
+        self.density
+    }
+}
+
+/// The resolution all CFDs were created with before it became configurable.
+impl Default for PayoutResolution {
+    fn default() -> Self {
+        Self {
+            n_payouts: 200,
+            bucket_digits: 0,
+            density: PayoutDensity::Uniform,
+        }
+    }
+}
+
+/// Unvalidated [`PayoutResolution`] as received from the counterparty.
+#[derive(Deserialize)]
+struct PayoutResolutionParams {
+    n_payouts: usize,
+    bucket_digits: u8,
+    density: PayoutDensity,
+}
+
+impl TryFrom<PayoutResolutionParams> for PayoutResolution {
+    type Error = anyhow::Error;
+
+    fn try_from(params: PayoutResolutionParams) -> Result<Self> {
+        Self::new(params.n_payouts, params.bucket_digits, params.density)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, sqlx::Type)]
 pub enum Position {
     Long,
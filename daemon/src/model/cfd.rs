@@ -1,22 +1,27 @@
 use crate::model::{
-    BitMexPriceEventId, InversePrice, Leverage, Percent, Position, Price, TakerId, Timestamp,
-    TradingPair, Usd,
+    BitMexPriceEventId, ContractType, FundingRate, InversePrice, Leverage, PayoutResolution,
+    Percent, Position, Price, TakerId, Timestamp, TradingPair, Usd,
 };
 use crate::{monitor, oracle, payout_curve};
 use anyhow::{bail, Context, Result};
 use bdk::bitcoin::secp256k1::{SecretKey, Signature};
-use bdk::bitcoin::{Address, Amount, PublicKey, Script, SignedAmount, Transaction, Txid};
+use bdk::bitcoin::util::psbt::{self, PartiallySignedTransaction};
+use bdk::bitcoin::{
+    Address, Amount, Denomination, OutPoint, PublicKey, Script, SigHashType, SignedAmount,
+    Transaction, TxIn, TxOut, Txid,
+};
 use bdk::descriptor::Descriptor;
 use bdk::miniscript::DescriptorTrait;
 use maia::secp256k1_zkp::{self, EcdsaAdaptorSignature, SECP256K1};
 use maia::{finalize_spend_transaction, spending_tx_sighash, TransactionExt};
 use rocket::request::FromParam;
-use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
 use rust_decimal::Decimal;
 use serde::de::Error as _;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::iter::FromIterator;
 use std::ops::RangeInclusive;
 use time::{Duration, OffsetDateTime};
 use uuid::adapter::Hyphenated;
@@ -77,7 +82,7 @@ pub enum Origin {
 }
 
 /// Role in the Cfd
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
 pub enum Role {
     Maker,
     Taker,
@@ -98,6 +103,9 @@ pub struct Order {
     pub id: OrderId,
 
     pub trading_pair: TradingPair,
+    /// Orders from before linear contracts were introduced are inverse
+    #[serde(default)]
+    pub contract_type: ContractType,
     pub position: Position,
 
     pub price: Price,
@@ -112,6 +120,13 @@ pub struct Order {
     pub leverage: Leverage,
     pub liquidation_price: Price,
 
+    /// Number and distribution of the CETs created for CFDs from this order
+    pub payout_resolution: PayoutResolution,
+
+    /// The funding rate the maker charges on every roll-over of CFDs from this order
+    #[serde(default)]
+    pub funding_rate: FundingRate,
+
     pub creation_timestamp: Timestamp,
 
     /// The duration that will be used for calculating the settlement timestamp
@@ -126,16 +141,20 @@ pub struct Order {
 }
 
 impl Order {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         price: Price,
         min_quantity: Usd,
         max_quantity: Usd,
+        contract_type: ContractType,
+        payout_resolution: PayoutResolution,
+        funding_rate: FundingRate,
         origin: Origin,
         oracle_event_id: BitMexPriceEventId,
         settlement_time_interval_hours: Duration,
     ) -> Result<Self> {
         let leverage = Leverage::new(2)?;
-        let liquidation_price = calculate_long_liquidation_price(leverage, price);
+        let liquidation_price = calculate_liquidation_price(contract_type, leverage, price);
 
         Ok(Order {
             id: OrderId::default(),
@@ -144,7 +163,10 @@ impl Order {
             max_quantity,
             leverage,
             trading_pair: TradingPair::BtcUsd,
+            contract_type,
             liquidation_price,
+            payout_resolution,
+            funding_rate,
             position: Position::Short,
             creation_timestamp: Timestamp::now()?,
             settlement_time_interval_hours,
@@ -152,6 +174,49 @@ impl Order {
             oracle_event_id,
         })
     }
+
+    /// Changes the entry price of the order, the liquidation price follows it.
+    pub fn set_price(&mut self, price: Price) {
+        self.price = price;
+        self.liquidation_price =
+            calculate_liquidation_price(self.contract_type, self.leverage, price);
+    }
+}
+
+#[cfg(test)]
+impl Order {
+    /// An inverse BTC/USD order at 40,000 USD that can be taken for 100 to 100,000 USD
+    pub(crate) fn dummy() -> Self {
+        Order::new(
+            Price::new(rust_decimal_macros::dec!(40_000)).unwrap(),
+            Usd::new(rust_decimal_macros::dec!(100)),
+            Usd::new(rust_decimal_macros::dec!(100_000)),
+            ContractType::Inverse,
+            PayoutResolution::default(),
+            FundingRate::default(),
+            Origin::Theirs,
+            BitMexPriceEventId::with_20_digits(OffsetDateTime::now_utc()),
+            Duration::hours(24),
+        )
+        .unwrap()
+    }
+
+    pub(crate) fn with_price(mut self, price: Price) -> Self {
+        self.set_price(price);
+        self
+    }
+
+    pub(crate) fn with_contract_type(mut self, contract_type: ContractType) -> Self {
+        self.contract_type = contract_type;
+        self.liquidation_price =
+            calculate_liquidation_price(contract_type, self.leverage, self.price);
+        self
+    }
+
+    pub(crate) fn with_funding_rate(mut self, funding_rate: FundingRate) -> Self {
+        self.funding_rate = funding_rate;
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -445,6 +510,25 @@ impl fmt::Display for CetStatus {
 }
 
 impl CfdState {
+    fn get_common_mut(&mut self) -> &mut CfdStateCommon {
+        match self {
+            CfdState::OutgoingOrderRequest { common } => common,
+            CfdState::IncomingOrderRequest { common, .. } => common,
+            CfdState::Accepted { common } => common,
+            CfdState::Rejected { common } => common,
+            CfdState::ContractSetup { common } => common,
+            CfdState::PendingOpen { common, .. } => common,
+            CfdState::Open { common, .. } => common,
+            CfdState::OpenCommitted { common, .. } => common,
+            CfdState::PendingRefund { common, .. } => common,
+            CfdState::Refunded { common, .. } => common,
+            CfdState::SetupFailed { common, .. } => common,
+            CfdState::PendingCommit { common, .. } => common,
+            CfdState::PendingCet { common, .. } => common,
+            CfdState::Closed { common, .. } => common,
+        }
+    }
+
     fn get_common(&self) -> CfdStateCommon {
         let common = match self {
             CfdState::OutgoingOrderRequest { common } => common,
@@ -470,6 +554,10 @@ impl CfdState {
         self.get_common().transition_timestamp
     }
 
+    fn set_transition_timestamp(&mut self, timestamp: Timestamp) {
+        self.get_common_mut().transition_timestamp = timestamp;
+    }
+
     pub fn get_collaborative_close(&self) -> Option<CollaborativeSettlement> {
         match self {
             CfdState::Open {
@@ -479,6 +567,66 @@ impl CfdState {
             _ => None,
         }
     }
+
+    /// The DLC of the state, including states in which the CFD is refunded.
+    pub fn get_dlc(&self) -> Option<&Dlc> {
+        match self {
+            CfdState::PendingOpen { dlc, .. }
+            | CfdState::Open { dlc, .. }
+            | CfdState::PendingCommit { dlc, .. }
+            | CfdState::OpenCommitted { dlc, .. }
+            | CfdState::PendingCet { dlc, .. }
+            | CfdState::PendingRefund { dlc, .. }
+            | CfdState::Refunded { dlc, .. } => Some(dlc),
+            CfdState::OutgoingOrderRequest { .. }
+            | CfdState::IncomingOrderRequest { .. }
+            | CfdState::Accepted { .. }
+            | CfdState::Rejected { .. }
+            | CfdState::ContractSetup { .. }
+            | CfdState::Closed { .. }
+            | CfdState::SetupFailed { .. } => None,
+        }
+    }
+
+    pub fn get_attestation(&self) -> Option<Attestation> {
+        match self.clone() {
+            CfdState::PendingOpen {
+                attestation: Some(attestation),
+                ..
+            }
+            | CfdState::Open {
+                attestation: Some(attestation),
+                ..
+            }
+            | CfdState::PendingCommit {
+                attestation: Some(attestation),
+                ..
+            }
+            | CfdState::OpenCommitted {
+                cet_status: CetStatus::OracleSigned(attestation) | CetStatus::Ready(attestation),
+                ..
+            }
+            | CfdState::PendingCet { attestation, .. }
+            | CfdState::Closed {
+                payout: Payout::Cet(attestation),
+                ..
+            } => Some(attestation),
+
+            CfdState::OutgoingOrderRequest { .. }
+            | CfdState::IncomingOrderRequest { .. }
+            | CfdState::Accepted { .. }
+            | CfdState::Rejected { .. }
+            | CfdState::ContractSetup { .. }
+            | CfdState::PendingOpen { .. }
+            | CfdState::Open { .. }
+            | CfdState::PendingCommit { .. }
+            | CfdState::Closed { .. }
+            | CfdState::OpenCommitted { .. }
+            | CfdState::PendingRefund { .. }
+            | CfdState::Refunded { .. }
+            | CfdState::SetupFailed { .. } => None,
+        }
+    }
 }
 
 impl fmt::Display for CfdState {
@@ -530,7 +678,7 @@ impl fmt::Display for CfdState {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum UpdateCfdProposal {
     Settlement {
         proposal: SettlementProposal,
@@ -540,26 +688,240 @@ pub enum UpdateCfdProposal {
         proposal: RollOverProposal,
         direction: SettlementKind,
     },
+    PartialSettlement {
+        proposal: PartialSettlementProposal,
+        direction: SettlementKind,
+    },
+    AddToPosition {
+        proposal: AddToPositionProposal,
+        direction: SettlementKind,
+    },
+}
+
+impl UpdateCfdProposal {
+    /// How long after it was made a proposal can be accepted.
+    pub const EXPIRY: Duration = Duration::hours(1);
+
+    pub fn order_id(&self) -> OrderId {
+        match self {
+            UpdateCfdProposal::Settlement { proposal, .. } => proposal.order_id,
+            UpdateCfdProposal::RollOverProposal { proposal, .. } => proposal.order_id,
+            UpdateCfdProposal::PartialSettlement { proposal, .. } => proposal.order_id,
+            UpdateCfdProposal::AddToPosition { proposal, .. } => proposal.order_id,
+        }
+    }
+
+    pub fn timestamp(&self) -> Timestamp {
+        match self {
+            UpdateCfdProposal::Settlement { proposal, .. } => proposal.timestamp,
+            UpdateCfdProposal::RollOverProposal { proposal, .. } => proposal.timestamp,
+            UpdateCfdProposal::PartialSettlement { proposal, .. } => proposal.timestamp,
+            UpdateCfdProposal::AddToPosition { proposal, .. } => proposal.timestamp,
+        }
+    }
+
+    pub fn direction(&self) -> &SettlementKind {
+        match self {
+            UpdateCfdProposal::Settlement { direction, .. }
+            | UpdateCfdProposal::RollOverProposal { direction, .. }
+            | UpdateCfdProposal::PartialSettlement { direction, .. }
+            | UpdateCfdProposal::AddToPosition { direction, .. } => direction,
+        }
+    }
+
+    pub fn expiry_timestamp(&self) -> Timestamp {
+        Timestamp::new(self.timestamp().seconds() + Self::EXPIRY.whole_seconds())
+    }
+
+    pub fn is_expired(&self) -> Result<bool> {
+        Ok(self.expiry_timestamp().seconds() <= Timestamp::now()?.seconds())
+    }
 }
 
 /// Proposed collaborative settlement
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SettlementProposal {
     pub order_id: OrderId,
     pub timestamp: Timestamp,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_sat")]
     pub taker: Amount,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_sat")]
     pub maker: Amount,
     pub price: Price,
 }
 
+/// Prices at which the taker settles a CFD without being asked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SettlementTriggers {
+    /// Settle once the price moved against the position up to this price.
+    pub stop_loss: Option<Price>,
+    /// Settle once the price moved in favour of the position up to this price.
+    pub take_profit: Option<Price>,
+}
+
+impl SettlementTriggers {
+    pub fn is_empty(&self) -> bool {
+        self.stop_loss.is_none() && self.take_profit.is_none()
+    }
+
+    /// Makes sure the stop-loss is below the take-profit for a long position and above it for a
+    /// short position
+    pub fn validate(&self, position: &Position) -> Result<()> {
+        if let (Some(stop_loss), Some(take_profit)) = (self.stop_loss, self.take_profit) {
+            let valid = match position {
+                Position::Long => stop_loss < take_profit,
+                Position::Short => stop_loss > take_profit,
+            };
+            if !valid {
+                bail!(
+                    "Stop-loss {} and take-profit {} are the wrong way round for a {:?} position",
+                    stop_loss,
+                    take_profit,
+                    position
+                )
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether one of the triggers is hit at `price` for a CFD in which we hold `position`
+    pub fn is_triggered(&self, position: &Position, price: Price) -> bool {
+        let (stop_loss_hit, take_profit_hit) = match position {
+            Position::Long => (
+                self.stop_loss.map_or(false, |stop_loss| price <= stop_loss),
+                self.take_profit
+                    .map_or(false, |take_profit| price >= take_profit),
+            ),
+            Position::Short => (
+                self.stop_loss.map_or(false, |stop_loss| price >= stop_loss),
+                self.take_profit
+                    .map_or(false, |take_profit| price <= take_profit),
+            ),
+        };
+
+        stop_loss_hit || take_profit_hit
+    }
+}
+
+/// Why the maker did not agree to a collaborative settlement
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SettlementRejectionReason {
+    /// The maker declined the proposal.
+    Declined,
+    /// The proposal was not answered before it expired.
+    Expired,
+    /// The proposal was made too long before it reached the maker.
+    Stale { timestamp: Timestamp },
+    /// The proposal claims to be made later than it reached the maker.
+    FromTheFuture { timestamp: Timestamp },
+    /// The proposed price is too far off the maker's current price.
+    PriceDeviation { proposed: Price, current: Price },
+    /// The proposed payout does not match the payout curve at the proposed price.
+    InvalidPayout,
+    /// The proposal settles more of the position than it can, or adds a quantity outside the
+    /// limits of the order.
+    InvalidQuantity,
+}
+
+impl fmt::Display for SettlementRejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettlementRejectionReason::Declined => write!(f, "declined by the maker"),
+            SettlementRejectionReason::Expired => write!(f, "expired before it was answered"),
+            SettlementRejectionReason::Stale { timestamp } => {
+                write!(f, "proposal from {} is stale", timestamp.seconds())
+            }
+            SettlementRejectionReason::FromTheFuture { timestamp } => {
+                write!(f, "proposal from {} is in the future", timestamp.seconds())
+            }
+            SettlementRejectionReason::PriceDeviation { proposed, current } => write!(
+                f,
+                "proposed price {} deviates too much from the current price {}",
+                proposed, current
+            ),
+            SettlementRejectionReason::InvalidPayout => {
+                write!(f, "payout does not match the payout curve")
+            }
+            SettlementRejectionReason::InvalidQuantity => {
+                write!(f, "quantity is not allowed")
+            }
+        }
+    }
+}
+
+/// Whether a CFD has to be rolled over to not expire
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RollOverDue {
+    /// The CFD is not open or does not expire soon
+    No,
+    Yes,
+    /// The CFD cannot be rolled over anymore, it settles at the oracle price
+    Expired,
+}
+
+/// Why a CFD was not rolled over automatically
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutoRollOverFailure {
+    Failed { reason: String },
+    Expired,
+}
+
+impl fmt::Display for AutoRollOverFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoRollOverFailure::Failed { reason } => {
+                write!(f, "Automatic roll-over failed: {}", reason)
+            }
+            AutoRollOverFailure::Expired => write!(
+                f,
+                "Expired before it could be rolled over, settles at the oracle price"
+            ),
+        }
+    }
+}
+
+pub type AutoRollOverFailures = HashMap<OrderId, AutoRollOverFailure>;
+
 /// Proposed collaborative settlement
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RollOverProposal {
     pub order_id: OrderId,
     pub timestamp: Timestamp,
+    /// The funding rate asked for in a counter-proposal of the maker, otherwise the maker charges
+    /// its current rate.
+    #[serde(default)]
+    pub funding_rate: Option<FundingRate>,
+}
+
+/// Proposed collaborative settlement of a part of the position
+///
+/// `taker` and `maker` are the payouts for the settled `quantity`, the rest of the position
+/// stays open.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartialSettlementProposal {
+    pub order_id: OrderId,
+    pub timestamp: Timestamp,
+    pub quantity: Usd,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_sat")]
+    pub taker: Amount,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_sat")]
+    pub maker: Amount,
+    pub price: Price,
+}
+
+/// Proposed increase of the position
+///
+/// The additional `quantity` enters the position at `price`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddToPositionProposal {
+    pub order_id: OrderId,
+    pub timestamp: Timestamp,
+    pub quantity: Usd,
+    pub price: Price,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum SettlementKind {
     Incoming,
     Outgoing,
@@ -573,6 +935,10 @@ pub struct Cfd {
     pub order: Order,
     pub quantity_usd: Usd,
     pub state: CfdState,
+    /// The funding paid by the long party to the short party over all roll-overs, negative if
+    /// the short party paid the long party.
+    #[serde(default, with = "::bdk::bitcoin::util::amount::serde::as_sat")]
+    pub funding: SignedAmount,
     /* TODO: Leverage is currently derived from the Order, but the actual leverage should be
      * stored in the Cfd once there is multiple choices of leverage */
 }
@@ -583,6 +949,7 @@ impl Cfd {
             order,
             quantity_usd: quantity,
             state,
+            funding: SignedAmount::ZERO,
         }
     }
 
@@ -616,20 +983,28 @@ impl Cfd {
             (None, None) => current_price,
         };
 
-        let (p_n_l, p_n_l_percent) = calculate_profit(
-            self.order.price,
-            closing_price,
-            self.quantity_usd,
-            self.order.leverage,
-            self.position(),
-        )?;
+        let (p_n_l, p_n_l_percent) = match self.order.contract_type {
+            ContractType::Inverse => calculate_profit(
+                self.order.price,
+                closing_price,
+                self.quantity_usd,
+                self.order.leverage,
+                self.position(),
+            )?,
+            ContractType::Linear => calculate_linear_profit(
+                self.order.price,
+                closing_price,
+                self.quantity_usd,
+                self.order.leverage,
+                self.position(),
+            )?,
+        };
 
         Ok((p_n_l, p_n_l_percent))
     }
 
     pub fn calculate_settlement(&self, current_price: Price) -> Result<SettlementProposal> {
-        let payout_curve =
-            payout_curve::calculate(self.order.price, self.quantity_usd, self.order.leverage)?;
+        let payout_curve = self.payouts()?;
 
         let payout = {
             let current_price = current_price.try_into_u64()?;
@@ -650,6 +1025,182 @@ impl Cfd {
         Ok(settlement)
     }
 
+    /// Fails unless `proposal` pays out what settling at its price pays according to the payout
+    /// curve.
+    pub fn verify_settlement(&self, proposal: &SettlementProposal) -> Result<()> {
+        let expected = self.calculate_settlement(proposal.price)?;
+
+        if proposal.taker != expected.taker || proposal.maker != expected.maker {
+            bail!(
+                "Settlement at {} has to pay {} to the taker and {} to the maker instead of {} and \
+                 {}",
+                proposal.price,
+                expected.taker,
+                expected.maker,
+                proposal.taker,
+                proposal.maker
+            )
+        }
+
+        Ok(())
+    }
+
+    /// Calculates the payouts for settling `quantity` of the position at the current price.
+    pub fn calculate_partial_settlement(
+        &self,
+        quantity: Usd,
+        current_price: Price,
+    ) -> Result<PartialSettlementProposal> {
+        if quantity >= self.quantity_usd {
+            bail!(
+                "Cannot partially settle {} of {}, settling everything is a regular settlement",
+                quantity,
+                self.quantity_usd
+            )
+        }
+        if self.quantity_usd - quantity < self.order.min_quantity {
+            bail!(
+                "Cannot partially settle {} of {}, the remaining position would be below the \
+                 minimum quantity {}",
+                quantity,
+                self.quantity_usd,
+                self.order.min_quantity
+            )
+        }
+
+        let settlement = self
+            .with_quantity(quantity)?
+            .calculate_settlement(current_price)?;
+
+        Ok(PartialSettlementProposal {
+            order_id: self.order.id,
+            timestamp: settlement.timestamp,
+            quantity,
+            taker: settlement.taker,
+            maker: settlement.maker,
+            price: settlement.price,
+        })
+    }
+
+    /// The CFD with only `quantity` of the position, on the same terms.
+    ///
+    /// The funding paid so far is shared in proportion to the quantity.
+    pub fn with_quantity(&self, quantity: Usd) -> Result<Cfd> {
+        if quantity <= Usd::new(Decimal::ZERO) || quantity > self.quantity_usd {
+            bail!(
+                "Quantity {} has to be positive and must not exceed the quantity {} of the CFD",
+                quantity,
+                self.quantity_usd
+            )
+        }
+
+        let funding = Decimal::from(self.funding.as_sat()) * quantity.into_decimal()
+            / self.quantity_usd.into_decimal();
+        let funding = funding
+            .trunc()
+            .to_i64()
+            .context("Funding does not fit into an amount")?;
+
+        Ok(Cfd {
+            quantity_usd: quantity,
+            funding: SignedAmount::from_sat(funding),
+            ..self.clone()
+        })
+    }
+
+    /// The CFD with `quantity` added to the position at `price`.
+    ///
+    /// The entry price of the combined position is the average of both entry prices weighted by
+    /// the margin each part requires, so that the margin of the combined position is the sum of
+    /// the margins of its parts.
+    pub fn with_added_position(&self, quantity: Usd, price: Price) -> Result<Cfd> {
+        if quantity <= Usd::new(Decimal::ZERO) {
+            bail!("Quantity {} to add has to be positive", quantity)
+        }
+
+        let total_quantity = self.quantity_usd + quantity;
+        let weighted = self.quantity_usd.into_decimal() / self.order.price.into_decimal()
+            + quantity.into_decimal() / price.into_decimal();
+        let entry_price = Price::new((total_quantity.into_decimal() / weighted).round_dp(2))?;
+
+        let mut cfd = self.clone();
+        cfd.quantity_usd = total_quantity;
+        cfd.order.set_price(entry_price);
+
+        Ok(cfd)
+    }
+
+    /// The amounts locked by the maker and the taker respectively.
+    ///
+    /// The funding paid so far is moved from the margin of the paying party to the margin of the
+    /// receiving party.
+    pub fn lock_amounts(&self) -> Result<(Amount, Amount)> {
+        let received = self.funding_received();
+        let ours = (self.margin()?.to_signed()? + received)
+            .to_unsigned()
+            .context("Funding paid exceeds our margin")?;
+        let theirs = (self.counterparty_margin()?.to_signed()? - received)
+            .to_unsigned()
+            .context("Funding paid exceeds the margin of the counterparty")?;
+
+        let amounts = match self.role() {
+            Role::Maker => (ours, theirs),
+            Role::Taker => (theirs, ours),
+        };
+
+        Ok(amounts)
+    }
+
+    /// The payouts of the CETs, with the funding paid so far moved between the parties.
+    pub fn payouts(&self) -> Result<Vec<maia::Payout>> {
+        payout_curve::calculate_with_funding(
+            self.order.price,
+            self.quantity_usd,
+            self.order.leverage,
+            self.order.contract_type,
+            self.order.payout_resolution,
+            self.funding,
+        )
+    }
+
+    /// The funding paid by the long party for one roll-over at the given rate.
+    ///
+    /// The rate applies to the value of the position in BTC at the entry price.
+    pub fn funding_fee(&self, rate: FundingRate) -> Result<SignedAmount> {
+        calculate_funding_fee(rate, self.quantity_usd, self.order.price)
+    }
+
+    /// Whether paying funding at `rate` costs us at most as much as at the rate quoted in the
+    /// order.
+    pub fn is_within_quoted_funding_rate(&self, rate: FundingRate) -> bool {
+        let quoted = self.order.funding_rate;
+
+        match self.position() {
+            Position::Long => rate <= quoted,
+            Position::Short => rate >= quoted,
+        }
+    }
+
+    /// The CFD after paying `fee` as funding, fails if either party cannot afford the funding.
+    pub fn with_funding_fee(&self, fee: SignedAmount) -> Result<Cfd> {
+        let cfd = Cfd {
+            funding: self.funding + fee,
+            ..self.clone()
+        };
+        cfd.lock_amounts()
+            .with_context(|| format!("Cannot pay funding of {}", fee))?;
+
+        Ok(cfd)
+    }
+
+    /// The funding we received over all roll-overs, negative if we paid funding.
+    pub fn funding_received(&self) -> SignedAmount {
+        match self.position() {
+            Position::Long => -self.funding,
+            Position::Short => self.funding,
+        }
+    }
+
     pub fn position(&self) -> Position {
         match self.order.origin {
             Origin::Ours => self.order.position.clone(),
@@ -668,8 +1219,30 @@ impl Cfd {
             .ceil() as u32
     }
 
+    /// When the CFD settles at the oracle price unless it is rolled over or settled before.
+    ///
+    /// Every roll-over moves the expiry to the oracle event the new CETs are based on.
     pub fn expiry_timestamp(&self) -> OffsetDateTime {
-        self.order.oracle_event_id.timestamp()
+        self.dlc()
+            .and_then(|dlc| dlc.settlement_event_id())
+            .unwrap_or(self.order.oracle_event_id)
+            .timestamp()
+    }
+
+    /// Whether the open CFD has to be rolled over at `now` to not expire within `before_expiry`
+    pub fn roll_over_due(&self, before_expiry: Duration, now: OffsetDateTime) -> RollOverDue {
+        if !matches!(self.state, CfdState::Open { .. }) {
+            return RollOverDue::No;
+        }
+
+        let expiry = self.expiry_timestamp();
+        if expiry <= now {
+            RollOverDue::Expired
+        } else if expiry - before_expiry <= now {
+            RollOverDue::Yes
+        } else {
+            RollOverDue::No
+        }
     }
 
     /// A factor to be added to the CFD order settlement_time_interval_hours for calculating the
@@ -982,10 +1555,158 @@ impl Cfd {
         Ok(Some(new_state))
     }
 
-    pub fn refund_tx(&self) -> Result<Transaction> {
-        let dlc = if let CfdState::PendingRefund { dlc, .. } = self.state.clone() {
-            dlc
-        } else {
+    /// Applies an event to the CFD, returns whether the state of the CFD changed.
+    ///
+    /// Fails if the event cannot happen in the current state of the CFD. New states take their
+    /// transition timestamp from the event, so that replaying the events of a CFD yields the same
+    /// state again.
+    pub fn apply(&mut self, event: CfdEvent, timestamp: Timestamp) -> Result<bool> {
+        // State changes are checked when they are handled
+        let applies = match &event {
+            CfdEvent::Created { .. } => false,
+            CfdEvent::LegacyState(_) | CfdEvent::StateChange(_) => true,
+            CfdEvent::OrderRejected => matches!(
+                self.state,
+                CfdState::OutgoingOrderRequest { .. } | CfdState::IncomingOrderRequest { .. }
+            ),
+            CfdEvent::ContractSetupStarted => matches!(
+                self.state,
+                CfdState::OutgoingOrderRequest { .. }
+                    | CfdState::IncomingOrderRequest { .. }
+                    | CfdState::Accepted { .. }
+            ),
+            CfdEvent::ContractSetupCompleted { .. } => {
+                matches!(self.state, CfdState::ContractSetup { .. })
+            }
+            CfdEvent::ContractSetupFailed { .. } => self.is_cleanup(),
+            CfdEvent::RollOverCompleted { .. }
+            | CfdEvent::PartialSettlementCompleted { .. }
+            | CfdEvent::AddToPositionCompleted { .. } => {
+                matches!(self.state, CfdState::Open { .. })
+            }
+        };
+        if !applies {
+            bail!(
+                "Cannot apply event {} to CFD {} in state {}",
+                event.kind(),
+                self.order.id,
+                self.state
+            )
+        }
+
+        // Adding a settlement proposal does not transition the CFD into a new state
+        let keeps_timestamp = matches!(
+            event,
+            CfdEvent::StateChange(CfdStateChangeEvent::ProposalSigned(_))
+        );
+
+        self.state = match event {
+            CfdEvent::Created { state, .. } | CfdEvent::LegacyState(state) => state,
+            CfdEvent::OrderRejected => CfdState::rejected(),
+            CfdEvent::ContractSetupStarted => CfdState::contract_setup(),
+            CfdEvent::ContractSetupCompleted { dlc } => CfdState::PendingOpen {
+                common: CfdStateCommon::default(),
+                dlc,
+                attestation: None,
+            },
+            CfdEvent::ContractSetupFailed { info } => CfdState::setup_failed(info),
+            CfdEvent::RollOverCompleted { dlc, funding_fee } => {
+                self.funding = self.funding + funding_fee;
+
+                CfdState::Open {
+                    common: CfdStateCommon::default(),
+                    dlc,
+                    attestation: None,
+                    collaborative_close: None,
+                }
+            }
+            // The remaining position is open once the partial settlement transaction is final
+            CfdEvent::PartialSettlementCompleted { dlc, quantity, .. } => {
+                self.funding = self.with_quantity(quantity)?.funding;
+                self.quantity_usd = quantity;
+
+                CfdState::PendingOpen {
+                    common: CfdStateCommon::default(),
+                    dlc,
+                    attestation: None,
+                }
+            }
+            // The increased position is open once the new lock transaction is final
+            CfdEvent::AddToPositionCompleted {
+                dlc,
+                quantity,
+                price,
+            } => {
+                self.quantity_usd = quantity;
+                self.order.set_price(price);
+
+                CfdState::PendingOpen {
+                    common: CfdStateCommon::default(),
+                    dlc,
+                    attestation: None,
+                }
+            }
+            CfdEvent::StateChange(event) => match self.handle(event)? {
+                Some(state) => state,
+                None => return Ok(false),
+            },
+        };
+
+        if !keeps_timestamp {
+            self.state.set_transition_timestamp(timestamp);
+        }
+
+        Ok(true)
+    }
+
+    /// Rebuilds a CFD from all its events in the order they were recorded.
+    ///
+    /// The quantity, entry price and funding of the CFD are taken from its creation event, the
+    /// given `order` and `quantity` are only used for CFDs that were created before events were
+    /// recorded.
+    pub fn replay(
+        order: Order,
+        quantity: Usd,
+        events: impl IntoIterator<Item = (CfdEvent, Timestamp)>,
+    ) -> Result<Self> {
+        let mut events = events.into_iter();
+
+        let mut cfd = match events.next() {
+            Some((
+                CfdEvent::Created {
+                    state,
+                    quantity,
+                    price,
+                    funding,
+                },
+                timestamp,
+            )) => {
+                let mut cfd = Cfd::new(order, quantity, state);
+                cfd.order.set_price(price);
+                cfd.funding = funding;
+                cfd.state.set_transition_timestamp(timestamp);
+                cfd
+            }
+            Some((CfdEvent::LegacyState(state), timestamp)) => {
+                let mut cfd = Cfd::new(order, quantity, state);
+                cfd.state.set_transition_timestamp(timestamp);
+                cfd
+            }
+            Some((event, _)) => bail!("History of a CFD has to start with its creation: {}", event),
+            None => bail!("No events recorded for CFD {}", order.id),
+        };
+
+        for (event, timestamp) in events {
+            cfd.apply(event, timestamp)?;
+        }
+
+        Ok(cfd)
+    }
+
+    pub fn refund_tx(&self) -> Result<Transaction> {
+        let dlc = if let CfdState::PendingRefund { dlc, .. } = self.state.clone() {
+            dlc
+        } else {
             bail!("Refund transaction can only be constructed when in state PendingRefund, but we are currently in {}", self.state.clone())
         };
 
@@ -1177,44 +1898,8 @@ impl Cfd {
         }
     }
 
-    fn attestation(&self) -> Option<Attestation> {
-        match self.state.clone() {
-            CfdState::PendingOpen {
-                attestation: Some(attestation),
-                ..
-            }
-            | CfdState::Open {
-                attestation: Some(attestation),
-                ..
-            }
-            | CfdState::PendingCommit {
-                attestation: Some(attestation),
-                ..
-            }
-            | CfdState::OpenCommitted {
-                cet_status: CetStatus::OracleSigned(attestation) | CetStatus::Ready(attestation),
-                ..
-            }
-            | CfdState::PendingCet { attestation, .. }
-            | CfdState::Closed {
-                payout: Payout::Cet(attestation),
-                ..
-            } => Some(attestation),
-
-            CfdState::OutgoingOrderRequest { .. }
-            | CfdState::IncomingOrderRequest { .. }
-            | CfdState::Accepted { .. }
-            | CfdState::Rejected { .. }
-            | CfdState::ContractSetup { .. }
-            | CfdState::PendingOpen { .. }
-            | CfdState::Open { .. }
-            | CfdState::PendingCommit { .. }
-            | CfdState::Closed { .. }
-            | CfdState::OpenCommitted { .. }
-            | CfdState::PendingRefund { .. }
-            | CfdState::Refunded { .. }
-            | CfdState::SetupFailed { .. } => None,
-        }
+    pub fn attestation(&self) -> Option<Attestation> {
+        self.state.get_attestation()
     }
 
     pub fn collaborative_close(&self) -> Option<CollaborativeSettlement> {
@@ -1274,7 +1959,7 @@ pub struct NotReadyYet {
     cet_status: CetStatus,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum CfdStateChangeEvent {
     // TODO: group other events by actors into enums and add them here so we can bundle all
     // transitions into cfd.transition_to(...)
@@ -1286,6 +1971,168 @@ pub enum CfdStateChangeEvent {
     ProposalSigned(CollaborativeSettlement),
 }
 
+impl fmt::Display for CfdStateChangeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CfdStateChangeEvent::Monitor(event) => {
+                let event = match event {
+                    monitor::Event::LockFinality(_) => "Lock transaction final",
+                    monitor::Event::CommitFinality(_) => "Commit transaction final",
+                    monitor::Event::CloseFinality(_) => "Close transaction final",
+                    monitor::Event::CetTimelockExpired(_) => "CET timelock expired",
+                    monitor::Event::CetFinality(_) => "CET final",
+                    monitor::Event::RefundTimelockExpired(_) => "Refund timelock expired",
+                    monitor::Event::RefundFinality(_) => "Refund transaction final",
+                    monitor::Event::RevokedTransactionFound(_) => "Revoked transaction found",
+                };
+
+                write!(f, "{}", event)
+            }
+            CfdStateChangeEvent::CommitTxSent => write!(f, "Commit transaction sent"),
+            CfdStateChangeEvent::OracleAttestation(attestation) => {
+                write!(f, "Oracle attested price {}", attestation.price)
+            }
+            CfdStateChangeEvent::CetSent => write!(f, "CET sent"),
+            CfdStateChangeEvent::ProposalSigned(settlement) => {
+                write!(f, "Settlement signed at price {}", settlement.price)
+            }
+        }
+    }
+}
+
+/// Something that happened to a CFD.
+///
+/// Events are the source of truth for the state of a CFD, applying all events of a CFD in the
+/// order they were recorded yields its current state, see [`Cfd::replay`].
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", content = "payload")]
+pub enum CfdEvent {
+    /// The CFD was created in the given state.
+    ///
+    /// Later events change the quantity, entry price and funding of the CFD, but not its order,
+    /// so the terms it was created with are recorded here.
+    Created {
+        state: CfdState,
+        quantity: Usd,
+        price: Price,
+        #[serde(with = "::bdk::bitcoin::util::amount::serde::as_sat")]
+        funding: SignedAmount,
+    },
+    OrderRejected,
+    ContractSetupStarted,
+    ContractSetupCompleted {
+        dlc: Dlc,
+    },
+    ContractSetupFailed {
+        info: String,
+    },
+    /// The contract was rolled over, the long party paid `funding_fee` to the short party.
+    RollOverCompleted {
+        dlc: Dlc,
+        #[serde(default, with = "::bdk::bitcoin::util::amount::serde::as_sat")]
+        funding_fee: SignedAmount,
+    },
+    /// A part of the position was settled, `quantity` is what remains open.
+    PartialSettlementCompleted {
+        dlc: Dlc,
+        quantity: Usd,
+        price: Price,
+    },
+    /// The position was increased to `quantity` with the new entry `price`.
+    AddToPositionCompleted {
+        dlc: Dlc,
+        quantity: Usd,
+        price: Price,
+    },
+    StateChange(CfdStateChangeEvent),
+    /// A state that was recorded before events were kept, only created when migrating.
+    LegacyState(CfdState),
+}
+
+impl CfdEvent {
+    /// The event recording the creation of the CFD in its current state.
+    pub fn created(cfd: &Cfd) -> Self {
+        CfdEvent::Created {
+            state: cfd.state.clone(),
+            quantity: cfd.quantity_usd,
+            price: cfd.order.price,
+            funding: cfd.funding,
+        }
+    }
+
+    /// The name of the event, as it is used for the type tag when serializing it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CfdEvent::Created { .. } => "Created",
+            CfdEvent::OrderRejected => "OrderRejected",
+            CfdEvent::ContractSetupStarted => "ContractSetupStarted",
+            CfdEvent::ContractSetupCompleted { .. } => "ContractSetupCompleted",
+            CfdEvent::ContractSetupFailed { .. } => "ContractSetupFailed",
+            CfdEvent::RollOverCompleted { .. } => "RollOverCompleted",
+            CfdEvent::PartialSettlementCompleted { .. } => "PartialSettlementCompleted",
+            CfdEvent::AddToPositionCompleted { .. } => "AddToPositionCompleted",
+            CfdEvent::StateChange(_) => "StateChange",
+            CfdEvent::LegacyState(_) => "LegacyState",
+        }
+    }
+}
+
+impl fmt::Display for CfdEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CfdEvent::Created {
+                state,
+                quantity,
+                price,
+                ..
+            } => write!(
+                f,
+                "Created in state {} with quantity {} at price {}",
+                state, quantity, price
+            ),
+            CfdEvent::OrderRejected => write!(f, "Order rejected"),
+            CfdEvent::ContractSetupStarted => write!(f, "Contract setup started"),
+            CfdEvent::ContractSetupCompleted { dlc } => write!(
+                f,
+                "Contract setup completed with lock transaction {}",
+                dlc.lock.0.txid()
+            ),
+            CfdEvent::ContractSetupFailed { info } => write!(f, "Contract setup failed: {}", info),
+            CfdEvent::RollOverCompleted { dlc, funding_fee } => write!(
+                f,
+                "Rolled over to commit transaction {} with funding of {}",
+                dlc.commit.0.txid(),
+                funding_fee
+            ),
+            CfdEvent::PartialSettlementCompleted {
+                dlc,
+                quantity,
+                price,
+            } => write!(
+                f,
+                "Partially settled at price {} with transaction {}, {} remain open",
+                price,
+                dlc.lock.0.txid(),
+                quantity
+            ),
+            CfdEvent::AddToPositionCompleted {
+                dlc,
+                quantity,
+                price,
+            } => write!(
+                f,
+                "Increased position to {} at entry price {} with lock transaction {}",
+                quantity,
+                price,
+                dlc.lock.0.txid()
+            ),
+            CfdEvent::StateChange(event) => write!(f, "{}", event),
+            CfdEvent::LegacyState(state) => write!(f, "Recorded state {}", state),
+        }
+    }
+}
+
 pub trait AsBlocks {
     /// Calculates the duration in Bitcoin blocks.
     ///
@@ -1314,14 +2161,46 @@ pub fn calculate_long_margin(price: Price, quantity: Usd, leverage: Leverage) ->
 /// The short margin is represented as the quantity of the contract given the
 /// initial price. The short side can currently not leverage the position but
 /// always has to cover the complete quantity.
-fn calculate_short_margin(price: Price, quantity: Usd) -> Amount {
+pub fn calculate_short_margin(price: Price, quantity: Usd) -> Amount {
     quantity / price
 }
 
+/// Calculates the funding paid by the long party for one roll-over at the given rate
+///
+/// The rate applies to the value of the position in BTC at the entry price. A negative fee is
+/// paid by the short party.
+pub fn calculate_funding_fee(
+    rate: FundingRate,
+    quantity: Usd,
+    price: Price,
+) -> Result<SignedAmount> {
+    let fee = rate.into_decimal() * quantity.into_decimal() / price.into_decimal();
+    let fee = SignedAmount::from_str_in(&fee.round_dp(8).to_string(), Denomination::Bitcoin)
+        .context("Unable to convert to SignedAmount")?;
+
+    Ok(fee)
+}
+
 fn calculate_long_liquidation_price(leverage: Leverage, price: Price) -> Price {
     price * leverage / (leverage + 1)
 }
 
+fn calculate_liquidation_price(
+    contract_type: ContractType,
+    leverage: Leverage,
+    price: Price,
+) -> Price {
+    match contract_type {
+        ContractType::Inverse => calculate_long_liquidation_price(leverage, price),
+        ContractType::Linear => calculate_long_linear_liquidation_price(leverage, price),
+    }
+}
+
+/// The price at which the long party of a linear contract loses its complete margin
+fn calculate_long_linear_liquidation_price(leverage: Leverage, price: Price) -> Price {
+    price - price / leverage
+}
+
 // PLACEHOLDER
 // fn calculate_short_liquidation_price(leverage: Leverage, price: Price) -> Price {
 //     price * leverage / (leverage - 1)
@@ -1408,10 +2287,109 @@ fn calculate_profit(
     Ok((profit, Percent(percent)))
 }
 
+/// Returns the Profit/Loss (P/L) of a linear contract as Bitcoin.
+///
+/// The payout of the long party changes by `quantity * (closing_price - initial_price) /
+/// initial_price^2`, i.e. linearly with the price. Losses are capped by the margin of either
+/// party.
+fn calculate_linear_profit(
+    initial_price: Price,
+    closing_price: Price,
+    quantity: Usd,
+    leverage: Leverage,
+    position: Position,
+) -> Result<(SignedAmount, Percent)> {
+    let long_margin = calculate_long_margin(initial_price, quantity, leverage)
+        .to_signed()
+        .context("Unable to compute long margin")?;
+    let short_margin = calculate_short_margin(initial_price, quantity)
+        .to_signed()
+        .context("Unable to compute short margin")?;
+    let amount_changed = {
+        let initial_price = initial_price.into_decimal();
+        let mut btc = quantity.into_decimal() * (closing_price.into_decimal() - initial_price)
+            / (initial_price * initial_price);
+        btc.rescale(8);
+
+        SignedAmount::from_str_in(&btc.to_string(), Denomination::Bitcoin)
+            .context("Unable to convert to SignedAmount")?
+    };
+
+    let long_payout =
+        (long_margin + amount_changed).clamp(SignedAmount::ZERO, long_margin + short_margin);
+
+    let (margin, payout) = match position {
+        Position::Long => (long_margin, long_payout),
+        Position::Short => (short_margin, long_margin + short_margin - long_payout),
+    };
+
+    let profit = payout - margin;
+    let percent = Decimal::from_f64(100. * profit.as_sat() as f64 / margin.as_sat() as f64)
+        .context("Unable to compute percent")?;
+
+    Ok((profit, Percent(percent)))
+}
+
+/// The script both wallets pay the funded amount to when building the funding PSBTs
+///
+/// Apart from it, the PSBTs only pay to the change addresses of the wallets, which never
+/// coincide.
+fn funding_script_pubkey(
+    maker: &PartiallySignedTransaction,
+    taker: &PartiallySignedTransaction,
+) -> Result<Script> {
+    let taker_scripts = taker
+        .global
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|output| &output.script_pubkey)
+        .collect::<HashSet<_>>();
+    let mut shared_scripts = maker
+        .global
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|output| &output.script_pubkey)
+        .filter(|script_pubkey| taker_scripts.contains(script_pubkey));
+
+    match (shared_scripts.next(), shared_scripts.next()) {
+        (Some(script_pubkey), None) => Ok(script_pubkey.clone()),
+        (None, _) => bail!("Funding PSBTs do not pay to the same script"),
+        (Some(_), Some(_)) => bail!("Funding PSBTs pay to more than one common script"),
+    }
+}
+
+/// The outputs of a funding PSBT apart from the one paying the funded `amount` to
+/// `funding_script_pubkey`
+fn change_outputs(
+    psbt: &PartiallySignedTransaction,
+    funding_script_pubkey: &Script,
+    amount: Amount,
+) -> Result<Vec<TxOut>> {
+    let mut outputs = psbt.global.unsigned_tx.output.clone();
+    let funded = outputs
+        .iter()
+        .position(|output| &output.script_pubkey == funding_script_pubkey)
+        .context("Funding PSBT does not pay to the funding script")?;
+    let funding = outputs.remove(funded);
+
+    if funding.value != amount.as_sat() {
+        bail!(
+            "Funding PSBT pays {} instead of {}",
+            Amount::from_sat(funding.value),
+            amount
+        )
+    }
+
+    Ok(outputs)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use rust_decimal_macros::dec;
+    use time::macros::datetime;
 
     #[test]
     fn given_default_values_then_expected_liquidation_price() {
@@ -1612,6 +2590,60 @@ mod tests {
         );
     }
 
+    #[test]
+    fn calculate_linear_profit_and_loss() {
+        let initial_price = Price::new(dec!(10_000)).unwrap();
+        let quantity = Usd::new(dec!(10_000));
+        let leverage = Leverage::new(2).unwrap();
+
+        let (profit, in_percent) = calculate_linear_profit(
+            initial_price,
+            Price::new(dec!(15_000)).unwrap(),
+            quantity,
+            leverage,
+            Position::Long,
+        )
+        .unwrap();
+        assert_eq!(profit, SignedAmount::from_sat(50_000_000));
+        assert_eq!(in_percent, Percent::from(dec!(100)));
+
+        let (loss, in_percent) = calculate_linear_profit(
+            initial_price,
+            Price::new(dec!(15_000)).unwrap(),
+            quantity,
+            leverage,
+            Position::Short,
+        )
+        .unwrap();
+        assert_eq!(loss, SignedAmount::from_sat(-50_000_000));
+        assert_eq!(in_percent, Percent::from(dec!(-50)));
+
+        let (loss, in_percent) = calculate_linear_profit(
+            initial_price,
+            Price::new(dec!(25_000)).unwrap(),
+            quantity,
+            leverage,
+            Position::Short,
+        )
+        .unwrap();
+        assert_eq!(
+            loss,
+            SignedAmount::from_sat(-100_000_000),
+            "A loss should be capped at 100% (short)"
+        );
+        assert_eq!(in_percent, Percent::from(dec!(-100)));
+    }
+
+    #[test]
+    fn given_linear_contract_then_long_is_liquidated_at_inverse_of_leverage() {
+        let price = Price::new(dec!(40000)).unwrap();
+        let leverage = Leverage::new(5).unwrap();
+
+        let liquidation_price = calculate_long_linear_liquidation_price(leverage, price);
+
+        assert_eq!(liquidation_price, Price::new(dec!(32000)).unwrap());
+    }
+
     #[test]
     fn margin_remains_constant() {
         let initial_price = Price::new(dec!(15_000)).unwrap();
@@ -1650,6 +2682,117 @@ mod tests {
         }
     }
 
+    #[test]
+    fn added_position_requires_the_margin_of_both_parts() {
+        let order = Order::dummy();
+        let cfd = Cfd::new(
+            order,
+            Usd::new(dec!(10_000)),
+            CfdState::outgoing_order_request(),
+        );
+        let added = cfd
+            .with_added_position(Usd::new(dec!(10_000)), Price::new(dec!(60_000)).unwrap())
+            .unwrap();
+
+        assert_eq!(added.quantity_usd, Usd::new(dec!(20_000)));
+        assert_eq!(added.order.price, Price::new(dec!(48_000)).unwrap());
+        assert_eq!(
+            added.margin().unwrap(),
+            calculate_long_margin(
+                Price::new(dec!(40_000)).unwrap(),
+                Usd::new(dec!(10_000)),
+                cfd.order.leverage
+            ) + calculate_long_margin(
+                Price::new(dec!(60_000)).unwrap(),
+                Usd::new(dec!(10_000)),
+                cfd.order.leverage
+            )
+        );
+    }
+
+    #[test]
+    fn funding_moves_margin_from_long_to_short() {
+        let order = Order::dummy();
+        let cfd = Cfd::new(
+            order,
+            Usd::new(dec!(10_000)),
+            CfdState::outgoing_order_request(),
+        );
+
+        let fee = cfd
+            .funding_fee(FundingRate::new(dec!(0.001)).unwrap())
+            .unwrap();
+        let rolled_over = cfd.with_funding_fee(fee).unwrap();
+
+        assert_eq!(fee, SignedAmount::from_sat(25_000));
+        assert_eq!(cfd.position(), Position::Long);
+        assert_eq!(
+            rolled_over.funding_received(),
+            SignedAmount::from_sat(-25_000)
+        );
+        assert_eq!(
+            rolled_over.lock_amounts().unwrap(),
+            (
+                cfd.counterparty_margin().unwrap() + Amount::from_sat(25_000),
+                cfd.margin().unwrap() - Amount::from_sat(25_000)
+            )
+        );
+        assert!(cfd
+            .with_funding_fee(cfd.margin().unwrap().to_signed().unwrap() + fee)
+            .is_err());
+    }
+
+    #[test]
+    fn settlement_has_to_pay_out_according_to_the_curve() {
+        let order = Order::dummy();
+        let cfd = Cfd::new(
+            order,
+            Usd::new(dec!(10_000)),
+            CfdState::outgoing_order_request(),
+        );
+
+        let settlement = cfd
+            .calculate_settlement(Price::new(dec!(42_000)).unwrap())
+            .unwrap();
+        let overpaid = SettlementProposal {
+            taker: settlement.taker + Amount::from_sat(1),
+            maker: settlement.maker - Amount::from_sat(1),
+            ..settlement.clone()
+        };
+
+        assert!(cfd.verify_settlement(&settlement).is_ok());
+        assert!(cfd.verify_settlement(&overpaid).is_err());
+    }
+
+    #[test]
+    fn funding_rate_above_quote_is_not_accepted_by_payer() {
+        let order = Order::dummy().with_funding_rate(FundingRate::new(dec!(0.001)).unwrap());
+        let long = Cfd::new(
+            order.clone(),
+            Usd::new(dec!(10_000)),
+            CfdState::outgoing_order_request(),
+        );
+        let short = Cfd::new(
+            Order {
+                position: Position::Long,
+                ..order
+            },
+            Usd::new(dec!(10_000)),
+            CfdState::outgoing_order_request(),
+        );
+        let rate = |rate| FundingRate::new(rate).unwrap();
+
+        assert_eq!(long.position(), Position::Long);
+        assert!(long.is_within_quoted_funding_rate(rate(dec!(0.001))));
+        assert!(long.is_within_quoted_funding_rate(rate(dec!(-0.001))));
+        assert!(!long.is_within_quoted_funding_rate(rate(dec!(0.002))));
+
+        assert_eq!(short.position(), Position::Short);
+        assert!(short.is_within_quoted_funding_rate(rate(dec!(0.001))));
+        assert!(short.is_within_quoted_funding_rate(rate(dec!(0.002))));
+        assert!(!short.is_within_quoted_funding_rate(rate(dec!(0.0005))));
+    }
+
     #[test]
     fn order_id_serde_roundtrip() {
         let id = OrderId::default();
@@ -1658,6 +2801,430 @@ mod tests {
 
         assert_eq!(id, deserialized);
     }
+
+    #[test]
+    fn order_without_contract_type_deserializes_as_inverse() {
+        let order = Order::dummy().with_contract_type(ContractType::Linear);
+        let mut json = serde_json::to_value(&order).unwrap();
+        json.as_object_mut().unwrap().remove("contract_type");
+
+        let deserialized = serde_json::from_value::<Order>(json).unwrap();
+
+        assert_eq!(deserialized.contract_type, ContractType::Inverse);
+    }
+
+    #[test]
+    fn settlement_triggers_fire_in_the_direction_of_the_position() {
+        let triggers = SettlementTriggers {
+            stop_loss: Some(Price::new(dec!(40_000)).unwrap()),
+            take_profit: Some(Price::new(dec!(60_000)).unwrap()),
+        };
+        let price = |price| Price::new(price).unwrap();
+
+        assert!(triggers.validate(&Position::Long).is_ok());
+        assert!(!triggers.is_triggered(&Position::Long, price(dec!(50_000))));
+        assert!(triggers.is_triggered(&Position::Long, price(dec!(40_000))));
+        assert!(triggers.is_triggered(&Position::Long, price(dec!(60_001))));
+
+        let triggers = SettlementTriggers {
+            stop_loss: triggers.take_profit,
+            take_profit: triggers.stop_loss,
+        };
+
+        assert!(triggers.validate(&Position::Long).is_err());
+        assert!(triggers.validate(&Position::Short).is_ok());
+        assert!(!triggers.is_triggered(&Position::Short, price(dec!(50_000))));
+        assert!(triggers.is_triggered(&Position::Short, price(dec!(60_000))));
+        assert!(triggers.is_triggered(&Position::Short, price(dec!(39_999))));
+        assert!(!SettlementTriggers::default().is_triggered(&Position::Long, price(dec!(1))));
+    }
+
+    #[test]
+    fn events_only_apply_in_the_states_they_can_happen_in() {
+        let order = Order::dummy();
+        let mut cfd = Cfd::new(
+            order,
+            Usd::new(dec!(10_000)),
+            CfdState::outgoing_order_request(),
+        );
+        let timestamp = Timestamp::new(1_000_000);
+
+        assert!(cfd
+            .apply(
+                CfdEvent::Created {
+                    state: CfdState::contract_setup(),
+                    quantity: Usd::new(dec!(10_000)),
+                    price: Price::new(dec!(40_000)).unwrap(),
+                    funding: SignedAmount::ZERO,
+                },
+                timestamp
+            )
+            .is_err());
+        assert!(cfd
+            .apply(
+                CfdEvent::ContractSetupCompleted {
+                    dlc: Dlc::dummy(Amount::ONE_BTC, Amount::ONE_BTC)
+                },
+                timestamp
+            )
+            .is_err());
+        assert!(matches!(cfd.state, CfdState::OutgoingOrderRequest { .. }));
+
+        assert!(cfd
+            .apply(CfdEvent::ContractSetupStarted, timestamp)
+            .unwrap());
+        assert!(cfd.apply(CfdEvent::OrderRejected, timestamp).is_err());
+        assert!(cfd
+            .apply(
+                CfdEvent::ContractSetupFailed {
+                    info: String::from("dummy failure")
+                },
+                timestamp
+            )
+            .unwrap());
+        assert!(cfd
+            .apply(CfdEvent::ContractSetupStarted, timestamp)
+            .is_err());
+        assert!(matches!(cfd.state, CfdState::SetupFailed { .. }));
+    }
+
+    #[test]
+    fn replay_starts_from_the_terms_the_cfd_was_created_with() {
+        let order = Order::dummy();
+        let mut cfd = Cfd::new(order, Usd::new(dec!(1000)), CfdState::contract_setup());
+        let dlc = Dlc::dummy(Amount::ONE_BTC, Amount::ONE_BTC);
+        let lock_finality = CfdEvent::StateChange(CfdStateChangeEvent::Monitor(
+            monitor::Event::LockFinality(cfd.order.id),
+        ));
+
+        let mut events = vec![(CfdEvent::created(&cfd), Timestamp::new(1))];
+        for event in vec![
+            CfdEvent::ContractSetupCompleted { dlc: dlc.clone() },
+            lock_finality.clone(),
+            CfdEvent::RollOverCompleted {
+                dlc: dlc.clone(),
+                funding_fee: SignedAmount::from_sat(1000),
+            },
+            CfdEvent::PartialSettlementCompleted {
+                dlc: dlc.clone(),
+                quantity: Usd::new(dec!(600)),
+                price: Price::new(dec!(41_000)).unwrap(),
+            },
+            lock_finality.clone(),
+            CfdEvent::PartialSettlementCompleted {
+                dlc: dlc.clone(),
+                quantity: Usd::new(dec!(300)),
+                price: Price::new(dec!(42_000)).unwrap(),
+            },
+            lock_finality.clone(),
+            CfdEvent::AddToPositionCompleted {
+                dlc,
+                quantity: Usd::new(dec!(800)),
+                price: Price::new(dec!(50_000)).unwrap(),
+            },
+            lock_finality,
+        ] {
+            let timestamp = Timestamp::new(events.len() as i64 + 1);
+            assert!(cfd.apply(event.clone(), timestamp).unwrap());
+            events.push((event, timestamp));
+        }
+
+        // The order and quantity of the CFD changed since it was created
+        let replayed = Cfd::replay(cfd.order.clone(), cfd.quantity_usd, events.clone()).unwrap();
+        let timeline =
+            crate::audit_log::timeline(cfd.order.clone(), cfd.quantity_usd, events).unwrap();
+
+        assert_eq!(replayed, cfd);
+        assert_eq!(replayed.quantity_usd, Usd::new(dec!(800)));
+        assert_eq!(replayed.order.price, Price::new(dec!(50_000)).unwrap());
+        assert_eq!(replayed.funding, SignedAmount::from_sat(300));
+        assert_eq!(timeline.len(), 10);
+        assert_eq!(timeline.last().unwrap().state, cfd.state.to_string());
+    }
+
+    #[test]
+    fn partial_settlement_pays_out_the_settled_part_according_to_the_curve() {
+        let order = Order::dummy();
+        let cfd = Cfd::new(
+            order,
+            Usd::new(dec!(10_000)),
+            CfdState::outgoing_order_request(),
+        );
+        let price = Price::new(dec!(42_000)).unwrap();
+
+        let partial = cfd
+            .calculate_partial_settlement(Usd::new(dec!(2_500)), price)
+            .unwrap();
+        let settled_part = cfd
+            .with_quantity(Usd::new(dec!(2_500)))
+            .unwrap()
+            .calculate_settlement(price)
+            .unwrap();
+
+        assert_eq!(partial.quantity, Usd::new(dec!(2_500)));
+        assert_eq!(
+            (partial.taker, partial.maker),
+            (settled_part.taker, settled_part.maker)
+        );
+        assert!(cfd
+            .calculate_partial_settlement(Usd::new(dec!(10_000)), price)
+            .is_err());
+        assert!(
+            cfd.calculate_partial_settlement(Usd::new(dec!(9_950)), price)
+                .is_err(),
+            "remaining position has to be at least the minimum quantity"
+        );
+    }
+
+    #[test]
+    fn partial_settlement_transaction_pays_the_fee_out_of_the_payouts() {
+        let dlc = Dlc::dummy(Amount::from_sat(500_000), Amount::from_sat(100_000));
+        let (lock_outpoint, lock_amount) = dlc.lock_output();
+        let proposal =
+            dummy_partial_settlement(Amount::from_sat(100_000), Amount::from_sat(20_000));
+
+        let (tx, _) = dlc
+            .partial_settlement_transaction(
+                &proposal,
+                (Amount::from_sat(400_000), Amount::from_sat(80_000)),
+            )
+            .unwrap();
+
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, lock_outpoint);
+        assert_eq!(tx.output.len(), 3);
+        assert_eq!(tx.output[0].value, 480_000);
+        assert_eq!(tx.output[0].script_pubkey, dlc.lock.1.script_pubkey());
+        assert_eq!(
+            tx.output[1].script_pubkey,
+            dlc.maker_address.script_pubkey()
+        );
+        assert_eq!(
+            tx.output[2].script_pubkey,
+            dlc.taker_address.script_pubkey()
+        );
+
+        let fee_share = 100_000 - tx.output[1].value;
+        assert_eq!(20_000 - tx.output[2].value, fee_share);
+
+        let fee = lock_amount.as_sat() - tx.output.iter().map(|o| o.value).sum::<u64>();
+        let expected_fee = partial_settlement_fee(&tx, &dlc.lock.1).unwrap().as_sat();
+        assert_eq!(fee, 2 * fee_share);
+        assert!(fee == expected_fee || fee == expected_fee + 1);
+    }
+
+    #[test]
+    fn partial_settlement_transaction_leaves_dust_to_the_fee() {
+        let dlc = Dlc::dummy(Amount::from_sat(500_000), Amount::from_sat(100_000));
+        let proposal = dummy_partial_settlement(Amount::from_sat(100_000), Amount::from_sat(600));
+
+        let (tx, _) = dlc
+            .partial_settlement_transaction(
+                &proposal,
+                (Amount::from_sat(400_000), Amount::from_sat(99_400)),
+            )
+            .unwrap();
+
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output[1].script_pubkey,
+            dlc.maker_address.script_pubkey()
+        );
+    }
+
+    #[test]
+    fn partial_settlement_transaction_cannot_pay_out_more_than_locked() {
+        let dlc = Dlc::dummy(Amount::from_sat(500_000), Amount::from_sat(100_000));
+        let proposal =
+            dummy_partial_settlement(Amount::from_sat(200_000), Amount::from_sat(20_000));
+
+        assert!(dlc
+            .partial_settlement_transaction(
+                &proposal,
+                (Amount::from_sat(400_000), Amount::from_sat(80_000)),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn add_to_position_transaction_spends_lock_output_and_funding() {
+        let dlc = Dlc::dummy(Amount::from_sat(500_000), Amount::from_sat(100_000));
+        let (lock_outpoint, lock_amount) = dlc.lock_output();
+        let funding_script_pubkey = dummy_address().script_pubkey();
+
+        // The maker's change happens to be worth as much as its additional margin
+        let maker_change = TxOut {
+            value: 50_000,
+            script_pubkey: dummy_address().script_pubkey(),
+        };
+        let taker_change = TxOut {
+            value: 20_000,
+            script_pubkey: dummy_address().script_pubkey(),
+        };
+        let maker_funding = dummy_funding_psbt(
+            0,
+            vec![
+                maker_change.clone(),
+                TxOut {
+                    value: 50_000,
+                    script_pubkey: funding_script_pubkey.clone(),
+                },
+            ],
+        );
+        let taker_funding = dummy_funding_psbt(
+            1,
+            vec![
+                TxOut {
+                    value: 10_000,
+                    script_pubkey: funding_script_pubkey.clone(),
+                },
+                taker_change.clone(),
+            ],
+        );
+
+        let (psbt, _) = dlc
+            .add_to_position_transaction(
+                (&maker_funding, Amount::from_sat(50_000)),
+                (&taker_funding, Amount::from_sat(10_000)),
+            )
+            .unwrap();
+        let tx = psbt.global.unsigned_tx;
+
+        assert_eq!(tx.input.len(), 3);
+        assert_eq!(tx.input[0].previous_output, lock_outpoint);
+        assert_eq!(psbt.inputs.len(), 3);
+        assert_eq!(
+            tx.output,
+            vec![
+                TxOut {
+                    value: lock_amount.as_sat() + 60_000,
+                    script_pubkey: dlc.lock.1.script_pubkey(),
+                },
+                maker_change,
+                taker_change,
+            ]
+        );
+
+        let other_script_funding = dummy_funding_psbt(
+            1,
+            vec![TxOut {
+                value: 10_000,
+                script_pubkey: dummy_address().script_pubkey(),
+            }],
+        );
+        assert!(dlc
+            .add_to_position_transaction(
+                (&maker_funding, Amount::from_sat(50_000)),
+                (&other_script_funding, Amount::from_sat(10_000)),
+            )
+            .is_err());
+        assert!(dlc
+            .add_to_position_transaction(
+                (&maker_funding, Amount::from_sat(40_000)),
+                (&taker_funding, Amount::from_sat(10_000)),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn add_to_position_transaction_is_finalized_with_both_signatures() {
+        let dlc = Dlc::dummy(Amount::from_sat(500_000), Amount::from_sat(100_000));
+        let funding_script_pubkey = dummy_address().script_pubkey();
+        let funding = |index| {
+            dummy_funding_psbt(
+                index,
+                vec![TxOut {
+                    value: 10_000,
+                    script_pubkey: funding_script_pubkey.clone(),
+                }],
+            )
+        };
+
+        let (psbt, own_sig) = dlc
+            .add_to_position_transaction(
+                (&funding(0), Amount::from_sat(10_000)),
+                (&funding(1), Amount::from_sat(10_000)),
+            )
+            .unwrap();
+        let tx = psbt.global.unsigned_tx;
+        let (_, lock_amount) = dlc.lock_output();
+        let sighash = spending_tx_sighash(&tx, &dlc.lock.1, lock_amount);
+        let counterparty_sig = SECP256K1.sign(&sighash, &Dlc::dummy_counterparty_identity());
+
+        let tx = dlc
+            .finalize_add_to_position_transaction(tx, own_sig, counterparty_sig)
+            .unwrap();
+
+        assert!(!tx.input[0].witness.is_empty());
+    }
+
+    #[test]
+    fn open_cfds_are_due_for_roll_over_until_they_expire() {
+        let expiry = datetime!(2021-11-19 10:00:00).assume_utc();
+        let order = Order {
+            oracle_event_id: BitMexPriceEventId::with_20_digits(expiry),
+            ..Order::dummy()
+        };
+        let open = CfdState::Open {
+            common: CfdStateCommon::default(),
+            dlc: Dlc::dummy(Amount::ONE_BTC, Amount::ONE_BTC),
+            attestation: None,
+            collaborative_close: None,
+        };
+        let cfd = Cfd::new(order.clone(), Usd::new(dec!(10_000)), open);
+        let before_expiry = Duration::hours(2);
+
+        assert_eq!(
+            cfd.roll_over_due(before_expiry, expiry - Duration::hours(3)),
+            RollOverDue::No
+        );
+        assert_eq!(
+            cfd.roll_over_due(before_expiry, expiry - Duration::hours(1)),
+            RollOverDue::Yes
+        );
+        assert_eq!(
+            cfd.roll_over_due(before_expiry, expiry),
+            RollOverDue::Expired
+        );
+
+        let pending = Cfd::new(order, Usd::new(dec!(10_000)), CfdState::contract_setup());
+        assert_eq!(
+            pending.roll_over_due(before_expiry, expiry - Duration::hours(1)),
+            RollOverDue::No
+        );
+    }
+
+    fn dummy_partial_settlement(maker: Amount, taker: Amount) -> PartialSettlementProposal {
+        PartialSettlementProposal {
+            order_id: OrderId::default(),
+            timestamp: Timestamp::new(0),
+            quantity: Usd::new(dec!(1_000)),
+            taker,
+            maker,
+            price: Price::new(dec!(50_000)).unwrap(),
+        }
+    }
+
+    fn dummy_address() -> Address {
+        let (_, pk) = crate::keypair::new(&mut rand::thread_rng());
+        Address::p2wpkh(&pk, bdk::bitcoin::Network::Regtest).unwrap()
+    }
+
+    fn dummy_funding_psbt(index: u32, output: Vec<TxOut>) -> PartiallySignedTransaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::default(), index),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output,
+        };
+
+        PartiallySignedTransaction::from_unsigned_tx(tx).unwrap()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -1671,6 +3238,26 @@ pub struct Cet {
     pub n_bits: usize,
 }
 
+/// The fee rate of partial settlement transactions in satoshi per vbyte, both parties pay half of
+/// the fee out of the payouts of the settled part.
+const PARTIAL_SETTLEMENT_SATS_PER_VBYTE: u64 = 5;
+
+/// Outputs below this amount are not relayed by the network.
+const DUST_LIMIT_SATS: u64 = 546;
+
+/// The fee of `tx` spending the lock output described by `lock_desc`, once it is signed.
+fn partial_settlement_fee(tx: &Transaction, lock_desc: &Descriptor<PublicKey>) -> Result<Amount> {
+    // The segwit marker and flag add 2 weight units on top of the witness
+    let weight = tx.get_weight()
+        + 2
+        + lock_desc
+            .max_satisfaction_weight()
+            .context("Failed to estimate the weight of spending the lock output")?;
+    let vbytes = (weight as u64 + 3) / 4;
+
+    Ok(Amount::from_sat(vbytes * PARTIAL_SETTLEMENT_SATS_PER_VBYTE))
+}
+
 /// Contains all data we've assembled about the CFD through the setup protocol.
 ///
 /// All contained signatures are the signatures of THE OTHER PARTY.
@@ -1701,20 +3288,18 @@ pub struct Dlc {
 }
 
 impl Dlc {
+    /// The oracle event the CETs of this DLC settle on.
+    pub fn settlement_event_id(&self) -> Option<BitMexPriceEventId> {
+        self.cets.keys().max().copied()
+    }
+
     /// Create a close transaction based on the current contract and a settlement proposals
     pub fn close_transaction(
         &self,
         proposal: &crate::model::cfd::SettlementProposal,
     ) -> Result<(Transaction, Signature)> {
-        let (lock_tx, lock_desc) = &self.lock;
-        let (lock_outpoint, lock_amount) = {
-            let outpoint = lock_tx
-                .outpoint(&lock_desc.script_pubkey())
-                .expect("lock script to be in lock tx");
-            let amount = Amount::from_sat(lock_tx.output[outpoint.vout as usize].value);
-
-            (outpoint, amount)
-        };
+        let lock_desc = &self.lock.1;
+        let (lock_outpoint, lock_amount) = self.lock_output();
         let (tx, sighash) = maia::close_transaction(
             lock_desc,
             lock_outpoint,
@@ -1729,6 +3314,182 @@ impl Dlc {
         Ok((tx, sig))
     }
 
+    /// Create the transaction that pays out the settled part of a partial settlement proposal
+    /// and locks the amounts of the remaining position in a new lock output.
+    ///
+    /// The new lock output uses the same descriptor as the current one, so it takes the place
+    /// of the lock transaction for the remaining position. Payouts below the dust limit are
+    /// left to the fee.
+    pub fn partial_settlement_transaction(
+        &self,
+        proposal: &PartialSettlementProposal,
+        (maker_lock_amount, taker_lock_amount): (Amount, Amount),
+    ) -> Result<(Transaction, Signature)> {
+        let lock_desc = &self.lock.1;
+        let (lock_outpoint, lock_amount) = self.lock_output();
+
+        let new_lock_amount = maker_lock_amount + taker_lock_amount;
+        let lock_output = TxOut {
+            value: new_lock_amount.as_sat(),
+            script_pubkey: lock_desc.script_pubkey(),
+        };
+        let payouts = [
+            (&self.maker_address, proposal.maker),
+            (&self.taker_address, proposal.taker),
+        ];
+        let transaction = |payouts: &[(&Address, Amount)]| Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: lock_outpoint,
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output: std::iter::once(lock_output.clone())
+                .chain(payouts.iter().map(|(address, amount)| TxOut {
+                    value: amount.as_sat(),
+                    script_pubkey: address.script_pubkey(),
+                }))
+                .collect(),
+        };
+
+        // The fee is estimated with both payouts, dropping a payout only makes it pay a bit more
+        let fee = partial_settlement_fee(&transaction(&payouts), lock_desc)?;
+        let fee_share = Amount::from_sat((fee.as_sat() + 1) / 2);
+        let payouts = payouts
+            .iter()
+            .map(|(address, amount)| (*address, amount.checked_sub(fee_share).unwrap_or_default()))
+            .collect::<Vec<_>>();
+
+        let paid_out = payouts.iter().map(|(_, amount)| *amount).sum::<Amount>();
+        if new_lock_amount + paid_out > lock_amount {
+            bail!(
+                "Partial settlement pays out more than the {} locked in the contract",
+                lock_amount
+            )
+        }
+
+        let payouts = payouts
+            .into_iter()
+            .filter(|(_, amount)| amount.as_sat() >= DUST_LIMIT_SATS)
+            .collect::<Vec<_>>();
+
+        let tx = transaction(&payouts);
+
+        let sighash = spending_tx_sighash(&tx, lock_desc, lock_amount);
+        let sig = SECP256K1.sign(&sighash, &self.identity);
+
+        Ok((tx, sig))
+    }
+
+    /// Create the lock transaction of an increased position.
+    ///
+    /// It spends the current lock output together with the additional funding of both parties
+    /// into a single lock output with the same descriptor. The funding PSBTs are built like the
+    /// ones of the initial lock transaction: their output paying `amount` to the script both
+    /// wallets fund is replaced by the lock output, all other outputs are kept as change.
+    ///
+    /// Returns the unsigned lock transaction and our signature spending the current lock output.
+    pub fn add_to_position_transaction(
+        &self,
+        maker: (&PartiallySignedTransaction, Amount),
+        taker: (&PartiallySignedTransaction, Amount),
+    ) -> Result<(PartiallySignedTransaction, Signature)> {
+        let lock_desc = &self.lock.1;
+        let (lock_outpoint, lock_amount) = self.lock_output();
+
+        let lock_output = TxOut {
+            value: (lock_amount + maker.1 + taker.1).as_sat(),
+            script_pubkey: lock_desc.script_pubkey(),
+        };
+        let lock_input = TxIn {
+            previous_output: lock_outpoint,
+            script_sig: Script::new(),
+            sequence: 0xFFFFFFFF,
+            witness: vec![],
+        };
+
+        let funding_script_pubkey = funding_script_pubkey(maker.0, taker.0)?;
+        let funding = [maker, taker];
+        let inputs = funding
+            .iter()
+            .flat_map(|(psbt, _)| psbt.global.unsigned_tx.input.iter().cloned());
+        let change = funding
+            .iter()
+            .map(|(psbt, amount)| change_outputs(psbt, &funding_script_pubkey, *amount))
+            .collect::<Result<Vec<_>>>()?;
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: std::iter::once(lock_input).chain(inputs).collect(),
+            output: std::iter::once(lock_output)
+                .chain(change.into_iter().flatten())
+                .collect(),
+        };
+
+        let sighash = spending_tx_sighash(&tx, lock_desc, lock_amount);
+        let sig = SECP256K1.sign(&sighash, &self.identity);
+
+        let n_outputs = tx.output.len();
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx)?;
+        psbt.inputs = std::iter::once(psbt::Input {
+            witness_utxo: Some(TxOut {
+                value: lock_amount.as_sat(),
+                script_pubkey: lock_desc.script_pubkey(),
+            }),
+            ..Default::default()
+        })
+        .chain(
+            funding
+                .iter()
+                .flat_map(|(psbt, _)| psbt.inputs.iter().cloned()),
+        )
+        .collect();
+        psbt.outputs = vec![Default::default(); n_outputs];
+
+        Ok((psbt, sig))
+    }
+
+    /// Adds the witness spending the current lock output to the lock transaction of an increased
+    /// position, whose other inputs are already signed by the wallets.
+    pub fn finalize_add_to_position_transaction(
+        &self,
+        mut tx: Transaction,
+        own_sig: Signature,
+        counterparty_sig: Signature,
+    ) -> Result<Transaction> {
+        let own_pk = PublicKey::new(secp256k1_zkp::PublicKey::from_secret_key(
+            SECP256K1,
+            &self.identity,
+        ));
+
+        let satisfier = HashMap::from_iter([
+            (own_pk, (own_sig, SigHashType::All)),
+            (
+                self.identity_counterparty,
+                (counterparty_sig, SigHashType::All),
+            ),
+        ]);
+        self.lock
+            .1
+            .satisfy(&mut tx.input[0], satisfier)
+            .context("Failed to satisfy the current lock output")?;
+
+        Ok(tx)
+    }
+
+    fn lock_output(&self) -> (OutPoint, Amount) {
+        let (lock_tx, lock_desc) = &self.lock;
+        let outpoint = lock_tx
+            .outpoint(&lock_desc.script_pubkey())
+            .expect("lock script to be in lock tx");
+        let amount = Amount::from_sat(lock_tx.output[outpoint.vout as usize].value);
+
+        (outpoint, amount)
+    }
+
     pub fn finalize_spend_transaction(
         &self,
         (close_tx, own_sig): (Transaction, Signature),
@@ -1773,6 +3534,76 @@ impl Dlc {
     }
 }
 
+#[cfg(test)]
+impl Dlc {
+    /// The secret key the counterparty of [`Dlc::dummy`] identifies with
+    pub(crate) fn dummy_counterparty_identity() -> SecretKey {
+        SecretKey::from_slice(&[2u8; 32]).unwrap()
+    }
+
+    /// A DLC with the given lock amounts, whose transactions only have the outputs the contract
+    /// updates build on
+    pub(crate) fn dummy(maker_lock_amount: Amount, taker_lock_amount: Amount) -> Self {
+        let identity = SecretKey::from_slice(&[1u8; 32]).unwrap();
+        let pk = |sk: &SecretKey| {
+            PublicKey::new(secp256k1_zkp::PublicKey::from_secret_key(SECP256K1, sk))
+        };
+        let identity_counterparty = pk(&Dlc::dummy_counterparty_identity());
+        let (revocation, _) = crate::keypair::new(&mut rand::thread_rng());
+        let (publish, _) = crate::keypair::new(&mut rand::thread_rng());
+        let (_, revocation_pk_counterparty) = crate::keypair::new(&mut rand::thread_rng());
+        let (_, publish_pk_counterparty) = crate::keypair::new(&mut rand::thread_rng());
+
+        let address = || {
+            let (_, pk) = crate::keypair::new(&mut rand::thread_rng());
+            Address::p2wpkh(&pk, bdk::bitcoin::Network::Regtest).unwrap()
+        };
+
+        let lock_desc = maia::lock_descriptor(pk(&identity), identity_counterparty);
+        let tx_paying = |script_pubkey: Script, value: Amount| Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::default(), 0),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: value.as_sat(),
+                script_pubkey,
+            }],
+        };
+        let lock_amount = maker_lock_amount + taker_lock_amount;
+        let lock_tx = tx_paying(lock_desc.script_pubkey(), lock_amount);
+        let commit_tx = tx_paying(lock_desc.script_pubkey(), lock_amount);
+        let refund_tx = tx_paying(address().script_pubkey(), lock_amount);
+
+        let msg = secp256k1_zkp::Message::from_slice(&[3u8; 32]).unwrap();
+        let commit_encsig =
+            EcdsaAdaptorSignature::encrypt(SECP256K1, &msg, &identity, &identity_counterparty.key);
+        let refund_sig = SECP256K1.sign(&msg, &identity);
+
+        Self {
+            identity,
+            identity_counterparty,
+            revocation,
+            revocation_pk_counterparty,
+            publish,
+            publish_pk_counterparty,
+            maker_address: address(),
+            taker_address: address(),
+            lock: (lock_tx, lock_desc.clone()),
+            commit: (commit_tx, commit_encsig, lock_desc),
+            cets: HashMap::new(),
+            refund: (refund_tx, refund_sig),
+            maker_lock_amount,
+            taker_lock_amount,
+            revoked_commit: Vec::new(),
+        }
+    }
+}
+
 /// Information which we need to remember in order to construct a
 /// punishment transaction in case the counterparty publishes a
 /// revoked commit transaction.
@@ -1833,4 +3664,8 @@ impl CollaborativeSettlement {
     pub fn payout(&self) -> Amount {
         self.payout
     }
+
+    pub fn price(&self) -> Price {
+        self.price
+    }
 }
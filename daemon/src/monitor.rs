@@ -8,6 +8,7 @@ use bdk::bitcoin::{PublicKey, Script, Txid};
 use bdk::descriptor::Descriptor;
 use bdk::electrum_client::{ElectrumApi, GetHistoryRes, HeaderNotification};
 use bdk::miniscript::DescriptorTrait;
+use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
 use std::collections::{BTreeMap, HashMap};
 use std::convert::{TryFrom, TryInto};
@@ -531,7 +532,7 @@ impl Add<u32> for BlockHeight {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Event {
     LockFinality(OrderId),
     CommitFinality(OrderId),
@@ -1,6 +1,6 @@
 use std::fmt;
 
-use crate::model::{Leverage, Price, Usd};
+use crate::model::{ContractType, Leverage, PayoutDensity, PayoutResolution, Price, Usd};
 use crate::payout_curve::curve::Curve;
 use anyhow::{Context, Result};
 use bdk::bitcoin;
@@ -8,7 +8,11 @@ use itertools::Itertools;
 use maia::{generate_payouts, Payout};
 use ndarray::prelude::*;
 use num::{FromPrimitive, ToPrimitive};
+use once_cell::sync::Lazy;
 use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::{Mutex, MutexGuard};
 
 mod basis;
 mod basis_eval;
@@ -39,24 +43,149 @@ mod utils;
 /// * quantity: Interger number of one-dollar USD contracts contained in the
 /// CFD; expressed as a Usd amount
 /// * leverage: Leveraging used by the taker
+/// * contract_type: Whether the payout is inverse or linear in the price
+/// * resolution: Number, alignment and distribution of the payout segments
 ///
 /// ### Returns
 ///
 /// The list of [`Payout`]s for the given price, quantity and leverage.
-pub fn calculate(price: Price, quantity: Usd, leverage: Leverage) -> Result<Vec<Payout>> {
-    let payouts = calculate_payout_parameters(price, quantity, leverage)?
-        .into_iter()
-        .map(PayoutParameter::into_payouts)
-        .flatten_ok()
-        .collect::<Result<Vec<_>>>()?;
+pub fn calculate(
+    price: Price,
+    quantity: Usd,
+    leverage: Leverage,
+    contract_type: ContractType,
+    resolution: PayoutResolution,
+) -> Result<Vec<Payout>> {
+    let payouts =
+        calculate_payout_parameters(price, quantity, leverage, contract_type, resolution)?
+            .into_iter()
+            .map(PayoutParameter::into_payouts)
+            .flatten_ok()
+            .collect::<Result<Vec<_>>>()?;
+
+    Ok(payouts)
+}
+
+/// Upper bound for the number of payout curves kept in [`PAYOUT_CACHE`].
+const PAYOUT_CACHE_CAPACITY: usize = 64;
+
+type PayoutCacheKey = (Decimal, Decimal, u8, ContractType, PayoutResolution, i64);
+
+static PAYOUT_CACHE: Lazy<Mutex<HashMap<PayoutCacheKey, Vec<Payout>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Like [`calculate`], but reuses the payouts of earlier calls with the same parameters.
+///
+/// The payouts of a CFD only depend on its order, so every roll-over would otherwise compute the
+/// same payout curve again.
+pub fn calculate_cached(
+    price: Price,
+    quantity: Usd,
+    leverage: Leverage,
+    contract_type: ContractType,
+    resolution: PayoutResolution,
+) -> Result<Vec<Payout>> {
+    calculate_with_funding(
+        price,
+        quantity,
+        leverage,
+        contract_type,
+        resolution,
+        bitcoin::SignedAmount::ZERO,
+    )
+}
+
+/// Like [`calculate_cached`], but with `funding` moved from the long to the short party in every
+/// payout.
+///
+/// A negative amount is moved from the short to the long party. The party paying funding cannot
+/// pay more than its payout, the total of each payout stays the same.
+pub fn calculate_with_funding(
+    price: Price,
+    quantity: Usd,
+    leverage: Leverage,
+    contract_type: ContractType,
+    resolution: PayoutResolution,
+    funding: bitcoin::SignedAmount,
+) -> Result<Vec<Payout>> {
+    let key = (
+        price.into_decimal(),
+        quantity.into_decimal(),
+        leverage.get(),
+        contract_type,
+        resolution,
+        funding.as_sat(),
+    );
+
+    if let Some(payouts) = lock_payout_cache().get(&key) {
+        return Ok(payouts.clone());
+    }
+
+    let payouts =
+        calculate_payout_parameters(price, quantity, leverage, contract_type, resolution)?
+            .into_iter()
+            .map(|parameter| parameter.with_funding(funding))
+            .map(PayoutParameter::into_payouts)
+            .flatten_ok()
+            .collect::<Result<Vec<_>>>()?;
+
+    let mut cache = lock_payout_cache();
+    if cache.len() >= PAYOUT_CACHE_CAPACITY {
+        cache.clear();
+    }
+    cache.insert(key, payouts.clone());
 
     Ok(payouts)
 }
 
+fn lock_payout_cache() -> MutexGuard<'static, HashMap<PayoutCacheKey, Vec<Payout>>> {
+    // The cache is only ever written with complete entries, a poisoned lock is safe to reuse
+    PAYOUT_CACHE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The payout of both parties for a range of prices.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayoutInterval {
+    pub price_range: RangeInclusive<u64>,
+    pub long: bitcoin::Amount,
+    pub short: bitcoin::Amount,
+}
+
+/// Calculate the payouts in the price intervals the CETs are generated for.
+///
+/// Takes the same parameters as [`calculate`], but returns one entry per interval instead of
+/// one per CET. This is meant for displaying the payout curve, not for setting up contracts.
+pub fn calculate_intervals(
+    price: Price,
+    quantity: Usd,
+    leverage: Leverage,
+    contract_type: ContractType,
+    resolution: PayoutResolution,
+) -> Result<Vec<PayoutInterval>> {
+    let intervals =
+        calculate_payout_parameters(price, quantity, leverage, contract_type, resolution)?
+            .into_iter()
+            .map(|parameter| PayoutInterval {
+                price_range: parameter.left_bound..=parameter.right_bound,
+                long: bitcoin::Amount::from_sat(parameter.long_amount),
+                short: bitcoin::Amount::from_sat(parameter.short_amount),
+            })
+            .collect();
+
+    Ok(intervals)
+}
+
 const CONTRACT_VALUE: f64 = 1.;
-const N_PAYOUTS: usize = 200;
 const SHORT_LEVERAGE: usize = 1;
 
+/// How much finer the payouts at the initial price are compared to uniform density, must be in
+/// `[0, 1)`.
+///
+/// Segments at the liquidation prices are coarser by the same factor.
+const ADAPTIVE_DENSITY_FACTOR: f64 = 0.5;
+
 /// Internal calculate function for the payout curve.
 ///
 /// To ease testing, we write our tests against this function because it has a more human-friendly
@@ -65,6 +194,8 @@ fn calculate_payout_parameters(
     price: Price,
     quantity: Usd,
     long_leverage: Leverage,
+    contract_type: ContractType,
+    resolution: PayoutResolution,
 ) -> Result<Vec<PayoutParameter>> {
     let initial_rate = price
         .try_into_f64()
@@ -73,23 +204,37 @@ fn calculate_payout_parameters(
         .try_into_u64()
         .context("Cannot convert quantity to u64")? as usize;
 
-    let payout_curve = PayoutCurve::new(
-        initial_rate,
-        long_leverage.get() as usize,
-        SHORT_LEVERAGE,
-        quantity,
-        CONTRACT_VALUE,
-        None,
-    )?;
+    let payout_curve = match contract_type {
+        ContractType::Inverse => PayoutCurve::new(
+            initial_rate,
+            long_leverage.get() as usize,
+            SHORT_LEVERAGE,
+            quantity,
+            CONTRACT_VALUE,
+            None,
+        )?,
+        ContractType::Linear => PayoutCurve::new_linear(
+            initial_rate,
+            long_leverage.get() as usize,
+            SHORT_LEVERAGE,
+            quantity,
+            CONTRACT_VALUE,
+            None,
+        )?,
+    };
 
     let payout_parameters = payout_curve
-        .generate_payout_scheme(N_PAYOUTS)?
+        .generate_payout_scheme(resolution.n_payouts(), resolution.density())?
         .rows()
         .into_iter()
         .map(|row| {
             let left_bound = row[0] as u64;
             let right_bound = row[1] as u64;
-            let long_amount = row[2];
+            // the spline of the linear curve may overshoot its flat parts by a rounding error
+            let long_amount = match contract_type {
+                ContractType::Inverse => row[2],
+                ContractType::Linear => row[2].clamp(0., payout_curve.total_value),
+            };
 
             let short_amount = to_sats(payout_curve.total_value - long_amount)?;
             let long_amount = to_sats(long_amount)?;
@@ -103,7 +248,35 @@ fn calculate_payout_parameters(
         })
         .collect::<Result<Vec<_>>>()?;
 
-    Ok(payout_parameters)
+    Ok(align_to_buckets(
+        payout_parameters,
+        resolution.bucket_size(),
+    ))
+}
+
+/// Moves the boundaries between payout segments to the closest multiple of `bucket_size`.
+///
+/// Segments that become empty are dropped, their price range is covered by the preceding
+/// segment.
+fn align_to_buckets(parameters: Vec<PayoutParameter>, bucket_size: u64) -> Vec<PayoutParameter> {
+    let mut aligned = Vec::<PayoutParameter>::with_capacity(parameters.len());
+
+    for mut parameter in parameters {
+        if let Some(previous) = aligned.last_mut() {
+            let left_bound = (parameter.left_bound + bucket_size / 2) / bucket_size * bucket_size;
+            if left_bound <= previous.left_bound || left_bound > parameter.right_bound {
+                previous.right_bound = parameter.right_bound;
+                continue;
+            }
+
+            previous.right_bound = left_bound - 1;
+            parameter.left_bound = left_bound;
+        }
+
+        aligned.push(parameter);
+    }
+
+    aligned
 }
 
 #[derive(PartialEq)]
@@ -115,6 +288,18 @@ struct PayoutParameter {
 }
 
 impl PayoutParameter {
+    fn with_funding(self, funding: bitcoin::SignedAmount) -> Self {
+        let total = self.long_amount + self.short_amount;
+        let long_amount =
+            (self.long_amount as i64 - funding.as_sat()).clamp(0, total as i64) as u64;
+
+        PayoutParameter {
+            long_amount,
+            short_amount: total - long_amount,
+            ..self
+        }
+    }
+
     fn into_payouts(self) -> Result<Vec<Payout>> {
         generate_payouts(
             self.left_bound..=self.right_bound,
@@ -185,6 +370,7 @@ pub enum Error {
 #[derive(Clone, Debug)]
 struct PayoutCurve {
     curve: Curve,
+    initial_rate: f64,
     has_upper_limit: bool,
     lower_corner: f64,
     upper_corner: f64,
@@ -232,6 +418,7 @@ impl PayoutCurve {
 
         Ok(PayoutCurve {
             curve,
+            initial_rate,
             has_upper_limit: bounds.2,
             lower_corner: bounds.0,
             upper_corner,
@@ -239,7 +426,63 @@ impl PayoutCurve {
         })
     }
 
-    pub fn generate_payout_scheme(&self, n_segments: usize) -> Result<Array2<f64>, Error> {
+    /// Builds the payout curve of a linear contract.
+    ///
+    /// In contrast to the inverse contract the payout of the long party changes linearly with
+    /// the price. This means the short party is liquidated once the price rises by the inverse
+    /// of its leverage, hence the curve always has an upper limit.
+    fn new_linear(
+        initial_rate: f64,
+        leverage_long: usize,
+        leverage_short: usize,
+        n_contracts: usize,
+        contract_value: f64,
+        tolerance: Option<f64>,
+    ) -> Result<Self, Error> {
+        let tolerance = tolerance.unwrap_or(1e-6);
+        let (lower_corner, upper_corner) =
+            linear_cutoffs(initial_rate, leverage_long, leverage_short);
+        let total_value = pool_value(
+            initial_rate,
+            n_contracts,
+            contract_value,
+            leverage_long,
+            leverage_short,
+        );
+        let mut curve = curve_factory::line((0., 0.), (lower_corner, 0.), false)?;
+
+        let payout = create_long_linear_payout_function(
+            initial_rate,
+            n_contracts,
+            contract_value,
+            leverage_long,
+        );
+        let variable_payout =
+            curve_factory::fit(payout, lower_corner, upper_corner, Some(tolerance), None)?;
+        curve.append(variable_payout)?;
+
+        let upper_liquidation = curve_factory::line(
+            (upper_corner, total_value),
+            (4. * initial_rate, total_value),
+            false,
+        )?;
+        curve.append(upper_liquidation)?;
+
+        Ok(PayoutCurve {
+            curve,
+            initial_rate,
+            has_upper_limit: true,
+            lower_corner,
+            upper_corner,
+            total_value,
+        })
+    }
+
+    pub fn generate_payout_scheme(
+        &self,
+        n_segments: usize,
+        density: PayoutDensity,
+    ) -> Result<Array2<f64>, Error> {
         let n_min;
         if self.has_upper_limit {
             n_min = 3;
@@ -251,12 +494,15 @@ impl PayoutCurve {
             return Result::Err(Error::InvalidSegmentation);
         }
 
-        let t;
+        let mut t;
         if self.has_upper_limit {
             t = self.build_sampling_vector_upper_bounded(n_segments);
         } else {
             t = self.build_sampling_vector_upper_unbounded(n_segments)
         }
+        if density == PayoutDensity::Adaptive {
+            self.concentrate_samples_at_initial_rate(&mut t);
+        }
 
         let mut z_arr = self.curve.evaluate(&mut &[t][..])?;
         if self.has_upper_limit {
@@ -328,6 +574,33 @@ impl PayoutCurve {
         Array1::<f64>::from_vec(vec)
     }
 
+    /// Moves the samples between the corners of the curve towards the initial rate.
+    ///
+    /// Each side of the initial rate is mapped onto itself by a blend of the identity and a
+    /// quadratic, which keeps the order of the samples and leaves the corners in place.
+    fn concentrate_samples_at_initial_rate(&self, t: &mut Array1<f64>) {
+        let knots = &self.curve.spline.knots(0, None)[0];
+        let klen = knots.len();
+        let lower = knots[1];
+        let upper = if self.has_upper_limit {
+            knots[klen - 2]
+        } else {
+            knots[klen - 1]
+        };
+        // within the variable part the parametric domain is the price shifted to the lower corner
+        let center = lower + (self.initial_rate - self.lower_corner);
+
+        let warp = |s: f64| ADAPTIVE_DENSITY_FACTOR * s * s + (1. - ADAPTIVE_DENSITY_FACTOR) * s;
+
+        for e in t.iter_mut().filter(|e| **e > lower && **e < upper) {
+            if *e <= center {
+                *e = center - (center - lower) * warp((center - *e) / (center - lower));
+            } else {
+                *e = center + (upper - center) * warp((*e - center) / (upper - center));
+            }
+        }
+    }
+
     fn modify_samples_bounded(&self, arr: &mut Array2<f64>) {
         let n = arr.shape()[0];
         let capacity = 2 * (n - 2);
@@ -414,6 +687,20 @@ fn cutoffs(initial_rate: f64, leverage_long: usize, leverage_short: usize) -> (f
     (a, b, true)
 }
 
+/// The liquidation prices of the long and short party of a linear contract.
+///
+/// With a long leverage of one the long party is only liquidated at a price of zero. Prices are
+/// attested as integers, so in that case the lower corner is moved to the smallest price that can
+/// be attested.
+fn linear_cutoffs(initial_rate: f64, leverage_long: usize, leverage_short: usize) -> (f64, f64) {
+    let ll_64 = leverage_long as f64;
+    let ls_64 = leverage_short as f64;
+    let a = initial_rate * (ll_64 - 1_f64) / ll_64;
+    let b = initial_rate * (ls_64 + 1_f64) / ls_64;
+
+    (a.max(1.), b)
+}
+
 fn pool_value(
     initial_rate: f64,
     n_contracts: usize,
@@ -450,11 +737,37 @@ fn create_long_payout_function(
     }
 }
 
+/// The payout of the long party of a linear contract.
+///
+/// The position is worth `n_contracts * contract_value / initial_rate` BTC, its value changes by
+/// the relative change of the price.
+fn create_long_linear_payout_function(
+    initial_rate: f64,
+    n_contracts: usize,
+    contract_value: f64,
+    leverage_long: usize,
+) -> impl Fn(&Array1<f64>) -> Array2<f64> {
+    let n_64 = n_contracts as f64;
+    let ll_64 = leverage_long as f64;
+
+    move |t: &Array1<f64>| {
+        let mut vec = Vec::<f64>::with_capacity(2 * t.len());
+        for e in t.iter() {
+            let eval = (n_64 * contract_value / initial_rate)
+                * (1_f64 / ll_64 + (e - initial_rate) / initial_rate);
+            vec.push(*e);
+            vec.push(eval);
+        }
+
+        Array2::<f64>::from_shape_vec((t.len(), 2), vec).expect("vec is a 2D array")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
     use rust_decimal_macros::dec;
-    use std::ops::RangeInclusive;
 
     #[test]
     fn test_bounded() {
@@ -474,7 +787,9 @@ mod tests {
         )
         .unwrap();
 
-        let z = payout.generate_payout_scheme(5000).unwrap();
+        let z = payout
+            .generate_payout_scheme(5000, PayoutDensity::Uniform)
+            .unwrap();
 
         assert!(z.shape()[0] == 5000);
     }
@@ -497,7 +812,9 @@ mod tests {
         )
         .unwrap();
 
-        let z = payout.generate_payout_scheme(5000).unwrap();
+        let z = payout
+            .generate_payout_scheme(5000, PayoutDensity::Uniform)
+            .unwrap();
 
         // out-by-one error expected at this point in time
         assert!(z.shape()[0] == 5001);
@@ -509,6 +826,8 @@ mod tests {
             Price::new(dec!(54000.00)).unwrap(),
             Usd::new(dec!(3500.00)),
             Leverage::new(5).unwrap(),
+            ContractType::Inverse,
+            PayoutResolution::default(),
         )
         .unwrap();
 
@@ -725,6 +1044,8 @@ mod tests {
             Price::new(dec!(54000.00)).unwrap(),
             Usd::new(dec!(3500.00)),
             Leverage::new(5).unwrap(),
+            ContractType::Inverse,
+            PayoutResolution::default(),
         )
         .unwrap();
 
@@ -735,6 +1056,195 @@ mod tests {
         pretty_assertions::assert_eq!(actual_payouts.last().unwrap(), &upper_tail);
     }
 
+    #[test]
+    fn test_linear() {
+        let payout = PayoutCurve::new_linear(40000.0, 5, 1, 200, 100., None).unwrap();
+
+        let z = payout
+            .generate_payout_scheme(5000, PayoutDensity::Uniform)
+            .unwrap();
+
+        assert!(z.shape()[0] == 5000);
+    }
+
+    #[test]
+    fn linear_payouts_liquidate_both_parties() {
+        let actual_payouts = calculate_payout_parameters(
+            Price::new(dec!(54000.00)).unwrap(),
+            Usd::new(dec!(3500.00)),
+            Leverage::new(5).unwrap(),
+            ContractType::Linear,
+            PayoutResolution::default(),
+        )
+        .unwrap();
+
+        let first = actual_payouts.first().unwrap();
+        let last = actual_payouts.last().unwrap();
+
+        // long is liquidated at 80% of the initial price, short at twice the initial price
+        assert_eq!((first.left_bound, first.right_bound), (0, 43200));
+        assert_eq!(first.long_amount, 0);
+        assert_eq!(last.right_bound, 216000);
+        assert_eq!(last.short_amount, 0);
+        assert!(actual_payouts
+            .windows(2)
+            .all(|pair| pair[0].long_amount <= pair[1].long_amount));
+    }
+
+    #[test]
+    fn aligned_buckets_need_fewer_cets() {
+        let cets = |bucket_digits| {
+            calculate(
+                Price::new(dec!(54000.00)).unwrap(),
+                Usd::new(dec!(3500.00)),
+                Leverage::new(5).unwrap(),
+                ContractType::Inverse,
+                PayoutResolution::new(200, bucket_digits, PayoutDensity::Uniform).unwrap(),
+            )
+            .unwrap()
+            .len()
+        };
+
+        assert!(cets(6) < cets(0));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn payout_error_stays_within_tolerance(
+            price in 1_000u64..100_000,
+            quantity in 1u64..100_000,
+            leverage in 1u8..=5,
+            n_payouts in PayoutResolution::MIN_PAYOUTS..=400,
+            bucket_digits in 0u8..=6,
+            adaptive in any::<bool>(),
+            linear in any::<bool>(),
+        ) {
+            let contract_type = if linear { ContractType::Linear } else { ContractType::Inverse };
+            let density = if adaptive { PayoutDensity::Adaptive } else { PayoutDensity::Uniform };
+            let resolution = PayoutResolution::new(n_payouts, bucket_digits, density).unwrap();
+
+            let payouts = calculate_payout_parameters(
+                Price::new(Decimal::from(price)).unwrap(),
+                Usd::new(Decimal::from(quantity)),
+                Leverage::new(leverage).unwrap(),
+                contract_type,
+                resolution,
+            )
+            .unwrap();
+
+            let (initial, quantity, leverage) = (price as f64, quantity as f64, leverage as f64);
+            let (lower_corner, upper_corner, max_slope) = match contract_type {
+                ContractType::Inverse => {
+                    let lower_corner = initial * leverage / (leverage + 1.);
+                    (lower_corner, 2. * initial, quantity / lower_corner.powi(2))
+                }
+                ContractType::Linear => {
+                    let lower_corner = (initial * (leverage - 1.) / leverage).max(1.);
+                    (lower_corner, 2. * initial, quantity / initial.powi(2))
+                }
+            };
+            let max_width = 3. * (upper_corner - lower_corner) / n_payouts as f64
+                + 2. * resolution.bucket_size() as f64
+                + 2.;
+            let tolerance = max_slope * max_width + 2e-8;
+
+            for pair in payouts.windows(2) {
+                prop_assert_eq!(pair[0].right_bound + 1, pair[1].left_bound);
+            }
+
+            for payout in &payouts {
+                let long_amount = payout.long_amount as f64 / 100_000_000.;
+
+                for bound in [payout.left_bound, payout.right_bound] {
+                    let expected =
+                        exact_long_payout(contract_type, initial, quantity, leverage, bound as f64);
+                    prop_assert!(
+                        (long_amount - expected).abs() <= tolerance,
+                        "payout of {} BTC at {} deviates from {} BTC by more than {}",
+                        long_amount,
+                        bound,
+                        expected,
+                        tolerance
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn payouts_are_cached_per_funding() {
+        let price = Price::new(dec!(54000.00)).unwrap();
+        let quantity = Usd::new(dec!(3500.00));
+        let leverage = Leverage::new(2).unwrap();
+        let resolution = PayoutResolution::default();
+        let funding = bitcoin::SignedAmount::from_sat(1000);
+
+        calculate_cached(price, quantity, leverage, ContractType::Inverse, resolution).unwrap();
+        calculate_with_funding(
+            price,
+            quantity,
+            leverage,
+            ContractType::Inverse,
+            resolution,
+            funding,
+        )
+        .unwrap();
+
+        let key = |funding: bitcoin::SignedAmount| {
+            (
+                price.into_decimal(),
+                quantity.into_decimal(),
+                leverage.get(),
+                ContractType::Inverse,
+                resolution,
+                funding.as_sat(),
+            )
+        };
+        let cache = lock_payout_cache();
+        assert!(cache.contains_key(&key(bitcoin::SignedAmount::ZERO)));
+        assert!(cache.contains_key(&key(funding)));
+    }
+
+    #[test]
+    fn funding_moves_payout_between_parties() {
+        let parameter = || payout(0..=10, 100, 50);
+
+        assert_eq!(
+            parameter().with_funding(bitcoin::SignedAmount::from_sat(20)),
+            payout(0..=10, 120, 30)
+        );
+        assert_eq!(
+            parameter().with_funding(bitcoin::SignedAmount::from_sat(-30)),
+            payout(0..=10, 70, 80)
+        );
+        assert_eq!(
+            parameter().with_funding(bitcoin::SignedAmount::from_sat(80)),
+            payout(0..=10, 150, 0)
+        );
+    }
+
+    fn exact_long_payout(
+        contract_type: ContractType,
+        initial: f64,
+        quantity: f64,
+        leverage: f64,
+        price: f64,
+    ) -> f64 {
+        let total = quantity / initial * (1. / leverage + 1.);
+        let payout = match contract_type {
+            ContractType::Inverse => {
+                quantity * (1. / (initial * leverage) + 1. / initial - 1. / price)
+            }
+            ContractType::Linear => {
+                quantity / initial * (1. / leverage + (price - initial) / initial)
+            }
+        };
+
+        payout.clamp(0., total)
+    }
+
     fn payout(range: RangeInclusive<u64>, short: u64, long: u64) -> PayoutParameter {
         PayoutParameter {
             left_bound: *range.start(),
@@ -1,5 +1,11 @@
+use crate::wallet::WithdrawPreview;
+use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
+use bdk::bitcoin::{Address, Amount, Txid};
+use bdk::FeeRate;
 use rocket::http::{ContentType, Status};
 use rust_embed::EmbeddedFile;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
 use std::borrow::Cow;
 use std::path::PathBuf;
 
@@ -21,3 +27,58 @@ impl EmbeddedFileExt for Option<EmbeddedFile> {
         Ok((content_type, embedded_file.data))
     }
 }
+
+/// Request to withdraw funds from the wallet.
+///
+/// If no amount is given the wallet will be drained, if no fee rate (sats per vbyte) is given the
+/// minimum relay fee is used.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WithdrawRequest {
+    pub address: Address,
+    #[serde(default, with = "::bdk::bitcoin::util::amount::serde::as_btc::opt")]
+    pub amount: Option<Amount>,
+    pub fee: Option<f32>,
+}
+
+impl WithdrawRequest {
+    pub fn fee_rate(&self) -> Option<FeeRate> {
+        self.fee.map(FeeRate::from_sat_per_vb)
+    }
+}
+
+/// The unsigned withdraw transaction, to be confirmed through a [`BroadcastWithdrawRequest`].
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+pub struct WithdrawPreviewResponse {
+    #[serde_as(as = "DisplayFromStr")]
+    pub psbt: PartiallySignedTransaction,
+    pub txid: Txid,
+    pub address: Address,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub amount: Amount,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub fee: Amount,
+}
+
+impl WithdrawPreviewResponse {
+    pub fn new(preview: WithdrawPreview, address: Address) -> Self {
+        Self {
+            txid: preview.psbt.global.unsigned_tx.txid(),
+            psbt: preview.psbt,
+            address,
+            amount: preview.amount,
+            fee: preview.fee,
+        }
+    }
+}
+
+/// Request to broadcast the withdraw transaction of a [`WithdrawPreviewResponse`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct BroadcastWithdrawRequest {
+    pub txid: Txid,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WithdrawResponse {
+    pub txid: Txid,
+}
@@ -1,19 +1,29 @@
 use anyhow::Result;
 use bdk::bitcoin::Network;
+use daemon::audit_log::{self, AuditLogEntry};
 use daemon::auth::Authenticated;
+use daemon::backup::SnapshotDir;
+use daemon::db::load_archived_cfds_page;
+use daemon::export::{self, ExportFormat};
 use daemon::model::cfd::{Cfd, Order, OrderId, Role, UpdateCfdProposals};
-use daemon::model::{Price, Usd, WalletInfo};
-use daemon::routes::EmbeddedFileExt;
-use daemon::to_sse_event::{CfdAction, CfdsWithAuxData, ToSseEvent};
-use daemon::{bitmex_price_feed, maker_cfd};
+use daemon::model::{ContractType, FundingRate, PayoutResolution, Price, Usd, WalletInfo};
+use daemon::routes::{
+    BroadcastWithdrawRequest, EmbeddedFileExt, WithdrawPreviewResponse, WithdrawRequest,
+    WithdrawResponse,
+};
+use daemon::to_sse_event::{self, CfdAction, CfdsWithAuxData, ToSseEvent};
+use daemon::transaction_history::{self, TransactionHistoryEntry};
+use daemon::{bitmex_price_feed, maker_cfd, wallet};
 use http_api_problem::{HttpApiProblem, StatusCode};
-use rocket::http::{ContentType, Header, Status};
+use rocket::fs::NamedFile;
+use rocket::http::{ContentType, Status};
 use rocket::response::stream::EventStream;
 use rocket::response::{status, Responder};
 use rocket::serde::json::Json;
 use rocket::State;
 use rust_embed::RustEmbed;
 use serde::Deserialize;
+use sqlx::AnyPool;
 use std::borrow::Cow;
 use std::path::PathBuf;
 use tokio::select;
@@ -107,6 +117,10 @@ pub struct CfdNewOrderRequest {
     // always 1 USD
     pub min_quantity: Usd,
     pub max_quantity: Usd,
+    #[serde(default)]
+    pub contract_type: ContractType,
+    #[serde(default)]
+    pub payout_resolution: PayoutResolution,
 }
 
 #[rocket::post("/order/sell", data = "<order>")]
@@ -120,6 +134,8 @@ pub async fn post_sell_order(
             price: order.price,
             min_quantity: order.min_quantity,
             max_quantity: order.max_quantity,
+            contract_type: order.contract_type,
+            payout_resolution: order.payout_resolution,
         })
         .await
         .unwrap_or_else(|e| anyhow::bail!(e))
@@ -132,28 +148,12 @@ pub async fn post_sell_order(
     Ok(status::Accepted(None))
 }
 
-/// A "catcher" for all 401 responses, triggers the browser's basic auth implementation.
-#[rocket::catch(401)]
-pub fn unauthorized() -> PromptAuthentication {
-    PromptAuthentication {
-        inner: (),
-        www_authenticate: Header::new("WWW-Authenticate", r#"Basic charset="UTF-8"#),
-    }
-}
-
-/// A rocket responder that prompts the user to sign in to access the API.
-#[derive(rocket::Responder)]
-#[response(status = 401)]
-pub struct PromptAuthentication {
-    inner: (),
-    www_authenticate: Header<'static>,
-}
-
 #[rocket::post("/cfd/<id>/<action>")]
 pub async fn post_cfd_action(
     id: OrderId,
     action: CfdAction,
     cfd_action_channel: &State<Box<dyn MessageChannel<maker_cfd::CfdAction>>>,
+    quote_updates: &State<watch::Receiver<bitmex_price_feed::Quote>>,
     _auth: Authenticated,
 ) -> Result<status::Accepted<()>, HttpApiProblem> {
     use maker_cfd::CfdAction::*;
@@ -164,17 +164,38 @@ pub async fn post_cfd_action(
         CfdAction::RejectSettlement => cfd_action_channel.send(RejectSettlement { order_id: id }),
         CfdAction::AcceptRollOver => cfd_action_channel.send(AcceptRollOver { order_id: id }),
         CfdAction::RejectRollOver => cfd_action_channel.send(RejectRollOver { order_id: id }),
+        CfdAction::AcceptPartialSettlement => {
+            cfd_action_channel.send(AcceptPartialSettlement { order_id: id })
+        }
+        CfdAction::RejectPartialSettlement => {
+            cfd_action_channel.send(RejectPartialSettlement { order_id: id })
+        }
+        CfdAction::AcceptAddToPosition => {
+            cfd_action_channel.send(AcceptAddToPosition { order_id: id })
+        }
+        CfdAction::RejectAddToPosition => {
+            cfd_action_channel.send(RejectAddToPosition { order_id: id })
+        }
         CfdAction::Commit => cfd_action_channel.send(Commit { order_id: id }),
         CfdAction::Settle => {
-            let msg = "Collaborative settlement can only be triggered by taker";
-            tracing::error!(msg);
-            return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST).detail(msg));
+            // Propose at the price takers settle at, the same price our settlement policy accepts
+            // their proposals at. The taker only checks the payout matches the curve at that price.
+            let current_price = quote_updates.borrow().for_taker();
+            cfd_action_channel.send(ProposeSettlement {
+                order_id: id,
+                current_price,
+            })
         }
         CfdAction::RollOver => {
             let msg = "RollOver proposal can only be triggered by taker";
             tracing::error!(msg);
             return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST).detail(msg));
         }
+        CfdAction::AcceptCounterProposal | CfdAction::RejectCounterProposal => {
+            let msg = "Counter-proposals can only be answered by taker";
+            tracing::error!(msg);
+            return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST).detail(msg));
+        }
     };
 
     result
@@ -189,6 +210,233 @@ pub async fn post_cfd_action(
     Ok(status::Accepted(None))
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct CounterSettlementRequest {
+    pub price: Price,
+}
+
+#[rocket::post("/cfd/<id>/settle/counter", data = "<request>")]
+pub async fn post_counter_settlement(
+    id: OrderId,
+    request: Json<CounterSettlementRequest>,
+    counter_settlement_channel: &State<Box<dyn MessageChannel<maker_cfd::CounterSettlement>>>,
+    _auth: Authenticated,
+) -> Result<status::Accepted<()>, HttpApiProblem> {
+    counter_settlement_channel
+        .send(maker_cfd::CounterSettlement {
+            order_id: id,
+            price: request.price,
+        })
+        .await
+        .unwrap_or_else(|e| anyhow::bail!(e))
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Counter settlement failed")
+                .detail(e.to_string())
+        })?;
+
+    Ok(status::Accepted(None))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CounterRollOverRequest {
+    pub funding_rate: FundingRate,
+}
+
+#[rocket::post("/cfd/<id>/roll-over/counter", data = "<request>")]
+pub async fn post_counter_roll_over(
+    id: OrderId,
+    request: Json<CounterRollOverRequest>,
+    counter_roll_over_channel: &State<Box<dyn MessageChannel<maker_cfd::CounterRollOver>>>,
+    _auth: Authenticated,
+) -> Result<status::Accepted<()>, HttpApiProblem> {
+    counter_roll_over_channel
+        .send(maker_cfd::CounterRollOver {
+            order_id: id,
+            funding_rate: request.funding_rate,
+        })
+        .await
+        .unwrap_or_else(|e| anyhow::bail!(e))
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Counter roll over failed")
+                .detail(e.to_string())
+        })?;
+
+    Ok(status::Accepted(None))
+}
+
+#[rocket::get("/wallet/transactions")]
+pub async fn get_wallet_transactions(
+    wallet: &State<Box<dyn MessageChannel<wallet::TransactionHistory>>>,
+    db: &State<AnyPool>,
+    _auth: Authenticated,
+) -> Result<Json<Vec<TransactionHistoryEntry>>, HttpApiProblem> {
+    let load_history = async {
+        let mut conn = db.acquire().await?;
+        transaction_history::load(wallet.inner().as_ref(), &mut conn).await
+    };
+
+    let history = load_history.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Loading transaction history failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(Json(history))
+}
+
+#[rocket::get("/export/cfds?<format>")]
+pub async fn export_cfds(
+    format: Option<ExportFormat>,
+    db: &State<AnyPool>,
+    _auth: Authenticated,
+) -> Result<(ContentType, String), HttpApiProblem> {
+    let load_export = async {
+        let mut conn = db.acquire().await?;
+        let entries = export::load(&mut conn).await?;
+
+        let export = match format.unwrap_or_default() {
+            ExportFormat::Csv => (ContentType::CSV, export::to_csv(&entries)?),
+            ExportFormat::Json => (ContentType::JSON, serde_json::to_string(&entries)?),
+        };
+
+        Ok::<_, anyhow::Error>(export)
+    };
+
+    let export = load_export.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Exporting CFDs failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(export)
+}
+
+/// Archived CFDs are not part of the feed, they are loaded page by page on demand.
+#[rocket::get("/cfds/archived?<offset>&<limit>")]
+pub async fn get_archived_cfds(
+    offset: Option<u32>,
+    limit: Option<u32>,
+    db: &State<AnyPool>,
+    rx_quote: &State<watch::Receiver<bitmex_price_feed::Quote>>,
+    network: &State<Network>,
+    _auth: Authenticated,
+) -> Result<Json<Vec<to_sse_event::Cfd>>, HttpApiProblem> {
+    let load_archived = async {
+        let mut conn = db.acquire().await?;
+        load_archived_cfds_page(offset.unwrap_or(0), limit.unwrap_or(20), &mut conn).await
+    };
+
+    let cfds = load_archived.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Loading archived CFDs failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(Json(
+        CfdsWithAuxData::archived(cfds, rx_quote, Role::Maker, *network.inner()).to_cfds(),
+    ))
+}
+
+#[rocket::get("/cfd/<id>/events")]
+pub async fn get_cfd_events(
+    id: OrderId,
+    db: &State<AnyPool>,
+    _auth: Authenticated,
+) -> Result<Json<Vec<AuditLogEntry>>, HttpApiProblem> {
+    let load_events = async {
+        let mut conn = db.acquire().await?;
+        audit_log::load(id, &mut conn).await
+    };
+
+    let entries = load_events.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Loading CFD events failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(Json(entries))
+}
+
+#[rocket::post("/backup")]
+pub async fn post_backup(
+    db: &State<AnyPool>,
+    snapshot_dir: &State<SnapshotDir>,
+    _auth: Authenticated,
+) -> Result<NamedFile, HttpApiProblem> {
+    let take_snapshot = async {
+        let path = snapshot_dir.snapshot(db.inner(), "maker").await?;
+        let file = NamedFile::open(path).await?;
+
+        Ok::<_, anyhow::Error>(file)
+    };
+
+    let file = take_snapshot.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Taking database snapshot failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(file)
+}
+
+#[rocket::post("/wallet/withdraw", data = "<withdraw_request>")]
+pub async fn post_withdraw_request(
+    withdraw_request: Json<WithdrawRequest>,
+    preview_withdraw_channel: &State<Box<dyn MessageChannel<wallet::PreviewWithdraw>>>,
+    network: &State<Network>,
+    _auth: Authenticated,
+) -> Result<status::Accepted<Json<WithdrawPreviewResponse>>, HttpApiProblem> {
+    let withdraw_request = withdraw_request.into_inner();
+
+    if let Err(e) = wallet::validate_address_network(&withdraw_request.address, *network.inner()) {
+        return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST)
+            .title("Invalid withdraw address")
+            .detail(e.to_string()));
+    }
+
+    let preview = preview_withdraw_channel
+        .send(wallet::PreviewWithdraw {
+            amount: withdraw_request.amount,
+            fee: withdraw_request.fee_rate(),
+            address: withdraw_request.address.clone(),
+        })
+        .await
+        .unwrap_or_else(|e| anyhow::bail!(e.to_string()))
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Withdraw preview failed")
+                .detail(e.to_string())
+        })?;
+
+    Ok(status::Accepted(Some(Json(WithdrawPreviewResponse::new(
+        preview,
+        withdraw_request.address,
+    )))))
+}
+
+#[rocket::post("/wallet/withdraw/broadcast", data = "<broadcast_request>")]
+pub async fn post_withdraw_broadcast(
+    broadcast_request: Json<BroadcastWithdrawRequest>,
+    broadcast_withdraw_channel: &State<Box<dyn MessageChannel<wallet::BroadcastWithdraw>>>,
+    _auth: Authenticated,
+) -> Result<status::Accepted<Json<WithdrawResponse>>, HttpApiProblem> {
+    let txid = broadcast_withdraw_channel
+        .send(wallet::BroadcastWithdraw {
+            txid: broadcast_request.txid,
+        })
+        .await
+        .unwrap_or_else(|e| anyhow::bail!(e.to_string()))
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Withdraw failed")
+                .detail(e.to_string())
+        })?;
+
+    Ok(status::Accepted(Some(Json(WithdrawResponse { txid }))))
+}
+
 #[rocket::get("/alive")]
 pub fn get_health_check() {}
 
@@ -211,7 +459,7 @@ pub fn index<'r>(_paths: PathBuf, _auth: Authenticated) -> impl Responder<'r, 's
 #[cfg(test)]
 mod tests {
     use super::*;
-    use daemon::auth::Password;
+    use daemon::auth::{unauthorized, Password, Username, MAKER_USERNAME};
     use rocket::http::{Header, Status};
     use rocket::local::blocking::Client;
     use rocket::{Build, Rocket};
@@ -245,6 +493,7 @@ mod tests {
     fn rocket() -> Rocket<Build> {
         rocket::build()
             .manage(Password::from(*b"Now I'm feelin' so fly like a G6"))
+            .manage(Username(MAKER_USERNAME))
             .mount("/", rocket::routes![protected])
             .register("/", rocket::catchers![unauthorized])
     }
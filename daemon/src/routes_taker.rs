@@ -1,17 +1,31 @@
-use bdk::bitcoin::{Amount, Network};
-use daemon::model::cfd::{calculate_long_margin, Cfd, Order, OrderId, Role, UpdateCfdProposals};
-use daemon::model::{Leverage, Price, Usd, WalletInfo};
-use daemon::routes::EmbeddedFileExt;
-use daemon::to_sse_event::{CfdAction, CfdsWithAuxData, ToSseEvent};
-use daemon::{bitmex_price_feed, taker_cfd};
+use bdk::bitcoin::{Amount, Network, SignedAmount};
+use daemon::audit_log::{self, AuditLogEntry};
+use daemon::auth::{Authenticated, CsrfToken, CsrfVerified};
+use daemon::backup::SnapshotDir;
+use daemon::db::{load_archived_cfds_page, load_settlement_triggers};
+use daemon::export::{self, ExportFormat};
+use daemon::model::cfd::{
+    calculate_funding_fee, calculate_long_margin, calculate_short_margin, AutoRollOverFailures,
+    Cfd, Order, OrderId, Role, SettlementTriggers, UpdateCfdProposals,
+};
+use daemon::model::{FundingRate, Leverage, Position, Price, Usd, WalletInfo};
+use daemon::routes::{
+    BroadcastWithdrawRequest, EmbeddedFileExt, WithdrawPreviewResponse, WithdrawRequest,
+    WithdrawResponse,
+};
+use daemon::to_sse_event::{self, CfdAction, CfdsWithAuxData, ToSseEvent};
+use daemon::transaction_history::{self, TransactionHistoryEntry};
+use daemon::{bitmex_price_feed, payout_curve, taker_cfd, wallet};
 use http_api_problem::{HttpApiProblem, StatusCode};
-use rocket::http::{ContentType, Status};
+use rocket::fs::NamedFile;
+use rocket::http::{ContentType, CookieJar, Status};
 use rocket::response::stream::EventStream;
 use rocket::response::{status, Responder};
 use rocket::serde::json::Json;
 use rocket::State;
 use rust_embed::RustEmbed;
 use serde::{Deserialize, Serialize};
+use sqlx::AnyPool;
 use std::borrow::Cow;
 use std::path::PathBuf;
 use tokio::select;
@@ -25,13 +39,16 @@ pub async fn feed(
     rx_wallet: &State<watch::Receiver<WalletInfo>>,
     rx_quote: &State<watch::Receiver<bitmex_price_feed::Quote>>,
     rx_settlements: &State<watch::Receiver<UpdateCfdProposals>>,
+    rx_auto_roll_over_failures: &State<watch::Receiver<AutoRollOverFailures>>,
     network: &State<Network>,
+    _auth: Authenticated,
 ) -> EventStream![] {
     let mut rx_cfds = rx_cfds.inner().clone();
     let mut rx_order = rx_order.inner().clone();
     let mut rx_wallet = rx_wallet.inner().clone();
     let mut rx_quote = rx_quote.inner().clone();
     let mut rx_settlements = rx_settlements.inner().clone();
+    let mut rx_auto_roll_over_failures = rx_auto_roll_over_failures.inner().clone();
     let network = *network.inner();
 
     EventStream! {
@@ -50,7 +67,9 @@ pub async fn feed(
             &rx_settlements,
             Role::Taker,
             network
-        ).to_sse_event();
+        )
+        .with_auto_roll_over_failures(&rx_auto_roll_over_failures)
+        .to_sse_event();
 
         loop{
             select! {
@@ -69,7 +88,9 @@ pub async fn feed(
                         &rx_settlements,
                         Role::Taker,
                         network
-                    ).to_sse_event();
+                    )
+                    .with_auto_roll_over_failures(&rx_auto_roll_over_failures)
+                    .to_sse_event();
                 }
                 Ok(()) = rx_settlements.changed() => {
                     yield CfdsWithAuxData::new(
@@ -78,7 +99,20 @@ pub async fn feed(
                         &rx_settlements,
                         Role::Taker,
                         network
-                    ).to_sse_event();
+                    )
+                    .with_auto_roll_over_failures(&rx_auto_roll_over_failures)
+                    .to_sse_event();
+                }
+                Ok(()) = rx_auto_roll_over_failures.changed() => {
+                    yield CfdsWithAuxData::new(
+                        &rx_cfds,
+                        &rx_quote,
+                        &rx_settlements,
+                        Role::Taker,
+                        network
+                    )
+                    .with_auto_roll_over_failures(&rx_auto_roll_over_failures)
+                    .to_sse_event();
                 }
                 Ok(()) = rx_quote.changed() => {
                     let quote = rx_quote.borrow().clone();
@@ -89,7 +123,9 @@ pub async fn feed(
                         &rx_settlements,
                         Role::Taker,
                         network
-                    ).to_sse_event();
+                    )
+                    .with_auto_roll_over_failures(&rx_auto_roll_over_failures)
+                    .to_sse_event();
                 }
             }
         }
@@ -106,6 +142,8 @@ pub struct CfdOrderRequest {
 pub async fn post_order_request(
     cfd_order_request: Json<CfdOrderRequest>,
     take_offer_channel: &State<Box<dyn MessageChannel<taker_cfd::TakeOffer>>>,
+    _auth: Authenticated,
+    _csrf: CsrfVerified,
 ) -> Result<status::Accepted<()>, HttpApiProblem> {
     take_offer_channel
         .send(taker_cfd::TakeOffer {
@@ -129,15 +167,19 @@ pub async fn post_cfd_action(
     action: CfdAction,
     cfd_action_channel: &State<Box<dyn MessageChannel<taker_cfd::CfdAction>>>,
     quote_updates: &State<watch::Receiver<bitmex_price_feed::Quote>>,
+    _auth: Authenticated,
+    _csrf: CsrfVerified,
 ) -> Result<status::Accepted<()>, HttpApiProblem> {
     use taker_cfd::CfdAction::*;
     let result = match action {
         CfdAction::AcceptOrder
         | CfdAction::RejectOrder
-        | CfdAction::AcceptSettlement
-        | CfdAction::RejectSettlement
         | CfdAction::AcceptRollOver
-        | CfdAction::RejectRollOver => {
+        | CfdAction::RejectRollOver
+        | CfdAction::AcceptPartialSettlement
+        | CfdAction::RejectPartialSettlement
+        | CfdAction::AcceptAddToPosition
+        | CfdAction::RejectAddToPosition => {
             return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST)
                 .detail(format!("taker cannot invoke action {}", action)));
         }
@@ -149,7 +191,15 @@ pub async fn post_cfd_action(
                 current_price,
             })
         }
+        CfdAction::AcceptSettlement => cfd_action_channel.send(AcceptSettlement { order_id: id }),
+        CfdAction::RejectSettlement => cfd_action_channel.send(RejectSettlement { order_id: id }),
         CfdAction::RollOver => cfd_action_channel.send(ProposeRollOver { order_id: id }),
+        CfdAction::AcceptCounterProposal => {
+            cfd_action_channel.send(AcceptCounterProposal { order_id: id })
+        }
+        CfdAction::RejectCounterProposal => {
+            cfd_action_channel.send(RejectCounterProposal { order_id: id })
+        }
     };
 
     result
@@ -164,6 +214,290 @@ pub async fn post_cfd_action(
     Ok(status::Accepted(None))
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartialSettlementRequest {
+    pub quantity: Usd,
+}
+
+#[rocket::post("/cfd/<id>/settle/partial", data = "<request>")]
+pub async fn post_partial_settlement(
+    id: OrderId,
+    request: Json<PartialSettlementRequest>,
+    cfd_action_channel: &State<Box<dyn MessageChannel<taker_cfd::CfdAction>>>,
+    quote_updates: &State<watch::Receiver<bitmex_price_feed::Quote>>,
+    _auth: Authenticated,
+    _csrf: CsrfVerified,
+) -> Result<status::Accepted<()>, HttpApiProblem> {
+    let current_price = quote_updates.borrow().for_taker();
+
+    cfd_action_channel
+        .send(taker_cfd::CfdAction::ProposePartialSettlement {
+            order_id: id,
+            quantity: request.quantity,
+            current_price,
+        })
+        .await
+        .unwrap_or_else(|e| anyhow::bail!(e.to_string()))
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Partial settlement failed")
+                .detail(e.to_string())
+        })?;
+
+    Ok(status::Accepted(None))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddToPositionRequest {
+    pub quantity: Usd,
+}
+
+#[rocket::post("/cfd/<id>/position/add", data = "<request>")]
+pub async fn post_add_to_position(
+    id: OrderId,
+    request: Json<AddToPositionRequest>,
+    cfd_action_channel: &State<Box<dyn MessageChannel<taker_cfd::CfdAction>>>,
+    quote_updates: &State<watch::Receiver<bitmex_price_feed::Quote>>,
+    _auth: Authenticated,
+    _csrf: CsrfVerified,
+) -> Result<status::Accepted<()>, HttpApiProblem> {
+    let current_price = quote_updates.borrow().for_taker();
+
+    cfd_action_channel
+        .send(taker_cfd::CfdAction::ProposeAddToPosition {
+            order_id: id,
+            quantity: request.quantity,
+            current_price,
+        })
+        .await
+        .unwrap_or_else(|e| anyhow::bail!(e.to_string()))
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Add to position failed")
+                .detail(e.to_string())
+        })?;
+
+    Ok(status::Accepted(None))
+}
+
+#[rocket::get("/cfd/<id>/triggers")]
+pub async fn get_settlement_triggers(
+    id: OrderId,
+    db: &State<AnyPool>,
+    _auth: Authenticated,
+) -> Result<Json<SettlementTriggers>, HttpApiProblem> {
+    let load_triggers = async {
+        let mut conn = db.acquire().await?;
+        load_settlement_triggers(&mut conn).await
+    };
+
+    let mut triggers = load_triggers.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Loading settlement triggers failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(Json(triggers.remove(&id).unwrap_or_default()))
+}
+
+#[rocket::put("/cfd/<id>/triggers", data = "<triggers>")]
+pub async fn put_settlement_triggers(
+    id: OrderId,
+    triggers: Json<SettlementTriggers>,
+    cfd_action_channel: &State<Box<dyn MessageChannel<taker_cfd::CfdAction>>>,
+    _auth: Authenticated,
+    _csrf: CsrfVerified,
+) -> Result<status::Accepted<()>, HttpApiProblem> {
+    cfd_action_channel
+        .send(taker_cfd::CfdAction::SetSettlementTriggers {
+            order_id: id,
+            triggers: triggers.into_inner(),
+        })
+        .await
+        .unwrap_or_else(|e| anyhow::bail!(e.to_string()))
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Setting settlement triggers failed")
+                .detail(e.to_string())
+        })?;
+
+    Ok(status::Accepted(None))
+}
+
+#[rocket::get("/wallet/transactions")]
+pub async fn get_wallet_transactions(
+    wallet: &State<Box<dyn MessageChannel<wallet::TransactionHistory>>>,
+    db: &State<AnyPool>,
+    _auth: Authenticated,
+) -> Result<Json<Vec<TransactionHistoryEntry>>, HttpApiProblem> {
+    let load_history = async {
+        let mut conn = db.acquire().await?;
+        transaction_history::load(wallet.inner().as_ref(), &mut conn).await
+    };
+
+    let history = load_history.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Loading transaction history failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(Json(history))
+}
+
+#[rocket::get("/export/cfds?<format>")]
+pub async fn export_cfds(
+    format: Option<ExportFormat>,
+    db: &State<AnyPool>,
+    _auth: Authenticated,
+) -> Result<(ContentType, String), HttpApiProblem> {
+    let load_export = async {
+        let mut conn = db.acquire().await?;
+        let entries = export::load(&mut conn).await?;
+
+        let export = match format.unwrap_or_default() {
+            ExportFormat::Csv => (ContentType::CSV, export::to_csv(&entries)?),
+            ExportFormat::Json => (ContentType::JSON, serde_json::to_string(&entries)?),
+        };
+
+        Ok::<_, anyhow::Error>(export)
+    };
+
+    let export = load_export.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Exporting CFDs failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(export)
+}
+
+/// Archived CFDs are not part of the feed, they are loaded page by page on demand.
+#[rocket::get("/cfds/archived?<offset>&<limit>")]
+pub async fn get_archived_cfds(
+    offset: Option<u32>,
+    limit: Option<u32>,
+    db: &State<AnyPool>,
+    rx_quote: &State<watch::Receiver<bitmex_price_feed::Quote>>,
+    network: &State<Network>,
+    _auth: Authenticated,
+) -> Result<Json<Vec<to_sse_event::Cfd>>, HttpApiProblem> {
+    let load_archived = async {
+        let mut conn = db.acquire().await?;
+        load_archived_cfds_page(offset.unwrap_or(0), limit.unwrap_or(20), &mut conn).await
+    };
+
+    let cfds = load_archived.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Loading archived CFDs failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(Json(
+        CfdsWithAuxData::archived(cfds, rx_quote, Role::Taker, *network.inner()).to_cfds(),
+    ))
+}
+
+#[rocket::get("/cfd/<id>/events")]
+pub async fn get_cfd_events(
+    id: OrderId,
+    db: &State<AnyPool>,
+    _auth: Authenticated,
+) -> Result<Json<Vec<AuditLogEntry>>, HttpApiProblem> {
+    let load_events = async {
+        let mut conn = db.acquire().await?;
+        audit_log::load(id, &mut conn).await
+    };
+
+    let entries = load_events.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Loading CFD events failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(Json(entries))
+}
+
+#[rocket::post("/backup")]
+pub async fn post_backup(
+    db: &State<AnyPool>,
+    snapshot_dir: &State<SnapshotDir>,
+    _auth: Authenticated,
+    _csrf: CsrfVerified,
+) -> Result<NamedFile, HttpApiProblem> {
+    let take_snapshot = async {
+        let path = snapshot_dir.snapshot(db.inner(), "taker").await?;
+        let file = NamedFile::open(path).await?;
+
+        Ok::<_, anyhow::Error>(file)
+    };
+
+    let file = take_snapshot.await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Taking database snapshot failed")
+            .detail(e.to_string())
+    })?;
+
+    Ok(file)
+}
+
+#[rocket::post("/wallet/withdraw", data = "<withdraw_request>")]
+pub async fn post_withdraw_request(
+    withdraw_request: Json<WithdrawRequest>,
+    preview_withdraw_channel: &State<Box<dyn MessageChannel<wallet::PreviewWithdraw>>>,
+    network: &State<Network>,
+    _auth: Authenticated,
+    _csrf: CsrfVerified,
+) -> Result<status::Accepted<Json<WithdrawPreviewResponse>>, HttpApiProblem> {
+    let withdraw_request = withdraw_request.into_inner();
+
+    if let Err(e) = wallet::validate_address_network(&withdraw_request.address, *network.inner()) {
+        return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST)
+            .title("Invalid withdraw address")
+            .detail(e.to_string()));
+    }
+
+    let preview = preview_withdraw_channel
+        .send(wallet::PreviewWithdraw {
+            amount: withdraw_request.amount,
+            fee: withdraw_request.fee_rate(),
+            address: withdraw_request.address.clone(),
+        })
+        .await
+        .unwrap_or_else(|e| anyhow::bail!(e.to_string()))
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Withdraw preview failed")
+                .detail(e.to_string())
+        })?;
+
+    Ok(status::Accepted(Some(Json(WithdrawPreviewResponse::new(
+        preview,
+        withdraw_request.address,
+    )))))
+}
+
+#[rocket::post("/wallet/withdraw/broadcast", data = "<broadcast_request>")]
+pub async fn post_withdraw_broadcast(
+    broadcast_request: Json<BroadcastWithdrawRequest>,
+    broadcast_withdraw_channel: &State<Box<dyn MessageChannel<wallet::BroadcastWithdraw>>>,
+    _auth: Authenticated,
+    _csrf: CsrfVerified,
+) -> Result<status::Accepted<Json<WithdrawResponse>>, HttpApiProblem> {
+    let txid = broadcast_withdraw_channel
+        .send(wallet::BroadcastWithdraw {
+            txid: broadcast_request.txid,
+        })
+        .await
+        .unwrap_or_else(|e| anyhow::bail!(e.to_string()))
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Withdraw failed")
+                .detail(e.to_string())
+        })?;
+
+    Ok(status::Accepted(Some(Json(WithdrawResponse { txid }))))
+}
+
 #[rocket::get("/alive")]
 pub fn get_health_check() {}
 
@@ -186,6 +520,7 @@ pub struct MarginResponse {
 #[rocket::post("/calculate/margin", data = "<margin_request>")]
 pub fn margin_calc(
     margin_request: Json<MarginRequest>,
+    _auth: Authenticated,
 ) -> Result<status::Accepted<Json<MarginResponse>>, HttpApiProblem> {
     let margin = calculate_long_margin(
         margin_request.price,
@@ -196,18 +531,380 @@ pub fn margin_calc(
     Ok(status::Accepted(Some(Json(MarginResponse { margin }))))
 }
 
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct PayoutRequest {
+    pub order_id: OrderId,
+    pub quantity: Usd,
+    pub leverage: Leverage,
+}
+
+/// The payouts of taker and maker if the oracle attests to a price within the interval
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct PayoutInterval {
+    pub from_price: u64,
+    pub to_price: u64,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub taker: Amount,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub maker: Amount,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PayoutResponse {
+    pub intervals: Vec<PayoutInterval>,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub taker_margin: Amount,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub maker_margin: Amount,
+    /// Price at which the taker loses the complete margin, `None` if the taker's margin covers
+    /// all prices the oracle can attest to
+    pub taker_liquidation_price: Option<u64>,
+    /// Price at which the maker loses the complete margin, `None` if the maker's margin covers
+    /// all prices the oracle can attest to
+    pub maker_liquidation_price: Option<u64>,
+    /// The funding rate the maker quoted for every roll-over
+    pub funding_rate: FundingRate,
+    /// The funding the taker pays with every roll-over, negative if the taker receives funding
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub taker_funding_per_roll_over: SignedAmount,
+}
+
+/// Computes the payout schedule for taking the current order with the given quantity and leverage
+#[rocket::post("/calculate/payout", data = "<payout_request>")]
+pub async fn payout_calc(
+    payout_request: Json<PayoutRequest>,
+    rx_order: &State<watch::Receiver<Option<Order>>>,
+    _auth: Authenticated,
+) -> Result<status::Accepted<Json<PayoutResponse>>, HttpApiProblem> {
+    let order = rx_order
+        .borrow()
+        .clone()
+        .filter(|order| order.id == payout_request.order_id)
+        .ok_or_else(|| {
+            HttpApiProblem::new(StatusCode::NOT_FOUND)
+                .title("Order not found")
+                .detail(format!(
+                    "Order {} is not the current order",
+                    payout_request.order_id
+                ))
+        })?;
+
+    validate_payout_request(&order, &payout_request)?;
+
+    // Computing the payout curve is CPU heavy, it must not block the async runtime
+    let quantity = payout_request.quantity;
+    let response = tokio::task::spawn_blocking(move || calculate_payouts(&order, quantity))
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Payout calculation failed")
+                .detail(e.to_string())
+        })??;
+
+    Ok(status::Accepted(Some(Json(response))))
+}
+
+/// Rejects requests the maker would not accept when taking the order
+fn validate_payout_request(
+    order: &Order,
+    payout_request: &PayoutRequest,
+) -> Result<(), HttpApiProblem> {
+    if payout_request.quantity < order.min_quantity || payout_request.quantity > order.max_quantity
+    {
+        return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST)
+            .title("Invalid quantity")
+            .detail(format!(
+                "Quantity {} is not within the order's limits of {} to {}",
+                payout_request.quantity, order.min_quantity, order.max_quantity
+            )));
+    }
+
+    if payout_request.leverage != order.leverage {
+        return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST)
+            .title("Invalid leverage")
+            .detail(format!(
+                "Leverage {} does not match the order's leverage of {}",
+                payout_request.leverage.get(),
+                order.leverage.get()
+            )));
+    }
+
+    Ok(())
+}
+
+fn calculate_payouts(order: &Order, quantity: Usd) -> Result<PayoutResponse, HttpApiProblem> {
+    let intervals = payout_curve::calculate_intervals(
+        order.price,
+        quantity,
+        order.leverage,
+        order.contract_type,
+        order.payout_resolution,
+    )
+    .map_err(|e| {
+        HttpApiProblem::new(StatusCode::BAD_REQUEST)
+            .title("Payout calculation failed")
+            .detail(e.to_string())
+    })?;
+
+    // The long party is liquidated in the intervals at the lower end of the price range in which
+    // it does not get anything, the short party in those at the upper end
+    let long_liquidation_price = intervals
+        .iter()
+        .take_while(|interval| interval.long == Amount::ZERO)
+        .last()
+        .map(|interval| *interval.price_range.end());
+    let short_liquidation_price = intervals
+        .iter()
+        .rev()
+        .take_while(|interval| interval.short == Amount::ZERO)
+        .last()
+        .map(|interval| *interval.price_range.start());
+
+    let long_margin = calculate_long_margin(order.price, quantity, order.leverage);
+    let short_margin = calculate_short_margin(order.price, quantity);
+
+    // The taker takes the opposite position of the maker's order
+    let taker_is_long = order.position == Position::Short;
+    let intervals = intervals
+        .into_iter()
+        .map(|interval| {
+            let (taker, maker) = match taker_is_long {
+                true => (interval.long, interval.short),
+                false => (interval.short, interval.long),
+            };
+
+            PayoutInterval {
+                from_price: *interval.price_range.start(),
+                to_price: *interval.price_range.end(),
+                taker,
+                maker,
+            }
+        })
+        .collect();
+
+    let (taker_margin, maker_margin, taker_liquidation_price, maker_liquidation_price) =
+        match taker_is_long {
+            true => (
+                long_margin,
+                short_margin,
+                long_liquidation_price,
+                short_liquidation_price,
+            ),
+            false => (
+                short_margin,
+                long_margin,
+                short_liquidation_price,
+                long_liquidation_price,
+            ),
+        };
+
+    // The funding fee is paid by the long party
+    let funding_fee = calculate_funding_fee(order.funding_rate, quantity, order.price);
+    let funding_fee = funding_fee.map_err(|e| {
+        HttpApiProblem::new(StatusCode::BAD_REQUEST)
+            .title("Funding calculation failed")
+            .detail(e.to_string())
+    })?;
+    let taker_funding_per_roll_over = match taker_is_long {
+        true => funding_fee,
+        false => -funding_fee,
+    };
+
+    Ok(PayoutResponse {
+        intervals,
+        taker_margin,
+        maker_margin,
+        taker_liquidation_price,
+        maker_liquidation_price,
+        funding_rate: order.funding_rate,
+        taker_funding_per_roll_over,
+    })
+}
+
 #[derive(RustEmbed)]
 #[folder = "../taker-frontend/dist/taker"]
 struct Asset;
 
 #[rocket::get("/assets/<file..>")]
-pub fn dist<'r>(file: PathBuf) -> impl Responder<'r, 'static> {
+pub fn dist<'r>(file: PathBuf, _auth: Authenticated) -> impl Responder<'r, 'static> {
     let filename = format!("assets/{}", file.display().to_string());
     Asset::get(&filename).into_response(file)
 }
 
 #[rocket::get("/<_paths..>", format = "text/html")]
-pub fn index<'r>(_paths: PathBuf) -> impl Responder<'r, 'static> {
+pub fn index<'r>(
+    _paths: PathBuf,
+    cookies: &CookieJar<'_>,
+    csrf_token: &State<CsrfToken>,
+    _auth: Authenticated,
+) -> impl Responder<'r, 'static> {
+    // The frontend sends the token back on requests that change state
+    cookies.add(csrf_token.cookie());
+
     let asset = Asset::get("index.html").ok_or(Status::NotFound)?;
     Ok::<(ContentType, Cow<[u8]>), Status>((ContentType::HTML, asset.data))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::Txid;
+    use daemon::auth::{Password, Username, CSRF_HEADER, TAKER_USERNAME};
+    use daemon::model::cfd::Origin;
+    use daemon::model::{BitMexPriceEventId, ContractType, PayoutResolution};
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+    use rocket::{Build, Rocket};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+    use time::{Duration, OffsetDateTime};
+
+    #[test]
+    fn payout_request_within_the_order_limits_is_valid() {
+        let order = dummy_order();
+
+        let result = validate_payout_request(&order, &payout_request(&order, dec!(1_000), 2));
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn payout_request_outside_the_order_limits_is_rejected() {
+        let order = dummy_order();
+
+        let too_small = validate_payout_request(&order, &payout_request(&order, dec!(99), 2));
+        let too_large = validate_payout_request(&order, &payout_request(&order, dec!(10_001), 2));
+
+        assert_eq!(too_small.unwrap_err().status, Some(StatusCode::BAD_REQUEST));
+        assert_eq!(too_large.unwrap_err().status, Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn payout_request_with_other_leverage_than_the_order_is_rejected() {
+        let order = dummy_order();
+
+        let result = validate_payout_request(&order, &payout_request(&order, dec!(1_000), 5));
+
+        assert_eq!(result.unwrap_err().status, Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn taker_takes_the_opposite_position_of_the_order() {
+        let order = dummy_order();
+        let quantity = Usd::new(dec!(1_000));
+
+        let response = calculate_payouts(&order, quantity).unwrap();
+
+        // The order is short, the taker goes long with the order's leverage
+        assert_eq!(
+            response.taker_margin,
+            calculate_long_margin(order.price, quantity, order.leverage)
+        );
+        assert_eq!(
+            response.maker_margin,
+            calculate_short_margin(order.price, quantity)
+        );
+        assert!(response.taker_liquidation_price.is_some());
+        assert!(!response.intervals.is_empty());
+    }
+
+    #[tokio::test]
+    async fn withdrawals_require_authentication_and_csrf_token() {
+        let client = Client::tracked(withdraw_rocket()).await.unwrap();
+
+        for uri in ["/wallet/withdraw", "/wallet/withdraw/broadcast"] {
+            let unauthenticated = client
+                .post(uri)
+                .header(Header::new(CSRF_HEADER, "token"))
+                .dispatch()
+                .await;
+            let without_token = client.post(uri).header(auth_header()).dispatch().await;
+
+            assert_eq!(unauthenticated.status(), Status::Unauthorized);
+            assert_eq!(without_token.status(), Status::Forbidden);
+        }
+    }
+
+    /// A wallet that refuses all withdrawals, requests are rejected before they reach it.
+    struct DummyWallet;
+
+    impl xtra::Actor for DummyWallet {}
+
+    #[async_trait::async_trait]
+    impl xtra::Handler<wallet::PreviewWithdraw> for DummyWallet {
+        async fn handle(
+            &mut self,
+            _: wallet::PreviewWithdraw,
+            _: &mut xtra::Context<Self>,
+        ) -> anyhow::Result<wallet::WithdrawPreview> {
+            anyhow::bail!("Dummy wallet does not withdraw")
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl xtra::Handler<wallet::BroadcastWithdraw> for DummyWallet {
+        async fn handle(
+            &mut self,
+            _: wallet::BroadcastWithdraw,
+            _: &mut xtra::Context<Self>,
+        ) -> anyhow::Result<Txid> {
+            anyhow::bail!("Dummy wallet does not withdraw")
+        }
+    }
+
+    /// Constructs a Rocket instance serving the withdraw routes for testing.
+    fn withdraw_rocket() -> Rocket<Build> {
+        let wallet = DummyWallet.create(None).spawn_global();
+
+        rocket::build()
+            .manage(MessageChannel::<wallet::PreviewWithdraw>::clone_channel(
+                &wallet,
+            ))
+            .manage(MessageChannel::<wallet::BroadcastWithdraw>::clone_channel(
+                &wallet,
+            ))
+            .manage(Network::Testnet)
+            .manage(Password::from(*b"Now I'm feelin' so fly like a G6"))
+            .manage(Username(TAKER_USERNAME))
+            .manage(CsrfToken::from([42u8; 32]))
+            .mount(
+                "/",
+                rocket::routes![post_withdraw_request, post_withdraw_broadcast],
+            )
+    }
+
+    /// Creates an "Authorization" header that matches the password above,
+    /// in particular it has been created through:
+    /// ```
+    /// base64(taker:hex("Now I'm feelin' so fly like a G6"))
+    /// ```
+    fn auth_header() -> Header<'static> {
+        Header::new(
+            "Authorization",
+            "Basic dGFrZXI6NGU2Zjc3MjA0OTI3NmQyMDY2NjU2NTZjNjk2ZTI3MjA3MzZmMjA2NjZjNzkyMDZjNjk2YjY1MjA2MTIwNDczNg==",
+        )
+    }
+
+    fn payout_request(order: &Order, quantity: Decimal, leverage: u8) -> PayoutRequest {
+        PayoutRequest {
+            order_id: order.id,
+            quantity: Usd::new(quantity),
+            leverage: Leverage::new(leverage).unwrap(),
+        }
+    }
+
+    fn dummy_order() -> Order {
+        Order::new(
+            Price::new(dec!(40_000)).unwrap(),
+            Usd::new(dec!(100)),
+            Usd::new(dec!(10_000)),
+            ContractType::Inverse,
+            PayoutResolution::default(),
+            FundingRate::default(),
+            Origin::Theirs,
+            BitMexPriceEventId::with_20_digits(OffsetDateTime::now_utc()),
+            Duration::hours(24),
+        )
+        .unwrap()
+    }
+}
@@ -1,13 +1,45 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use bdk::bitcoin::util::bip32::ExtendedPrivKey;
 use bdk::bitcoin::Network;
+use bip39::Mnemonic;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use hkdf::Hkdf;
 use rand::Rng;
 use sha2::Sha256;
 use std::convert::TryInto;
 use std::path::Path;
+use tokio::io::{AsyncBufReadExt, BufReader};
 
-pub struct Seed([u8; 256]);
+const SEED_SIZE: usize = 256;
+const ENTROPY_SIZE: usize = 32;
+
+/// Environment variable to read the seed passphrase from instead of prompting for it.
+pub const PASSPHRASE_ENV_VAR: &str = "SEED_PASSPHRASE";
+
+/// Prefix of encrypted seed files, followed by salt, nonce and ciphertext.
+const ENCRYPTED_SEED_MAGIC: &[u8] = b"ENCSEED1";
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+/// scrypt cost parameter, 2^15 iterations with r = 8 use 32 MiB of memory.
+const SCRYPT_LOG_N: u8 = 15;
+
+/// Seeds generated before mnemonic support cannot be exported as a mnemonic.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "This seed was generated before mnemonic backups were supported and has no mnemonic, back up \
+     the seed file instead"
+)]
+pub struct NoMnemonic;
+
+pub struct Seed {
+    bytes: [u8; SEED_SIZE],
+    /// The entropy the seed was expanded from.
+    ///
+    /// Seeds generated before mnemonic support were drawn directly from the RNG and cannot be
+    /// represented as a mnemonic, for those this is `None`.
+    entropy: Option<[u8; ENTROPY_SIZE]>,
+}
 
 impl Seed {
     /// Initialize a [`Seed`] from a path.
@@ -16,7 +48,8 @@ impl Seed {
         let seed = if !seed_file.exists() {
             tracing::info!("No seed found. Generating new seed");
             let seed = Seed::default();
-            seed.write_to(seed_file).await?;
+            seed.write_to_maybe_encrypted(seed_file).await?;
+            seed.warn_backup()?;
             seed
         } else {
             Seed::read_from(seed_file).await?
@@ -24,14 +57,131 @@ impl Seed {
         Ok(seed)
     }
 
+    /// Restores a [`Seed`] from a mnemonic entered on stdin and writes it to the given path.
+    pub async fn restore(seed_file: &Path) -> Result<Seed> {
+        if seed_file.exists() {
+            bail!(
+                "A seed already exists at {}, move it away before restoring",
+                seed_file.display()
+            )
+        }
+
+        eprintln!("Enter the 24 words of your mnemonic, separated by spaces:");
+        let mnemonic = BufReader::new(tokio::io::stdin())
+            .lines()
+            .next_line()
+            .await?
+            .context("Stdin closed before a mnemonic was entered")?;
+
+        let seed = Seed::from_mnemonic(mnemonic.trim())?;
+        seed.write_to_maybe_encrypted(seed_file).await?;
+
+        Ok(seed)
+    }
+
+    /// Encrypts an existing plaintext seed file in place.
+    ///
+    /// The passphrase is taken from [`PASSPHRASE_ENV_VAR`] or prompted for.
+    pub async fn encrypt_file(seed_file: &Path) -> Result<()> {
+        let bytes = tokio::fs::read(seed_file).await?;
+        if bytes.starts_with(ENCRYPTED_SEED_MAGIC) {
+            bail!("Seed at {} is already encrypted", seed_file.display())
+        }
+        let seed = Seed::from_bytes(bytes)?;
+
+        let passphrase = match passphrase_from_env()? {
+            Some(passphrase) => passphrase,
+            None => tokio::task::spawn_blocking(prompt_new_passphrase).await??,
+        };
+
+        // Write to a temporary file first to never end up without a seed
+        let tmp_file = seed_file.with_extension("encrypted");
+        seed.write_encrypted_to(&tmp_file, &passphrase).await?;
+        tokio::fs::rename(&tmp_file, seed_file).await?;
+
+        Ok(())
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Result<Self> {
+        let mnemonic = Mnemonic::parse(mnemonic).context("Invalid mnemonic")?;
+        let entropy = mnemonic
+            .to_entropy()
+            .try_into()
+            .map_err(|_| anyhow!("Mnemonic must consist of 24 words"))?;
+
+        Ok(Self::from_entropy(entropy))
+    }
+
+    /// Opens an existing [`Seed`] without generating a new one if there is none.
+    pub async fn open(seed_file: &Path) -> Result<Seed> {
+        if !seed_file.exists() {
+            bail!("No seed found at {}", seed_file.display())
+        }
+
+        Seed::read_from(seed_file).await
+    }
+
+    /// The mnemonic to back up this seed with.
+    pub fn mnemonic(&self) -> Result<Mnemonic> {
+        let entropy = self.entropy.ok_or(NoMnemonic)?;
+
+        Ok(Mnemonic::from_entropy(&entropy)?)
+    }
+
+    /// Reads a [`Seed`] from a file, unlocking it if it is encrypted.
+    ///
+    /// The passphrase of an encrypted seed is taken from [`PASSPHRASE_ENV_VAR`] or prompted for.
     pub async fn read_from(path: &Path) -> Result<Self> {
         let bytes = tokio::fs::read(path).await?;
 
-        let bytes = bytes
-            .try_into()
-            .map_err(|_| anyhow!("Bytes from seed file don't fit into array"))?;
+        let bytes = match bytes.strip_prefix(ENCRYPTED_SEED_MAGIC) {
+            Some(encrypted) => {
+                let passphrase = passphrase_from_env()?;
+                let encrypted = encrypted.to_vec();
+
+                // Both prompting for the passphrase and deriving the key from it block
+                tokio::task::spawn_blocking(move || {
+                    let passphrase = match passphrase {
+                        Some(passphrase) => passphrase,
+                        None => {
+                            rpassword::read_password_from_tty(Some("Passphrase to unlock seed: "))?
+                        }
+                    };
 
-        Ok(Seed(bytes))
+                    decrypt(&encrypted, &passphrase)
+                })
+                .await??
+            }
+            None => {
+                tracing::warn!(
+                    "Seed file is not encrypted, consider encrypting it using the `encrypt-seed` \
+                     command"
+                );
+                bytes
+            }
+        };
+
+        Seed::from_bytes(bytes)
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        match bytes.len() {
+            ENTROPY_SIZE => Ok(Self::from_entropy(
+                bytes.try_into().expect("length was checked"),
+            )),
+            SEED_SIZE => Ok(Self {
+                bytes: bytes.try_into().expect("length was checked"),
+                entropy: None,
+            }),
+            _ => bail!("Bytes from seed file don't fit into array"),
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match &self.entropy {
+            Some(entropy) => entropy.to_vec(),
+            None => self.bytes.to_vec(),
+        }
     }
 
     pub async fn write_to(&self, path: &Path) -> Result<()> {
@@ -39,15 +189,36 @@ impl Seed {
             anyhow::bail!("Refusing to overwrite file at {}", path.display())
         }
 
-        tokio::fs::write(path, &self.0).await?;
+        tokio::fs::write(path, self.to_bytes()).await?;
 
         Ok(())
     }
 
+    pub async fn write_encrypted_to(&self, path: &Path, passphrase: &str) -> Result<()> {
+        if path.exists() {
+            anyhow::bail!("Refusing to overwrite file at {}", path.display())
+        }
+
+        let bytes = self.to_bytes();
+        let passphrase = passphrase.to_owned();
+        let encrypted = tokio::task::spawn_blocking(move || encrypt(&bytes, &passphrase)).await??;
+        tokio::fs::write(path, encrypted).await?;
+
+        Ok(())
+    }
+
+    /// Encrypts the seed if a passphrase is configured in the environment.
+    async fn write_to_maybe_encrypted(&self, path: &Path) -> Result<()> {
+        match passphrase_from_env()? {
+            Some(passphrase) => self.write_encrypted_to(path, &passphrase).await,
+            None => self.write_to(path).await,
+        }
+    }
+
     pub fn derive_extended_priv_key(&self, network: Network) -> Result<ExtendedPrivKey> {
         let mut ext_priv_key_seed = [0u8; 64];
 
-        Hkdf::<Sha256>::new(None, &self.0)
+        Hkdf::<Sha256>::new(None, &self.bytes)
             .expand(b"BITCOIN_WALLET_SEED", &mut ext_priv_key_seed)
             .expect("okm array is of correct length");
 
@@ -59,29 +230,207 @@ impl Seed {
     pub fn derive_auth_password<P: From<[u8; 32]>>(&self) -> P {
         let mut password = [0u8; 32];
 
-        Hkdf::<Sha256>::new(None, &self.0)
+        Hkdf::<Sha256>::new(None, &self.bytes)
             .expand(b"HTTP_AUTH_PASSWORD", &mut password)
             .expect("okm array is of correct length");
 
         P::from(password)
     }
 
+    pub fn derive_csrf_token<T: From<[u8; 32]>>(&self) -> T {
+        let mut token = [0u8; 32];
+
+        Hkdf::<Sha256>::new(None, &self.bytes)
+            .expand(b"HTTP_CSRF_TOKEN", &mut token)
+            .expect("okm array is of correct length");
+
+        T::from(token)
+    }
+
     pub fn derive_noise_static_secret(&self) -> x25519_dalek::StaticSecret {
         let mut secret = [0u8; 32];
 
-        Hkdf::<Sha256>::new(None, &self.0)
+        Hkdf::<Sha256>::new(None, &self.bytes)
             .expand(b"NOISE_STATIC_SECRET", &mut secret)
             .expect("okm array is of correct length");
 
         x25519_dalek::StaticSecret::from(secret)
     }
+
+    /// Expands the entropy of a mnemonic into a seed.
+    ///
+    /// All keys are derived from the expanded seed, which keeps the derivations identical for
+    /// seeds with and without a mnemonic.
+    fn from_entropy(entropy: [u8; ENTROPY_SIZE]) -> Self {
+        let mut bytes = [0u8; SEED_SIZE];
+
+        Hkdf::<Sha256>::new(None, &entropy)
+            .expand(b"SEED_FROM_MNEMONIC", &mut bytes)
+            .expect("okm array is of correct length");
+
+        Self {
+            bytes,
+            entropy: Some(entropy),
+        }
+    }
+
+    /// Asks the user to back up a freshly generated seed.
+    ///
+    /// The mnemonic is only ever printed to an interactive terminal, never to the logs.
+    fn warn_backup(&self) -> Result<()> {
+        if atty::is(atty::Stream::Stderr) {
+            eprintln!(
+                "\n\
+                 !!! A new seed was generated. Write down the following words and keep them safe. !!!\n\
+                 !!! They are the only way to restore your funds if the seed file is lost.        !!!\n\n\
+                 {}\n",
+                self.mnemonic()?
+            );
+        } else {
+            tracing::warn!(
+                "A new seed was generated. Back it up using the `export-seed` command, it is the only way to restore your funds if the seed file is lost"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn passphrase_from_env() -> Result<Option<String>> {
+    match std::env::var(PASSPHRASE_ENV_VAR) {
+        Ok(passphrase) if passphrase.is_empty() => {
+            bail!("Passphrase in {} must not be empty", PASSPHRASE_ENV_VAR)
+        }
+        Ok(passphrase) => Ok(Some(passphrase)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn prompt_new_passphrase() -> Result<String> {
+    let passphrase = rpassword::read_password_from_tty(Some("New seed passphrase: "))?;
+    let confirmation = rpassword::read_password_from_tty(Some("Repeat passphrase: "))?;
+
+    if passphrase != confirmation {
+        bail!("Passphrases do not match")
+    }
+    if passphrase.is_empty() {
+        bail!("Passphrase must not be empty")
+    }
+
+    Ok(passphrase)
+}
+
+fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_SIZE];
+    let mut nonce = [0u8; NONCE_SIZE];
+    rand::thread_rng().fill(&mut salt);
+    rand::thread_rng().fill(&mut nonce);
+
+    let key = derive_encryption_key(passphrase, &salt)?;
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow!("Failed to encrypt seed"))?;
+
+    Ok([ENCRYPTED_SEED_MAGIC, &salt, &nonce, &ciphertext].concat())
+}
+
+/// Decrypts the content of a seed file, without the magic prefix.
+fn decrypt(encrypted: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if encrypted.len() < SALT_SIZE + NONCE_SIZE {
+        bail!("Encrypted seed file is truncated")
+    }
+    let (salt, rest) = encrypted.split_at(SALT_SIZE);
+    let (nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+
+    let key = derive_encryption_key(passphrase, salt)?;
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Failed to decrypt seed, wrong passphrase?"))?;
+
+    Ok(plaintext)
+}
+
+fn derive_encryption_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let params = scrypt::Params::new(SCRYPT_LOG_N, 8, 1)?;
+
+    let mut key = [0u8; 32];
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key)
+        .expect("key array is of valid length");
+
+    Ok(key)
 }
 
 impl Default for Seed {
     fn default() -> Self {
-        let mut seed = [0u8; 256];
-        rand::thread_rng().fill(&mut seed);
+        let mut entropy = [0u8; ENTROPY_SIZE];
+        rand::thread_rng().fill(&mut entropy);
+
+        Self::from_entropy(entropy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mnemonic_round_trip_yields_same_keys() {
+        let seed = Seed::default();
+
+        let restored = Seed::from_mnemonic(&seed.mnemonic().unwrap().to_string()).unwrap();
+
+        assert_eq!(restored.bytes, seed.bytes);
+        assert_eq!(
+            restored.derive_noise_static_secret().to_bytes(),
+            seed.derive_noise_static_secret().to_bytes()
+        );
+    }
+
+    #[test]
+    fn legacy_seed_derivations_are_stable() {
+        let seed = Seed {
+            bytes: [7u8; SEED_SIZE],
+            entropy: None,
+        };
+
+        let password = seed.derive_auth_password::<[u8; 32]>();
+
+        assert_eq!(
+            hex::encode(password),
+            "79e8e3c41a3d3b7735c5549022fa6e97ac22639d3c4f49282148db22acf59f3e"
+        );
+        assert!(seed.mnemonic().unwrap_err().is::<NoMnemonic>());
+    }
+
+    #[test]
+    fn csrf_token_is_independent_of_the_auth_password() {
+        let seed = Seed::default();
+
+        let token = seed.derive_csrf_token::<[u8; 32]>();
+
+        assert_eq!(token, seed.derive_csrf_token::<[u8; 32]>());
+        assert_ne!(token, seed.derive_auth_password::<[u8; 32]>());
+    }
+
+    #[tokio::test]
+    async fn opening_a_missing_seed_does_not_generate_one() {
+        let seed_file = std::env::temp_dir().join(format!("seed-{}", uuid::Uuid::new_v4()));
+
+        let result = Seed::open(&seed_file).await;
+
+        assert!(result.is_err());
+        assert!(!seed_file.exists());
+    }
+
+    #[test]
+    fn encrypted_seed_round_trip() {
+        let seed = Seed::default();
+
+        let encrypted = encrypt(&seed.to_bytes(), "correct horse battery staple").unwrap();
+        let encrypted = encrypted.strip_prefix(ENCRYPTED_SEED_MAGIC).unwrap();
 
-        Self(seed)
+        let decrypted = decrypt(encrypted, "correct horse battery staple").unwrap();
+        assert_eq!(Seed::from_bytes(decrypted).unwrap().bytes, seed.bytes);
+        assert!(decrypt(encrypted, "wrong passphrase").is_err());
     }
 }
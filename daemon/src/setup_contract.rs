@@ -1,13 +1,17 @@
-use crate::model::cfd::{Cet, Cfd, Dlc, RevokedCommit, Role};
+use crate::model::cfd::{
+    AddToPositionProposal, Cet, Cfd, Dlc, PartialSettlementProposal, RevokedCommit, Role,
+};
+use crate::model::BitMexPriceEventId;
 use crate::tokio_ext::FutureExt;
 use crate::wire::{
-    Msg0, Msg1, Msg2, RollOverMsg, RollOverMsg0, RollOverMsg1, RollOverMsg2, SetupMsg,
+    AddToPositionMsg, AddToPositionMsg0, AddToPositionMsg2, Msg0, Msg1, Msg2, PartialSettlementMsg,
+    PartialSettlementMsg2, RollOverMsg, RollOverMsg0, RollOverMsg1, RollOverMsg2, SetupMsg,
 };
 use crate::{model, oracle, payout_curve, wallet};
-use anyhow::{Context, Result};
-use bdk::bitcoin::secp256k1::{schnorrsig, Signature, SECP256K1};
+use anyhow::{ensure, Context, Result};
+use bdk::bitcoin::secp256k1::{schnorrsig, SecretKey, Signature, SECP256K1};
 use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
-use bdk::bitcoin::{Amount, PublicKey, Transaction};
+use bdk::bitcoin::{Address, Amount, PublicKey, SignedAmount, Transaction};
 use bdk::descriptor::Descriptor;
 use bdk::miniscript::DescriptorTrait;
 use futures::stream::FusedStream;
@@ -15,12 +19,14 @@ use futures::{Sink, SinkExt, StreamExt};
 use maia::secp256k1_zkp::EcdsaAdaptorSignature;
 use maia::{
     commit_descriptor, compute_adaptor_pk, create_cfd_transactions, interval, lock_descriptor,
-    renew_cfd_transactions, secp256k1_zkp, spending_tx_sighash, Announcement, PartyParams,
-    PunishParams,
+    renew_cfd_transactions, secp256k1_zkp, spending_tx_sighash, Announcement, CfdTransactions,
+    PartyParams, Payout, PunishParams,
 };
+use rayon::prelude::*;
 use std::collections::HashMap;
 use std::iter::FromIterator;
 use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 use xtra::Address;
 
@@ -83,20 +89,28 @@ where
 
     let payouts = HashMap::from_iter([(
         announcement.into(),
-        payout_curve::calculate(cfd.order.price, cfd.quantity_usd, cfd.order.leverage)?,
+        payout_curve::calculate_cached(
+            cfd.order.price,
+            cfd.quantity_usd,
+            cfd.order.leverage,
+            cfd.order.contract_type,
+            cfd.order.payout_resolution,
+        )?,
     )]);
 
-    let own_cfd_txs = create_cfd_transactions(
-        (params.maker().clone(), *params.maker_punish()),
-        (params.taker().clone(), *params.taker_punish()),
-        oracle_pk,
-        (
+    // Signing the CETs is expensive, keep it off the async runtime
+    let own_cfd_txs = tokio::task::spawn_blocking({
+        let maker = (params.maker().clone(), *params.maker_punish());
+        let taker = (params.taker().clone(), *params.taker_punish());
+        let timelocks = (
             model::cfd::Cfd::CET_TIMELOCK,
             cfd.refund_timelock_in_blocks(),
-        ),
-        payouts,
-        sk,
-    )
+        );
+
+        move || create_cfd_transactions_in_parallel(maker, taker, oracle_pk, timelocks, payouts, sk)
+    })
+    .await
+    .context("CFD transaction creation task failed")?
     .context("Failed to create CFD transactions")?;
 
     tracing::info!("Created CFD transactions");
@@ -154,13 +168,14 @@ where
             .context("Expect event to exist in msg")?;
 
         verify_cets(
-            (&oracle_pk, &own_grouped_cets.event.nonce_pks),
-            &params.other,
-            own_grouped_cets.cets.as_slice(),
-            other_cets.as_slice(),
-            &commit_desc,
+            (oracle_pk, own_grouped_cets.event.nonce_pks.clone()),
+            params.other.clone(),
+            own_grouped_cets.cets.clone(),
+            other_cets.clone(),
+            commit_desc.clone(),
             commit_amount,
         )
+        .await
         .context("CET signatures don't verify")?;
     }
 
@@ -203,41 +218,12 @@ where
     // need some fallback handling (after x time) to spend the outputs in a different way so the
     // other party cannot hold us hostage
 
-    let cets = own_cets
-        .into_iter()
-        .map(|grouped_cets| {
-            let event_id = grouped_cets.event.id;
-            let other_cets = msg1
-                .cets
-                .get(&event_id)
-                .with_context(|| format!("Counterparty CETs for event {} missing", event_id))?;
-            let cets = grouped_cets
-                .cets
-                .into_iter()
-                .map(|(tx, _, digits)| {
-                    let other_encsig = other_cets
-                        .iter()
-                        .find_map(|(other_range, other_encsig)| {
-                            (other_range == &digits.range()).then(|| other_encsig)
-                        })
-                        .with_context(|| {
-                            format!(
-                                "Missing counterparty adaptor signature for CET corresponding to
-                                 price range {:?}",
-                                digits.range()
-                            )
-                        })?;
-                    Ok(Cet {
-                        tx,
-                        adaptor_sig: *other_encsig,
-                        range: digits.range(),
-                        n_bits: digits.len(),
-                    })
-                })
-                .collect::<Result<Vec<_>>>()?;
-            Ok((event_id.parse()?, cets))
-        })
-        .collect::<Result<HashMap<_, _>>>()?;
+    let cets = pair_cets_with_encsigs(
+        own_cets
+            .into_iter()
+            .map(|grouped_cets| (grouped_cets.event.id, grouped_cets.cets)),
+        &msg1.cets,
+    )?;
 
     tracing::info!("Exchanged signed lock transaction");
 
@@ -265,20 +251,13 @@ pub async fn roll_over(
     mut stream: impl FusedStream<Item = RollOverMsg> + Unpin,
     (oracle_pk, announcement): (schnorrsig::PublicKey, oracle::Announcement),
     cfd: Cfd,
+    funding_fee: SignedAmount,
     our_role: Role,
     dlc: Dlc,
 ) -> Result<Dlc> {
-    let sk = dlc.identity;
-    let pk = PublicKey::new(secp256k1_zkp::PublicKey::from_secret_key(SECP256K1, &sk));
-
     let (rev_sk, rev_pk) = crate::keypair::new(&mut rand::thread_rng());
     let (publish_sk, publish_pk) = crate::keypair::new(&mut rand::thread_rng());
 
-    let own_punish = PunishParams {
-        revocation_pk: rev_pk,
-        publish_pk,
-    };
-
     sink.send(RollOverMsg::Msg0(RollOverMsg0 {
         revocation_pk: rev_pk,
         publish_pk,
@@ -293,16 +272,8 @@ pub async fn roll_over(
         .try_into_msg0()
         .context("Failed to read Msg0")?;
 
-    let maker_lock_amount = dlc.maker_lock_amount;
-    let taker_lock_amount = dlc.taker_lock_amount;
-    let payouts = HashMap::from_iter([(
-        // TODO : we want to support multiple announcements
-        Announcement {
-            id: announcement.id.to_string(),
-            nonce_pks: announcement.nonce_pks.clone(),
-        },
-        payout_curve::calculate(cfd.order.price, cfd.quantity_usd, cfd.order.leverage)?,
-    )]);
+    // The funding is paid by moving it between the margins of the new DLC
+    let rolled_over = cfd.with_funding_fee(funding_fee)?;
 
     // unsign lock tx because PartiallySignedTransaction needs an unsigned tx
     let mut unsigned_lock_tx = dlc.lock.0.clone();
@@ -311,49 +282,31 @@ pub async fn roll_over(
         .iter_mut()
         .for_each(|input| input.witness.clear());
 
-    let lock_tx = PartiallySignedTransaction::from_unsigned_tx(unsigned_lock_tx)?;
-    let other_punish_params = PunishParams {
-        revocation_pk: msg0.revocation_pk,
-        publish_pk: msg0.publish_pk,
-    };
-    let ((maker_identity, maker_punish_params), (taker_identity, taker_punish_params)) =
-        match our_role {
-            Role::Maker => (
-                (pk, own_punish),
-                (dlc.identity_counterparty, other_punish_params),
-            ),
-            Role::Taker => (
-                (dlc.identity_counterparty, other_punish_params),
-                (pk, own_punish),
-            ),
-        };
-    let own_cfd_txs = renew_cfd_transactions(
-        lock_tx.clone(),
+    let renewed = RenewedContract::new(
+        &dlc,
+        our_role,
         (
-            maker_identity,
-            maker_lock_amount,
-            dlc.maker_address.clone(),
-            maker_punish_params,
-        ),
-        (
-            taker_identity,
-            taker_lock_amount,
-            dlc.taker_address.clone(),
-            taker_punish_params,
-        ),
-        oracle_pk,
-        (
-            model::cfd::Cfd::CET_TIMELOCK,
-            cfd.refund_timelock_in_blocks(),
+            PunishParams {
+                revocation_pk: rev_pk,
+                publish_pk,
+            },
+            PunishParams {
+                revocation_pk: msg0.revocation_pk,
+                publish_pk: msg0.publish_pk,
+            },
         ),
-        payouts,
-        sk,
+        PartiallySignedTransaction::from_unsigned_tx(unsigned_lock_tx)?,
+        rolled_over.lock_amounts()?,
+        (oracle_pk, &announcement),
+        &rolled_over,
     )
-    .context("Failed to create new CFD transactions")?;
+    .await?;
 
-    sink.send(RollOverMsg::Msg1(RollOverMsg1::from(own_cfd_txs.clone())))
-        .await
-        .context("Failed to send Msg1")?;
+    sink.send(RollOverMsg::Msg1(RollOverMsg1::from(
+        renewed.own_cfd_txs.clone(),
+    )))
+    .await
+    .context("Failed to send Msg1")?;
 
     let msg1 = stream
         .select_next_some()
@@ -363,84 +316,583 @@ pub async fn roll_over(
         .try_into_msg1()
         .context("Failed to read Msg1")?;
 
-    let lock_amount = taker_lock_amount + maker_lock_amount;
+    let cets = renewed
+        .verify(&dlc, our_role, &msg1, (oracle_pk, &announcement))
+        .await?;
 
-    let commit_desc = commit_descriptor(
-        (
-            maker_identity,
-            maker_punish_params.revocation_pk,
-            maker_punish_params.publish_pk,
-        ),
+    // reveal revocation secrets to the other party
+    sink.send(RollOverMsg::Msg2(RollOverMsg2 {
+        revocation_sk: dlc.revocation,
+    }))
+    .await
+    .context("Failed to send Msg2")?;
+
+    let msg2 = stream
+        .select_next_some()
+        .timeout(Duration::from_secs(60))
+        .await
+        .context("Expected Msg2 within 60 seconds")?
+        .try_into_msg2()
+        .context("Failed to read Msg2")?;
+
+    let lock_tx = dlc.lock.0.clone();
+    renewed.into_dlc(
+        dlc,
+        (rev_sk, publish_sk),
+        lock_tx,
+        (cets, msg1),
+        msg2.revocation_sk,
+    )
+}
+
+/// Settles a part of the position and sets up the contract for the rest of it.
+///
+/// The partial settlement transaction spends the current lock output. It pays out the settled
+/// part and locks the amounts of the remaining position in a new lock output, which the new
+/// commit, refund and cet transactions build on. It is only signed once these are complete,
+/// after that the previous commit transaction is revoked.
+pub async fn partial_settlement(
+    mut sink: impl Sink<PartialSettlementMsg, Error = anyhow::Error> + Unpin,
+    mut stream: impl FusedStream<Item = PartialSettlementMsg> + Unpin,
+    (oracle_pk, announcement): (schnorrsig::PublicKey, oracle::Announcement),
+    cfd: Cfd,
+    proposal: PartialSettlementProposal,
+    our_role: Role,
+    dlc: Dlc,
+) -> Result<Dlc> {
+    let (rev_sk, rev_pk) = crate::keypair::new(&mut rand::thread_rng());
+    let (publish_sk, publish_pk) = crate::keypair::new(&mut rand::thread_rng());
+
+    sink.send(PartialSettlementMsg::Msg0(RollOverMsg0 {
+        revocation_pk: rev_pk,
+        publish_pk,
+    }))
+    .await
+    .context("Failed to send Msg0")?;
+    let msg0 = stream
+        .select_next_some()
+        .timeout(Duration::from_secs(60))
+        .await
+        .context("Expected Msg0 within 60 seconds")?
+        .try_into_msg0()
+        .context("Failed to read Msg0")?;
+
+    let remaining = cfd.with_quantity(cfd.quantity_usd - proposal.quantity)?;
+    let lock_amounts = remaining.lock_amounts()?;
+
+    let (settlement_tx, own_settlement_sig) =
+        dlc.partial_settlement_transaction(&proposal, lock_amounts)?;
+
+    // The partial settlement transaction takes the place of the lock transaction
+    let renewed = RenewedContract::new(
+        &dlc,
+        our_role,
         (
-            taker_identity,
-            taker_punish_params.revocation_pk,
-            taker_punish_params.publish_pk,
+            PunishParams {
+                revocation_pk: rev_pk,
+                publish_pk,
+            },
+            PunishParams {
+                revocation_pk: msg0.revocation_pk,
+                publish_pk: msg0.publish_pk,
+            },
         ),
-    );
+        PartiallySignedTransaction::from_unsigned_tx(settlement_tx.clone())?,
+        lock_amounts,
+        (oracle_pk, &announcement),
+        &remaining,
+    )
+    .await?;
 
-    let own_cets = own_cfd_txs.cets;
-    let commit_tx = own_cfd_txs.commit.0.clone();
+    sink.send(PartialSettlementMsg::Msg1(RollOverMsg1::from(
+        renewed.own_cfd_txs.clone(),
+    )))
+    .await
+    .context("Failed to send Msg1")?;
 
-    let commit_amount = Amount::from_sat(commit_tx.output[0].value);
+    let msg1 = stream
+        .select_next_some()
+        .timeout(Duration::from_secs(60))
+        .await
+        .context("Expected Msg1 within 60 seconds")?
+        .try_into_msg1()
+        .context("Failed to read Msg1")?;
 
-    verify_adaptor_signature(
-        &commit_tx,
+    let cets = renewed
+        .verify(&dlc, our_role, &msg1, (oracle_pk, &announcement))
+        .await?;
+
+    tracing::info!("Verified all signatures of the remaining position");
+
+    sink.send(PartialSettlementMsg::Msg2(PartialSettlementMsg2 {
+        signature: own_settlement_sig,
+    }))
+    .await
+    .context("Failed to send Msg2")?;
+
+    let msg2 = stream
+        .select_next_some()
+        .timeout(Duration::from_secs(60))
+        .await
+        .context("Expected Msg2 within 60 seconds")?
+        .try_into_msg2()
+        .context("Failed to read Msg2")?;
+
+    verify_signature(
+        &settlement_tx,
         &dlc.lock.1,
-        lock_amount,
-        &msg1.commit,
-        &publish_pk,
+        dlc.maker_lock_amount + dlc.taker_lock_amount,
+        &msg2.signature,
         &dlc.identity_counterparty,
     )
-    .context("Commit adaptor signature does not verify")?;
+    .context("Partial settlement signature does not verify")?;
+
+    let signed_settlement_tx = dlc
+        .finalize_spend_transaction((settlement_tx, own_settlement_sig), msg2.signature)
+        .context("Failed to sign partial settlement transaction")?;
 
-    let other_address = match our_role {
-        Role::Maker => dlc.taker_address.clone(),
-        Role::Taker => dlc.maker_address.clone(),
+    // reveal revocation secrets to the other party
+    sink.send(PartialSettlementMsg::Msg3(RollOverMsg2 {
+        revocation_sk: dlc.revocation,
+    }))
+    .await
+    .context("Failed to send Msg3")?;
+
+    let msg3 = stream
+        .select_next_some()
+        .timeout(Duration::from_secs(60))
+        .await
+        .context("Expected Msg3 within 60 seconds")?
+        .try_into_msg3()
+        .context("Failed to read Msg3")?;
+
+    renewed.into_dlc(
+        dlc,
+        (rev_sk, publish_sk),
+        signed_settlement_tx,
+        (cets, msg1),
+        msg3.revocation_sk,
+    )
+}
+
+/// Increases the position and sets up the contract for the combined position.
+///
+/// The new lock transaction spends the current lock output together with the additional margin
+/// of both parties, so there is still a single lock output for the contract. The new commit,
+/// refund and cet transactions build on it like in a roll over. The current lock output is only
+/// signed once these are complete, after that the previous commit transaction is revoked.
+#[allow(clippy::too_many_arguments)]
+pub async fn add_to_position<W>(
+    mut sink: impl Sink<AddToPositionMsg, Error = anyhow::Error> + Unpin,
+    mut stream: impl FusedStream<Item = AddToPositionMsg> + Unpin,
+    (oracle_pk, announcement): (schnorrsig::PublicKey, oracle::Announcement),
+    cfd: Cfd,
+    proposal: AddToPositionProposal,
+    wallet: Address<W>,
+    our_role: Role,
+    dlc: Dlc,
+) -> Result<Dlc>
+where
+    W: xtra::Handler<wallet::Sign> + xtra::Handler<wallet::BuildPartyParams>,
+{
+    let pk = PublicKey::new(secp256k1_zkp::PublicKey::from_secret_key(
+        SECP256K1,
+        &dlc.identity,
+    ));
+
+    let (rev_sk, rev_pk) = crate::keypair::new(&mut rand::thread_rng());
+    let (publish_sk, publish_pk) = crate::keypair::new(&mut rand::thread_rng());
+
+    let increased = cfd.with_added_position(proposal.quantity, proposal.price)?;
+    let (maker_lock_amount, taker_lock_amount) = increased.lock_amounts()?;
+
+    let maker_additional = maker_lock_amount
+        .checked_sub(dlc.maker_lock_amount)
+        .context("Increased position requires less margin from the maker")?;
+    let taker_additional = taker_lock_amount
+        .checked_sub(dlc.taker_lock_amount)
+        .context("Increased position requires less margin from the taker")?;
+    let (own_additional, other_additional) = match our_role {
+        Role::Maker => (maker_additional, taker_additional),
+        Role::Taker => (taker_additional, maker_additional),
     };
 
-    for own_grouped_cets in &own_cets {
-        let other_cets = msg1
-            .cets
-            .get(&own_grouped_cets.event.id)
-            .context("Expect event to exist in msg")?;
+    let own_funding = wallet
+        .send(wallet::BuildPartyParams {
+            amount: own_additional,
+            identity_pk: pk,
+        })
+        .await
+        .context("Failed to send message to wallet actor")?
+        .context("Failed to build party params")?;
 
-        verify_cets(
-            (&oracle_pk, &announcement.nonce_pks),
-            &PartyParams {
-                lock_psbt: lock_tx.clone(),
-                identity_pk: dlc.identity_counterparty,
-                lock_amount,
-                address: other_address.clone(),
-            },
-            own_grouped_cets.cets.as_slice(),
-            other_cets.as_slice(),
-            &commit_desc,
-            commit_amount,
+    sink.send(AddToPositionMsg::Msg0(AddToPositionMsg0 {
+        lock_psbt: own_funding.lock_psbt.clone(),
+        lock_amount: own_additional,
+        revocation_pk: rev_pk,
+        publish_pk,
+    }))
+    .await
+    .context("Failed to send Msg0")?;
+    let msg0 = stream
+        .select_next_some()
+        .timeout(Duration::from_secs(60))
+        .await
+        .context("Expected Msg0 within 60 seconds")?
+        .try_into_msg0()
+        .context("Failed to read Msg0")?;
+
+    if msg0.lock_amount != other_additional {
+        anyhow::bail!(
+            "Amounts sent by counterparty don't add up, expected additional margin {} but got {}",
+            other_additional,
+            msg0.lock_amount
         )
-        .context("CET signatures don't verify")?;
     }
 
-    let refund_tx = own_cfd_txs.refund.0;
+    let (maker_funding, taker_funding) = match our_role {
+        Role::Maker => (&own_funding.lock_psbt, &msg0.lock_psbt),
+        Role::Taker => (&msg0.lock_psbt, &own_funding.lock_psbt),
+    };
+    let (lock_tx, own_lock_sig) = dlc.add_to_position_transaction(
+        (maker_funding, maker_additional),
+        (taker_funding, taker_additional),
+    )?;
+
+    let renewed = RenewedContract::new(
+        &dlc,
+        our_role,
+        (
+            PunishParams {
+                revocation_pk: rev_pk,
+                publish_pk,
+            },
+            PunishParams {
+                revocation_pk: msg0.revocation_pk,
+                publish_pk: msg0.publish_pk,
+            },
+        ),
+        lock_tx.clone(),
+        (maker_lock_amount, taker_lock_amount),
+        (oracle_pk, &announcement),
+        &increased,
+    )
+    .await?;
+
+    sink.send(AddToPositionMsg::Msg1(RollOverMsg1::from(
+        renewed.own_cfd_txs.clone(),
+    )))
+    .await
+    .context("Failed to send Msg1")?;
+
+    let msg1 = stream
+        .select_next_some()
+        .timeout(Duration::from_secs(60))
+        .await
+        .context("Expected Msg1 within 60 seconds")?
+        .try_into_msg1()
+        .context("Failed to read Msg1")?;
+
+    let cets = renewed
+        .verify(&dlc, our_role, &msg1, (oracle_pk, &announcement))
+        .await?;
+
+    tracing::info!("Verified all signatures of the increased position");
+
+    let mut signed_lock_tx = wallet
+        .send(wallet::Sign {
+            psbt: lock_tx.clone(),
+        })
+        .await
+        .context("Failed to send message to wallet actor")?
+        .context("Failed to sign transaction")?;
+    sink.send(AddToPositionMsg::Msg2(AddToPositionMsg2 {
+        signed_lock: signed_lock_tx.clone(),
+        signature: own_lock_sig,
+    }))
+    .await
+    .context("Failed to send Msg2")?;
+
+    let msg2 = stream
+        .select_next_some()
+        .timeout(Duration::from_secs(60))
+        .await
+        .context("Expected Msg2 within 60 seconds")?
+        .try_into_msg2()
+        .context("Failed to read Msg2")?;
 
     verify_signature(
-        &refund_tx,
-        &commit_desc,
-        commit_amount,
-        &msg1.refund,
+        &lock_tx.global.unsigned_tx,
+        &dlc.lock.1,
+        dlc.maker_lock_amount + dlc.taker_lock_amount,
+        &msg2.signature,
         &dlc.identity_counterparty,
     )
-    .context("Refund signature does not verify")?;
+    .context("Signature spending the current lock output does not verify")?;
 
-    let cets = own_cets
-        .into_iter()
-        .map(|grouped_cets| {
-            let event_id = grouped_cets.event.id;
+    signed_lock_tx
+        .merge(msg2.signed_lock)
+        .context("Failed to merge lock PSBTs")?;
+    let signed_lock_tx = dlc
+        .finalize_add_to_position_transaction(
+            signed_lock_tx.extract_tx(),
+            own_lock_sig,
+            msg2.signature,
+        )
+        .context("Failed to sign new lock transaction")?;
+
+    // reveal revocation secrets to the other party
+    sink.send(AddToPositionMsg::Msg3(RollOverMsg2 {
+        revocation_sk: dlc.revocation,
+    }))
+    .await
+    .context("Failed to send Msg3")?;
+
+    let msg3 = stream
+        .select_next_some()
+        .timeout(Duration::from_secs(60))
+        .await
+        .context("Expected Msg3 within 60 seconds")?
+        .try_into_msg3()
+        .context("Failed to read Msg3")?;
+
+    renewed.into_dlc(
+        dlc,
+        (rev_sk, publish_sk),
+        signed_lock_tx,
+        (cets, msg1),
+        msg3.revocation_sk,
+    )
+}
+
+/// Our side of a contract renewed on a new lock transaction.
+///
+/// Rolling over, partially settling and adding to a position all replace the commit, refund and
+/// cet transactions of the contract with new ones and revoke the previous commit transaction.
+struct RenewedContract {
+    lock_tx: PartiallySignedTransaction,
+    maker_lock_amount: Amount,
+    taker_lock_amount: Amount,
+    commit_desc: Descriptor<PublicKey>,
+    own_cfd_txs: CfdTransactions,
+    own_punish: PunishParams,
+    other_punish: PunishParams,
+}
+
+impl RenewedContract {
+    /// Creates and signs our transactions of `renewed`, spending the lock output of `lock_tx`.
+    async fn new(
+        dlc: &Dlc,
+        our_role: Role,
+        (own_punish, other_punish): (PunishParams, PunishParams),
+        lock_tx: PartiallySignedTransaction,
+        (maker_lock_amount, taker_lock_amount): (Amount, Amount),
+        (oracle_pk, announcement): (schnorrsig::PublicKey, &oracle::Announcement),
+        renewed: &Cfd,
+    ) -> Result<Self> {
+        let sk = dlc.identity;
+        let pk = PublicKey::new(secp256k1_zkp::PublicKey::from_secret_key(SECP256K1, &sk));
+
+        let payouts = HashMap::from_iter([(
+            // TODO : we want to support multiple announcements
+            Announcement {
+                id: announcement.id.to_string(),
+                nonce_pks: announcement.nonce_pks.clone(),
+            },
+            renewed.payouts()?,
+        )]);
+
+        let ((maker_identity, maker_punish), (taker_identity, taker_punish)) = match our_role {
+            Role::Maker => ((pk, own_punish), (dlc.identity_counterparty, other_punish)),
+            Role::Taker => ((dlc.identity_counterparty, other_punish), (pk, own_punish)),
+        };
+
+        // Signing the CETs is expensive, keep it off the async runtime
+        let own_cfd_txs = tokio::task::spawn_blocking({
+            let lock_tx = lock_tx.clone();
+            let maker = (
+                maker_identity,
+                maker_lock_amount,
+                dlc.maker_address.clone(),
+                maker_punish,
+            );
+            let taker = (
+                taker_identity,
+                taker_lock_amount,
+                dlc.taker_address.clone(),
+                taker_punish,
+            );
+            let timelocks = (
+                model::cfd::Cfd::CET_TIMELOCK,
+                renewed.refund_timelock_in_blocks(),
+            );
+
+            move || {
+                renew_cfd_transactions_in_parallel(
+                    lock_tx, maker, taker, oracle_pk, timelocks, payouts, sk,
+                )
+            }
+        })
+        .await
+        .context("CFD transaction renewal task failed")?
+        .context("Failed to create new CFD transactions")?;
+
+        let commit_desc = commit_descriptor(
+            (
+                maker_identity,
+                maker_punish.revocation_pk,
+                maker_punish.publish_pk,
+            ),
+            (
+                taker_identity,
+                taker_punish.revocation_pk,
+                taker_punish.publish_pk,
+            ),
+        );
+
+        Ok(Self {
+            lock_tx,
+            maker_lock_amount,
+            taker_lock_amount,
+            commit_desc,
+            own_cfd_txs,
+            own_punish,
+            other_punish,
+        })
+    }
+
+    /// Verifies the signatures of the counterparty on our transactions and pairs our CETs with
+    /// its adaptor signatures.
+    async fn verify(
+        &self,
+        dlc: &Dlc,
+        our_role: Role,
+        msg1: &RollOverMsg1,
+        (oracle_pk, announcement): (schnorrsig::PublicKey, &oracle::Announcement),
+    ) -> Result<HashMap<BitMexPriceEventId, Vec<Cet>>> {
+        let lock_amount = self.maker_lock_amount + self.taker_lock_amount;
+        let commit_tx = &self.own_cfd_txs.commit.0;
+        let commit_amount = Amount::from_sat(commit_tx.output[0].value);
+
+        verify_adaptor_signature(
+            commit_tx,
+            &dlc.lock.1,
+            lock_amount,
+            &msg1.commit,
+            &self.own_punish.publish_pk,
+            &dlc.identity_counterparty,
+        )
+        .context("Commit adaptor signature does not verify")?;
+
+        let other_address = match our_role {
+            Role::Maker => dlc.taker_address.clone(),
+            Role::Taker => dlc.maker_address.clone(),
+        };
+
+        for own_grouped_cets in &self.own_cfd_txs.cets {
             let other_cets = msg1
                 .cets
+                .get(&own_grouped_cets.event.id)
+                .context("Expect event to exist in msg")?;
+
+            verify_cets(
+                (oracle_pk, announcement.nonce_pks.clone()),
+                PartyParams {
+                    lock_psbt: self.lock_tx.clone(),
+                    identity_pk: dlc.identity_counterparty,
+                    lock_amount,
+                    address: other_address.clone(),
+                },
+                own_grouped_cets.cets.clone(),
+                other_cets.clone(),
+                self.commit_desc.clone(),
+                commit_amount,
+            )
+            .await
+            .context("CET signatures don't verify")?;
+        }
+
+        verify_signature(
+            &self.own_cfd_txs.refund.0,
+            &self.commit_desc,
+            commit_amount,
+            &msg1.refund,
+            &dlc.identity_counterparty,
+        )
+        .context("Refund signature does not verify")?;
+
+        pair_cets_with_encsigs(
+            self.own_cfd_txs
+                .cets
+                .iter()
+                .map(|grouped_cets| (grouped_cets.event.id.clone(), grouped_cets.cets.clone())),
+            &msg1.cets,
+        )
+    }
+
+    /// Completes the renewal once the counterparty revealed the revocation secret of the
+    /// previous commit transaction, which we keep to punish its publication.
+    fn into_dlc(
+        self,
+        dlc: Dlc,
+        (rev_sk, publish_sk): (SecretKey, SecretKey),
+        signed_lock_tx: Transaction,
+        (cets, msg1): (HashMap<BitMexPriceEventId, Vec<Cet>>, RollOverMsg1),
+        revocation_sk_theirs: SecretKey,
+    ) -> Result<Dlc> {
+        let derived_rev_pk = PublicKey::new(secp256k1_zkp::PublicKey::from_secret_key(
+            SECP256K1,
+            &revocation_sk_theirs,
+        ));
+        if derived_rev_pk != dlc.revocation_pk_counterparty {
+            anyhow::bail!("Counterparty sent invalid revocation sk");
+        }
+
+        let mut revoked_commit = dlc.revoked_commit;
+        revoked_commit.push(RevokedCommit {
+            encsig_ours: self.own_cfd_txs.commit.1,
+            revocation_sk_theirs,
+            publication_pk_theirs: dlc.publish_pk_counterparty,
+            txid: dlc.commit.0.txid(),
+            script_pubkey: dlc.commit.2.script_pubkey(),
+        });
+
+        Ok(Dlc {
+            identity: dlc.identity,
+            identity_counterparty: dlc.identity_counterparty,
+            revocation: rev_sk,
+            revocation_pk_counterparty: self.other_punish.revocation_pk,
+            publish: publish_sk,
+            publish_pk_counterparty: self.other_punish.publish_pk,
+            maker_address: dlc.maker_address,
+            taker_address: dlc.taker_address,
+            lock: (signed_lock_tx, dlc.lock.1),
+            commit: (self.own_cfd_txs.commit.0, msg1.commit, self.commit_desc),
+            cets,
+            refund: (self.own_cfd_txs.refund.0, msg1.refund),
+            maker_lock_amount: self.maker_lock_amount,
+            taker_lock_amount: self.taker_lock_amount,
+            revoked_commit,
+        })
+    }
+}
+
+/// Pairs our CETs with the adaptor signatures of the counterparty, grouped by oracle event.
+fn pair_cets_with_encsigs(
+    own_cets: impl Iterator<
+        Item = (
+            String,
+            Vec<(Transaction, EcdsaAdaptorSignature, interval::Digits)>,
+        ),
+    >,
+    other_cets: &HashMap<String, Vec<(RangeInclusive<u64>, EcdsaAdaptorSignature)>>,
+) -> Result<HashMap<BitMexPriceEventId, Vec<Cet>>> {
+    own_cets
+        .map(|(event_id, own_cets)| {
+            let other_cets = other_cets
                 .get(&event_id)
                 .with_context(|| format!("Counterparty CETs for event {} missing", event_id))?;
-            let cets = grouped_cets
-                .cets
+            let cets = own_cets
                 .into_iter()
                 .map(|(tx, _, digits)| {
                     let other_encsig = other_cets
@@ -465,61 +917,7 @@ pub async fn roll_over(
                 .collect::<Result<Vec<_>>>()?;
             Ok((event_id.parse()?, cets))
         })
-        .collect::<Result<HashMap<_, _>>>()?;
-
-    // reveal revocation secrets to the other party
-    sink.send(RollOverMsg::Msg2(RollOverMsg2 {
-        revocation_sk: dlc.revocation,
-    }))
-    .await
-    .context("Failed to send Msg2")?;
-
-    let msg2 = stream
-        .select_next_some()
-        .timeout(Duration::from_secs(60))
-        .await
-        .context("Expected Msg2 within 60 seconds")?
-        .try_into_msg2()
-        .context("Failed to read Msg2")?;
-    let revocation_sk_theirs = msg2.revocation_sk;
-
-    {
-        let derived_rev_pk = PublicKey::new(secp256k1_zkp::PublicKey::from_secret_key(
-            SECP256K1,
-            &revocation_sk_theirs,
-        ));
-
-        if derived_rev_pk != dlc.revocation_pk_counterparty {
-            anyhow::bail!("Counterparty sent invalid revocation sk");
-        }
-    }
-
-    let mut revoked_commit = dlc.revoked_commit;
-    revoked_commit.push(RevokedCommit {
-        encsig_ours: own_cfd_txs.commit.1,
-        revocation_sk_theirs,
-        publication_pk_theirs: dlc.publish_pk_counterparty,
-        txid: dlc.commit.0.txid(),
-        script_pubkey: dlc.commit.2.script_pubkey(),
-    });
-
-    Ok(Dlc {
-        identity: sk,
-        identity_counterparty: dlc.identity_counterparty,
-        revocation: rev_sk,
-        revocation_pk_counterparty: other_punish_params.revocation_pk,
-        publish: publish_sk,
-        publish_pk_counterparty: other_punish_params.publish_pk,
-        maker_address: dlc.maker_address,
-        taker_address: dlc.taker_address,
-        lock: dlc.lock.clone(),
-        commit: (commit_tx, msg1.commit, commit_desc),
-        cets,
-        refund: (refund_tx, msg1.refund),
-        maker_lock_amount,
-        taker_lock_amount,
-        revoked_commit,
-    })
+        .collect()
 }
 
 /// A convenience struct for storing PartyParams and PunishParams of both
@@ -577,37 +975,234 @@ impl AllParams {
     }
 }
 
-fn verify_cets(
-    oracle_params: (&schnorrsig::PublicKey, &[schnorrsig::PublicKey]),
-    other: &PartyParams,
-    own_cets: &[(Transaction, EcdsaAdaptorSignature, interval::Digits)],
-    cets: &[(RangeInclusive<u64>, EcdsaAdaptorSignature)],
-    commit_desc: &Descriptor<PublicKey>,
+/// Verifies the adaptor signatures of the counterparty on all CETs.
+///
+/// Verification runs in parallel on the blocking thread pool so that a large number of CETs does
+/// not stall the async runtime.
+async fn verify_cets(
+    (oracle_pk, nonce_pks): (schnorrsig::PublicKey, Vec<schnorrsig::PublicKey>),
+    other: PartyParams,
+    own_cets: Vec<(Transaction, EcdsaAdaptorSignature, interval::Digits)>,
+    cets: Vec<(RangeInclusive<u64>, EcdsaAdaptorSignature)>,
+    commit_desc: Descriptor<PublicKey>,
     commit_amount: Amount,
 ) -> Result<()> {
-    for (tx, _, digits) in own_cets.iter() {
-        let other_encsig = cets
-            .iter()
-            .find_map(|(range, encsig)| (range == &digits.range()).then(|| encsig))
-            .with_context(|| {
+    tokio::task::spawn_blocking(move || {
+        let encsigs = cets.into_iter().collect::<HashMap<_, _>>();
+        let progress = Progress::new("Verified CET adaptor signatures", own_cets.len());
+
+        own_cets.par_iter().try_for_each(|(tx, _, digits)| {
+            let other_encsig = encsigs.get(&digits.range()).with_context(|| {
                 format!(
                     "no enc sig from other party for price range {:?}",
                     digits.range()
                 )
             })?;
 
-        verify_cet_encsig(
-            tx,
-            other_encsig,
-            digits,
-            &other.identity_pk,
-            oracle_params,
-            commit_desc,
-            commit_amount,
+            verify_cet_encsig(
+                tx,
+                other_encsig,
+                digits,
+                &other.identity_pk,
+                (&oracle_pk, &nonce_pks),
+                &commit_desc,
+                commit_amount,
+            )
+            .context("enc sig on CET does not verify")?;
+
+            progress.tick();
+
+            Ok(())
+        })
+    })
+    .await
+    .context("CET verification task failed")?
+}
+
+type Payouts = HashMap<Announcement, Vec<Payout>>;
+
+/// Creates our transactions of a new contract, signing the CETs in parallel.
+///
+/// The first chunk of payouts yields the lock transaction, the CETs of the other chunks are then
+/// signed in parallel on top of it, see [`renew_cfd_transactions_in_parallel`].
+fn create_cfd_transactions_in_parallel(
+    (maker, maker_punish): (PartyParams, PunishParams),
+    (taker, taker_punish): (PartyParams, PunishParams),
+    oracle_pk: schnorrsig::PublicKey,
+    timelocks: (u32, u32),
+    payouts: Payouts,
+    identity_sk: SecretKey,
+) -> Result<CfdTransactions> {
+    let progress = Progress::new("Signed CETs", count_payouts(&payouts));
+    let mut chunks = split_payouts(payouts);
+    let first = match chunks.is_empty() {
+        true => Payouts::new(),
+        false => chunks.remove(0),
+    };
+
+    let n_payouts = count_payouts(&first);
+    let txs = create_cfd_transactions(
+        (maker.clone(), maker_punish),
+        (taker.clone(), taker_punish),
+        oracle_pk,
+        timelocks,
+        first,
+        identity_sk,
+    )?;
+    progress.advance(n_payouts);
+
+    let maker = (
+        maker.identity_pk,
+        maker.lock_amount,
+        maker.address,
+        maker_punish,
+    );
+    let taker = (
+        taker.identity_pk,
+        taker.lock_amount,
+        taker.address,
+        taker_punish,
+    );
+    let others = sign_in_parallel(chunks, &progress, |payouts| {
+        renew_cfd_transactions(
+            txs.lock.clone(),
+            maker.clone(),
+            taker.clone(),
+            oracle_pk,
+            timelocks,
+            payouts,
+            identity_sk,
         )
-        .context("enc sig on CET does not verify")?;
+    })?;
+
+    merge_cets(txs, others)
+}
+
+/// Creates our transactions on top of an existing lock transaction, signing the CETs in parallel.
+///
+/// maia signs one CET after the other. Instead, the payouts are split into chunks which are signed
+/// on the rayon thread pool. Every chunk yields the same commit and refund transactions, only their
+/// CETs differ.
+fn renew_cfd_transactions_in_parallel(
+    lock_tx: PartiallySignedTransaction,
+    maker: (PublicKey, Amount, Address, PunishParams),
+    taker: (PublicKey, Amount, Address, PunishParams),
+    oracle_pk: schnorrsig::PublicKey,
+    timelocks: (u32, u32),
+    payouts: Payouts,
+    identity_sk: SecretKey,
+) -> Result<CfdTransactions> {
+    let progress = Progress::new("Signed CETs", count_payouts(&payouts));
+    let mut chunks = split_payouts(payouts);
+    if chunks.is_empty() {
+        chunks.push(Payouts::new());
+    }
+
+    let mut txs = sign_in_parallel(chunks, &progress, |payouts| {
+        renew_cfd_transactions(
+            lock_tx.clone(),
+            maker.clone(),
+            taker.clone(),
+            oracle_pk,
+            timelocks,
+            payouts,
+            identity_sk,
+        )
+    })?;
+    let first = txs.remove(0);
+
+    merge_cets(first, txs)
+}
+
+fn sign_in_parallel(
+    chunks: Vec<Payouts>,
+    progress: &Progress,
+    sign: impl Fn(Payouts) -> Result<CfdTransactions> + Sync,
+) -> Result<Vec<CfdTransactions>> {
+    chunks
+        .into_par_iter()
+        .map(|payouts| {
+            let n_payouts = count_payouts(&payouts);
+            let txs = sign(payouts)?;
+            progress.advance(n_payouts);
+
+            Ok(txs)
+        })
+        .collect()
+}
+
+/// Splits the payouts of every announcement into one chunk per thread of the rayon thread pool.
+fn split_payouts(payouts: Payouts) -> Vec<Payouts> {
+    let n_threads = rayon::current_num_threads();
+
+    payouts
+        .into_iter()
+        .flat_map(|(announcement, payouts)| {
+            let chunk_size = ((payouts.len() + n_threads - 1) / n_threads).max(1);
+
+            payouts
+                .chunks(chunk_size)
+                .map(|chunk| HashMap::from_iter([(announcement.clone(), chunk.to_vec())]))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn count_payouts(payouts: &Payouts) -> usize {
+    payouts.values().map(Vec::len).sum()
+}
+
+/// Adds the CETs of the other chunks to the transactions of the first one.
+fn merge_cets(mut txs: CfdTransactions, others: Vec<CfdTransactions>) -> Result<CfdTransactions> {
+    for other in others {
+        ensure!(
+            other.commit.0.txid() == txs.commit.0.txid(),
+            "CETs were signed for different commit transactions"
+        );
+
+        for other_cets in other.cets {
+            match txs
+                .cets
+                .iter_mut()
+                .find(|cets| cets.event.id == other_cets.event.id)
+            {
+                Some(cets) => cets.cets.extend(other_cets.cets),
+                None => txs.cets.push(other_cets),
+            }
+        }
+    }
+
+    Ok(txs)
+}
+
+/// Logs the progress of an operation over many items in steps of 10%.
+struct Progress {
+    label: &'static str,
+    total: usize,
+    done: AtomicUsize,
+}
+
+impl Progress {
+    fn new(label: &'static str, total: usize) -> Self {
+        Self {
+            label,
+            total,
+            done: AtomicUsize::new(0),
+        }
+    }
+
+    fn tick(&self) {
+        self.advance(1);
+    }
+
+    fn advance(&self, n: usize) {
+        let done = self.done.fetch_add(n, Ordering::Relaxed) + n;
+        let step = (self.total / 10).max(1);
+
+        if (done - n) / step != done / step || done == self.total {
+            tracing::debug!("{}: {}/{}", self.label, done, self.total);
+        }
     }
-    Ok(())
 }
 
 fn verify_adaptor_signature(
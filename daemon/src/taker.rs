@@ -3,6 +3,8 @@ use bdk::bitcoin::secp256k1::schnorrsig;
 use bdk::bitcoin::{Address, Amount};
 use bdk::{bitcoin, FeeRate};
 use clap::{Parser, Subcommand};
+use daemon::auth::{self, TAKER_USERNAME};
+use daemon::backup::{self, SnapshotDir};
 use daemon::db::{self};
 use daemon::model::WalletInfo;
 use daemon::seed::Seed;
@@ -10,8 +12,6 @@ use daemon::{
     bitmex_price_feed, connection, housekeeping, logger, monitor, oracle, taker_cfd, wallet,
     wallet_sync, TakerActorSystem,
 };
-use sqlx::sqlite::SqliteConnectOptions;
-use sqlx::SqlitePool;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -39,6 +39,10 @@ struct Opts {
     #[clap(long, default_value = "127.0.0.1:8000")]
     http_address: SocketAddr,
 
+    /// Refuse to start if the HTTP API would be reachable from other hosts than this one.
+    #[clap(long)]
+    localhost_only: bool,
+
     /// Where to permanently store data, defaults to the current working directory.
     #[clap(long)]
     data_dir: Option<PathBuf>,
@@ -47,6 +51,21 @@ struct Opts {
     #[clap(short, long)]
     json: bool,
 
+    /// Finished CFDs are moved to the archive after this many days, archived CFDs are no longer
+    /// part of the feed.
+    #[clap(long, default_value = "7")]
+    archive_after_days: u16,
+
+    /// How many database snapshots are kept in the data directory, the oldest ones are deleted
+    /// when taking a new snapshot.
+    #[clap(long, default_value = "10")]
+    keep_snapshots: usize,
+
+    /// Propose to roll over open CFDs automatically this many hours before they expire. CFDs are
+    /// not rolled over automatically if not set.
+    #[clap(long)]
+    auto_roll_over_hours: Option<u8>,
+
     #[clap(subcommand)]
     network: Network,
 }
@@ -65,7 +84,7 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
     },
     Testnet {
         /// URL to the electrum backend to use for the wallet.
@@ -73,7 +92,7 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
     },
     /// Run on signet
     Signet {
@@ -82,12 +101,12 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
     },
 }
 
 #[derive(Subcommand)]
-enum Withdraw {
+enum Command {
     Withdraw {
         /// Optionally specify the amount of Bitcoin to be withdrawn. If not specified the wallet
         /// will be drained. Amount is to be specified with denomination, e.g. "0.1 BTC"
@@ -101,6 +120,29 @@ enum Withdraw {
         #[clap(long)]
         address: Address,
     },
+    /// Print the mnemonic to back up the seed with.
+    ExportSeed,
+    /// Restore the seed from a mnemonic entered on stdin and rebuild the wallet from the chain.
+    Restore,
+    /// Encrypt an existing plaintext seed file with a passphrase.
+    ///
+    /// The passphrase is read from the `SEED_PASSPHRASE` environment variable or prompted for.
+    EncryptSeed,
+    /// Take a consistent snapshot of the CFD database, also while the daemon is running.
+    Backup {
+        /// Where to write the snapshot to. Defaults to a file named after the current time in
+        /// the `backups` directory of the data directory.
+        #[clap(long)]
+        path: Option<PathBuf>,
+    },
+    /// Replace the CFD database with a snapshot, after validating it against the wallet.
+    ///
+    /// The daemon must not be running while the database is restored.
+    RestoreDb {
+        /// The snapshot to restore.
+        #[clap(long)]
+        path: PathBuf,
+    },
 }
 
 impl Network {
@@ -128,11 +170,11 @@ impl Network {
         }
     }
 
-    fn withdraw(&self) -> &Option<Withdraw> {
+    fn command(&self) -> &Option<Command> {
         match self {
-            Network::Mainnet { withdraw, .. } => withdraw,
-            Network::Testnet { withdraw, .. } => withdraw,
-            Network::Signet { withdraw, .. } => withdraw,
+            Network::Mainnet { command, .. } => command,
+            Network::Testnet { command, .. } => command,
+            Network::Signet { command, .. } => command,
         }
     }
 }
@@ -144,6 +186,13 @@ async fn main() -> Result<()> {
     logger::init(LevelFilter::DEBUG, opts.json).context("initialize logger")?;
     tracing::info!("Running version: {}", env!("VERGEN_GIT_SEMVER_LIGHTWEIGHT"));
 
+    if opts.localhost_only && !opts.http_address.ip().is_loopback() {
+        anyhow::bail!(
+            "HTTP address {} is not a localhost address but --localhost-only is set",
+            opts.http_address
+        )
+    }
+
     let data_dir = opts
         .data_dir
         .clone()
@@ -155,29 +204,81 @@ async fn main() -> Result<()> {
         tokio::fs::create_dir_all(&data_dir).await?;
     }
 
-    let seed = Seed::initialize(&data_dir.join("taker_seed")).await?;
+    let seed_file = data_dir.join("taker_seed");
+    let wallet_file = data_dir.join("taker_wallet.sqlite");
+    let db_file = data_dir.join("taker.sqlite");
+    let snapshot_dir = SnapshotDir::new(&data_dir, opts.keep_snapshots);
+
+    if let Some(Command::Backup { path }) = opts.network.command() {
+        let db = db::open_sqlite(&db_file).await?;
+        match path {
+            Some(path) => backup::snapshot(&db, path).await?,
+            None => {
+                snapshot_dir.snapshot(&db, "taker").await?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Command::Restore) = opts.network.command() {
+        Seed::restore(&seed_file).await?;
+
+        // The wallet database only caches what can be recovered from the chain, it has to go as
+        // it is bound to the previous seed.
+        if wallet_file.exists() {
+            tokio::fs::remove_file(&wallet_file).await?;
+        }
+
+        // The CFDs in the database were set up with the keys of the previous seed. The database is
+        // kept, it can be restored with `restore-db` if it belongs to the restored seed.
+        if db_file.exists() {
+            let moved = backup::move_aside(&db_file, "previous-seed").await?;
+            tracing::warn!(
+                "Moved the database of the previous seed to {}",
+                moved.display()
+            );
+        }
+
+        tracing::info!("Seed restored from mnemonic, rebuilding wallet");
+    }
+
+    if let Some(Command::EncryptSeed) = opts.network.command() {
+        Seed::encrypt_file(&seed_file).await?;
+        tracing::info!("Seed file encrypted");
+
+        return Ok(());
+    }
+
+    if let Some(Command::ExportSeed) = opts.network.command() {
+        let seed = Seed::open(&seed_file).await?;
+        let mnemonic = seed
+            .mnemonic()
+            .with_context(|| format!("Cannot export the seed at {}", seed_file.display()))?;
+        println!("{}", mnemonic);
+
+        return Ok(());
+    }
+
+    let seed = Seed::initialize(&seed_file).await?;
 
     let bitcoin_network = opts.network.bitcoin_network();
     let ext_priv_key = seed.derive_extended_priv_key(bitcoin_network)?;
     let noise_static_sk = seed.derive_noise_static_secret();
 
-    let wallet = wallet::Actor::new(
-        opts.network.electrum(),
-        &data_dir.join("taker_wallet.sqlite"),
-        ext_priv_key,
-    )
-    .await?
-    .create(None)
-    .spawn_global();
+    let wallet = wallet::Actor::new(opts.network.electrum(), &wallet_file, ext_priv_key)
+        .await?
+        .create(None)
+        .spawn_global();
 
     // do this before withdraw to ensure the wallet is synced
     let wallet_info = wallet.send(wallet::Sync).await??;
 
-    if let Some(Withdraw::Withdraw {
+    if let Some(Command::Withdraw {
         amount,
         address,
         fee,
-    }) = opts.network.withdraw()
+    }) = opts.network.command()
     {
         let txid = wallet
             .send(wallet::Withdraw {
@@ -192,6 +293,45 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(Command::RestoreDb { path }) = opts.network.command() {
+        let wallet_txids = wallet
+            .send(wallet::TransactionHistory)
+            .await??
+            .into_iter()
+            .map(|tx| tx.txid)
+            .collect();
+
+        let warnings = backup::restore(path, &db_file, &wallet_txids).await?;
+        if !warnings.is_empty() {
+            tracing::warn!(
+                "Database restored with {} warnings, do not start the daemon before resolving them",
+                warnings.len()
+            );
+        }
+
+        return Ok(());
+    }
+
+    if let Some(Command::Restore) = opts.network.command() {
+        let noise_static_pk = x25519_dalek::PublicKey::from(&seed.derive_noise_static_secret());
+
+        tracing::info!(
+            balance = %wallet_info.balance,
+            noise_public_key = %hex::encode(noise_static_pk.to_bytes()),
+            "Restore successful"
+        );
+
+        return Ok(());
+    }
+
+    let auth_password = seed.derive_auth_password::<auth::Password>();
+
+    tracing::info!(
+        "Authentication details: username='{}' password='{}'",
+        TAKER_USERNAME,
+        auth_password
+    );
+
     // TODO: Actually fetch it from Olivia
     let oracle = schnorrsig::PublicKey::from_str(
         "ddd4636845a90185991826be5a494cde9f4a6947b1727217afedc6292fa4caf7",
@@ -206,12 +346,7 @@ async fn main() -> Result<()> {
         .merge(("address", opts.http_address.ip()))
         .merge(("port", opts.http_address.port()));
 
-    let db = SqlitePool::connect_with(
-        SqliteConnectOptions::new()
-            .create_if_missing(true)
-            .filename(data_dir.join("taker.sqlite")),
-    )
-    .await?;
+    let db = db::open_sqlite(&db_file).await?;
 
     db::run_migrations(&db)
         .await
@@ -221,6 +356,8 @@ async fn main() -> Result<()> {
     let mut conn = db.acquire().await?;
 
     housekeeping::transition_non_continue_cfds_to_setup_failed(&mut conn).await?;
+    let archive_after = time::Duration::days(opts.archive_after_days.into());
+    housekeeping::archive_finished_cfds(&mut conn, archive_after).await?;
     housekeeping::rebroadcast_transactions(&mut conn, &wallet).await?;
 
     let connection::Actor {
@@ -233,6 +370,7 @@ async fn main() -> Result<()> {
         cfd_feed_receiver,
         order_feed_receiver,
         update_cfd_feed_receiver,
+        auto_roll_over_failures_receiver,
     } = TakerActorSystem::new(
         db.clone(),
         wallet.clone(),
@@ -246,9 +384,20 @@ async fn main() -> Result<()> {
                 monitor::Actor::new(electrum, channel, cfds)
             }
         },
+        opts.auto_roll_over_hours
+            .map(|hours| time::Duration::hours(hours.into())),
+        quote_updates.clone(),
+        archive_after,
     )
     .await?;
 
+    let transaction_history_channel =
+        MessageChannel::<wallet::TransactionHistory>::clone_channel(&wallet);
+    let preview_withdraw_channel =
+        MessageChannel::<wallet::PreviewWithdraw>::clone_channel(&wallet);
+    let broadcast_withdraw_channel =
+        MessageChannel::<wallet::BroadcastWithdraw>::clone_channel(&wallet);
+
     tokio::spawn(wallet_sync::new(wallet, wallet_feed_sender));
     let take_offer_channel = MessageChannel::<taker_cfd::TakeOffer>::clone_channel(&cfd_actor_addr);
     let cfd_action_channel = MessageChannel::<taker_cfd::CfdAction>::clone_channel(&cfd_actor_addr);
@@ -256,12 +405,21 @@ async fn main() -> Result<()> {
     rocket::custom(figment)
         .manage(order_feed_receiver)
         .manage(update_cfd_feed_receiver)
+        .manage(auto_roll_over_failures_receiver)
         .manage(take_offer_channel)
         .manage(cfd_action_channel)
+        .manage(transaction_history_channel)
+        .manage(preview_withdraw_channel)
+        .manage(broadcast_withdraw_channel)
         .manage(cfd_feed_receiver)
         .manage(wallet_feed_receiver)
         .manage(quote_updates)
         .manage(bitcoin_network)
+        .manage(db.clone())
+        .manage(snapshot_dir)
+        .manage(auth_password)
+        .manage(auth::Username(TAKER_USERNAME))
+        .manage(seed.derive_csrf_token::<auth::CsrfToken>())
         .mount(
             "/api",
             rocket::routes![
@@ -269,13 +427,27 @@ async fn main() -> Result<()> {
                 routes_taker::post_order_request,
                 routes_taker::get_health_check,
                 routes_taker::margin_calc,
+                routes_taker::payout_calc,
                 routes_taker::post_cfd_action,
+                routes_taker::post_partial_settlement,
+                routes_taker::post_add_to_position,
+                routes_taker::get_settlement_triggers,
+                routes_taker::put_settlement_triggers,
+                routes_taker::get_wallet_transactions,
+                routes_taker::export_cfds,
+                routes_taker::get_archived_cfds,
+                routes_taker::get_cfd_events,
+                routes_taker::post_backup,
+                routes_taker::post_withdraw_request,
+                routes_taker::post_withdraw_broadcast,
             ],
         )
+        .register("/api", rocket::catchers![auth::unauthorized])
         .mount(
             "/",
             rocket::routes![routes_taker::dist, routes_taker::index],
         )
+        .register("/", rocket::catchers![auth::unauthorized])
         .launch()
         .await?;
 
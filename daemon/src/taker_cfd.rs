@@ -1,20 +1,25 @@
-use crate::cfd_actors::{self, append_cfd_state, insert_cfd};
-use crate::db::{insert_order, load_cfd_by_order_id, load_order_by_id};
+use crate::cfd_actors::{self, apply_event, insert_cfd};
+use crate::db::{self, insert_order, load_all_cfds, load_cfd_by_order_id, load_order_by_id};
 use crate::model::cfd::{
-    Cfd, CfdState, CfdStateChangeEvent, CfdStateCommon, CollaborativeSettlement, Dlc, Order,
-    OrderId, Origin, Role, RollOverProposal, SettlementKind, SettlementProposal, UpdateCfdProposal,
+    AddToPositionProposal, AutoRollOverFailure, AutoRollOverFailures, Cfd, CfdEvent, CfdState,
+    CfdStateChangeEvent, CollaborativeSettlement, Dlc, Order, OrderId, Origin,
+    PartialSettlementProposal, Role, RollOverDue, RollOverProposal, SettlementKind,
+    SettlementProposal, SettlementRejectionReason, SettlementTriggers, UpdateCfdProposal,
     UpdateCfdProposals,
 };
-use crate::model::{BitMexPriceEventId, Price, Timestamp, Usd};
+use crate::model::{BitMexPriceEventId, FundingRate, Price, Timestamp, Usd};
 use crate::monitor::{self, MonitorParams};
-use crate::wire::{MakerToTaker, RollOverMsg, SetupMsg};
-use crate::{log_error, oracle, setup_contract, wallet, wire};
+use crate::wire::{AddToPositionMsg, MakerToTaker, PartialSettlementMsg, RollOverMsg, SetupMsg};
+use crate::{bitmex_price_feed, log_error, oracle, setup_contract, wallet, wire};
 use anyhow::{Context as _, Result};
 use async_trait::async_trait;
 use bdk::bitcoin::secp256k1::schnorrsig;
+use bdk::bitcoin::SignedAmount;
 use futures::channel::mpsc;
 use futures::{future, SinkExt};
+use sqlx::{AnyConnection, Connection};
 use std::collections::HashMap;
+use time::{Duration, OffsetDateTime};
 use tokio::sync::watch;
 use xtra::prelude::*;
 use xtra::KeepRunning;
@@ -32,11 +37,74 @@ pub enum CfdAction {
     ProposeRollOver {
         order_id: OrderId,
     },
+    ProposePartialSettlement {
+        order_id: OrderId,
+        quantity: Usd,
+        current_price: Price,
+    },
+    ProposeAddToPosition {
+        order_id: OrderId,
+        quantity: Usd,
+        current_price: Price,
+    },
     Commit {
         order_id: OrderId,
     },
+    AcceptCounterProposal {
+        order_id: OrderId,
+    },
+    RejectCounterProposal {
+        order_id: OrderId,
+    },
+    AcceptSettlement {
+        order_id: OrderId,
+    },
+    RejectSettlement {
+        order_id: OrderId,
+    },
+    /// Replaces the stop-loss and take-profit of the CFD, removing them if both are `None`.
+    SetSettlementTriggers {
+        order_id: OrderId,
+        triggers: SettlementTriggers,
+    },
 }
 
+/// Proposes to roll over the CFDs that are about to expire, if automatic roll-over is enabled.
+pub struct AutoRollOver;
+
+/// Drops the proposals the maker did not answer in time.
+pub struct ExpireProposals;
+
+/// Archives the CFDs that have been finished for longer than `archive_after`.
+pub struct ArchiveFinishedCfds {
+    pub archive_after: Duration,
+}
+
+/// Sends our pending proposals to the maker again, e.g. after reconnecting.
+pub struct ReannounceProposals;
+
+/// Tells the maker which of its CFDs are ours, so that it can make proposals for them.
+pub struct AnnounceCfds;
+
+/// Settles the CFDs whose stop-loss or take-profit was hit, sent on every new quote.
+pub struct CheckSettlementTriggers;
+
+/// Commits the CFD if it was not settled since its settlement trigger fired at `fired_at`, no
+/// matter whether the maker rejected the proposed settlement or did not answer.
+pub struct SettlementTriggerTimedOut {
+    pub order_id: OrderId,
+    pub fired_at: OffsetDateTime,
+}
+
+/// How long to wait before proposing an automatic roll-over again after it was rejected or failed.
+const AUTO_ROLL_OVER_RETRY_INTERVAL: Duration = Duration::minutes(5);
+
+/// How long the maker has to settle a CFD whose trigger fired before we commit it.
+const SETTLEMENT_TRIGGER_COMMIT_TIMEOUT: Duration = Duration::minutes(10);
+
+/// Settlement triggers are not checked against quotes older than this.
+const SETTLEMENT_TRIGGER_MAX_QUOTE_AGE: Duration = Duration::minutes(5);
+
 pub struct MakerStreamMessage {
     pub item: Result<wire::MakerToTaker>,
 }
@@ -48,6 +116,19 @@ pub struct CfdSetupCompleted {
 
 pub struct CfdRollOverCompleted {
     pub order_id: OrderId,
+    pub funding_fee: SignedAmount,
+    pub dlc: Result<Dlc>,
+}
+
+pub struct CfdPartialSettlementCompleted {
+    pub order_id: OrderId,
+    pub proposal: PartialSettlementProposal,
+    pub dlc: Result<Dlc>,
+}
+
+pub struct CfdAddToPositionCompleted {
+    pub order_id: OrderId,
+    pub proposal: AddToPositionProposal,
     pub dlc: Result<Dlc>,
 }
 
@@ -65,8 +146,22 @@ enum RollOverState {
     None,
 }
 
+enum PartialSettlementState {
+    Active {
+        sender: mpsc::UnboundedSender<PartialSettlementMsg>,
+    },
+    None,
+}
+
+enum AddToPositionState {
+    Active {
+        sender: mpsc::UnboundedSender<AddToPositionMsg>,
+    },
+    None,
+}
+
 pub struct Actor<O, M, W> {
-    db: sqlx::SqlitePool,
+    db: sqlx::AnyPool,
     wallet: Address<W>,
     oracle_pk: schnorrsig::PublicKey,
     cfd_feed_actor_inbox: watch::Sender<Vec<Cfd>>,
@@ -76,8 +171,23 @@ pub struct Actor<O, M, W> {
     monitor_actor: Address<M>,
     setup_state: SetupState,
     roll_over_state: RollOverState,
+    partial_settlement_state: PartialSettlementState,
+    add_to_position_state: AddToPositionState,
     oracle_actor: Address<O>,
     current_pending_proposals: UpdateCfdProposals,
+    /// How long before expiry CFDs are rolled over automatically, `None` if disabled.
+    auto_roll_over: Option<Duration>,
+    /// When an automatic roll-over was last proposed for a CFD that has not been rolled over yet.
+    auto_roll_over_attempts: HashMap<OrderId, OffsetDateTime>,
+    /// Why CFDs were not rolled over automatically, shown to the user.
+    auto_roll_over_failures: AutoRollOverFailures,
+    auto_roll_over_failures_sender: watch::Sender<AutoRollOverFailures>,
+    cfd_feed: watch::Receiver<Vec<Cfd>>,
+    price_feed: watch::Receiver<bitmex_price_feed::Quote>,
+    settlement_triggers: HashMap<OrderId, SettlementTriggers>,
+    /// When the settlement trigger of a CFD first fired. Settlement is proposed again if the maker
+    /// rejects, the CFD is committed if it is not settled in time after this.
+    fired_settlement_triggers: HashMap<OrderId, OffsetDateTime>,
 }
 
 impl<O, M, W> Actor<O, M, W>
@@ -88,7 +198,7 @@ where
 {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        db: sqlx::SqlitePool,
+        db: sqlx::AnyPool,
         wallet: Address<W>,
         oracle_pk: schnorrsig::PublicKey,
         cfd_feed_actor_inbox: watch::Sender<Vec<Cfd>>,
@@ -97,7 +207,18 @@ where
         send_to_maker: Box<dyn MessageChannel<wire::TakerToMaker>>,
         monitor_actor: Address<M>,
         oracle_actor: Address<O>,
+        auto_roll_over: Option<Duration>,
+        auto_roll_over_failures_sender: watch::Sender<AutoRollOverFailures>,
+        cfd_feed: watch::Receiver<Vec<Cfd>>,
+        price_feed: watch::Receiver<bitmex_price_feed::Quote>,
+        proposals: Vec<UpdateCfdProposal>,
+        settlement_triggers: HashMap<OrderId, SettlementTriggers>,
     ) -> Self {
+        let current_pending_proposals = proposals
+            .into_iter()
+            .map(|proposal| (proposal.order_id(), proposal))
+            .collect();
+
         Self {
             db,
             wallet,
@@ -109,8 +230,18 @@ where
             monitor_actor,
             setup_state: SetupState::None,
             roll_over_state: RollOverState::None,
+            partial_settlement_state: PartialSettlementState::None,
+            add_to_position_state: AddToPositionState::None,
             oracle_actor,
-            current_pending_proposals: HashMap::new(),
+            current_pending_proposals,
+            auto_roll_over,
+            auto_roll_over_attempts: HashMap::new(),
+            auto_roll_over_failures: AutoRollOverFailures::new(),
+            auto_roll_over_failures_sender,
+            cfd_feed,
+            price_feed,
+            settlement_triggers,
+            fired_settlement_triggers: HashMap::new(),
         }
     }
 }
@@ -122,15 +253,157 @@ impl<O, M, W> Actor<O, M, W> {
             .send(self.current_pending_proposals.clone())?)
     }
 
+    fn set_auto_roll_over_failure(
+        &mut self,
+        order_id: OrderId,
+        failure: AutoRollOverFailure,
+    ) -> Result<()> {
+        self.auto_roll_over_failures.insert(order_id, failure);
+        Ok(self
+            .auto_roll_over_failures_sender
+            .send(self.auto_roll_over_failures.clone())?)
+    }
+
+    fn clear_auto_roll_over_failure(&mut self, order_id: OrderId) -> Result<()> {
+        if self.auto_roll_over_failures.remove(&order_id).is_none() {
+            return Ok(());
+        }
+        Ok(self
+            .auto_roll_over_failures_sender
+            .send(self.auto_roll_over_failures.clone())?)
+    }
+
+    /// Stores a proposal and updates the update cfd proposals' feed
+    async fn add_pending_proposal(&mut self, proposal: UpdateCfdProposal) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        db::insert_proposal(&proposal, None, &mut conn).await?;
+
+        self.current_pending_proposals
+            .insert(proposal.order_id(), proposal);
+        self.send_pending_update_proposals()?;
+        Ok(())
+    }
+
     /// Removes a proposal and updates the update cfd proposals' feed
-    fn remove_pending_proposal(&mut self, order_id: &OrderId) -> Result<()> {
-        if self.current_pending_proposals.remove(order_id).is_none() {
+    async fn remove_pending_proposal(&mut self, order_id: &OrderId) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        self.remove_pending_proposal_with(order_id, &mut conn).await
+    }
+
+    /// Removes a proposal on the given connection, so it can be part of the transaction of the
+    /// state change concluding the proposal
+    async fn remove_pending_proposal_with(
+        &mut self,
+        order_id: &OrderId,
+        conn: &mut AnyConnection,
+    ) -> Result<()> {
+        if !self.current_pending_proposals.contains_key(order_id) {
             anyhow::bail!("Could not find proposal with order id: {}", &order_id)
         }
+
+        db::delete_proposal(*order_id, conn).await?;
+
+        self.current_pending_proposals.remove(order_id);
         self.send_pending_update_proposals()?;
         Ok(())
     }
 
+    /// Sends one of our proposals to the maker
+    fn announce_proposal(&self, proposal: &UpdateCfdProposal) -> Result<()> {
+        let msg = match proposal {
+            UpdateCfdProposal::Settlement { proposal, .. } => {
+                wire::TakerToMaker::ProposeSettlement {
+                    order_id: proposal.order_id,
+                    timestamp: proposal.timestamp,
+                    taker: proposal.taker,
+                    maker: proposal.maker,
+                    price: proposal.price,
+                }
+            }
+            UpdateCfdProposal::RollOverProposal { proposal, .. } => {
+                wire::TakerToMaker::ProposeRollOver {
+                    order_id: proposal.order_id,
+                    timestamp: proposal.timestamp,
+                }
+            }
+            UpdateCfdProposal::PartialSettlement { proposal, .. } => {
+                wire::TakerToMaker::ProposePartialSettlement {
+                    order_id: proposal.order_id,
+                    timestamp: proposal.timestamp,
+                    quantity: proposal.quantity,
+                    taker: proposal.taker,
+                    maker: proposal.maker,
+                    price: proposal.price,
+                }
+            }
+            UpdateCfdProposal::AddToPosition { proposal, .. } => {
+                wire::TakerToMaker::ProposeAddToPosition {
+                    order_id: proposal.order_id,
+                    timestamp: proposal.timestamp,
+                    quantity: proposal.quantity,
+                    price: proposal.price,
+                }
+            }
+        };
+
+        self.send_to_maker.do_send(msg)?;
+        Ok(())
+    }
+
+    async fn handle_expire_proposals(&mut self) -> Result<()> {
+        let mut expired = Vec::new();
+        for (order_id, proposal) in self.current_pending_proposals.iter() {
+            if proposal.is_expired()? {
+                expired.push(*order_id);
+            }
+        }
+
+        for order_id in expired {
+            tracing::info!(%order_id, "Proposal expired without an answer from the maker");
+            self.remove_pending_proposal(&order_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Our proposals are only known to the connection they were sent on, the maker has to be told
+    /// about them again after reconnecting
+    async fn handle_reannounce_proposals(&mut self) -> Result<()> {
+        self.handle_expire_proposals().await?;
+
+        for proposal in self.current_pending_proposals.values() {
+            if proposal.direction() == &SettlementKind::Outgoing {
+                tracing::info!(order_id = %proposal.order_id(), "Re-announcing proposal");
+                self.announce_proposal(proposal)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_announce_cfds(&mut self) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        let order_ids = load_all_cfds(&mut conn)
+            .await?
+            .into_iter()
+            .filter(|cfd| matches!(cfd.state, CfdState::Open { .. }))
+            .map(|cfd| cfd.order.id)
+            .collect();
+
+        self.send_to_maker
+            .do_send(wire::TakerToMaker::AnnounceCfds { order_ids })?;
+        Ok(())
+    }
+
+    async fn remove_settlement_triggers(&mut self, order_id: OrderId) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        db::delete_settlement_triggers(order_id, &mut conn).await?;
+
+        self.settlement_triggers.remove(&order_id);
+        self.fired_settlement_triggers.remove(&order_id);
+        Ok(())
+    }
+
     fn get_settlement_proposal(&self, order_id: OrderId) -> Result<&SettlementProposal> {
         match self
             .current_pending_proposals
@@ -141,6 +414,37 @@ impl<O, M, W> Actor<O, M, W> {
             UpdateCfdProposal::RollOverProposal { .. } => {
                 anyhow::bail!("did not expect a rollover proposal");
             }
+            UpdateCfdProposal::PartialSettlement { .. } => {
+                anyhow::bail!("did not expect a partial settlement proposal");
+            }
+            UpdateCfdProposal::AddToPosition { .. } => {
+                anyhow::bail!("did not expect an add to position proposal");
+            }
+        }
+    }
+
+    fn get_partial_settlement_proposal(
+        &self,
+        order_id: OrderId,
+    ) -> Result<&PartialSettlementProposal> {
+        match self
+            .current_pending_proposals
+            .get(&order_id)
+            .context("have a proposal that is about to be accepted")?
+        {
+            UpdateCfdProposal::PartialSettlement { proposal, .. } => Ok(proposal),
+            _ => anyhow::bail!("expected a partial settlement proposal"),
+        }
+    }
+
+    fn get_add_to_position_proposal(&self, order_id: OrderId) -> Result<&AddToPositionProposal> {
+        match self
+            .current_pending_proposals
+            .get(&order_id)
+            .context("have a proposal that is about to be accepted")?
+        {
+            UpdateCfdProposal::AddToPosition { proposal, .. } => Ok(proposal),
+            _ => anyhow::bail!("expected an add to position proposal"),
         }
     }
 
@@ -189,6 +493,31 @@ where
         Ok(())
     }
 
+    async fn handle_set_settlement_triggers(
+        &mut self,
+        order_id: OrderId,
+        triggers: SettlementTriggers,
+    ) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+
+        if !matches!(cfd.state, CfdState::Open { .. }) {
+            anyhow::bail!("Order is in invalid state. Cannot set settlement triggers.")
+        }
+        if self.fired_settlement_triggers.contains_key(&order_id) {
+            anyhow::bail!("Settlement triggers of order id {} already fired", order_id)
+        }
+        triggers.validate(&cfd.position())?;
+
+        if triggers.is_empty() {
+            return self.remove_settlement_triggers(order_id).await;
+        }
+
+        db::insert_settlement_triggers(order_id, &triggers, &mut conn).await?;
+        self.settlement_triggers.insert(order_id, triggers);
+        Ok(())
+    }
+
     async fn handle_propose_settlement(
         &mut self,
         order_id: OrderId,
@@ -209,23 +538,76 @@ where
             )
         }
 
-        self.current_pending_proposals.insert(
-            proposal.order_id,
-            UpdateCfdProposal::Settlement {
-                proposal: proposal.clone(),
-                direction: SettlementKind::Outgoing,
-            },
-        );
-        self.send_pending_update_proposals()?;
+        let proposal = UpdateCfdProposal::Settlement {
+            proposal,
+            direction: SettlementKind::Outgoing,
+        };
+        self.add_pending_proposal(proposal.clone()).await?;
+        self.announce_proposal(&proposal)?;
+        Ok(())
+    }
 
-        self.send_to_maker
-            .do_send(wire::TakerToMaker::ProposeSettlement {
-                order_id: proposal.order_id,
-                timestamp: proposal.timestamp,
-                taker: proposal.taker,
-                maker: proposal.maker,
-                price: proposal.price,
-            })?;
+    async fn handle_propose_partial_settlement(
+        &mut self,
+        order_id: OrderId,
+        quantity: Usd,
+        current_price: Price,
+    ) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+
+        if !matches!(cfd.state, CfdState::Open { .. }) {
+            anyhow::bail!("Order is in invalid state. Cannot propose partial settlement.")
+        }
+
+        let proposal = cfd.calculate_partial_settlement(quantity, current_price)?;
+
+        if self.current_pending_proposals.contains_key(&order_id) {
+            anyhow::bail!("An update for order id {} is already in progress", order_id)
+        }
+
+        let proposal = UpdateCfdProposal::PartialSettlement {
+            proposal,
+            direction: SettlementKind::Outgoing,
+        };
+        self.add_pending_proposal(proposal.clone()).await?;
+        self.announce_proposal(&proposal)?;
+        Ok(())
+    }
+
+    async fn handle_propose_add_to_position(
+        &mut self,
+        order_id: OrderId,
+        quantity: Usd,
+        current_price: Price,
+    ) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+
+        if !matches!(cfd.state, CfdState::Open { .. }) {
+            anyhow::bail!("Order is in invalid state. Cannot add to the position.")
+        }
+
+        // Fail early if the quantity cannot be added
+        cfd.with_added_position(quantity, current_price)?;
+
+        if self.current_pending_proposals.contains_key(&order_id) {
+            anyhow::bail!("An update for order id {} is already in progress", order_id)
+        }
+
+        let proposal = AddToPositionProposal {
+            order_id,
+            timestamp: Timestamp::now()?,
+            quantity,
+            price: current_price,
+        };
+
+        let proposal = UpdateCfdProposal::AddToPosition {
+            proposal,
+            direction: SettlementKind::Outgoing,
+        };
+        self.add_pending_proposal(proposal.clone()).await?;
+        self.announce_proposal(&proposal)?;
         Ok(())
     }
 
@@ -235,92 +617,445 @@ where
         Ok(())
     }
 
-    async fn handle_settlement_rejected(&mut self, order_id: OrderId) -> Result<()> {
-        tracing::info!(%order_id, "Settlement proposal got rejected");
+    async fn handle_settlement_rejected(
+        &mut self,
+        order_id: OrderId,
+        reason: Option<SettlementRejectionReason>,
+    ) -> Result<()> {
+        match reason {
+            Some(reason) => tracing::info!(%order_id, %reason, "Settlement proposal got rejected"),
+            None => tracing::info!(%order_id, "Settlement proposal got rejected"),
+        }
 
-        self.remove_pending_proposal(&order_id)?;
+        self.remove_pending_proposal(&order_id).await?;
+        self.retry_fired_settlement_trigger(order_id);
 
         Ok(())
     }
 
+    /// The maker answered the settlement we proposed because a settlement trigger fired without
+    /// settling, so we propose again on the next quote. The CFD is still committed once the
+    /// timeout since the trigger first fired has passed.
+    fn retry_fired_settlement_trigger(&self, order_id: OrderId) {
+        if let Some(fired_at) = self.fired_settlement_triggers.get(&order_id) {
+            tracing::info!(%order_id, %fired_at, "Proposing to settle again on the next quote");
+        }
+    }
+
     async fn handle_roll_over_rejected(&mut self, order_id: OrderId) -> Result<()> {
         tracing::info!(%order_id, "Roll over proposal got rejected");
 
+        if self.auto_roll_over_attempts.contains_key(&order_id) {
+            tracing::warn!(
+                %order_id,
+                "Automatic roll-over was rejected, retrying in {} minutes",
+                AUTO_ROLL_OVER_RETRY_INTERVAL.whole_minutes()
+            );
+            self.set_auto_roll_over_failure(
+                order_id,
+                AutoRollOverFailure::Failed {
+                    reason: String::from("rejected by the maker"),
+                },
+            )?;
+        }
+
         self.remove_pending_proposal(&order_id)
+            .await
             .context("rejected settlement")?;
 
         Ok(())
     }
 
-    async fn handle_inc_protocol_msg(&mut self, msg: SetupMsg) -> Result<()> {
-        match &mut self.setup_state {
-            SetupState::Active { sender } => {
-                sender.send(msg).await?;
-            }
-            SetupState::None => {
-                anyhow::bail!("Received setup message without an active contract setup")
-            }
-        }
-
-        Ok(())
-    }
+    async fn handle_partial_settlement_rejected(&mut self, order_id: OrderId) -> Result<()> {
+        tracing::info!(%order_id, "Partial settlement proposal got rejected");
 
-    async fn handle_inc_roll_over_msg(&mut self, msg: RollOverMsg) -> Result<()> {
-        match &mut self.roll_over_state {
-            RollOverState::Active { sender } => {
-                sender.send(msg).await?;
-            }
-            RollOverState::None => {
-                anyhow::bail!("Received message without an active roll_over setup")
-            }
-        }
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("rejected partial settlement")?;
 
         Ok(())
     }
 
-    async fn handle_invalid_order_id(&mut self, order_id: OrderId) -> Result<()> {
-        tracing::debug!(%order_id, "Invalid order ID");
+    async fn handle_add_to_position_rejected(&mut self, order_id: OrderId) -> Result<()> {
+        tracing::info!(%order_id, "Add to position proposal got rejected");
 
-        self.append_cfd_state_rejected(order_id).await?;
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("rejected add to position")?;
 
         Ok(())
     }
 
-    async fn append_cfd_state_rejected(&mut self, order_id: OrderId) -> Result<()> {
-        tracing::debug!(%order_id, "Order rejected");
+    async fn handle_settlement_countered(&mut self, proposal: SettlementProposal) -> Result<()> {
+        let order_id = proposal.order_id;
+        tracing::info!(%order_id, price = %proposal.price, "Maker countered settlement proposal");
+
+        match self.current_pending_proposals.get(&order_id) {
+            Some(UpdateCfdProposal::Settlement {
+                direction: SettlementKind::Outgoing,
+                ..
+            }) => {}
+            _ => anyhow::bail!("No settlement proposal pending for order id {}", order_id),
+        }
 
         let mut conn = self.db.acquire().await?;
-        let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
-        cfd.state = CfdState::rejected();
-        append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        if let Err(e) = cfd.verify_settlement(&proposal) {
+            self.send_to_maker
+                .do_send(wire::TakerToMaker::RejectCounterProposal { order_id })?;
+            self.remove_pending_proposal(&order_id).await?;
+            self.retry_fired_settlement_trigger(order_id);
 
-        Ok(())
+            return Err(e.context("Rejected counter-proposal of the maker"));
+        }
+
+        self.add_pending_proposal(UpdateCfdProposal::Settlement {
+            proposal,
+            direction: SettlementKind::Incoming,
+        })
+        .await
     }
-}
 
-impl<O, M, W> Actor<O, M, W> {
-    async fn handle_new_order(&mut self, order: Option<Order>) -> Result<()> {
-        match order {
-            Some(mut order) => {
-                order.origin = Origin::Theirs;
+    async fn handle_roll_over_countered(&mut self, proposal: RollOverProposal) -> Result<()> {
+        let order_id = proposal.order_id;
+        tracing::info!(
+            %order_id,
+            funding_rate = ?proposal.funding_rate,
+            "Maker countered roll over proposal"
+        );
 
-                let mut conn = self.db.acquire().await?;
-                insert_order(&order, &mut conn).await?;
-                self.order_feed_actor_inbox.send(Some(order))?;
-            }
-            None => {
-                self.order_feed_actor_inbox.send(None)?;
-            }
+        match self.current_pending_proposals.get(&order_id) {
+            Some(UpdateCfdProposal::RollOverProposal {
+                direction: SettlementKind::Outgoing,
+                ..
+            }) => {}
+            _ => anyhow::bail!("No roll over proposal pending for order id {}", order_id),
         }
-        Ok(())
+
+        self.add_pending_proposal(UpdateCfdProposal::RollOverProposal {
+            proposal,
+            direction: SettlementKind::Incoming,
+        })
+        .await
     }
-}
 
-impl<O, M, W> Actor<O, M, W>
-where
-    W: xtra::Handler<wallet::TryBroadcastTransaction>,
-{
-    async fn handle_oracle_attestation(&mut self, attestation: oracle::Attestation) -> Result<()> {
+    async fn handle_settlement_proposed(&mut self, proposal: SettlementProposal) -> Result<()> {
+        let order_id = proposal.order_id;
+        tracing::info!(%order_id, price = %proposal.price, "Maker proposed to settle");
+
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        if !matches!(cfd.state, CfdState::Open { .. }) {
+            anyhow::bail!("Order is in invalid state. Ignoring settlement proposal.")
+        }
+
+        if self.current_pending_proposals.contains_key(&order_id) {
+            self.send_to_maker
+                .do_send(wire::TakerToMaker::RejectSettlement { order_id })?;
+            anyhow::bail!(
+                "Rejected settlement proposal of the maker, an update for order id {} is already \
+                 in progress",
+                order_id
+            )
+        }
+
+        if let Err(e) = cfd.verify_settlement(&proposal) {
+            self.send_to_maker
+                .do_send(wire::TakerToMaker::RejectSettlement { order_id })?;
+            return Err(e.context("Rejected settlement proposal of the maker"));
+        }
+
+        self.add_pending_proposal(UpdateCfdProposal::Settlement {
+            proposal,
+            direction: SettlementKind::Incoming,
+        })
+        .await
+    }
+
+    /// Agrees to a settlement proposed by the maker, which then confirms it like our own proposal
+    async fn handle_accept_settlement(&mut self, order_id: OrderId) -> Result<()> {
+        match self.current_pending_proposals.get(&order_id) {
+            Some(UpdateCfdProposal::Settlement {
+                direction: SettlementKind::Incoming,
+                ..
+            }) => {}
+            _ => anyhow::bail!("Maker did not propose to settle order id {}", order_id),
+        }
+
+        self.send_to_maker
+            .do_send(wire::TakerToMaker::AcceptSettlement { order_id })?;
+        Ok(())
+    }
+
+    async fn handle_reject_settlement(&mut self, order_id: OrderId) -> Result<()> {
+        match self.current_pending_proposals.get(&order_id) {
+            Some(UpdateCfdProposal::Settlement {
+                direction: SettlementKind::Incoming,
+                ..
+            }) => {}
+            _ => anyhow::bail!("Maker did not propose to settle order id {}", order_id),
+        }
+
+        self.send_to_maker
+            .do_send(wire::TakerToMaker::RejectSettlement { order_id })?;
+
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("rejected settlement")?;
+        Ok(())
+    }
+
+    /// Agrees to the counter-proposal of the maker, which then confirms it like our own proposal
+    async fn handle_accept_counter_proposal(&mut self, order_id: OrderId) -> Result<()> {
+        match self.current_pending_proposals.get(&order_id) {
+            Some(UpdateCfdProposal::Settlement {
+                direction: SettlementKind::Incoming,
+                ..
+            })
+            | Some(UpdateCfdProposal::RollOverProposal {
+                direction: SettlementKind::Incoming,
+                ..
+            }) => {}
+            _ => anyhow::bail!("No counter-proposal pending for order id {}", order_id),
+        }
+
+        self.send_to_maker
+            .do_send(wire::TakerToMaker::AcceptCounterProposal { order_id })?;
+        Ok(())
+    }
+
+    async fn handle_reject_counter_proposal(&mut self, order_id: OrderId) -> Result<()> {
+        match self.current_pending_proposals.get(&order_id) {
+            Some(UpdateCfdProposal::Settlement {
+                direction: SettlementKind::Incoming,
+                ..
+            })
+            | Some(UpdateCfdProposal::RollOverProposal {
+                direction: SettlementKind::Incoming,
+                ..
+            }) => {}
+            _ => anyhow::bail!("No counter-proposal pending for order id {}", order_id),
+        }
+
+        self.send_to_maker
+            .do_send(wire::TakerToMaker::RejectCounterProposal { order_id })?;
+
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("rejected counter-proposal")?;
+        self.retry_fired_settlement_trigger(order_id);
+        Ok(())
+    }
+
+    async fn handle_inc_protocol_msg(&mut self, msg: SetupMsg) -> Result<()> {
+        match &mut self.setup_state {
+            SetupState::Active { sender } => {
+                sender.send(msg).await?;
+            }
+            SetupState::None => {
+                anyhow::bail!("Received setup message without an active contract setup")
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_inc_roll_over_msg(&mut self, msg: RollOverMsg) -> Result<()> {
+        match &mut self.roll_over_state {
+            RollOverState::Active { sender } => {
+                sender.send(msg).await?;
+            }
+            RollOverState::None => {
+                anyhow::bail!("Received message without an active roll_over setup")
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_inc_partial_settlement_msg(&mut self, msg: PartialSettlementMsg) -> Result<()> {
+        match &mut self.partial_settlement_state {
+            PartialSettlementState::Active { sender } => {
+                sender.send(msg).await?;
+            }
+            PartialSettlementState::None => {
+                anyhow::bail!("Received message without an active partial settlement")
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_inc_add_to_position_msg(&mut self, msg: AddToPositionMsg) -> Result<()> {
+        match &mut self.add_to_position_state {
+            AddToPositionState::Active { sender } => {
+                sender.send(msg).await?;
+            }
+            AddToPositionState::None => {
+                anyhow::bail!("Received message without an active add to position")
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_invalid_order_id(&mut self, order_id: OrderId) -> Result<()> {
+        tracing::debug!(%order_id, "Invalid order ID");
+
+        self.append_cfd_state_rejected(order_id).await?;
+
+        Ok(())
+    }
+
+    async fn append_cfd_state_rejected(&mut self, order_id: OrderId) -> Result<()> {
+        tracing::debug!(%order_id, "Order rejected");
+
+        let mut conn = self.db.acquire().await?;
+        let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        apply_event(
+            &mut cfd,
+            CfdEvent::OrderRejected,
+            &mut conn,
+            &self.cfd_feed_actor_inbox,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl<O: 'static, M: 'static, W: 'static> Actor<O, M, W>
+where
+    Self: xtra::Handler<SettlementTriggerTimedOut>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>
+        + xtra::Handler<wallet::Sign>
+        + xtra::Handler<wallet::BuildPartyParams>,
+{
+    /// Proposes to settle the CFDs whose trigger was hit and commits them if they are not settled
+    /// in time
+    async fn handle_check_settlement_triggers(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        if self.settlement_triggers.is_empty() {
+            return Ok(());
+        }
+
+        let quote = self.price_feed.borrow().clone();
+        let quote_age = Timestamp::now()?.seconds() - quote.timestamp.seconds();
+        if quote_age > SETTLEMENT_TRIGGER_MAX_QUOTE_AGE.whole_seconds() {
+            tracing::warn!(%quote_age, "Not checking settlement triggers against outdated quote");
+            return Ok(());
+        }
+        let current_price = quote.for_taker();
+
+        let pending = self
+            .settlement_triggers
+            .iter()
+            .filter(|(order_id, _)| !self.current_pending_proposals.contains_key(*order_id))
+            .map(|(order_id, triggers)| (*order_id, *triggers))
+            .collect::<Vec<_>>();
+
+        let mut conn = self.db.acquire().await?;
+        for (order_id, triggers) in pending {
+            let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+
+            // The CFD was settled or committed in the meantime
+            if !matches!(cfd.state, CfdState::Open { .. }) {
+                self.remove_settlement_triggers(order_id).await?;
+                continue;
+            }
+
+            if !triggers.is_triggered(&cfd.position(), current_price) {
+                continue;
+            }
+
+            tracing::info!(%order_id, %current_price, "Settlement trigger hit");
+
+            // E.g. another proposal is still pending, we try again on the next quote
+            if let Err(e) = self
+                .handle_propose_settlement(order_id, current_price)
+                .await
+            {
+                tracing::warn!(%order_id, "Failed to propose settlement: {:#}", e);
+                continue;
+            }
+
+            // Proposing again after a rejection does not extend the time the maker has to settle
+            if self.fired_settlement_triggers.contains_key(&order_id) {
+                continue;
+            }
+
+            let fired_at = OffsetDateTime::now_utc();
+            self.fired_settlement_triggers.insert(order_id, fired_at);
+
+            let this = ctx
+                .address()
+                .expect("actor to be able to give address to itself");
+            tokio::spawn(async move {
+                let timeout = SETTLEMENT_TRIGGER_COMMIT_TIMEOUT.whole_seconds() as u64;
+                tokio::time::sleep(std::time::Duration::from_secs(timeout)).await;
+                this.do_send_async(SettlementTriggerTimedOut { order_id, fired_at })
+                    .await
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn handle_settlement_trigger_timed_out(
+        &mut self,
+        order_id: OrderId,
+        fired_at: OffsetDateTime,
+    ) -> Result<()> {
+        // The triggers were removed or the CFD was settled in the meantime
+        if self.fired_settlement_triggers.get(&order_id) != Some(&fired_at) {
+            return Ok(());
+        }
+
+        let mut conn = self.db.acquire().await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        if !matches!(cfd.state, CfdState::Open { .. }) {
+            return self.remove_settlement_triggers(order_id).await;
+        }
+
+        tracing::info!(%order_id, "CFD was not settled in time, committing it");
+        if let Err(e) = self.handle_commit(order_id).await {
+            // The trigger is checked again on the next quote
+            self.fired_settlement_triggers.remove(&order_id);
+            return Err(e.context(format!("Failed to commit order id {}", order_id)));
+        }
+
+        self.remove_settlement_triggers(order_id).await
+    }
+}
+
+impl<O, M, W> Actor<O, M, W> {
+    async fn handle_new_order(&mut self, order: Option<Order>) -> Result<()> {
+        match order {
+            Some(mut order) => {
+                order.origin = Origin::Theirs;
+
+                let mut conn = self.db.acquire().await?;
+                insert_order(&order, &mut conn).await?;
+                self.order_feed_actor_inbox.send(Some(order))?;
+            }
+            None => {
+                self.order_feed_actor_inbox.send(None)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_archive_finished_cfds(&mut self, archive_after: Duration) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        cfd_actors::archive_finished_cfds(archive_after, &mut conn, &self.cfd_feed_actor_inbox)
+            .await
+    }
+}
+
+impl<O, M, W> Actor<O, M, W>
+where
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
+{
+    async fn handle_oracle_attestation(&mut self, attestation: oracle::Attestation) -> Result<()> {
         let mut conn = self.db.acquire().await?;
         cfd_actors::handle_oracle_attestation(
             attestation,
@@ -357,22 +1092,78 @@ where
         let proposal = RollOverProposal {
             order_id,
             timestamp: Timestamp::now()?,
+            funding_rate: None,
         };
 
-        self.current_pending_proposals.insert(
-            proposal.order_id,
-            UpdateCfdProposal::RollOverProposal {
-                proposal: proposal.clone(),
-                direction: SettlementKind::Outgoing,
-            },
-        );
-        self.send_pending_update_proposals()?;
+        let proposal = UpdateCfdProposal::RollOverProposal {
+            proposal,
+            direction: SettlementKind::Outgoing,
+        };
+        self.add_pending_proposal(proposal.clone()).await?;
+        self.announce_proposal(&proposal)?;
+        Ok(())
+    }
+
+    async fn handle_auto_roll_over(&mut self) -> Result<()> {
+        let before_expiry = match self.auto_roll_over {
+            Some(before_expiry) => before_expiry,
+            None => return Ok(()),
+        };
+
+        let now = OffsetDateTime::now_utc();
+        let mut due = Vec::new();
+        let mut expired = Vec::new();
+        for cfd in self.cfd_feed.borrow().iter() {
+            match cfd.roll_over_due(before_expiry, now) {
+                RollOverDue::Yes => due.push((cfd.order.id, cfd.expiry_timestamp())),
+                RollOverDue::Expired => expired.push(cfd.order.id),
+                RollOverDue::No => {}
+            }
+        }
+
+        // Expired CFDs are reported once and skipped afterwards
+        for order_id in expired {
+            if self.auto_roll_over_failures.get(&order_id) == Some(&AutoRollOverFailure::Expired) {
+                continue;
+            }
+            tracing::error!(
+                %order_id,
+                "CFD expired without automatic roll-over, it settles at the oracle price"
+            );
+            self.auto_roll_over_attempts.remove(&order_id);
+            self.set_auto_roll_over_failure(order_id, AutoRollOverFailure::Expired)?;
+        }
+
+        // Only one roll-over can be in progress at a time, the others follow on the next tick
+        if let RollOverState::Active { .. } = self.roll_over_state {
+            return Ok(());
+        }
+
+        let next = due.into_iter().find(|(order_id, _)| {
+            let retry_due = match self.auto_roll_over_attempts.get(order_id) {
+                Some(last_attempt) => now - *last_attempt >= AUTO_ROLL_OVER_RETRY_INTERVAL,
+                None => true,
+            };
+
+            !self.current_pending_proposals.contains_key(order_id) && retry_due
+        });
+        let (order_id, expiry) = match next {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+
+        tracing::info!(%order_id, %expiry, "Proposing automatic roll-over");
+        self.auto_roll_over_attempts.insert(order_id, now);
+        if let Err(e) = self.handle_propose_roll_over(order_id).await {
+            self.set_auto_roll_over_failure(
+                order_id,
+                AutoRollOverFailure::Failed {
+                    reason: format!("{:#}", e),
+                },
+            )?;
+            return Err(e.context("Failed to propose automatic roll-over"));
+        }
 
-        self.send_to_maker
-            .do_send(wire::TakerToMaker::ProposeRollOver {
-                order_id: proposal.order_id,
-                timestamp: proposal.timestamp,
-            })?;
         Ok(())
     }
 }
@@ -385,52 +1176,275 @@ impl<O, M, W> Actor<O, M, W> where
 
 impl<O, M, W> Actor<O, M, W>
 where
-    O: xtra::Handler<oracle::MonitorAttestation>,
+    O: xtra::Handler<oracle::MonitorAttestation>,
+    M: xtra::Handler<monitor::StartMonitoring>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
+{
+    async fn handle_cfd_setup_completed(
+        &mut self,
+        order_id: OrderId,
+        dlc: Result<Dlc>,
+    ) -> Result<()> {
+        self.setup_state = SetupState::None;
+
+        let mut conn = self.db.acquire().await?;
+        let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+
+        let dlc = match dlc {
+            Ok(dlc) => dlc,
+            Err(e) => {
+                let event = CfdEvent::ContractSetupFailed {
+                    info: e.to_string(),
+                };
+                apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
+
+                return Err(e);
+            }
+        };
+
+        tracing::info!("Setup complete, publishing on chain now");
+
+        let event = CfdEvent::ContractSetupCompleted { dlc: dlc.clone() };
+        apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
+
+        let txid = self
+            .wallet
+            .send(wallet::TryBroadcastTransaction {
+                tx: dlc.lock.0.clone(),
+            })
+            .await??;
+
+        tracing::info!("Lock transaction published with txid {}", txid);
+
+        self.monitor_actor
+            .do_send_async(monitor::StartMonitoring {
+                id: order_id,
+                params: MonitorParams::new(
+                    dlc,
+                    cfd.refund_timelock_in_blocks(),
+                    cfd.order.oracle_event_id,
+                ),
+            })
+            .await?;
+
+        self.oracle_actor
+            .do_send_async(oracle::MonitorAttestation {
+                event_id: cfd.order.oracle_event_id,
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl<O: 'static, M: 'static, W: 'static> Actor<O, M, W>
+where
+    Self: xtra::Handler<CfdSetupCompleted>,
+    O: xtra::Handler<oracle::GetAnnouncement> + xtra::Handler<oracle::MonitorAttestation>,
+    W: xtra::Handler<wallet::Sign> + xtra::Handler<wallet::BuildPartyParams>,
+{
+    async fn handle_order_accepted(
+        &mut self,
+        order_id: OrderId,
+        ctx: &mut Context<Self>,
+    ) -> Result<()> {
+        tracing::info!(%order_id, "Order got accepted");
+
+        let (sender, receiver) = mpsc::unbounded();
+
+        if let SetupState::Active { .. } = self.setup_state {
+            anyhow::bail!("Already setting up a contract!")
+        }
+
+        let mut conn = self.db.acquire().await?;
+        let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        apply_event(
+            &mut cfd,
+            CfdEvent::ContractSetupStarted,
+            &mut conn,
+            &self.cfd_feed_actor_inbox,
+        )
+        .await?;
+
+        let offer_announcement = self
+            .oracle_actor
+            .send(oracle::GetAnnouncement(cfd.order.oracle_event_id))
+            .await?
+            .with_context(|| format!("Announcement {} not found", cfd.order.oracle_event_id))?;
+
+        self.oracle_actor
+            .do_send_async(oracle::MonitorAttestation {
+                event_id: offer_announcement.id,
+            })
+            .await?;
+
+        let contract_future = setup_contract::new(
+            self.send_to_maker
+                .sink()
+                .clone_message_sink()
+                .with(|msg| future::ok(wire::TakerToMaker::Protocol(msg))),
+            receiver,
+            (self.oracle_pk, offer_announcement),
+            cfd,
+            self.wallet.clone(),
+            Role::Taker,
+        );
+
+        let this = ctx
+            .address()
+            .expect("actor to be able to give address to itself");
+
+        tokio::spawn(async move {
+            let dlc = contract_future.await;
+
+            this.do_send_async(CfdSetupCompleted { order_id, dlc })
+                .await
+        });
+
+        self.setup_state = SetupState::Active { sender };
+
+        Ok(())
+    }
+}
+
+impl<O: 'static, M: 'static, W: 'static> Actor<O, M, W>
+where
+    Self: xtra::Handler<CfdRollOverCompleted>,
+    O: xtra::Handler<oracle::GetAnnouncement>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>
+        + xtra::Handler<wallet::Sign>
+        + xtra::Handler<wallet::BuildPartyParams>,
+{
+    async fn handle_roll_over_accepted(
+        &mut self,
+        order_id: OrderId,
+        oracle_event_id: BitMexPriceEventId,
+        funding_rate: FundingRate,
+        ctx: &mut Context<Self>,
+    ) -> Result<()> {
+        tracing::info!(%order_id, %funding_rate, "Roll; over request got accepted");
+
+        let (sender, receiver) = mpsc::unbounded();
+
+        if let RollOverState::Active { .. } = self.roll_over_state {
+            anyhow::bail!("Already rolling over a contract!")
+        }
+
+        let mut conn = self.db.acquire().await?;
+
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        let dlc = cfd.open_dlc().context("CFD was in wrong state")?;
+
+        // Without a counter-proposal we agreed to, the maker has to stick to the quoted rate
+        let (agreed_funding_rate, acceptable) = match self.current_pending_proposals.get(&order_id)
+        {
+            Some(UpdateCfdProposal::RollOverProposal {
+                proposal:
+                    RollOverProposal {
+                        funding_rate: Some(agreed_funding_rate),
+                        ..
+                    },
+                ..
+            }) => (*agreed_funding_rate, *agreed_funding_rate == funding_rate),
+            _ => (
+                cfd.order.funding_rate,
+                cfd.is_within_quoted_funding_rate(funding_rate),
+            ),
+        };
+        if !acceptable {
+            // Not taking part in the protocol lets the roll-over of the maker time out
+            self.remove_pending_proposal(&order_id)
+                .await
+                .context("Could not remove rejected roll over")?;
+            anyhow::bail!(
+                "Rejected roll over of order id {}, the maker asked for funding rate {} instead of \
+                 {}",
+                order_id,
+                funding_rate,
+                agreed_funding_rate
+            )
+        }
+        let funding_fee = cfd.funding_fee(funding_rate)?;
+
+        let announcement = self
+            .oracle_actor
+            .send(oracle::GetAnnouncement(oracle_event_id))
+            .await?
+            .with_context(|| format!("Announcement {} not found", oracle_event_id))?;
+
+        let contract_future = setup_contract::roll_over(
+            self.send_to_maker
+                .sink()
+                .with(|msg| future::ok(wire::TakerToMaker::RollOverProtocol(msg))),
+            receiver,
+            (self.oracle_pk, announcement),
+            cfd,
+            funding_fee,
+            Role::Taker,
+            dlc,
+        );
+
+        let this = ctx
+            .address()
+            .expect("actor to be able to give address to itself");
+
+        self.roll_over_state = RollOverState::Active { sender };
+
+        tokio::spawn(async move {
+            let dlc = contract_future.await;
+
+            this.do_send_async(CfdRollOverCompleted {
+                order_id,
+                funding_fee,
+                dlc,
+            })
+            .await
+        });
+
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("Could not remove accepted roll over")?;
+        Ok(())
+    }
+}
+
+impl<O: 'static, M: 'static, W: 'static> Actor<O, M, W>
+where
     M: xtra::Handler<monitor::StartMonitoring>,
-    W: xtra::Handler<wallet::TryBroadcastTransaction>,
 {
-    async fn handle_cfd_setup_completed(
+    async fn handle_cfd_roll_over_completed(
         &mut self,
         order_id: OrderId,
+        funding_fee: SignedAmount,
         dlc: Result<Dlc>,
     ) -> Result<()> {
-        self.setup_state = SetupState::None;
-
-        let mut conn = self.db.acquire().await?;
-        let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
-
+        self.roll_over_state = RollOverState::None;
         let dlc = match dlc {
             Ok(dlc) => dlc,
-            Err(e) => {
-                cfd.state = CfdState::SetupFailed {
-                    common: CfdStateCommon::default(),
-                    info: e.to_string(),
-                };
-
-                append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
-
-                return Err(e);
+            Err(e) if self.auto_roll_over_attempts.contains_key(&order_id) => {
+                self.set_auto_roll_over_failure(
+                    order_id,
+                    AutoRollOverFailure::Failed {
+                        reason: format!("{:#}", e),
+                    },
+                )?;
+                return Err(e.context(format!(
+                    "Automatic roll-over with maker failed, retrying in {} minutes",
+                    AUTO_ROLL_OVER_RETRY_INTERVAL.whole_minutes()
+                )));
             }
+            Err(e) => return Err(e.context("Failed to roll over contract with maker")),
         };
+        self.auto_roll_over_attempts.remove(&order_id);
+        self.clear_auto_roll_over_failure(order_id)?;
 
-        tracing::info!("Setup complete, publishing on chain now");
-
-        cfd.state = CfdState::PendingOpen {
-            common: CfdStateCommon::default(),
+        let mut conn = self.db.acquire().await?;
+        let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        let event = CfdEvent::RollOverCompleted {
             dlc: dlc.clone(),
-            attestation: None,
+            funding_fee,
         };
-
-        append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
-
-        let txid = self
-            .wallet
-            .send(wallet::TryBroadcastTransaction {
-                tx: dlc.lock.0.clone(),
-            })
-            .await??;
-
-        tracing::info!("Lock transaction published with txid {}", txid);
+        apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
 
         self.monitor_actor
             .do_send_async(monitor::StartMonitoring {
@@ -443,77 +1457,124 @@ where
             })
             .await?;
 
-        self.oracle_actor
-            .do_send_async(oracle::MonitorAttestation {
-                event_id: cfd.order.oracle_event_id,
-            })
-            .await?;
-
         Ok(())
     }
 }
 
 impl<O: 'static, M: 'static, W: 'static> Actor<O, M, W>
 where
-    Self: xtra::Handler<CfdSetupCompleted>,
-    O: xtra::Handler<oracle::GetAnnouncement> + xtra::Handler<oracle::MonitorAttestation>,
-    W: xtra::Handler<wallet::Sign> + xtra::Handler<wallet::BuildPartyParams>,
+    Self: xtra::Handler<CfdPartialSettlementCompleted>,
+    O: xtra::Handler<oracle::GetAnnouncement>,
 {
-    async fn handle_order_accepted(
+    async fn handle_partial_settlement_accepted(
         &mut self,
         order_id: OrderId,
         ctx: &mut Context<Self>,
     ) -> Result<()> {
-        tracing::info!(%order_id, "Order got accepted");
+        tracing::info!(%order_id, "Partial settlement proposal got accepted");
 
         let (sender, receiver) = mpsc::unbounded();
 
-        if let SetupState::Active { .. } = self.setup_state {
-            anyhow::bail!("Already setting up a contract!")
+        if let PartialSettlementState::Active { .. } = self.partial_settlement_state {
+            anyhow::bail!("Already settling a contract partially!")
         }
 
         let mut conn = self.db.acquire().await?;
-        let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
-        cfd.state = CfdState::contract_setup();
 
-        append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
+        let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        let dlc = cfd.open_dlc().context("CFD was in wrong state")?;
+        let proposal = self.get_partial_settlement_proposal(order_id)?.clone();
 
-        let offer_announcement = self
+        // The remaining position keeps the oracle event of the current contract
+        let oracle_event_id = *dlc.cets.keys().next().context("Contract has no CETs")?;
+        let announcement = self
             .oracle_actor
-            .send(oracle::GetAnnouncement(cfd.order.oracle_event_id))
+            .send(oracle::GetAnnouncement(oracle_event_id))
             .await?
-            .with_context(|| format!("Announcement {} not found", cfd.order.oracle_event_id))?;
-
-        self.oracle_actor
-            .do_send_async(oracle::MonitorAttestation {
-                event_id: offer_announcement.id,
-            })
-            .await?;
+            .with_context(|| format!("Announcement {} not found", oracle_event_id))?;
 
-        let contract_future = setup_contract::new(
+        let contract_future = setup_contract::partial_settlement(
             self.send_to_maker
                 .sink()
-                .clone_message_sink()
-                .with(|msg| future::ok(wire::TakerToMaker::Protocol(msg))),
+                .with(|msg| future::ok(wire::TakerToMaker::PartialSettlementProtocol(msg))),
             receiver,
-            (self.oracle_pk, offer_announcement),
+            (self.oracle_pk, announcement),
             cfd,
-            self.wallet.clone(),
+            proposal.clone(),
             Role::Taker,
+            dlc,
         );
 
         let this = ctx
             .address()
             .expect("actor to be able to give address to itself");
 
+        self.partial_settlement_state = PartialSettlementState::Active { sender };
+
         tokio::spawn(async move {
             let dlc = contract_future.await;
 
-            this.do_send_async(CfdSetupCompleted { order_id, dlc })
-                .await
+            this.do_send_async(CfdPartialSettlementCompleted {
+                order_id,
+                proposal,
+                dlc,
+            })
+            .await
         });
 
-        self.setup_state = SetupState::Active { sender };
+        self.remove_pending_proposal(&order_id)
+            .await
+            .context("Could not remove accepted partial settlement")?;
+        Ok(())
+    }
+}
+
+impl<O: 'static, M: 'static, W: 'static> Actor<O, M, W>
+where
+    M: xtra::Handler<monitor::StartMonitoring>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
+{
+    async fn handle_cfd_partial_settlement_completed(
+        &mut self,
+        order_id: OrderId,
+        proposal: PartialSettlementProposal,
+        dlc: Result<Dlc>,
+    ) -> Result<()> {
+        self.partial_settlement_state = PartialSettlementState::None;
+        let dlc = dlc.context("Failed to settle contract partially with maker")?;
+
+        let mut conn = self.db.acquire().await?;
+        let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
+        let event = CfdEvent::PartialSettlementCompleted {
+            dlc: dlc.clone(),
+            quantity: cfd.quantity_usd - proposal.quantity,
+            price: proposal.price,
+        };
+        apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
+
+        // The maker publishes the transaction as well, whoever is first wins
+        let txid = self
+            .wallet
+            .send(wallet::TryBroadcastTransaction {
+                tx: dlc.lock.0.clone(),
+            })
+            .await??;
+
+        tracing::info!(
+            "Partial settlement transaction published with txid {}",
+            txid
+        );
+
+        self.monitor_actor
+            .do_send_async(monitor::StartMonitoring {
+                id: order_id,
+                params: MonitorParams::new(
+                    dlc,
+                    cfd.refund_timelock_in_blocks(),
+                    cfd.order.oracle_event_id,
+                ),
+            })
+            .await?;
 
         Ok(())
     }
@@ -521,44 +1582,46 @@ where
 
 impl<O: 'static, M: 'static, W: 'static> Actor<O, M, W>
 where
-    Self: xtra::Handler<CfdRollOverCompleted>,
+    Self: xtra::Handler<CfdAddToPositionCompleted>,
     O: xtra::Handler<oracle::GetAnnouncement>,
-    W: xtra::Handler<wallet::TryBroadcastTransaction>
-        + xtra::Handler<wallet::Sign>
-        + xtra::Handler<wallet::BuildPartyParams>,
+    W: xtra::Handler<wallet::Sign> + xtra::Handler<wallet::BuildPartyParams>,
 {
-    async fn handle_roll_over_accepted(
+    async fn handle_add_to_position_accepted(
         &mut self,
         order_id: OrderId,
-        oracle_event_id: BitMexPriceEventId,
         ctx: &mut Context<Self>,
     ) -> Result<()> {
-        tracing::info!(%order_id, "Roll; over request got accepted");
+        tracing::info!(%order_id, "Add to position proposal got accepted");
 
         let (sender, receiver) = mpsc::unbounded();
 
-        if let RollOverState::Active { .. } = self.roll_over_state {
-            anyhow::bail!("Already rolling over a contract!")
+        if let AddToPositionState::Active { .. } = self.add_to_position_state {
+            anyhow::bail!("Already adding to a position!")
         }
 
         let mut conn = self.db.acquire().await?;
 
         let cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
         let dlc = cfd.open_dlc().context("CFD was in wrong state")?;
+        let proposal = self.get_add_to_position_proposal(order_id)?.clone();
 
+        // The increased position keeps the oracle event of the current contract
+        let oracle_event_id = *dlc.cets.keys().next().context("Contract has no CETs")?;
         let announcement = self
             .oracle_actor
             .send(oracle::GetAnnouncement(oracle_event_id))
             .await?
             .with_context(|| format!("Announcement {} not found", oracle_event_id))?;
 
-        let contract_future = setup_contract::roll_over(
+        let contract_future = setup_contract::add_to_position(
             self.send_to_maker
                 .sink()
-                .with(|msg| future::ok(wire::TakerToMaker::RollOverProtocol(msg))),
+                .with(|msg| future::ok(wire::TakerToMaker::AddToPositionProtocol(msg))),
             receiver,
             (self.oracle_pk, announcement),
             cfd,
+            proposal.clone(),
+            self.wallet.clone(),
             Role::Taker,
             dlc,
         );
@@ -567,17 +1630,22 @@ where
             .address()
             .expect("actor to be able to give address to itself");
 
-        self.roll_over_state = RollOverState::Active { sender };
+        self.add_to_position_state = AddToPositionState::Active { sender };
 
         tokio::spawn(async move {
             let dlc = contract_future.await;
 
-            this.do_send_async(CfdRollOverCompleted { order_id, dlc })
-                .await
+            this.do_send_async(CfdAddToPositionCompleted {
+                order_id,
+                proposal,
+                dlc,
+            })
+            .await
         });
 
         self.remove_pending_proposal(&order_id)
-            .context("Could not remove accepted roll over")?;
+            .await
+            .context("Could not remove accepted add to position")?;
         Ok(())
     }
 }
@@ -585,25 +1653,39 @@ where
 impl<O: 'static, M: 'static, W: 'static> Actor<O, M, W>
 where
     M: xtra::Handler<monitor::StartMonitoring>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
 {
-    async fn handle_cfd_roll_over_completed(
+    async fn handle_cfd_add_to_position_completed(
         &mut self,
         order_id: OrderId,
+        proposal: AddToPositionProposal,
         dlc: Result<Dlc>,
     ) -> Result<()> {
-        let dlc = dlc.context("Failed to roll over contract with maker")?;
-        self.roll_over_state = RollOverState::None;
+        self.add_to_position_state = AddToPositionState::None;
+        let dlc = dlc.context("Failed to add to the position with maker")?;
 
         let mut conn = self.db.acquire().await?;
         let mut cfd = load_cfd_by_order_id(order_id, &mut conn).await?;
-        cfd.state = CfdState::Open {
-            common: CfdStateCommon::default(),
+        let increased = cfd.with_added_position(proposal.quantity, proposal.price)?;
+        let event = CfdEvent::AddToPositionCompleted {
             dlc: dlc.clone(),
-            attestation: None,
-            collaborative_close: None,
+            quantity: increased.quantity_usd,
+            price: increased.order.price,
         };
+        apply_event(&mut cfd, event, &mut conn, &self.cfd_feed_actor_inbox).await?;
+
+        // The maker publishes the transaction as well, whoever is first wins
+        let txid = self
+            .wallet
+            .send(wallet::TryBroadcastTransaction {
+                tx: dlc.lock.0.clone(),
+            })
+            .await??;
 
-        append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
+        tracing::info!(
+            "Lock transaction of increased position published with txid {}",
+            txid
+        );
 
         self.monitor_actor
             .do_send_async(monitor::StartMonitoring {
@@ -648,16 +1730,18 @@ where
                 sig_taker,
             })?;
 
-        cfd.handle(CfdStateChangeEvent::ProposalSigned(
+        let event = CfdEvent::StateChange(CfdStateChangeEvent::ProposalSigned(
             CollaborativeSettlement::new(
                 tx.clone(),
                 dlc.script_pubkey_for(cfd.role()),
                 proposal.price,
             )?,
-        ))?;
-        append_cfd_state(&cfd, &mut conn, &self.cfd_feed_actor_inbox).await?;
-
-        self.remove_pending_proposal(&order_id)?;
+        ));
+        let mut tx = conn.begin().await?;
+        apply_event(&mut cfd, event, &mut tx, &self.cfd_feed_actor_inbox).await?;
+        self.remove_pending_proposal_with(&order_id, &mut tx)
+            .await?;
+        tx.commit().await?;
 
         self.monitor_actor
             .do_send_async(monitor::CollaborativeSettlement {
@@ -697,6 +1781,34 @@ where
                     .await
             }
             ProposeRollOver { order_id } => self.handle_propose_roll_over(order_id).await,
+            ProposePartialSettlement {
+                order_id,
+                quantity,
+                current_price,
+            } => {
+                self.handle_propose_partial_settlement(order_id, quantity, current_price)
+                    .await
+            }
+            ProposeAddToPosition {
+                order_id,
+                quantity,
+                current_price,
+            } => {
+                self.handle_propose_add_to_position(order_id, quantity, current_price)
+                    .await
+            }
+            AcceptCounterProposal { order_id } => {
+                self.handle_accept_counter_proposal(order_id).await
+            }
+            RejectCounterProposal { order_id } => {
+                self.handle_reject_counter_proposal(order_id).await
+            }
+            AcceptSettlement { order_id } => self.handle_accept_settlement(order_id).await,
+            RejectSettlement { order_id } => self.handle_reject_settlement(order_id).await,
+            SetSettlementTriggers { order_id, triggers } => {
+                self.handle_set_settlement_triggers(order_id, triggers)
+                    .await
+            }
         } {
             tracing::error!("Message handler failed: {:#}", e);
             anyhow::bail!(e)
@@ -708,7 +1820,10 @@ where
 #[async_trait]
 impl<O: 'static, M: 'static, W: 'static> Handler<MakerStreamMessage> for Actor<O, M, W>
 where
-    Self: xtra::Handler<CfdSetupCompleted> + xtra::Handler<CfdRollOverCompleted>,
+    Self: xtra::Handler<CfdSetupCompleted>
+        + xtra::Handler<CfdRollOverCompleted>
+        + xtra::Handler<CfdPartialSettlementCompleted>
+        + xtra::Handler<CfdAddToPositionCompleted>,
     O: xtra::Handler<oracle::GetAnnouncement> + xtra::Handler<oracle::MonitorAttestation>,
     M: xtra::Handler<monitor::CollaborativeSettlement>,
     W: xtra::Handler<wallet::TryBroadcastTransaction>
@@ -742,7 +1857,51 @@ where
                 log_error!(self.handle_settlement_accepted(order_id, ctx))
             }
             wire::MakerToTaker::RejectSettlement(order_id) => {
-                log_error!(self.handle_settlement_rejected(order_id))
+                log_error!(self.handle_settlement_rejected(order_id, None))
+            }
+            wire::MakerToTaker::RejectSettlementWithReason { order_id, reason } => {
+                log_error!(self.handle_settlement_rejected(order_id, Some(reason)))
+            }
+            wire::MakerToTaker::CounterSettlement {
+                order_id,
+                timestamp,
+                taker,
+                maker,
+                price,
+            } => {
+                log_error!(self.handle_settlement_countered(SettlementProposal {
+                    order_id,
+                    timestamp,
+                    taker,
+                    maker,
+                    price
+                }))
+            }
+            wire::MakerToTaker::ProposeSettlement {
+                order_id,
+                timestamp,
+                taker,
+                maker,
+                price,
+            } => {
+                log_error!(self.handle_settlement_proposed(SettlementProposal {
+                    order_id,
+                    timestamp,
+                    taker,
+                    maker,
+                    price
+                }))
+            }
+            wire::MakerToTaker::CounterRollOver {
+                order_id,
+                timestamp,
+                funding_rate,
+            } => {
+                log_error!(self.handle_roll_over_countered(RollOverProposal {
+                    order_id,
+                    timestamp,
+                    funding_rate: Some(funding_rate)
+                }))
             }
             wire::MakerToTaker::InvalidOrderId(order_id) => {
                 log_error!(self.handle_invalid_order_id(order_id))
@@ -753,8 +1912,14 @@ where
             wire::MakerToTaker::ConfirmRollOver {
                 order_id,
                 oracle_event_id,
+                funding_rate,
             } => {
-                log_error!(self.handle_roll_over_accepted(order_id, oracle_event_id, ctx))
+                log_error!(self.handle_roll_over_accepted(
+                    order_id,
+                    oracle_event_id,
+                    funding_rate,
+                    ctx
+                ))
             }
             wire::MakerToTaker::RejectRollOver(order_id) => {
                 log_error!(self.handle_roll_over_rejected(order_id))
@@ -762,6 +1927,24 @@ where
             MakerToTaker::RollOverProtocol(roll_over_msg) => {
                 log_error!(self.handle_inc_roll_over_msg(roll_over_msg))
             }
+            wire::MakerToTaker::ConfirmPartialSettlement(order_id) => {
+                log_error!(self.handle_partial_settlement_accepted(order_id, ctx))
+            }
+            wire::MakerToTaker::RejectPartialSettlement(order_id) => {
+                log_error!(self.handle_partial_settlement_rejected(order_id))
+            }
+            MakerToTaker::PartialSettlementProtocol(msg) => {
+                log_error!(self.handle_inc_partial_settlement_msg(msg))
+            }
+            wire::MakerToTaker::ConfirmAddToPosition(order_id) => {
+                log_error!(self.handle_add_to_position_accepted(order_id, ctx))
+            }
+            wire::MakerToTaker::RejectAddToPosition(order_id) => {
+                log_error!(self.handle_add_to_position_rejected(order_id))
+            }
+            MakerToTaker::AddToPositionProtocol(msg) => {
+                log_error!(self.handle_inc_add_to_position_msg(msg))
+            }
         }
 
         KeepRunning::Yes
@@ -780,13 +1963,101 @@ where
     }
 }
 
+#[async_trait]
+impl<O: 'static, M: 'static, W: 'static> Handler<ExpireProposals> for Actor<O, M, W> {
+    async fn handle(&mut self, _msg: ExpireProposals, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_expire_proposals());
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, W: 'static> Handler<ArchiveFinishedCfds> for Actor<O, M, W> {
+    async fn handle(&mut self, msg: ArchiveFinishedCfds, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_archive_finished_cfds(msg.archive_after));
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, W: 'static> Handler<ReannounceProposals> for Actor<O, M, W> {
+    async fn handle(&mut self, _msg: ReannounceProposals, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_reannounce_proposals());
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, W: 'static> Handler<AnnounceCfds> for Actor<O, M, W> {
+    async fn handle(&mut self, _msg: AnnounceCfds, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_announce_cfds());
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, W: 'static> Handler<AutoRollOver> for Actor<O, M, W>
+where
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
+{
+    async fn handle(&mut self, _: AutoRollOver, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_auto_roll_over());
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, W: 'static> Handler<CheckSettlementTriggers> for Actor<O, M, W>
+where
+    W: xtra::Handler<wallet::TryBroadcastTransaction>
+        + xtra::Handler<wallet::Sign>
+        + xtra::Handler<wallet::BuildPartyParams>,
+{
+    async fn handle(&mut self, _: CheckSettlementTriggers, ctx: &mut Context<Self>) {
+        log_error!(self.handle_check_settlement_triggers(ctx));
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, W: 'static> Handler<SettlementTriggerTimedOut> for Actor<O, M, W>
+where
+    W: xtra::Handler<wallet::TryBroadcastTransaction>
+        + xtra::Handler<wallet::Sign>
+        + xtra::Handler<wallet::BuildPartyParams>,
+{
+    async fn handle(&mut self, msg: SettlementTriggerTimedOut, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_settlement_trigger_timed_out(msg.order_id, msg.fired_at));
+    }
+}
+
 #[async_trait]
 impl<O: 'static, M: 'static, W: 'static> Handler<CfdRollOverCompleted> for Actor<O, M, W>
 where
     M: xtra::Handler<monitor::StartMonitoring>,
 {
     async fn handle(&mut self, msg: CfdRollOverCompleted, _ctx: &mut Context<Self>) {
-        log_error!(self.handle_cfd_roll_over_completed(msg.order_id, msg.dlc));
+        log_error!(self.handle_cfd_roll_over_completed(msg.order_id, msg.funding_fee, msg.dlc));
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, W: 'static> Handler<CfdPartialSettlementCompleted> for Actor<O, M, W>
+where
+    M: xtra::Handler<monitor::StartMonitoring>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
+{
+    async fn handle(&mut self, msg: CfdPartialSettlementCompleted, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_cfd_partial_settlement_completed(
+            msg.order_id,
+            msg.proposal,
+            msg.dlc
+        ));
+    }
+}
+
+#[async_trait]
+impl<O: 'static, M: 'static, W: 'static> Handler<CfdAddToPositionCompleted> for Actor<O, M, W>
+where
+    M: xtra::Handler<monitor::StartMonitoring>,
+    W: xtra::Handler<wallet::TryBroadcastTransaction>,
+{
+    async fn handle(&mut self, msg: CfdAddToPositionCompleted, _ctx: &mut Context<Self>) {
+        log_error!(self.handle_cfd_add_to_position_completed(msg.order_id, msg.proposal, msg.dlc));
     }
 }
 
@@ -831,4 +2102,40 @@ impl Message for CfdRollOverCompleted {
     type Result = ();
 }
 
+impl Message for ExpireProposals {
+    type Result = ();
+}
+
+impl Message for ArchiveFinishedCfds {
+    type Result = ();
+}
+
+impl Message for CheckSettlementTriggers {
+    type Result = ();
+}
+
+impl Message for SettlementTriggerTimedOut {
+    type Result = ();
+}
+
+impl Message for AnnounceCfds {
+    type Result = ();
+}
+
+impl Message for ReannounceProposals {
+    type Result = ();
+}
+
+impl Message for AutoRollOver {
+    type Result = ();
+}
+
+impl Message for CfdPartialSettlementCompleted {
+    type Result = ();
+}
+
+impl Message for CfdAddToPositionCompleted {
+    type Result = ();
+}
+
 impl<O: 'static, M: 'static, W: 'static> xtra::Actor for Actor<O, M, W> {}
@@ -1,7 +1,8 @@
 use crate::model::cfd::{
-    Dlc, OrderId, Payout, Role, SettlementKind, UpdateCfdProposal, UpdateCfdProposals,
+    AutoRollOverFailures, Dlc, OrderId, Payout, Role, SettlementKind, UpdateCfdProposal,
+    UpdateCfdProposals,
 };
-use crate::model::{Leverage, Position, Timestamp, TradingPair};
+use crate::model::{ContractType, FundingRate, Leverage, Position, Timestamp, TradingPair};
 use crate::{bitmex_price_feed, model};
 use bdk::bitcoin::{Amount, Network, SignedAmount, Txid};
 use rocket::request::FromParam;
@@ -77,6 +78,7 @@ pub struct Cfd {
 
     pub leverage: Leverage,
     pub trading_pair: TradingPair,
+    pub contract_type: ContractType,
     pub position: Position,
     pub liquidation_price: Price,
 
@@ -91,14 +93,49 @@ pub struct Cfd {
     pub profit_btc: SignedAmount,
     pub profit_in_percent: String,
 
+    /// The funding received over all roll-overs, negative if funding was paid.
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub funding_btc: SignedAmount,
+
     pub state: CfdState,
     pub actions: Vec<CfdAction>,
     pub state_transition_timestamp: i64,
 
     pub details: CfdDetails,
 
+    /// The terms of the settlement that is being proposed, if any.
+    pub settlement_proposal: Option<SettlementTerms>,
+
     #[serde(with = "::time::serde::timestamp")]
     pub expiry_timestamp: OffsetDateTime,
+
+    /// Why the last automatic roll-over did not happen, if it failed.
+    pub auto_roll_over_failure: Option<String>,
+}
+
+/// The price and payouts of a settlement proposal, from our point of view.
+#[derive(Debug, Clone, Serialize)]
+pub struct SettlementTerms {
+    pub price: Price,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub payout: Amount,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub payout_counterparty: Amount,
+}
+
+impl SettlementTerms {
+    fn new(proposal: &model::cfd::SettlementProposal, role: Role) -> Self {
+        let (payout, payout_counterparty) = match role {
+            Role::Maker => (proposal.maker, proposal.taker),
+            Role::Taker => (proposal.taker, proposal.maker),
+        };
+
+        Self {
+            price: proposal.price.into(),
+            payout,
+            payout_counterparty,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -179,6 +216,12 @@ pub enum CfdAction {
     RollOver,
     AcceptRollOver,
     RejectRollOver,
+    AcceptPartialSettlement,
+    RejectPartialSettlement,
+    AcceptAddToPosition,
+    RejectAddToPosition,
+    AcceptCounterProposal,
+    RejectCounterProposal,
 }
 
 impl<'v> FromParam<'v> for CfdAction {
@@ -207,6 +250,10 @@ pub enum CfdState {
     OutgoingSettlementProposal,
     IncomingRollOverProposal,
     OutgoingRollOverProposal,
+    IncomingPartialSettlementProposal,
+    OutgoingPartialSettlementProposal,
+    IncomingAddToPositionProposal,
+    OutgoingAddToPositionProposal,
     Closed,
     PendingRefund,
     Refunded,
@@ -218,6 +265,7 @@ pub struct CfdOrder {
     pub id: OrderId,
 
     pub trading_pair: TradingPair,
+    pub contract_type: ContractType,
     pub position: Position,
 
     pub price: Price,
@@ -228,6 +276,9 @@ pub struct CfdOrder {
     pub leverage: Leverage,
     pub liquidation_price: Price,
 
+    /// The funding rate charged on every roll-over
+    pub funding_rate: FundingRate,
+
     pub creation_timestamp: Timestamp,
     pub settlement_time_interval_in_secs: u64,
 }
@@ -243,6 +294,7 @@ pub struct CfdsWithAuxData {
     pub cfds: Vec<model::cfd::Cfd>,
     pub current_price: model::Price,
     pub pending_proposals: UpdateCfdProposals,
+    pub auto_roll_over_failures: AutoRollOverFailures,
     pub network: Network,
 }
 
@@ -266,19 +318,47 @@ impl CfdsWithAuxData {
             cfds: rx_cfds.borrow().clone(),
             current_price,
             pending_proposals,
+            auto_roll_over_failures: AutoRollOverFailures::new(),
+            network,
+        }
+    }
+
+    pub fn with_auto_roll_over_failures(
+        mut self,
+        rx_auto_roll_over_failures: &watch::Receiver<AutoRollOverFailures>,
+    ) -> Self {
+        self.auto_roll_over_failures = rx_auto_roll_over_failures.borrow().clone();
+        self
+    }
+
+    /// Archived CFDs are final, there are no proposals for them
+    pub fn archived(
+        cfds: Vec<model::cfd::Cfd>,
+        rx_quote: &watch::Receiver<bitmex_price_feed::Quote>,
+        role: Role,
+        network: Network,
+    ) -> Self {
+        let quote = rx_quote.borrow().clone();
+        let current_price = match role {
+            Role::Maker => quote.for_maker(),
+            Role::Taker => quote.for_taker(),
+        };
+
+        CfdsWithAuxData {
+            cfds,
+            current_price,
+            pending_proposals: UpdateCfdProposals::new(),
+            auto_roll_over_failures: AutoRollOverFailures::new(),
             network,
         }
     }
-}
 
-impl ToSseEvent for CfdsWithAuxData {
     // TODO: This conversion can fail, we might want to change the API
-    fn to_sse_event(&self) -> Event {
+    pub fn to_cfds(&self) -> Vec<Cfd> {
         let current_price = self.current_price;
         let network = self.network;
 
-        let cfds = self
-            .cfds
+        self.cfds
             .iter()
             .map(|cfd| {
                 let (profit_btc, profit_in_percent) =
@@ -298,16 +378,25 @@ impl ToSseEvent for CfdsWithAuxData {
                     payout: cfd.payout(),
                 };
 
+                let settlement_proposal = match pending_proposal {
+                    Some(UpdateCfdProposal::Settlement { proposal, .. }) => {
+                        Some(SettlementTerms::new(proposal, cfd.role()))
+                    }
+                    _ => None,
+                };
+
                 Cfd {
                     order_id: cfd.order.id,
                     initial_price: cfd.order.price.into(),
                     leverage: cfd.order.leverage,
                     trading_pair: cfd.order.trading_pair.clone(),
+                    contract_type: cfd.order.contract_type,
                     position: cfd.position(),
                     liquidation_price: cfd.order.liquidation_price.into(),
                     quantity_usd: cfd.quantity_usd.into(),
                     profit_btc,
                     profit_in_percent: profit_in_percent.round_dp(1).to_string(),
+                    funding_btc: cfd.funding_received(),
                     state: state.clone(),
                     actions: available_actions(state, cfd.role()),
                     state_transition_timestamp: cfd.state.get_transition_timestamp().seconds(),
@@ -317,12 +406,21 @@ impl ToSseEvent for CfdsWithAuxData {
                     margin: cfd.margin().expect("margin to be available"),
                     margin_counterparty: cfd.counterparty_margin().expect("margin to be available"),
                     details,
+                    settlement_proposal,
                     expiry_timestamp: cfd.expiry_timestamp(),
+                    auto_roll_over_failure: self
+                        .auto_roll_over_failures
+                        .get(&cfd.order.id)
+                        .map(|failure| failure.to_string()),
                 }
             })
-            .collect::<Vec<Cfd>>();
+            .collect::<Vec<Cfd>>()
+    }
+}
 
-        Event::json(&cfds).event("cfds")
+impl ToSseEvent for CfdsWithAuxData {
+    fn to_sse_event(&self) -> Event {
+        Event::json(&self.to_cfds()).event("cfds")
     }
 }
 
@@ -331,12 +429,14 @@ impl ToSseEvent for Option<model::cfd::Order> {
         let order = self.clone().map(|order| CfdOrder {
             id: order.id,
             trading_pair: order.trading_pair,
+            contract_type: order.contract_type,
             position: order.position,
             price: order.price.into(),
             min_quantity: order.min_quantity.into(),
             max_quantity: order.max_quantity.into(),
             leverage: order.leverage,
             liquidation_price: order.liquidation_price.into(),
+            funding_rate: order.funding_rate,
             creation_timestamp: order.creation_timestamp,
             settlement_time_interval_in_secs: order
                 .settlement_time_interval_hours
@@ -390,6 +490,22 @@ fn to_cfd_state(
             direction: SettlementKind::Incoming,
             ..
         }) => CfdState::IncomingRollOverProposal,
+        Some(UpdateCfdProposal::PartialSettlement {
+            direction: SettlementKind::Outgoing,
+            ..
+        }) => CfdState::OutgoingPartialSettlementProposal,
+        Some(UpdateCfdProposal::PartialSettlement {
+            direction: SettlementKind::Incoming,
+            ..
+        }) => CfdState::IncomingPartialSettlementProposal,
+        Some(UpdateCfdProposal::AddToPosition {
+            direction: SettlementKind::Outgoing,
+            ..
+        }) => CfdState::OutgoingAddToPositionProposal,
+        Some(UpdateCfdProposal::AddToPosition {
+            direction: SettlementKind::Incoming,
+            ..
+        }) => CfdState::IncomingAddToPositionProposal,
         None => match cfd_state {
             // Filled in collaborative close in Open means that we're awaiting
             // a collaborative closure
@@ -494,6 +610,29 @@ fn available_actions(state: CfdState, role: Role) -> Vec<CfdAction> {
         (CfdState::IncomingRollOverProposal { .. }, Role::Maker) => {
             vec![CfdAction::AcceptRollOver, CfdAction::RejectRollOver]
         }
+        (CfdState::IncomingPartialSettlementProposal { .. }, Role::Maker) => {
+            vec![
+                CfdAction::AcceptPartialSettlement,
+                CfdAction::RejectPartialSettlement,
+            ]
+        }
+        (CfdState::IncomingAddToPositionProposal { .. }, Role::Maker) => {
+            vec![
+                CfdAction::AcceptAddToPosition,
+                CfdAction::RejectAddToPosition,
+            ]
+        }
+        // The maker proposed to settle, or countered a settlement proposal of the taker
+        (CfdState::IncomingSettlementProposal { .. }, Role::Taker) => {
+            vec![CfdAction::AcceptSettlement, CfdAction::RejectSettlement]
+        }
+        // The maker replied to a proposal of the taker with a counter-proposal
+        (CfdState::IncomingRollOverProposal { .. }, Role::Taker) => {
+            vec![
+                CfdAction::AcceptCounterProposal,
+                CfdAction::RejectCounterProposal,
+            ]
+        }
         // If there is an outgoing settlement proposal already, user can't
         // initiate new one
         (CfdState::OutgoingSettlementProposal { .. }, Role::Maker) => {
@@ -506,7 +645,7 @@ fn available_actions(state: CfdState, role: Role) -> Vec<CfdAction> {
         (CfdState::Open { .. }, Role::Taker) => {
             vec![CfdAction::RollOver, CfdAction::Commit, CfdAction::Settle]
         }
-        (CfdState::Open { .. }, Role::Maker) => vec![CfdAction::Commit],
+        (CfdState::Open { .. }, Role::Maker) => vec![CfdAction::Commit, CfdAction::Settle],
         _ => vec![],
     }
 }
@@ -0,0 +1,229 @@
+use crate::db::load_all_cfd_states;
+use crate::model::cfd::{CfdState, OrderId, Payout};
+use crate::model::Timestamp;
+use crate::wallet;
+use anyhow::Result;
+use bdk::bitcoin::{Amount, Txid};
+use bdk::TransactionDetails;
+use serde::Serialize;
+use sqlx::pool::PoolConnection;
+use sqlx::Any;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use xtra::prelude::MessageChannel;
+
+/// What a wallet transaction was used for.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum TransactionKind {
+    Lock,
+    Commit,
+    Cet,
+    Refund,
+    CollaborativeClose,
+    /// Funds sent out of the wallet that are not related to any CFD.
+    Withdraw,
+    /// Funds received by the wallet that are not related to any CFD.
+    Deposit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum ConfirmationStatus {
+    Unconfirmed,
+    Confirmed { height: u32, timestamp: Timestamp },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransactionHistoryEntry {
+    pub txid: Txid,
+    pub kind: TransactionKind,
+    /// The CFD this transaction belongs to, `None` for withdrawals and deposits.
+    pub order_id: Option<OrderId>,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub received: Amount,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub sent: Amount,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc::opt")]
+    pub fee: Option<Amount>,
+    pub confirmation: ConfirmationStatus,
+}
+
+/// Fetches the transactions of the wallet and attributes them to the CFDs in the database.
+pub async fn load(
+    wallet: &(dyn MessageChannel<wallet::TransactionHistory>),
+    conn: &mut PoolConnection<Any>,
+) -> Result<Vec<TransactionHistoryEntry>> {
+    let transactions = wallet.send(wallet::TransactionHistory).await??;
+    let cfd_states = load_all_cfd_states(conn).await?;
+
+    Ok(attribute(transactions, cfd_states))
+}
+
+/// Attributes wallet transactions to the CFDs they belong to.
+///
+/// Historic states have to be passed in as well, otherwise transactions of DLCs that were
+/// replaced by a roll-over cannot be attributed. Transactions unknown to all CFDs are
+/// classified as withdrawal or deposit depending on the direction of the funds.
+///
+/// The result is ordered with unconfirmed transactions first, followed by the most recently
+/// confirmed ones.
+pub fn attribute(
+    transactions: Vec<TransactionDetails>,
+    cfd_states: Vec<(OrderId, CfdState)>,
+) -> Vec<TransactionHistoryEntry> {
+    let cfd_txids = cfd_states
+        .iter()
+        .flat_map(|(order_id, state)| {
+            cfd_transactions(state)
+                .into_iter()
+                .map(move |(txid, kind)| (txid, (*order_id, kind)))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut entries = transactions
+        .into_iter()
+        .map(|tx| {
+            let (order_id, kind) = match cfd_txids.get(&tx.txid) {
+                Some((order_id, kind)) => (Some(*order_id), *kind),
+                None if tx.sent > tx.received => (None, TransactionKind::Withdraw),
+                None => (None, TransactionKind::Deposit),
+            };
+
+            let confirmation = match tx.confirmation_time {
+                Some(confirmation_time) => ConfirmationStatus::Confirmed {
+                    height: confirmation_time.height,
+                    timestamp: Timestamp::new(confirmation_time.timestamp as i64),
+                },
+                None => ConfirmationStatus::Unconfirmed,
+            };
+
+            TransactionHistoryEntry {
+                txid: tx.txid,
+                kind,
+                order_id,
+                received: Amount::from_sat(tx.received),
+                sent: Amount::from_sat(tx.sent),
+                fee: tx.fee.map(Amount::from_sat),
+                confirmation,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    entries.sort_by_key(|entry| match entry.confirmation {
+        ConfirmationStatus::Unconfirmed => Reverse(u32::MAX),
+        ConfirmationStatus::Confirmed { height, .. } => Reverse(height),
+    });
+
+    entries
+}
+
+/// All transactions of a CFD that can be derived from the given state.
+fn cfd_transactions(state: &CfdState) -> Vec<(Txid, TransactionKind)> {
+    let mut transactions = Vec::new();
+
+    if let Some(dlc) = state.get_dlc() {
+        transactions.push((dlc.lock.0.txid(), TransactionKind::Lock));
+        transactions.push((dlc.commit.0.txid(), TransactionKind::Commit));
+        transactions.push((dlc.refund.0.txid(), TransactionKind::Refund));
+        transactions.extend(
+            dlc.cets
+                .values()
+                .flatten()
+                .map(|cet| (cet.tx.txid(), TransactionKind::Cet)),
+        );
+        transactions.extend(
+            dlc.revoked_commit
+                .iter()
+                .map(|revoked| (revoked.txid, TransactionKind::Commit)),
+        );
+    }
+
+    if let Some(settlement) = state.get_collaborative_close() {
+        transactions.push((settlement.tx.txid(), TransactionKind::CollaborativeClose));
+    }
+
+    if let CfdState::Closed { payout, .. } = state {
+        match payout {
+            Payout::CollaborativeClose(settlement) => {
+                transactions.push((settlement.tx.txid(), TransactionKind::CollaborativeClose))
+            }
+            Payout::Cet(attestation) => {
+                transactions.push((attestation.txid(), TransactionKind::Cet))
+            }
+        }
+    }
+
+    transactions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::cfd::CollaborativeSettlement;
+    use crate::model::Price;
+    use bdk::bitcoin::{Script, Transaction, TxOut};
+    use bdk::ConfirmationTime;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn attributes_collaborative_close_and_classifies_unknown_transactions() {
+        let close_tx = dummy_tx(1);
+        let settlement = CollaborativeSettlement::new(
+            close_tx.clone(),
+            Script::new(),
+            Price::new(dec!(50_000)).unwrap(),
+        )
+        .unwrap();
+        let order_id = OrderId::default();
+        let cfd_states = vec![(
+            order_id,
+            CfdState::closed(Payout::CollaborativeClose(settlement)),
+        )];
+
+        let close = details(close_tx.txid(), 0, 100_000, Some(10));
+        let withdraw = details(dummy_tx(2).txid(), 50_000, 0, Some(20));
+        let deposit = details(dummy_tx(3).txid(), 0, 50_000, None);
+
+        let entries = attribute(vec![close, withdraw, deposit], cfd_states);
+
+        let kinds = entries
+            .iter()
+            .map(|entry| (entry.kind, entry.order_id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                (TransactionKind::Deposit, None),
+                (TransactionKind::Withdraw, None),
+                (TransactionKind::CollaborativeClose, Some(order_id)),
+            ]
+        );
+    }
+
+    fn dummy_tx(value: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value,
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+
+    fn details(txid: Txid, sent: u64, received: u64, height: Option<u32>) -> TransactionDetails {
+        TransactionDetails {
+            transaction: None,
+            txid,
+            received,
+            sent,
+            fee: Some(200),
+            confirmation_time: height.map(|height| ConfirmationTime {
+                height,
+                timestamp: 1_636_000_000,
+            }),
+            verified: true,
+        }
+    }
+}
@@ -1,25 +1,35 @@
+use crate::external_signer::ExternalSigner;
 use crate::model::{Timestamp, WalletInfo};
 use anyhow::{bail, Context, Result};
 use bdk::bitcoin::consensus::encode::serialize_hex;
-use bdk::bitcoin::util::bip32::ExtendedPrivKey;
+use bdk::bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey, Fingerprint};
 use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
-use bdk::bitcoin::{Address, Amount, PublicKey, Script, Transaction, Txid};
+use bdk::bitcoin::{Address, Amount, Network, PublicKey, Script, Transaction, Txid};
 use bdk::blockchain::{ElectrumBlockchain, NoopProgress};
 use bdk::wallet::AddressIndex;
-use bdk::{electrum_client, FeeRate, KeychainKind, SignOptions};
+use bdk::{electrum_client, FeeRate, KeychainKind, SignOptions, TransactionDetails};
 use maia::{PartyParams, WalletExt};
 use rocket::serde::json::Value;
 
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use xtra_productivity::xtra_productivity;
 
 const DUST_AMOUNT: u64 = 546;
 
+/// How long a previewed withdraw transaction can be broadcast.
+const WITHDRAW_PREVIEW_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
 #[derive(Clone)]
 pub struct Actor {
     wallet: Arc<Mutex<bdk::Wallet<ElectrumBlockchain, bdk::database::SqliteDatabase>>>,
+    /// Signs on behalf of the wallet if it is watch-only.
+    external_signer: Option<Arc<dyn ExternalSigner>>,
+    /// The withdraw transactions we built for a preview, only these are signed and broadcast.
+    withdraw_previews: Arc<Mutex<HashMap<Txid, (PartiallySignedTransaction, Instant)>>>,
 }
 
 #[derive(thiserror::Error, Debug, Clone, Copy)]
@@ -47,7 +57,52 @@ impl Actor {
 
         let wallet = Arc::new(Mutex::new(wallet));
 
-        Ok(Self { wallet })
+        Ok(Self {
+            wallet,
+            external_signer: None,
+            withdraw_previews: Arc::default(),
+        })
+    }
+
+    /// Creates a wallet that only knows the account-level extended public key.
+    ///
+    /// The key is expected to be derived at `m/84'/<coin>'/0'` from the master key with the given
+    /// fingerprint and has to belong to `network`. All signing is delegated to the external
+    /// signer.
+    pub async fn new_watch_only(
+        electrum_rpc_url: &str,
+        wallet_dir: &Path,
+        ext_pub_key: ExtendedPubKey,
+        master_fingerprint: Fingerprint,
+        network: Network,
+        external_signer: Arc<dyn ExternalSigner>,
+    ) -> Result<Self> {
+        validate_key_network(&ext_pub_key, network)?;
+
+        let client = bdk::electrum_client::Client::new(electrum_rpc_url)
+            .context("Failed to initialize Electrum RPC client")?;
+
+        let db = bdk::database::SqliteDatabase::new(wallet_dir.display().to_string());
+
+        let wallet = bdk::Wallet::new(
+            bdk::template::Bip84Public(ext_pub_key, master_fingerprint, KeychainKind::External),
+            Some(bdk::template::Bip84Public(
+                ext_pub_key,
+                master_fingerprint,
+                KeychainKind::Internal,
+            )),
+            network,
+            db,
+            ElectrumBlockchain::from(client),
+        )?;
+
+        let wallet = Arc::new(Mutex::new(wallet));
+
+        Ok(Self {
+            wallet,
+            external_signer: Some(external_signer),
+            withdraw_previews: Arc::default(),
+        })
     }
 
     /// Calculates the maximum "giveable" amount of this wallet.
@@ -88,6 +143,79 @@ impl Actor {
             Err(e) => bail!("Failed to build transaction. {:#}", e),
         }
     }
+
+    async fn build_withdraw_psbt(
+        &self,
+        amount: Option<Amount>,
+        fee: Option<FeeRate>,
+        address: &Address,
+    ) -> Result<(PartiallySignedTransaction, Amount)> {
+        let fee_rate = fee.unwrap_or_else(FeeRate::default_min_relay_fee);
+
+        let amount = if let Some(amount) = amount {
+            amount
+        } else {
+            self.max_giveable(address.script_pubkey().len(), fee_rate)
+                .await
+                .context("Unable to drain wallet")?
+        };
+
+        tracing::info!(%amount, %address, "Amount to be sent to address");
+
+        let wallet = self.wallet.lock().await;
+        let mut tx_builder = wallet.build_tx();
+
+        tx_builder
+            .add_recipient(address.script_pubkey(), amount.as_sat())
+            .fee_rate(fee_rate)
+            // Turn on RBF signaling
+            .enable_rbf();
+
+        let (psbt, details) = tx_builder.finish()?;
+        let fee = details
+            .fee
+            .expect("fees are always present with Electrum backend");
+
+        Ok((psbt, Amount::from_sat(fee)))
+    }
+
+    /// Signs the inputs of the PSBT that belong to this wallet, returns whether the PSBT could be
+    /// finalized.
+    async fn sign_psbt(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        sign_options: SignOptions,
+    ) -> Result<bool> {
+        let finalized = match &self.external_signer {
+            None => {
+                let wallet = self.wallet.lock().await;
+                wallet.sign(psbt, sign_options)?
+            }
+            Some(external_signer) => {
+                *psbt = external_signer
+                    .sign(psbt.clone())
+                    .await
+                    .context("External signer failed to sign transaction")?;
+
+                let wallet = self.wallet.lock().await;
+                wallet.finalize_psbt(psbt, sign_options)?
+            }
+        };
+
+        Ok(finalized)
+    }
+
+    async fn sign_and_broadcast(&self, mut psbt: PartiallySignedTransaction) -> Result<Txid> {
+        let finalized = self.sign_psbt(&mut psbt, SignOptions::default()).await?;
+        if !finalized {
+            bail!("Withdraw transaction could not be fully signed");
+        }
+
+        let wallet = self.wallet.lock().await;
+        let txid = wallet.broadcast(psbt.extract_tx())?;
+
+        Ok(txid)
+    }
 }
 
 #[xtra_productivity]
@@ -111,18 +239,30 @@ impl Actor {
         Ok(wallet_info)
     }
 
-    pub async fn handle_sign(&self, msg: Sign) -> Result<PartiallySignedTransaction> {
+    pub async fn handle_sign(
+        &mut self,
+        msg: Sign,
+        ctx: &mut xtra::Context<Self>,
+    ) -> Result<PartiallySignedTransaction> {
         let mut psbt = msg.psbt;
-        let wallet = self.wallet.lock().await;
 
-        wallet
-            .sign(
+        // An external signer can take a while, keep handling other messages meanwhile
+        let this = self.clone();
+        let signing = async move {
+            this.sign_psbt(
                 &mut psbt,
                 SignOptions {
                     trust_witness_utxo: true,
                     ..Default::default()
                 },
             )
+            .await
+            .map(|_| psbt)
+        };
+
+        let psbt = ctx
+            .handle_while(self, signing)
+            .await
             .context("could not sign transaction")?;
 
         Ok(psbt)
@@ -176,39 +316,136 @@ impl Actor {
         Ok(txid)
     }
 
-    pub async fn handle_withdraw(&self, msg: Withdraw) -> Result<Txid> {
-        let fee_rate = msg.fee.unwrap_or_else(FeeRate::default_min_relay_fee);
-        let address = msg.address;
+    pub async fn handle_transaction_history(
+        &self,
+        _msg: TransactionHistory,
+    ) -> Result<Vec<TransactionDetails>> {
+        let wallet = self.wallet.lock().await;
+        let transactions = wallet
+            .list_transactions(false)
+            .context("Failed to list wallet transactions")?;
 
-        let amount = if let Some(amount) = msg.amount {
-            amount
-        } else {
-            self.max_giveable(address.script_pubkey().len(), fee_rate)
-                .await
-                .context("Unable to drain wallet")?
-        };
+        Ok(transactions)
+    }
 
-        tracing::info!(%amount, %address, "Amount to be sent to address");
+    pub async fn handle_withdraw(
+        &mut self,
+        msg: Withdraw,
+        ctx: &mut xtra::Context<Self>,
+    ) -> Result<Txid> {
+        let (psbt, _) = self
+            .build_withdraw_psbt(msg.amount, msg.fee, &msg.address)
+            .await?;
 
-        let wallet = self.wallet.lock().await;
-        let mut tx_builder = wallet.build_tx();
+        let this = self.clone();
+        let txid = ctx
+            .handle_while(self, async move { this.sign_and_broadcast(psbt).await })
+            .await?;
 
-        tx_builder
-            .add_recipient(address.script_pubkey(), amount.as_sat())
-            .fee_rate(fee_rate)
-            // Turn on RBF signaling
-            .enable_rbf();
+        Ok(txid)
+    }
 
-        let (mut psbt, _) = tx_builder.finish()?;
+    pub async fn handle_preview_withdraw(&self, msg: PreviewWithdraw) -> Result<WithdrawPreview> {
+        let (psbt, fee) = self
+            .build_withdraw_psbt(msg.amount, msg.fee, &msg.address)
+            .await?;
 
-        wallet.sign(&mut psbt, SignOptions::default())?;
+        let amount = withdrawn_amount(&psbt, &msg.address)?;
 
-        let txid = wallet.broadcast(psbt.extract_tx())?;
+        let mut previews = self.withdraw_previews.lock().await;
+        previews.retain(|_, (_, created_at)| created_at.elapsed() < WITHDRAW_PREVIEW_EXPIRY);
+        previews.insert(
+            psbt.global.unsigned_tx.txid(),
+            (psbt.clone(), Instant::now()),
+        );
+
+        Ok(WithdrawPreview { psbt, amount, fee })
+    }
+
+    pub async fn handle_broadcast_withdraw(
+        &mut self,
+        msg: BroadcastWithdraw,
+        ctx: &mut xtra::Context<Self>,
+    ) -> Result<Txid> {
+        let txid = msg.txid;
+
+        let (psbt, created_at) = self
+            .withdraw_previews
+            .lock()
+            .await
+            .remove(&txid)
+            .with_context(|| format!("No withdraw transaction {} was previewed", txid))?;
+        if created_at.elapsed() >= WITHDRAW_PREVIEW_EXPIRY {
+            bail!("Preview of withdraw transaction {} expired", txid);
+        }
+
+        tracing::info!(%txid, "Broadcasting previewed withdrawal");
+
+        let this = self.clone();
+        let txid = ctx
+            .handle_while(self, async move { this.sign_and_broadcast(psbt).await })
+            .await?;
 
         Ok(txid)
     }
 }
 
+/// The amount a withdraw transaction pays to the given address.
+fn withdrawn_amount(psbt: &PartiallySignedTransaction, address: &Address) -> Result<Amount> {
+    let script_pubkey = address.script_pubkey();
+
+    psbt.global
+        .unsigned_tx
+        .output
+        .iter()
+        .find(|output| output.script_pubkey == script_pubkey)
+        .map(|output| Amount::from_sat(output.value))
+        .with_context(|| format!("Withdraw transaction does not pay to {}", address))
+}
+
+/// Ensures that the address can be used on the given network.
+///
+/// Signet shares its address format with testnet, hence testnet addresses are accepted for signet
+/// and vice versa.
+pub fn validate_address_network(address: &Address, network: Network) -> Result<()> {
+    let is_valid = match (address.network, network) {
+        (Network::Testnet, Network::Signet) | (Network::Signet, Network::Testnet) => true,
+        (address_network, network) => address_network == network,
+    };
+
+    if !is_valid {
+        bail!(
+            "Address {} is not valid on {}, it belongs to {}",
+            address,
+            network,
+            address.network
+        );
+    }
+
+    Ok(())
+}
+
+/// Ensures that the extended public key can be used on the given network.
+///
+/// Extended keys only tell mainnet from the test networks apart, hence testnet keys are accepted
+/// for signet and regtest.
+fn validate_key_network(ext_pub_key: &ExtendedPubKey, network: Network) -> Result<()> {
+    let is_valid = match ext_pub_key.network {
+        Network::Bitcoin => network == Network::Bitcoin,
+        _ => network != Network::Bitcoin,
+    };
+
+    if !is_valid {
+        bail!(
+            "Extended public key for {} cannot be used on {}",
+            ext_pub_key.network,
+            network
+        );
+    }
+
+    Ok(())
+}
+
 impl xtra::Actor for Actor {}
 
 pub struct BuildPartyParams {
@@ -226,12 +463,33 @@ pub struct TryBroadcastTransaction {
     pub tx: Transaction,
 }
 
+/// Lists all transactions relevant to the wallet, as known since the last sync.
+pub struct TransactionHistory;
+
 pub struct Withdraw {
     pub amount: Option<Amount>,
     pub fee: Option<FeeRate>,
     pub address: Address,
 }
 
+/// Builds the withdraw transaction without signing or broadcasting it.
+pub struct PreviewWithdraw {
+    pub amount: Option<Amount>,
+    pub fee: Option<FeeRate>,
+    pub address: Address,
+}
+
+pub struct WithdrawPreview {
+    pub psbt: PartiallySignedTransaction,
+    pub amount: Amount,
+    pub fee: Amount,
+}
+
+/// Signs and broadcasts the withdraw transaction previously built by [`PreviewWithdraw`].
+pub struct BroadcastWithdraw {
+    pub txid: Txid,
+}
+
 fn parse_rpc_protocol_error_code(error_value: &Value) -> Result<i64> {
     let json = error_value
         .as_str()
@@ -267,6 +525,7 @@ impl From<RpcErrorCode> for i64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     #[test]
     fn parse_error_response() {
@@ -276,4 +535,25 @@ mod tests {
 
         assert_eq!(code, -27);
     }
+
+    #[test]
+    fn signet_accepts_testnet_addresses() {
+        let address = Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+
+        assert!(validate_address_network(&address, Network::Signet).is_ok());
+        assert!(validate_address_network(&address, Network::Testnet).is_ok());
+        assert!(validate_address_network(&address, Network::Bitcoin).is_err());
+    }
+
+    #[test]
+    fn extended_public_key_has_to_match_network() {
+        let testnet_key = ExtendedPubKey::from_str("tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp").unwrap();
+        let mainnet_key = ExtendedPubKey::from_str("xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8").unwrap();
+
+        assert!(validate_key_network(&testnet_key, Network::Regtest).is_ok());
+        assert!(validate_key_network(&testnet_key, Network::Testnet).is_ok());
+        assert!(validate_key_network(&testnet_key, Network::Bitcoin).is_err());
+        assert!(validate_key_network(&mainnet_key, Network::Bitcoin).is_ok());
+        assert!(validate_key_network(&mainnet_key, Network::Testnet).is_err());
+    }
 }
@@ -1,5 +1,5 @@
-use crate::model::cfd::{Order, OrderId};
-use crate::model::{BitMexPriceEventId, Price, Timestamp, Usd};
+use crate::model::cfd::{Order, OrderId, SettlementRejectionReason};
+use crate::model::{BitMexPriceEventId, FundingRate, Price, Timestamp, Usd};
 use crate::noise::{NOISE_MAX_MSG_LEN, NOISE_TAG_LEN};
 use anyhow::{bail, Result};
 use bdk::bitcoin::secp256k1::Signature;
@@ -43,8 +43,43 @@ pub enum TakerToMaker {
         order_id: OrderId,
         timestamp: Timestamp,
     },
+    ProposePartialSettlement {
+        order_id: OrderId,
+        timestamp: Timestamp,
+        quantity: Usd,
+        #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+        taker: Amount,
+        #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+        maker: Amount,
+        price: Price,
+    },
+    ProposeAddToPosition {
+        order_id: OrderId,
+        timestamp: Timestamp,
+        quantity: Usd,
+        price: Price,
+    },
     Protocol(SetupMsg),
     RollOverProtocol(RollOverMsg),
+    PartialSettlementProtocol(PartialSettlementMsg),
+    AddToPositionProtocol(AddToPositionMsg),
+    /// Accepts the counter-proposal the maker made in reply to our proposal.
+    AcceptCounterProposal {
+        order_id: OrderId,
+    },
+    RejectCounterProposal {
+        order_id: OrderId,
+    },
+    AcceptSettlement {
+        order_id: OrderId,
+    },
+    RejectSettlement {
+        order_id: OrderId,
+    },
+    /// Tells the maker which CFDs belong to this taker after connecting
+    AnnounceCfds {
+        order_ids: Vec<OrderId>,
+    },
 }
 
 impl fmt::Display for TakerToMaker {
@@ -56,6 +91,17 @@ impl fmt::Display for TakerToMaker {
             TakerToMaker::Protocol(_) => write!(f, "Protocol"),
             TakerToMaker::ProposeRollOver { .. } => write!(f, "ProposeRollOver"),
             TakerToMaker::RollOverProtocol(_) => write!(f, "RollOverProtocol"),
+            TakerToMaker::ProposePartialSettlement { .. } => {
+                write!(f, "ProposePartialSettlement")
+            }
+            TakerToMaker::PartialSettlementProtocol(_) => write!(f, "PartialSettlementProtocol"),
+            TakerToMaker::ProposeAddToPosition { .. } => write!(f, "ProposeAddToPosition"),
+            TakerToMaker::AddToPositionProtocol(_) => write!(f, "AddToPositionProtocol"),
+            TakerToMaker::AcceptCounterProposal { .. } => write!(f, "AcceptCounterProposal"),
+            TakerToMaker::RejectCounterProposal { .. } => write!(f, "RejectCounterProposal"),
+            TakerToMaker::AcceptSettlement { .. } => write!(f, "AcceptSettlement"),
+            TakerToMaker::RejectSettlement { .. } => write!(f, "RejectSettlement"),
+            TakerToMaker::AnnounceCfds { .. } => write!(f, "AnnounceCfds"),
         }
     }
 }
@@ -68,15 +114,54 @@ pub enum MakerToTaker {
     ConfirmOrder(OrderId), // TODO: Include payout curve in "accept" message from maker
     RejectOrder(OrderId),
     ConfirmSettlement(OrderId),
+    /// Rejection of makers that do not tell the reason yet.
     RejectSettlement(OrderId),
+    RejectSettlementWithReason {
+        order_id: OrderId,
+        reason: SettlementRejectionReason,
+    },
+    /// Replaces the settlement proposal of the taker with different terms.
+    CounterSettlement {
+        order_id: OrderId,
+        timestamp: Timestamp,
+        #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+        taker: Amount,
+        #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+        maker: Amount,
+        price: Price,
+    },
+    ProposeSettlement {
+        order_id: OrderId,
+        timestamp: Timestamp,
+        #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+        taker: Amount,
+        #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+        maker: Amount,
+        price: Price,
+    },
     InvalidOrderId(OrderId),
     Protocol(SetupMsg),
     RollOverProtocol(RollOverMsg),
     ConfirmRollOver {
         order_id: OrderId,
         oracle_event_id: BitMexPriceEventId,
+        /// The funding rate the maker charges for this roll-over.
+        #[serde(default)]
+        funding_rate: FundingRate,
     },
     RejectRollOver(OrderId),
+    /// Replaces the roll-over proposal of the taker with a different funding rate.
+    CounterRollOver {
+        order_id: OrderId,
+        timestamp: Timestamp,
+        funding_rate: FundingRate,
+    },
+    ConfirmPartialSettlement(OrderId),
+    RejectPartialSettlement(OrderId),
+    PartialSettlementProtocol(PartialSettlementMsg),
+    ConfirmAddToPosition(OrderId),
+    RejectAddToPosition(OrderId),
+    AddToPositionProtocol(AddToPositionMsg),
 }
 
 impl fmt::Display for MakerToTaker {
@@ -87,11 +172,23 @@ impl fmt::Display for MakerToTaker {
             MakerToTaker::RejectOrder(_) => write!(f, "RejectOrder"),
             MakerToTaker::ConfirmSettlement(_) => write!(f, "ConfirmSettlement"),
             MakerToTaker::RejectSettlement(_) => write!(f, "RejectSettlement"),
+            MakerToTaker::RejectSettlementWithReason { .. } => {
+                write!(f, "RejectSettlementWithReason")
+            }
+            MakerToTaker::CounterSettlement { .. } => write!(f, "CounterSettlement"),
+            MakerToTaker::ProposeSettlement { .. } => write!(f, "ProposeSettlement"),
             MakerToTaker::InvalidOrderId(_) => write!(f, "InvalidOrderId"),
             MakerToTaker::Protocol(_) => write!(f, "Protocol"),
             MakerToTaker::ConfirmRollOver { .. } => write!(f, "ConfirmRollOver"),
             MakerToTaker::RejectRollOver(_) => write!(f, "RejectRollOver"),
+            MakerToTaker::CounterRollOver { .. } => write!(f, "CounterRollOver"),
             MakerToTaker::RollOverProtocol(_) => write!(f, "RollOverProtocol"),
+            MakerToTaker::ConfirmPartialSettlement(_) => write!(f, "ConfirmPartialSettlement"),
+            MakerToTaker::RejectPartialSettlement(_) => write!(f, "RejectPartialSettlement"),
+            MakerToTaker::PartialSettlementProtocol(_) => write!(f, "PartialSettlementProtocol"),
+            MakerToTaker::ConfirmAddToPosition(_) => write!(f, "ConfirmAddToPosition"),
+            MakerToTaker::RejectAddToPosition(_) => write!(f, "RejectAddToPosition"),
+            MakerToTaker::AddToPositionProtocol(_) => write!(f, "AddToPositionProtocol"),
         }
     }
 }
@@ -400,3 +497,148 @@ impl From<CfdTransactions> for RollOverMsg1 {
         }
     }
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum PartialSettlementMsg {
+    /// Message with the punish keys of the new commit transaction
+    ///
+    /// Each party sends and receives this message.
+    /// After receiving this message each party can construct the new commit, refund and cet
+    /// transactions on top of the partial settlement transaction.
+    Msg0(RollOverMsg0),
+    /// Message that ensures complete commit, cets and refund transactions
+    ///
+    /// Each party sends and receives this message.
+    /// Once verified we can sign the partial settlement transaction.
+    Msg1(RollOverMsg1),
+    /// Message with the signature on the partial settlement transaction
+    ///
+    /// Each party sends and receives this message.
+    /// Upon receiving this message the partial settlement transaction is fully signed and can be
+    /// published on chain.
+    Msg2(PartialSettlementMsg2),
+    /// Message revoking the previous commit transaction
+    ///
+    /// Each party sends and receives this message.
+    Msg3(RollOverMsg2),
+}
+
+impl PartialSettlementMsg {
+    pub fn try_into_msg0(self) -> Result<RollOverMsg0> {
+        if let Self::Msg0(v) = self {
+            Ok(v)
+        } else {
+            bail!("Not Msg0")
+        }
+    }
+
+    pub fn try_into_msg1(self) -> Result<RollOverMsg1> {
+        if let Self::Msg1(v) = self {
+            Ok(v)
+        } else {
+            bail!("Not Msg1")
+        }
+    }
+
+    pub fn try_into_msg2(self) -> Result<PartialSettlementMsg2> {
+        if let Self::Msg2(v) = self {
+            Ok(v)
+        } else {
+            bail!("Not Msg2")
+        }
+    }
+
+    pub fn try_into_msg3(self) -> Result<RollOverMsg2> {
+        if let Self::Msg3(v) = self {
+            Ok(v)
+        } else {
+            bail!("Not Msg3")
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PartialSettlementMsg2 {
+    pub signature: Signature,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+#[allow(clippy::large_enum_variant)]
+pub enum AddToPositionMsg {
+    /// Message with the additional funding and the punish keys of the new commit transaction
+    ///
+    /// Each party sends and receives this message.
+    /// After receiving this message each party can construct the new lock transaction, which
+    /// spends the current lock output together with the additional funding, and the new commit,
+    /// refund and cet transactions on top of it.
+    Msg0(AddToPositionMsg0),
+    /// Message that ensures complete commit, cets and refund transactions
+    ///
+    /// Each party sends and receives this message.
+    /// Once verified we can sign the new lock transaction.
+    Msg1(RollOverMsg1),
+    /// Message with the signatures on the new lock transaction
+    ///
+    /// Each party sends and receives this message.
+    /// Upon receiving this message the new lock transaction is fully signed and can be published
+    /// on chain.
+    Msg2(AddToPositionMsg2),
+    /// Message revoking the previous commit transaction
+    ///
+    /// Each party sends and receives this message.
+    Msg3(RollOverMsg2),
+}
+
+impl AddToPositionMsg {
+    pub fn try_into_msg0(self) -> Result<AddToPositionMsg0> {
+        if let Self::Msg0(v) = self {
+            Ok(v)
+        } else {
+            bail!("Not Msg0")
+        }
+    }
+
+    pub fn try_into_msg1(self) -> Result<RollOverMsg1> {
+        if let Self::Msg1(v) = self {
+            Ok(v)
+        } else {
+            bail!("Not Msg1")
+        }
+    }
+
+    pub fn try_into_msg2(self) -> Result<AddToPositionMsg2> {
+        if let Self::Msg2(v) = self {
+            Ok(v)
+        } else {
+            bail!("Not Msg2")
+        }
+    }
+
+    pub fn try_into_msg3(self) -> Result<RollOverMsg2> {
+        if let Self::Msg3(v) = self {
+            Ok(v)
+        } else {
+            bail!("Not Msg3")
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddToPositionMsg0 {
+    /// Funds the additional margin, built the same way as for the initial lock transaction
+    pub lock_psbt: PartiallySignedTransaction, // TODO: Use binary representation
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub lock_amount: Amount,
+    pub revocation_pk: PublicKey,
+    pub publish_pk: PublicKey,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddToPositionMsg2 {
+    /// The new lock transaction with the inputs of the sender signed
+    pub signed_lock: PartiallySignedTransaction, // TODO: Use binary representation
+    /// The signature spending the current lock output
+    pub signature: Signature,
+}
@@ -0,0 +1,101 @@
+use crate::harness::flow::{is_next_none, next_cfd, next_order};
+use crate::harness::{dummy_new_order, init_tracing, start_both};
+use daemon::model::cfd::CfdState;
+use daemon::model::{ContractType, Leverage, PayoutDensity, PayoutResolution, Price, Usd};
+use daemon::payout_curve;
+use rust_decimal_macros::dec;
+use std::time::{Duration, Instant};
+#[allow(dead_code)]
+mod harness;
+
+/// Resolutions the benchmarks are run for, from the default up to the maximum number of payouts.
+fn resolutions() -> Vec<PayoutResolution> {
+    vec![
+        PayoutResolution::default(),
+        PayoutResolution::new(200, 6, PayoutDensity::Adaptive).unwrap(),
+        PayoutResolution::new(PayoutResolution::MAX_PAYOUTS, 0, PayoutDensity::Uniform).unwrap(),
+    ]
+}
+
+#[test]
+#[cfg_attr(not(feature = "expensive_tests"), ignore)]
+fn bench_payout_calculation() {
+    let _guard = init_tracing();
+
+    for resolution in resolutions() {
+        let start = Instant::now();
+        let payouts = payout_curve::calculate(
+            Price::new(dec!(50_000)).unwrap(),
+            Usd::new(dec!(10_000)),
+            Leverage::new(2).unwrap(),
+            ContractType::Inverse,
+            resolution,
+        )
+        .unwrap();
+
+        report("payout calculation", resolution, payouts.len(), start.elapsed());
+    }
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "expensive_tests"), ignore)]
+async fn bench_contract_setup() {
+    let _guard = init_tracing();
+
+    for resolution in resolutions() {
+        let (mut maker, mut taker) = start_both().await;
+
+        is_next_none(&mut taker.order_feed).await;
+
+        maker
+            .publish_order(daemon::maker_cfd::NewOrder {
+                payout_resolution: resolution,
+                ..dummy_new_order()
+            })
+            .await;
+
+        let (_, received) = next_order(&mut maker.order_feed, &mut taker.order_feed).await;
+
+        taker.take_order(received.clone(), Usd::new(dec!(5))).await;
+        let (_, _) = next_cfd(&mut taker.cfd_feed, &mut maker.cfd_feed).await;
+
+        maker.mocks.mock_oracle_annoucement().await;
+        taker.mocks.mock_oracle_annoucement().await;
+
+        maker.mocks.mock_party_params().await;
+        taker.mocks.mock_party_params().await;
+
+        maker.mocks.mock_wallet_sign_and_broadcast().await;
+        taker.mocks.mock_wallet_sign_and_broadcast().await;
+
+        let start = Instant::now();
+        maker.accept_take_request(received.clone()).await;
+
+        let (taker_cfd, maker_cfd) = next_cfd(&mut taker.cfd_feed, &mut maker.cfd_feed).await;
+        assert!(matches!(taker_cfd.state, CfdState::ContractSetup { .. }));
+        assert!(matches!(maker_cfd.state, CfdState::ContractSetup { .. }));
+
+        let (taker_cfd, maker_cfd) = next_cfd(&mut taker.cfd_feed, &mut maker.cfd_feed).await;
+        assert!(matches!(taker_cfd.state, CfdState::PendingOpen { .. }));
+        assert!(matches!(maker_cfd.state, CfdState::PendingOpen { .. }));
+
+        let n_cets = match maker_cfd.state {
+            CfdState::PendingOpen { dlc, .. } => dlc.cets.values().map(Vec::len).sum(),
+            _ => unreachable!(),
+        };
+
+        report("contract setup", resolution, n_cets, start.elapsed());
+    }
+}
+
+fn report(name: &str, resolution: PayoutResolution, n_cets: usize, elapsed: Duration) {
+    tracing::info!(
+        "{}: {} payouts, {} bucket digits, {:?} density -> {} CETs in {:?}",
+        name,
+        resolution.n_payouts(),
+        resolution.bucket_digits(),
+        resolution.density(),
+        n_cets,
+        elapsed
+    );
+}
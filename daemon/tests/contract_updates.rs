@@ -0,0 +1,229 @@
+use crate::harness::mocks::oracle::dummy_announcement;
+use crate::harness::mocks::wallet::{build_party_params, MockWallet, WalletActor};
+use crate::harness::{dummy_order, init_tracing};
+use daemon::model::cfd::{AddToPositionProposal, Cfd, CfdState, Dlc, Origin, Role};
+use daemon::model::{Price, Timestamp, Usd};
+use daemon::{oracle, setup_contract, wire};
+use futures::channel::mpsc;
+use futures::SinkExt;
+use maia::secp256k1_zkp::schnorrsig;
+use rust_decimal_macros::dec;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use xtra::spawn::TokioGlobalSpawnExt;
+use xtra::Actor;
+#[allow(dead_code)]
+mod harness;
+
+#[tokio::test]
+#[cfg_attr(not(feature = "expensive_tests"), ignore)]
+async fn add_to_position_renews_contract_on_increased_lock_output() {
+    let _guard = init_tracing();
+    let oracle = (oracle_pk(), dummy_announcement());
+    let maker_cfd = dummy_cfd(Origin::Ours);
+    let taker_cfd = dummy_cfd(Origin::Theirs);
+
+    let (maker_dlc, taker_dlc) = setup(&oracle, &maker_cfd, &taker_cfd).await;
+
+    let proposal = AddToPositionProposal {
+        order_id: maker_cfd.order.id,
+        timestamp: Timestamp::now().unwrap(),
+        quantity: Usd::new(dec!(5_000)),
+        price: Price::new(dec!(51_000)).unwrap(),
+    };
+    let (maker_sink, taker_stream) = mpsc::unbounded::<wire::AddToPositionMsg>();
+    let (taker_sink, maker_stream) = mpsc::unbounded::<wire::AddToPositionMsg>();
+
+    let (increased_maker_dlc, increased_taker_dlc) = tokio::try_join!(
+        setup_contract::add_to_position(
+            maker_sink.sink_map_err(anyhow::Error::from),
+            maker_stream,
+            oracle.clone(),
+            maker_cfd.clone(),
+            proposal.clone(),
+            wallet(),
+            Role::Maker,
+            maker_dlc.clone(),
+        ),
+        setup_contract::add_to_position(
+            taker_sink.sink_map_err(anyhow::Error::from),
+            taker_stream,
+            oracle.clone(),
+            taker_cfd.clone(),
+            proposal.clone(),
+            wallet(),
+            Role::Taker,
+            taker_dlc,
+        )
+    )
+    .unwrap();
+
+    let (maker_lock_amount, taker_lock_amount) = maker_cfd
+        .with_added_position(proposal.quantity, proposal.price)
+        .unwrap()
+        .lock_amounts()
+        .unwrap();
+    let lock_tx = &increased_maker_dlc.lock.0;
+
+    assert_eq!(lock_tx.txid(), increased_taker_dlc.lock.0.txid());
+    assert_eq!(
+        lock_tx.input[0].previous_output.txid,
+        maker_dlc.lock.0.txid()
+    );
+    assert_eq!(
+        lock_tx.output[0].value,
+        (maker_lock_amount + taker_lock_amount).as_sat()
+    );
+    assert_eq!(
+        (
+            increased_maker_dlc.maker_lock_amount,
+            increased_maker_dlc.taker_lock_amount
+        ),
+        (maker_lock_amount, taker_lock_amount)
+    );
+    assert_eq!(
+        increased_maker_dlc.commit.0.txid(),
+        increased_taker_dlc.commit.0.txid()
+    );
+    assert_eq!(increased_maker_dlc.revoked_commit.len(), 1);
+    assert_eq!(
+        increased_maker_dlc.revoked_commit[0].txid,
+        maker_dlc.commit.0.txid()
+    );
+}
+
+#[tokio::test]
+#[cfg_attr(not(feature = "expensive_tests"), ignore)]
+async fn partial_settlement_renews_contract_on_remaining_lock_output() {
+    let _guard = init_tracing();
+    let oracle = (oracle_pk(), dummy_announcement());
+    let maker_cfd = dummy_cfd(Origin::Ours);
+    let taker_cfd = dummy_cfd(Origin::Theirs);
+
+    let (maker_dlc, taker_dlc) = setup(&oracle, &maker_cfd, &taker_cfd).await;
+
+    let proposal = taker_cfd
+        .calculate_partial_settlement(Usd::new(dec!(2_500)), Price::new(dec!(51_000)).unwrap())
+        .unwrap();
+    let (maker_sink, taker_stream) = mpsc::unbounded::<wire::PartialSettlementMsg>();
+    let (taker_sink, maker_stream) = mpsc::unbounded::<wire::PartialSettlementMsg>();
+
+    let (remaining_maker_dlc, remaining_taker_dlc) = tokio::try_join!(
+        setup_contract::partial_settlement(
+            maker_sink.sink_map_err(anyhow::Error::from),
+            maker_stream,
+            oracle.clone(),
+            maker_cfd.clone(),
+            proposal.clone(),
+            Role::Maker,
+            maker_dlc.clone(),
+        ),
+        setup_contract::partial_settlement(
+            taker_sink.sink_map_err(anyhow::Error::from),
+            taker_stream,
+            oracle.clone(),
+            taker_cfd.clone(),
+            proposal.clone(),
+            Role::Taker,
+            taker_dlc,
+        )
+    )
+    .unwrap();
+
+    let (maker_lock_amount, taker_lock_amount) = maker_cfd
+        .with_quantity(Usd::new(dec!(7_500)))
+        .unwrap()
+        .lock_amounts()
+        .unwrap();
+    let settlement_tx = &remaining_maker_dlc.lock.0;
+
+    assert_eq!(settlement_tx.txid(), remaining_taker_dlc.lock.0.txid());
+    assert_eq!(
+        settlement_tx.input[0].previous_output.txid,
+        maker_dlc.lock.0.txid()
+    );
+    assert_eq!(
+        settlement_tx.output[0].value,
+        (maker_lock_amount + taker_lock_amount).as_sat()
+    );
+    assert_eq!(
+        (
+            remaining_maker_dlc.maker_lock_amount,
+            remaining_maker_dlc.taker_lock_amount
+        ),
+        (maker_lock_amount, taker_lock_amount)
+    );
+    assert_eq!(
+        remaining_maker_dlc.commit.0.txid(),
+        remaining_taker_dlc.commit.0.txid()
+    );
+    assert_eq!(remaining_maker_dlc.revoked_commit.len(), 1);
+    assert_eq!(
+        remaining_maker_dlc.revoked_commit[0].txid,
+        maker_dlc.commit.0.txid()
+    );
+}
+
+/// Sets up the contract of both parties
+async fn setup(
+    oracle: &(schnorrsig::PublicKey, oracle::Announcement),
+    maker_cfd: &Cfd,
+    taker_cfd: &Cfd,
+) -> (Dlc, Dlc) {
+    let (maker_sink, taker_stream) = mpsc::unbounded::<wire::SetupMsg>();
+    let (taker_sink, maker_stream) = mpsc::unbounded::<wire::SetupMsg>();
+
+    tokio::try_join!(
+        setup_contract::new(
+            maker_sink.sink_map_err(anyhow::Error::from),
+            maker_stream,
+            oracle.clone(),
+            maker_cfd.clone(),
+            wallet(),
+            Role::Maker,
+        ),
+        setup_contract::new(
+            taker_sink.sink_map_err(anyhow::Error::from),
+            taker_stream,
+            oracle.clone(),
+            taker_cfd.clone(),
+            wallet(),
+            Role::Taker,
+        )
+    )
+    .unwrap()
+}
+
+/// A wallet funding from its own dummy UTXOs, whose signatures are not checked in these tests
+fn wallet() -> xtra::Address<WalletActor> {
+    let mut mock = MockWallet::new();
+    #[allow(clippy::redundant_closure)] // clippy is in the wrong here
+    mock.expect_build_party_params()
+        .returning(|msg| build_party_params(msg));
+    mock.expect_sign().returning(|msg| Ok(msg.psbt));
+
+    WalletActor {
+        mock: Arc::new(Mutex::new(mock)),
+    }
+    .create(None)
+    .spawn_global()
+}
+
+fn oracle_pk() -> schnorrsig::PublicKey {
+    schnorrsig::PublicKey::from_str(
+        "ddd4636845a90185991826be5a494cde9f4a6947b1727217afedc6292fa4caf7",
+    )
+    .unwrap()
+}
+
+fn dummy_cfd(origin: Origin) -> Cfd {
+    let mut order = dummy_order(origin);
+    order.set_price(Price::new(dec!(50_000)).unwrap());
+
+    Cfd::new(
+        order,
+        Usd::new(dec!(10_000)),
+        CfdState::outgoing_order_request(),
+    )
+}
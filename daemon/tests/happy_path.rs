@@ -4,6 +4,7 @@ use daemon::model::cfd::CfdState;
 use daemon::model::Usd;
 use maia::secp256k1_zkp::schnorrsig;
 use rust_decimal_macros::dec;
+#[allow(dead_code)]
 mod harness;
 
 #[tokio::test]
@@ -2,16 +2,21 @@ use crate::harness::mocks::monitor::MonitorActor;
 use crate::harness::mocks::oracle::OracleActor;
 use crate::harness::mocks::wallet::WalletActor;
 use crate::schnorrsig;
-use daemon::maker_cfd::CfdAction;
+use daemon::bitmex_price_feed::Quote;
+use daemon::maker_cfd::{CfdAction, RollOverPolicy, SettlementPolicy};
 use daemon::model::cfd::{Cfd, Order, Origin};
-use daemon::model::{Price, Usd};
+use daemon::model::{
+    BitMexPriceEventId, ContractType, FundingRate, PayoutResolution, Price, Timestamp, Usd,
+};
 use daemon::seed::Seed;
 use daemon::{connection, db, maker_cfd, maker_inc_connections, taker_cfd};
 use rust_decimal_macros::dec;
-use sqlx::SqlitePool;
+use sqlx::any::AnyPoolOptions;
+use sqlx::{AnyConnection, AnyPool, Connection};
 use std::net::SocketAddr;
 use std::str::FromStr;
 use std::task::Poll;
+use time::{Duration, OffsetDateTime};
 use tokio::sync::watch;
 use tracing::subscriber::DefaultGuard;
 use tracing_subscriber::filter::LevelFilter;
@@ -53,7 +58,7 @@ pub struct Maker {
 
 impl Maker {
     pub async fn start(oracle_pk: schnorrsig::PublicKey) -> Self {
-        let db = in_memory_db().await;
+        let db = test_db().await;
 
         let mut mocks = mocks::Mocks::default();
         let (oracle, monitor, wallet) = mocks::create_actors(&mocks);
@@ -78,6 +83,11 @@ impl Maker {
                 maker_inc_connections::Actor::new(channel0, channel1, noise_static_sk)
             },
             settlement_time_interval_hours,
+            FundingRate::default(),
+            RollOverPolicy::default(),
+            SettlementPolicy::default(),
+            dummy_price_feed(),
+            time::Duration::days(7),
         )
         .await
         .unwrap();
@@ -162,7 +172,7 @@ impl Taker {
             .await
             .expect("Connected to maker");
 
-        let db = in_memory_db().await;
+        let db = test_db().await;
 
         let mut mocks = mocks::Mocks::default();
         let (oracle, monitor, wallet) = mocks::create_actors(&mocks);
@@ -178,6 +188,9 @@ impl Taker {
             read_from_maker,
             |_, _| oracle,
             |_, _| async { Ok(monitor) },
+            None,
+            dummy_price_feed(),
+            time::Duration::days(7),
         )
         .await
         .unwrap();
@@ -202,17 +215,54 @@ impl Taker {
     }
 }
 
-async fn in_memory_db() -> SqlitePool {
-    // Note: Every :memory: database is distinct from every other. So, opening two database
-    // connections each with the filename ":memory:" will create two independent in-memory
-    // databases. see: https://www.sqlite.org/inmemorydb.html
-    let pool = SqlitePool::connect(":memory:").await.unwrap();
+/// Connects to a schema of its own in the Postgres database in `ITCHYSATS_TEST_POSTGRES_URL` if it
+/// is set and to an in-memory SQLite database otherwise.
+async fn test_db() -> AnyPool {
+    let pool = match std::env::var("ITCHYSATS_TEST_POSTGRES_URL") {
+        Ok(url) => {
+            let schema = format!("test_{}", uuid::Uuid::new_v4().to_simple());
+
+            let mut conn = AnyConnection::connect(&url).await.unwrap();
+            sqlx::query(&format!("create schema {}", schema))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+
+            AnyPoolOptions::new()
+                .after_connect(move |conn| {
+                    let set_search_path = format!("set search_path to {}", schema);
+
+                    Box::pin(async move {
+                        sqlx::query(&set_search_path).execute(&mut *conn).await?;
+                        Ok(())
+                    })
+                })
+                .connect(&url)
+                .await
+                .unwrap()
+        }
+        // Note: Every :memory: database is distinct from every other. So, opening two database
+        // connections each with the filename ":memory:" will create two independent in-memory
+        // databases. see: https://www.sqlite.org/inmemorydb.html
+        Err(_) => AnyPool::connect("sqlite::memory:").await.unwrap(),
+    };
 
     db::run_migrations(&pool).await.unwrap();
 
     pool
 }
 
+/// A price feed whose quote never changes
+fn dummy_price_feed() -> watch::Receiver<Quote> {
+    let (_, price_feed) = watch::channel(Quote {
+        timestamp: Timestamp::now().unwrap(),
+        bid: Price::new(dec!(50_000)).unwrap(),
+        ask: Price::new(dec!(50_000)).unwrap(),
+    });
+
+    price_feed
+}
+
 /// The order cannot be directly compared in tests as the origin is different,
 /// therefore wrap the assertion macro in a code that unifies the 'Origin'
 pub fn assert_is_same_order(a: &Order, b: &Order) {
@@ -225,11 +275,32 @@ pub fn assert_is_same_order(a: &Order, b: &Order) {
     assert_eq!(a, b);
 }
 
+/// Mirrors `Order::dummy` of the daemon's unit tests, which is not available to integration tests
+pub fn dummy_order(origin: Origin) -> Order {
+    Order {
+        origin,
+        ..Order::new(
+            Price::new(dec!(40_000)).unwrap(),
+            Usd::new(dec!(100)),
+            Usd::new(dec!(100_000)),
+            ContractType::Inverse,
+            PayoutResolution::default(),
+            FundingRate::default(),
+            Origin::Theirs,
+            BitMexPriceEventId::with_20_digits(OffsetDateTime::now_utc()),
+            Duration::hours(24),
+        )
+        .unwrap()
+    }
+}
+
 pub fn dummy_new_order() -> maker_cfd::NewOrder {
     maker_cfd::NewOrder {
         price: Price::new(dec!(50_000)).expect("unexpected failure"),
         min_quantity: Usd::new(dec!(5)),
         max_quantity: Usd::new(dec!(100)),
+        contract_type: ContractType::Inverse,
+        payout_resolution: PayoutResolution::default(),
     }
 }
 
@@ -243,6 +314,11 @@ pub fn init_tracing() -> DefaultGuard {
                 .parse()
                 .unwrap(),
         )
+        .add_directive(
+            format!("benchmarks={}", LevelFilter::DEBUG)
+                .parse()
+                .unwrap(),
+        )
         .add_directive(format!("taker={}", LevelFilter::DEBUG).parse().unwrap())
         .add_directive(format!("maker={}", LevelFilter::DEBUG).parse().unwrap())
         .add_directive(format!("daemon={}", LevelFilter::DEBUG).parse().unwrap())
@@ -70,6 +70,9 @@ export default function App() {
     const pendingOrders = cfds.filter((value) => value.state.getGroup() === StateGroupKey.PENDING_ORDER);
     const pendingSettlements = cfds.filter((value) => value.state.getGroup() === StateGroupKey.PENDING_SETTLEMENT);
     const pendingRollOvers = cfds.filter((value) => value.state.getGroup() === StateGroupKey.PENDING_ROLL_OVER);
+    const pendingAddToPositions = cfds.filter((value) =>
+        value.state.getGroup() === StateGroupKey.PENDING_ADD_TO_POSITION
+    );
     const opening = cfds.filter((value) => value.state.getGroup() === StateGroupKey.OPENING);
     const open = cfds.filter((value) => value.state.getGroup() === StateGroupKey.OPEN);
     const closed = cfds.filter((value) => value.state.getGroup() === StateGroupKey.CLOSED);
@@ -163,6 +166,7 @@ export default function App() {
                     <Tab>Pending Orders [{pendingOrders.length}]</Tab>
                     <Tab>Pending Settlements [{pendingSettlements.length}]</Tab>
                     <Tab>Pending Roll Overs [{pendingRollOvers.length}]</Tab>
+                    <Tab>Pending Add to Position [{pendingAddToPositions.length}]</Tab>
                     <Tab>Opening [{opening.length}]</Tab>
                     <Tab>Closed [{closed.length}]</Tab>
                 </TabList>
@@ -180,6 +184,9 @@ export default function App() {
                     <TabPanel>
                         <CfdTable data={pendingRollOvers} />
                     </TabPanel>
+                    <TabPanel>
+                        <CfdTable data={pendingAddToPositions} />
+                    </TabPanel>
                     <TabPanel>
                         <CfdTable data={opening} />
                     </TabPanel>
@@ -1,12 +1,14 @@
 export interface Order {
     id: string;
     trading_pair: string;
+    contract_type: string;
     position: Position;
     price: number;
     min_quantity: number;
     max_quantity: number;
     leverage: number;
     liquidation_price: number;
+    funding_rate: string;
     creation_timestamp: number;
     settlement_time_interval_in_secs: number;
 }
@@ -35,6 +37,7 @@ export interface Cfd {
 
     leverage: number;
     trading_pair: string;
+    contract_type: string;
     position: Position;
     liquidation_price: number;
 
@@ -45,6 +48,8 @@ export interface Cfd {
     profit_btc: number;
     profit_in_percent: number;
 
+    funding_btc: number;
+
     state: State;
     actions: Action[];
     state_transition_timestamp: number;
@@ -95,6 +100,14 @@ export class State {
                 return "Rollover Proposed";
             case StateKey.OUTGOING_ROLL_OVER_PROPOSAL:
                 return "Rollover Proposed";
+            case StateKey.INCOMING_PARTIAL_SETTLEMENT_PROPOSAL:
+                return "Partial Settlement Proposed";
+            case StateKey.OUTGOING_PARTIAL_SETTLEMENT_PROPOSAL:
+                return "Partial Settlement Proposed";
+            case StateKey.INCOMING_ADD_TO_POSITION_PROPOSAL:
+                return "Add to Position Proposed";
+            case StateKey.OUTGOING_ADD_TO_POSITION_PROPOSAL:
+                return "Add to Position Proposed";
             case StateKey.PENDING_REFUND:
                 return "Refunding";
             case StateKey.REFUNDED:
@@ -135,6 +148,10 @@ export class State {
             case StateKey.INCOMING_SETTLEMENT_PROPOSAL:
             case StateKey.INCOMING_ROLL_OVER_PROPOSAL:
             case StateKey.OUTGOING_ROLL_OVER_PROPOSAL:
+            case StateKey.INCOMING_PARTIAL_SETTLEMENT_PROPOSAL:
+            case StateKey.OUTGOING_PARTIAL_SETTLEMENT_PROPOSAL:
+            case StateKey.INCOMING_ADD_TO_POSITION_PROPOSAL:
+            case StateKey.OUTGOING_ADD_TO_POSITION_PROPOSAL:
             case StateKey.CONTRACT_SETUP:
             case StateKey.PENDING_OPEN:
             case StateKey.REFUNDED:
@@ -161,16 +178,22 @@ export class State {
             case StateKey.PENDING_REFUND:
             case StateKey.OUTGOING_SETTLEMENT_PROPOSAL:
             case StateKey.OUTGOING_ROLL_OVER_PROPOSAL:
+            case StateKey.OUTGOING_PARTIAL_SETTLEMENT_PROPOSAL:
+            case StateKey.OUTGOING_ADD_TO_POSITION_PROPOSAL:
             case StateKey.PENDING_CET:
             case StateKey.PENDING_CLOSE:
                 return StateGroupKey.OPEN;
 
             case StateKey.INCOMING_SETTLEMENT_PROPOSAL:
+            case StateKey.INCOMING_PARTIAL_SETTLEMENT_PROPOSAL:
                 return StateGroupKey.PENDING_SETTLEMENT;
 
             case StateKey.INCOMING_ROLL_OVER_PROPOSAL:
                 return StateGroupKey.PENDING_ROLL_OVER;
 
+            case StateKey.INCOMING_ADD_TO_POSITION_PROPOSAL:
+                return StateGroupKey.PENDING_ADD_TO_POSITION;
+
             case StateKey.REJECTED:
             case StateKey.REFUNDED:
             case StateKey.SETUP_FAILED:
@@ -190,6 +213,10 @@ export enum Action {
     REJECT_SETTLEMENT = "rejectSettlement",
     ACCEPT_ROLL_OVER = "acceptRollOver",
     REJECT_ROLL_OVER = "rejectRollOver",
+    ACCEPT_PARTIAL_SETTLEMENT = "acceptPartialSettlement",
+    REJECT_PARTIAL_SETTLEMENT = "rejectPartialSettlement",
+    ACCEPT_ADD_TO_POSITION = "acceptAddToPosition",
+    REJECT_ADD_TO_POSITION = "rejectAddToPosition",
 }
 
 const enum StateKey {
@@ -208,6 +235,10 @@ const enum StateKey {
     INCOMING_SETTLEMENT_PROPOSAL = "IncomingSettlementProposal",
     OUTGOING_ROLL_OVER_PROPOSAL = "OutgoingRollOverProposal",
     INCOMING_ROLL_OVER_PROPOSAL = "IncomingRollOverProposal",
+    OUTGOING_PARTIAL_SETTLEMENT_PROPOSAL = "OutgoingPartialSettlementProposal",
+    INCOMING_PARTIAL_SETTLEMENT_PROPOSAL = "IncomingPartialSettlementProposal",
+    OUTGOING_ADD_TO_POSITION_PROPOSAL = "OutgoingAddToPositionProposal",
+    INCOMING_ADD_TO_POSITION_PROPOSAL = "IncomingAddToPositionProposal",
     PENDING_REFUND = "PendingRefund",
     REFUNDED = "Refunded",
     SETUP_FAILED = "SetupFailed",
@@ -222,6 +253,7 @@ export enum StateGroupKey {
     OPEN = "Open",
     PENDING_SETTLEMENT = "Pending Settlement",
     PENDING_ROLL_OVER = "Pending Roll Over",
+    PENDING_ADD_TO_POSITION = "Pending Add to Position",
     /// A CFD that has been successfully or not-successfully terminated
     CLOSED = "Closed",
 }
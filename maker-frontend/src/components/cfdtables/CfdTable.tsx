@@ -164,6 +164,11 @@ export function CfdTable(
                 accessor: "profit_in_percent",
                 isNumeric: true,
             },
+            {
+                Header: "Funding",
+                accessor: "funding_btc",
+                isNumeric: true,
+            },
             {
                 Header: "Timestamp",
                 accessor: "state_transition_timestamp",
@@ -231,6 +236,14 @@ function iconForAction(action: Action): any {
             return <CheckIcon />;
         case Action.REJECT_ROLL_OVER:
             return <CloseIcon />;
+        case Action.ACCEPT_PARTIAL_SETTLEMENT:
+            return <CheckIcon />;
+        case Action.REJECT_PARTIAL_SETTLEMENT:
+            return <CloseIcon />;
+        case Action.ACCEPT_ADD_TO_POSITION:
+            return <CheckIcon />;
+        case Action.REJECT_ADD_TO_POSITION:
+            return <CloseIcon />;
     }
 }
 
@@ -254,6 +267,14 @@ function colorSchemaForAction(action: Action): string {
             return "green";
         case Action.REJECT_ROLL_OVER:
             return "red";
+        case Action.ACCEPT_PARTIAL_SETTLEMENT:
+            return "green";
+        case Action.REJECT_PARTIAL_SETTLEMENT:
+            return "red";
+        case Action.ACCEPT_ADD_TO_POSITION:
+            return "green";
+        case Action.REJECT_ADD_TO_POSITION:
+            return "red";
     }
 }
 
@@ -21,6 +21,7 @@ import {
     WalletInfo,
 } from "./components/Types";
 import { Wallet } from "./components/Wallet";
+import csrfHeaders from "./Csrf";
 import useLatestEvent from "./Hooks";
 
 async function getMargin(payload: MarginRequestPayload): Promise<MarginResponse> {
@@ -34,7 +35,11 @@ async function getMargin(payload: MarginRequestPayload): Promise<MarginResponse>
 }
 
 async function postCfdOrderRequest(payload: CfdOrderRequestPayload) {
-    let res = await fetch(`/api/cfd/order`, { method: "POST", body: JSON.stringify(payload) });
+    let res = await fetch(`/api/cfd/order`, {
+        method: "POST",
+        body: JSON.stringify(payload),
+        headers: csrfHeaders(),
+    });
     if (!res.status.toString().startsWith("2")) {
         console.log(`Error${JSON.stringify(res)}`);
         throw new Error("failed to create new CFD order request: " + res.status + ", " + res.statusText);
@@ -71,7 +76,14 @@ export const App = () => {
     let [margin, setMargin] = useState("0");
     let [userHasEdited, setUserHasEdited] = useState(false);
 
-    const { price: askPrice, min_quantity, max_quantity, leverage, liquidation_price: liquidationPrice } = order || {};
+    const {
+        price: askPrice,
+        min_quantity,
+        max_quantity,
+        leverage,
+        liquidation_price: liquidationPrice,
+        funding_rate: fundingRate,
+    } = order || {};
 
     let effectiveQuantity = userHasEdited ? quantity : (min_quantity?.toString() || "0");
 
@@ -153,6 +165,7 @@ export const App = () => {
                                 margin={margin}
                                 leverage={leverage}
                                 liquidationPrice={liquidationPrice}
+                                fundingRate={fundingRate}
                                 onQuantityChange={(valueString: string) => {
                                     setUserHasEdited(true);
                                     setQuantity(parse(valueString));
@@ -0,0 +1,12 @@
+const CSRF_COOKIE = "csrf-token";
+
+// The daemon hands out a token in a cookie that requests changing state have to send back in a
+// header, which other sites cannot do.
+export default function csrfHeaders(): Record<string, string> {
+    const token = document.cookie
+        .split("; ")
+        .find((cookie) => cookie.startsWith(`${CSRF_COOKIE}=`))
+        ?.substring(CSRF_COOKIE.length + 1);
+
+    return token ? { "X-CSRF-Token": token } : {};
+}
@@ -30,6 +30,7 @@ import {
 } from "@chakra-ui/react";
 import * as React from "react";
 import { useAsync } from "react-async";
+import csrfHeaders from "../Csrf";
 import { Cfd, StateGroupKey, StateKey, Tx, TxLabel } from "./Types";
 
 interface HistoryProps {
@@ -64,7 +65,7 @@ interface CfdDetailsProps {
 async function doPostAction(id: string, action: string) {
     await fetch(
         `/api/cfd/${id}/${action}`,
-        { method: "POST", credentials: "include" },
+        { method: "POST", credentials: "include", headers: csrfHeaders() },
     );
 }
 
@@ -75,6 +76,7 @@ const CfdDetails = ({ cfd }: CfdDetailsProps) => {
     const margin = `₿${Math.round((cfd.margin) * 1_000_000) / 1_000_000}`;
     const liquidationPrice = `$${cfd.liquidation_price}`;
     const pAndL = Math.round((cfd.profit_btc) * 1_000_000) / 1_000_000;
+    const funding = Math.round((cfd.funding_btc) * 1_000_000) / 1_000_000;
     const expiry = cfd.expiry_timestamp;
     const profit = Math.round((cfd.margin + cfd.profit_btc) * 1_000_000) / 1_000_000;
 
@@ -103,6 +105,12 @@ const CfdDetails = ({ cfd }: CfdDetailsProps) => {
         },
     });
 
+    const hasCounterProposal = cfd.actions.includes("acceptCounterProposal");
+    const hasSettlementProposal = cfd.actions.includes("acceptSettlement");
+    const settlementTerms = cfd.settlement_proposal
+        ? ` at ${cfd.settlement_proposal.price}, you would receive ₿ ${cfd.settlement_proposal.payout}`
+        : "";
+
     const disableCloseButton = cfd.state.getGroup() === StateGroupKey.CLOSED
         || [StateKey.OPEN_COMMITTED, StateKey.OUTGOING_SETTLEMENT_PROPOSAL, StateKey.PENDING_CLOSE].includes(
             cfd.state.key,
@@ -133,11 +141,17 @@ const CfdDetails = ({ cfd }: CfdDetailsProps) => {
                             <Td><Text as={"b"}>Unrealized P/L</Text></Td>
                             <Td>{pAndL.toString()}</Td>
                         </Tr>
+                        <Tr>
+                            <Td><Text as={"b"}>Funding</Text></Td>
+                            <Td>{funding.toString()}</Td>
+                        </Tr>
                     </Tbody>
                 </Table>
             </Center>
             <VStack>
                 <Badge colorScheme={cfd.state.getColorScheme()}>{cfd.state.getLabel()}</Badge>
+                {cfd.auto_roll_over_failure
+                    && <Text fontSize={"sm"} color={"red.500"}>{cfd.auto_roll_over_failure}</Text>}
                 <HStack w={"95%"}>
                     <VStack>
                         <TxIcon tx={txLock} />
@@ -175,6 +189,46 @@ const CfdDetails = ({ cfd }: CfdDetailsProps) => {
                                 </>}
                         </>}
                 </HStack>
+                {hasCounterProposal
+                    && <HStack>
+                        <Text fontSize={"sm"}>The maker made a counter-offer{settlementTerms}</Text>
+                        <Button
+                            size="sm"
+                            colorScheme="green"
+                            onClick={async () => postAction(cfd.order_id, "acceptCounterProposal")}
+                            isLoading={isActioning}
+                        >
+                            Accept
+                        </Button>
+                        <Button
+                            size="sm"
+                            colorScheme="red"
+                            onClick={async () => postAction(cfd.order_id, "rejectCounterProposal")}
+                            isLoading={isActioning}
+                        >
+                            Reject
+                        </Button>
+                    </HStack>}
+                {hasSettlementProposal
+                    && <HStack>
+                        <Text fontSize={"sm"}>The maker proposes to close the position{settlementTerms}</Text>
+                        <Button
+                            size="sm"
+                            colorScheme="green"
+                            onClick={async () => postAction(cfd.order_id, "acceptSettlement")}
+                            isLoading={isActioning}
+                        >
+                            Accept
+                        </Button>
+                        <Button
+                            size="sm"
+                            colorScheme="red"
+                            onClick={async () => postAction(cfd.order_id, "rejectSettlement")}
+                            isLoading={isActioning}
+                        >
+                            Reject
+                        </Button>
+                    </HStack>}
                 <HStack>
                     <Box w={"45%"}>
                         <Text fontSize={"sm"} align={"left"}>
@@ -55,6 +55,7 @@ interface TradeProps {
     leverage?: number;
     quantity: string;
     liquidationPrice?: number;
+    fundingRate?: string;
     isSubmitting: boolean;
     onQuantityChange: any;
     onLongSubmit: (payload: CfdOrderRequestPayload) => void;
@@ -71,6 +72,7 @@ const Trade = (
         margin: marginAsNumber,
         leverage,
         liquidationPrice: liquidationPriceAsNumber,
+        fundingRate,
         onLongSubmit,
         order_id,
     }: TradeProps,
@@ -187,6 +189,10 @@ const Trade = (
                                                     <Td><Text as={"b"}>Liquidation Price</Text></Td>
                                                     <Td>{liquidationPrice}</Td>
                                                 </Tr>
+                                                <Tr>
+                                                    <Td><Text as={"b"}>Funding Rate per Roll-over</Text></Td>
+                                                    <Td>{fundingRate}</Td>
+                                                </Tr>
                                             </Tbody>
                                         </Table>
                                     </ModalBody>
@@ -11,12 +11,14 @@ export function unixTimestampToDate(unixTimestamp: number): Date {
 export interface Order {
     id: string;
     trading_pair: string;
+    contract_type: string;
     position: Position;
     price: number;
     min_quantity: number;
     max_quantity: number;
     leverage: number;
     liquidation_price: number;
+    funding_rate: string;
     creation_timestamp: number;
     settlement_time_interval_in_secs: number;
 }
@@ -45,6 +47,7 @@ export interface Cfd {
 
     leverage: number;
     trading_pair: string;
+    contract_type: string;
     position: Position;
     liquidation_price: number;
 
@@ -55,10 +58,22 @@ export interface Cfd {
     profit_btc: number;
     profit_in_percent: number;
 
+    funding_btc: number;
+
     state: State;
     state_transition_timestamp: number;
     details: CfdDetails;
+    settlement_proposal?: SettlementTerms;
     expiry_timestamp: number;
+    auto_roll_over_failure?: string;
+
+    actions: string[];
+}
+
+export interface SettlementTerms {
+    price: number;
+    payout: number;
+    payout_counterparty: number;
 }
 
 export interface CfdDetails {
@@ -112,6 +127,14 @@ export class State {
                 return "Rollover Proposed";
             case StateKey.OUTGOING_ROLL_OVER_PROPOSAL:
                 return "Rollover Proposed";
+            case StateKey.INCOMING_PARTIAL_SETTLEMENT_PROPOSAL:
+                return "Partial Settlement Proposed";
+            case StateKey.OUTGOING_PARTIAL_SETTLEMENT_PROPOSAL:
+                return "Partial Settlement Proposed";
+            case StateKey.INCOMING_ADD_TO_POSITION_PROPOSAL:
+                return "Add to Position Proposed";
+            case StateKey.OUTGOING_ADD_TO_POSITION_PROPOSAL:
+                return "Add to Position Proposed";
             case StateKey.MUST_REFUND:
                 return "Refunding";
             case StateKey.REFUNDED:
@@ -152,6 +175,10 @@ export class State {
             case StateKey.INCOMING_SETTLEMENT_PROPOSAL:
             case StateKey.INCOMING_ROLL_OVER_PROPOSAL:
             case StateKey.OUTGOING_ROLL_OVER_PROPOSAL:
+            case StateKey.INCOMING_PARTIAL_SETTLEMENT_PROPOSAL:
+            case StateKey.OUTGOING_PARTIAL_SETTLEMENT_PROPOSAL:
+            case StateKey.INCOMING_ADD_TO_POSITION_PROPOSAL:
+            case StateKey.OUTGOING_ADD_TO_POSITION_PROPOSAL:
             case StateKey.CONTRACT_SETUP:
             case StateKey.PENDING_OPEN:
             case StateKey.REFUNDED:
@@ -178,16 +205,22 @@ export class State {
             case StateKey.MUST_REFUND:
             case StateKey.OUTGOING_SETTLEMENT_PROPOSAL:
             case StateKey.OUTGOING_ROLL_OVER_PROPOSAL:
+            case StateKey.OUTGOING_PARTIAL_SETTLEMENT_PROPOSAL:
+            case StateKey.OUTGOING_ADD_TO_POSITION_PROPOSAL:
             case StateKey.PENDING_CET:
             case StateKey.PENDING_CLOSE:
                 return StateGroupKey.OPEN;
 
             case StateKey.INCOMING_SETTLEMENT_PROPOSAL:
+            case StateKey.INCOMING_PARTIAL_SETTLEMENT_PROPOSAL:
                 return StateGroupKey.PENDING_SETTLEMENT;
 
             case StateKey.INCOMING_ROLL_OVER_PROPOSAL:
                 return StateGroupKey.PENDING_ROLL_OVER;
 
+            case StateKey.INCOMING_ADD_TO_POSITION_PROPOSAL:
+                return StateGroupKey.PENDING_ADD_TO_POSITION;
+
             case StateKey.REJECTED:
             case StateKey.REFUNDED:
             case StateKey.SETUP_FAILED:
@@ -213,6 +246,10 @@ export const enum StateKey {
     INCOMING_SETTLEMENT_PROPOSAL = "IncomingSettlementProposal",
     OUTGOING_ROLL_OVER_PROPOSAL = "OutgoingRollOverProposal",
     INCOMING_ROLL_OVER_PROPOSAL = "IncomingRollOverProposal",
+    OUTGOING_PARTIAL_SETTLEMENT_PROPOSAL = "OutgoingPartialSettlementProposal",
+    INCOMING_PARTIAL_SETTLEMENT_PROPOSAL = "IncomingPartialSettlementProposal",
+    OUTGOING_ADD_TO_POSITION_PROPOSAL = "OutgoingAddToPositionProposal",
+    INCOMING_ADD_TO_POSITION_PROPOSAL = "IncomingAddToPositionProposal",
     MUST_REFUND = "MustRefund",
     REFUNDED = "Refunded",
     SETUP_FAILED = "SetupFailed",
@@ -227,6 +264,7 @@ export enum StateGroupKey {
     OPEN = "Open",
     PENDING_SETTLEMENT = "Pending Settlement",
     PENDING_ROLL_OVER = "Pending Roll Over",
+    PENDING_ADD_TO_POSITION = "Pending Add to Position",
     /// A CFD that has been successfully or not-successfully terminated
     CLOSED = "Closed",
 }